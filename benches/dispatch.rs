@@ -0,0 +1,79 @@
+//
+// genetic - genetic programming experiments
+// Copyright (c) 2019 Filip Szczerek <ga.software@yahoo.com>
+//
+// This project is licensed under the terms of the MIT license
+// (see the LICENSE file for details).
+//
+//
+// File description:
+//   Benchmark: dynamic vs. static dispatch of virtual machine's input/output handler.
+//
+//   Run with `cargo bench --bench dispatch`.
+//
+
+extern crate genetic;
+
+use genetic::vm::{InputOutputHandler, OpCode, Program, RegValue, VirtualMachine};
+use std::time::{Duration, Instant};
+
+const NUM_RUNS: usize = 2000;
+const NUM_EXEC_INSTRUCTIONS: usize = 10000;
+
+struct IoHandler {
+    sum: RegValue
+}
+
+impl InputOutputHandler for IoHandler {
+    fn input(&mut self, input_num: i32) -> RegValue { input_num as RegValue }
+
+    fn output(&mut self, _output_num: i32, output_val: RegValue) { self.sum += output_val; }
+
+    fn check_end_condition(&self, _num_execd_instructions: usize) -> bool { self.sum > 1.0e+9 }
+}
+
+/// Input/output-heavy program, so that handler calls dominate.
+fn benchmark_program() -> Program {
+    Program::new(&[
+        OpCode::Input(1),
+        OpCode::Output(0),
+        OpCode::Input(2),
+        OpCode::Output(1),
+        OpCode::Input(3),
+        OpCode::Output(2),
+        OpCode::Input(-1),
+        OpCode::Output(3)
+    ], 1, false)
+}
+
+fn measure<F: FnMut() -> RegValue>(mut run: F) -> (Duration, RegValue) {
+    let start = Instant::now();
+    let mut checksum = 0.0;
+    for _ in 0..NUM_RUNS {
+        checksum += run();
+    }
+    (start.elapsed(), checksum)
+}
+
+fn main() {
+    let program = benchmark_program();
+
+    let (dynamic_time, dynamic_checksum) = measure(|| {
+        let mut io_handler = IoHandler{ sum: 0.0 };
+        VirtualMachine::new(&program, Some(&mut io_handler)).run(Some(NUM_EXEC_INSTRUCTIONS), true, true);
+        io_handler.sum
+    });
+
+    let (static_time, static_checksum) = measure(|| {
+        let mut io_handler = IoHandler{ sum: 0.0 };
+        VirtualMachine::with_handler(&program, Some(&mut io_handler)).run(Some(NUM_EXEC_INSTRUCTIONS), true, true);
+        io_handler.sum
+    });
+
+    assert!(dynamic_checksum == static_checksum);
+
+    let num_instructions = (NUM_RUNS * NUM_EXEC_INSTRUCTIONS) as f64;
+    println!("dynamic dispatch: {:?} ({:.2} ns/instruction)", dynamic_time, dynamic_time.as_nanos() as f64 / num_instructions);
+    println!("static dispatch:  {:?} ({:.2} ns/instruction)", static_time, static_time.as_nanos() as f64 / num_instructions);
+    println!("speedup: {:.2}x", dynamic_time.as_secs_f64() / static_time.as_secs_f64());
+}
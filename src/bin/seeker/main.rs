@@ -14,12 +14,12 @@
 extern crate genetic;
 extern crate rand;
 extern crate rand_xorshift;
-extern crate rayon;
 
+use genetic::serialization::ParseError;
 use genetic::utils;
 use genetic::vm;
 use rand::prelude::*;
-use rayon::prelude::*;
+use std::collections::HashSet;
 
 // --------------- Tunable experiment parameters ---------------
 
@@ -29,14 +29,34 @@ const RND_SEED: u64 = 2;
 /// Size of the world (a square grid).
 const WORLD_SIZE: u32 = 128;
 
+/// Allowed agent moves.
+const CONNECTIVITY: Connectivity = Connectivity::Four;
+
+/// Behavior of the world's edges.
+const TOPOLOGY: WorldTopology = WorldTopology::Bounded;
+
+/// Metric of the final agent-target distance (the main component of fitness).
+const DISTANCE_METRIC: DistanceMetric = DistanceMetric::Euclidean;
+
+/// Number of impassable cells placed randomly in the world; if non-zero, programs can also sense obstacles
+/// (see `inputs::OBSTACLE_POS_X` etc.).
+const NUM_OBSTACLES: usize = 0;
+
 const NUM_PROGRAMS: usize = 128;
 const MIN_INITIAL_PROG_LEN: usize = 16;
 const MAX_INITIAL_PROG_LEN: usize = 32;
 const MAX_PROGRAM_LENGTH: usize = 1024;
 
+/// Offspring shorter than this are padded with `Nop`s.
+const MIN_PROGRAM_LENGTH: usize = 8;
+
 /// Number of virtual machine data slots used by programs.
 const NUM_PROG_DATA_SLOTS: usize = 4;
 
+/// VM assembly files with programs (e.g. partial solutions) included in the initial population;
+/// the remaining programs are random.
+const SEED_PROGRAM_FILES: &[&str] = &[];
+
 const NUM_TEST_CASES: usize = 32;
 
 /// Max. number of evolution iterations (evolution stops earlier if a program that solves all the test cases emerges).
@@ -51,12 +71,32 @@ const BEST_PROG_FRACTION: f64 = 0.2;
 /// Used instead of `BEST_PROG_FRACTION` when mitigating a fitness plateau.
 const BEST_PROG_FRACTION_IN_PLATEAU: f64 = 0.5;
 
+/// Method of recombination (crossover).
+const CROSSOVER_KIND: utils::CrossoverKind = utils::CrossoverKind::SingleSegment;
+
 /// Min. length of program segment exchanged during recombination (crossover).
 const MIN_CROSSOVER_SEG_LENGTH: usize = 6;
 
 /// Max. length of program segment exchanged during recombination (crossover).
 const MAX_CROSSOVER_SEG_LENGTH: usize = MAX_PROGRAM_LENGTH/4;
 
+/// If true, jump tables may match control flow instructions across blocks (see `vm::Program::new`);
+/// also allows exchanging program segments which cross blocks. Used for both the initial population and offspring.
+const ALLOW_CROSSING_BLOCKS: bool = true;
+
+/// If true, mutations do not create unmatched control flow instructions (see `utils::mutate`).
+const PRESERVE_BLOCKS: bool = false;
+
+/// If false, `IfP`/`IfN` never directly precede a control flow instruction in the initial population and offspring
+/// (see `utils::repair_guarded_control_flow`).
+const ALLOW_GUARDED_CONTROL_FLOW: bool = true;
+
+/// Number of the best programs copied unchanged into the new generation.
+const ELITISM_COUNT: usize = 1;
+
+/// If true, duplicate programs are replaced by random ones before breeding (see `utils::create_new_population`).
+const ENFORCE_UNIQUENESS: bool = false;
+
 /// Probability that a program undergoes mutation during an evolution step.
 const MUTATION_PROBABILITY: f64 = 0.2;
 
@@ -69,8 +109,94 @@ const NUM_MUTATIONS: usize = 3;
 /// Used instead of `NUM_MUTATIONS` when mitigating a fitness plateau.
 const NUM_MUTATIONS_IN_PLATEAU: usize = 16;
 
+/// Relative probabilities of mutation types.
+const MUTATION_WEIGHTS: utils::MutationWeights = utils::MutationWeights{
+    insert: 1.0, delete: 1.0, substitute: 1.0, transpose: 1.0, operand_perturbation: None
+};
+
+/// If set, evaluation of a program is aborted once its fitness exceeds the best fitness so far by this margin
+/// (its fitness is then reported as the best fitness plus the margin).
+const EARLY_ABORT_MARGIN: Option<f64> = None;
+
+/// Number of randomly chosen program pairs used to estimate population diversity in each generation.
+const NUM_DIVERSITY_SAMPLES: usize = 64;
+
+/// Per-generation mean fitness and diversity are saved to this file.
+const GENERATION_LOG_FILE: &str = "generations.csv";
+
+/// Evolution state is saved to this file every `CHECKPOINT_INTERVAL` generations; run with `--resume` to continue from it.
+const CHECKPOINT_FILE: &str = "seeker.checkpoint";
+
+const CHECKPOINT_INTERVAL: usize = 100;
+
 // ------------------------------------------------------------
 
+/// Moves the agent can make in a single step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Connectivity {
+    /// Moves along the axes only.
+    Four,
+    /// Moves along the axes and diagonally.
+    Eight
+}
+
+/// Behavior of the world's edges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WorldTopology {
+    /// Moves which would leave the world are ignored.
+    Bounded,
+    /// The world wraps around (e.g. moving right from X = `WORLD_SIZE - 1` leads to X = 0).
+    #[allow(dead_code)] // selected by changing `TOPOLOGY`
+    Toroidal
+}
+
+/// Metric of the distance between two points of the world.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DistanceMetric {
+    Euclidean,
+    /// Number of moves needed to get from one point to the other with `Connectivity::Four`.
+    #[allow(dead_code)] // selected by changing `DISTANCE_METRIC`
+    Manhattan,
+    /// Number of moves needed to get from one point to the other with `Connectivity::Eight`.
+    #[allow(dead_code)] // selected by changing `DISTANCE_METRIC`
+    Chebyshev
+}
+
+impl DistanceMetric {
+    /// Returns the distance corresponding to distances `dx`, `dy` along the axes.
+    fn distance(self, dx: i32, dy: i32) -> f64 {
+        let (dx, dy) = (dx.abs() as f64, dy.abs() as f64);
+        match self {
+            DistanceMetric::Euclidean => f64::sqrt(dx * dx + dy * dy),
+            DistanceMetric::Manhattan => dx + dy,
+            DistanceMetric::Chebyshev => f64::max(dx, dy)
+        }
+    }
+}
+
+impl WorldTopology {
+    /// Returns the position after moving from `pos` by `delta` along an axis, or `None` if the move is not possible.
+    fn moved(self, pos: i32, delta: i32) -> Option<i32> {
+        let new_pos = pos + delta;
+        match self {
+            WorldTopology::Bounded => if new_pos >= 0 && new_pos < WORLD_SIZE as i32 { Some(new_pos) } else { None },
+            WorldTopology::Toroidal => Some(new_pos.rem_euclid(WORLD_SIZE as i32))
+        }
+    }
+
+    /// Returns the distance between two points (for `Toroidal`, the shortest one, possibly across the edges).
+    fn distance(self, metric: DistanceMetric, x1: i32, y1: i32, x2: i32, y2: i32) -> f64 {
+        let axis_distance = |c1: i32, c2: i32| {
+            let d = (c1 - c2).abs();
+            match self {
+                WorldTopology::Bounded => d,
+                WorldTopology::Toroidal => std::cmp::min(d, WORLD_SIZE as i32 - d)
+            }
+        };
+        metric.distance(axis_distance(x1, x2), axis_distance(y1, y2))
+    }
+}
+
 // VM program outputs.
 mod outputs {
     /// Add 1 to agent's X coord.
@@ -81,6 +207,14 @@ mod outputs {
     pub const INC_Y: i32 = 2;
     /// Subtract 1 from agent's Y coord.
     pub const DEC_Y: i32 = 3;
+    /// Add 1 to agent's X and Y coords (only with `Connectivity::Eight`).
+    pub const INC_X_INC_Y: i32 = 4;
+    /// Add 1 to agent's X coord, subtract 1 from Y coord (only with `Connectivity::Eight`).
+    pub const INC_X_DEC_Y: i32 = 5;
+    /// Subtract 1 from agent's X coord, add 1 to Y coord (only with `Connectivity::Eight`).
+    pub const DEC_X_INC_Y: i32 = 6;
+    /// Subtract 1 from agent's X and Y coords (only with `Connectivity::Eight`).
+    pub const DEC_X_DEC_Y: i32 = 7;
 }
 
 /// VM program inputs.
@@ -93,45 +227,56 @@ mod inputs {
     pub const TARGET_X: i32 = 2;
     /// Get target's Y coord.
     pub const TARGET_Y: i32 = 3;
+    /// Get distance to the nearest obstacle in the direction of increasing X coord (only with obstacles).
+    pub const OBSTACLE_POS_X: i32 = 4;
+    /// Get distance to the nearest obstacle in the direction of decreasing X coord (only with obstacles).
+    pub const OBSTACLE_NEG_X: i32 = 5;
+    /// Get distance to the nearest obstacle in the direction of increasing Y coord (only with obstacles).
+    pub const OBSTACLE_POS_Y: i32 = 6;
+    /// Get distance to the nearest obstacle in the direction of decreasing Y coord (only with obstacles).
+    pub const OBSTACLE_NEG_Y: i32 = 7;
 }
 
-fn get_allowed_instructions() -> &'static [vm::OpCode] {
-    &[vm::OpCode::SetI(0),
-      vm::OpCode::SetI(1),
-      vm::OpCode::SetI(2),
-      vm::OpCode::SetI(3),
-      vm::OpCode::SetI(4),
-      vm::OpCode::Input(inputs::POS_X),
-      vm::OpCode::Input(inputs::POS_Y),
-      vm::OpCode::Input(inputs::TARGET_X),
-      vm::OpCode::Input(inputs::TARGET_Y),
-      vm::OpCode::Output(outputs::INC_X),
-      vm::OpCode::Output(outputs::DEC_X),
-      vm::OpCode::Output(outputs::INC_Y),
-      vm::OpCode::Output(outputs::DEC_Y),
-      vm::OpCode::ItoV,
-      vm::OpCode::VtoI,
-      vm::OpCode::IncV,
-      vm::OpCode::DecV,
-      vm::OpCode::IncI,
-      vm::OpCode::DecI,
-      vm::OpCode::Load,
-      vm::OpCode::Store,
-      vm::OpCode::Swap,
-      vm::OpCode::EndGoTo,
-      vm::OpCode::GoToIfP,
-      vm::OpCode::JumpIfN,
-      vm::OpCode::EndJump,
-      vm::OpCode::IfP,
-      vm::OpCode::IfN,
-      vm::OpCode::Cmp,
-      vm::OpCode::Add,
-      vm::OpCode::Sub,
-      vm::OpCode::Mul,
-      vm::OpCode::Div,
-      vm::OpCode::Abs,
-      vm::OpCode::Neg,
-      vm::OpCode::Nop]
+fn get_allowed_instructions(connectivity: Connectivity, sense_obstacles: bool) -> Vec<vm::OpCode> {
+    let mut allowed = utils::InstructionSetBuilder::new();
+    allowed
+        .set_i_range(0..=4)
+        .inputs(inputs::POS_X..=inputs::TARGET_Y)
+        .outputs(outputs::INC_X..=outputs::DEC_Y)
+        .enable_all(&[
+            vm::OpCode::ItoV,
+            vm::OpCode::VtoI,
+            vm::OpCode::IncV,
+            vm::OpCode::DecV,
+            vm::OpCode::IncI,
+            vm::OpCode::DecI,
+            vm::OpCode::Load,
+            vm::OpCode::Store,
+            vm::OpCode::Swap,
+            vm::OpCode::EndGoTo,
+            vm::OpCode::GoToIfP,
+            vm::OpCode::JumpIfN,
+            vm::OpCode::EndJump,
+            vm::OpCode::IfP,
+            vm::OpCode::IfN,
+            vm::OpCode::Cmp,
+            vm::OpCode::Add,
+            vm::OpCode::Sub,
+            vm::OpCode::Mul,
+            vm::OpCode::Div,
+            vm::OpCode::Abs,
+            vm::OpCode::Neg,
+            vm::OpCode::Nop
+        ]);
+
+    if connectivity == Connectivity::Eight {
+        allowed.outputs(outputs::INC_X_INC_Y..=outputs::DEC_X_DEC_Y);
+    }
+    if sense_obstacles {
+        allowed.inputs(inputs::OBSTACLE_POS_X..=inputs::OBSTACLE_NEG_Y);
+    }
+
+    allowed.build()
 }
 
 /// Test case for evaluating program's fitness.
@@ -158,17 +303,152 @@ fn generate_test_cases(count: usize, world_size: u32, rng: &mut rand_xorshift::X
     result
 }
 
-fn generate_initial_population(rng: &mut rand_xorshift::XorShiftRng) -> utils::SortedEvaluatedPrograms {
-    let programs = utils::generate_random_programs(
-        NUM_PROGRAMS,
+/// Impassable cells of the world.
+struct World {
+    pub obstacles: HashSet<(i32, i32)>
+}
+
+impl World {
+    /// Creates a world with `num_obstacles` randomly placed obstacles, none of them on test cases' start or target.
+    fn generate(num_obstacles: usize, test_cases: &[TestCase], rng: &mut rand_xorshift::XorShiftRng) -> World {
+        let reserved: HashSet<(i32, i32)> = test_cases.iter()
+            .flat_map(|t| vec![(t.pos_x, t.pos_y), (t.target_x, t.target_y)])
+            .collect();
+        assert!(num_obstacles + reserved.len() <= (WORLD_SIZE * WORLD_SIZE) as usize, "Too many obstacles.");
+
+        let mut obstacles = HashSet::new();
+        while obstacles.len() < num_obstacles {
+            let cell = (rng.gen_range(0, WORLD_SIZE) as i32, rng.gen_range(0, WORLD_SIZE) as i32);
+            if !reserved.contains(&cell) {
+                obstacles.insert(cell);
+            }
+        }
+
+        World{ obstacles }
+    }
+
+    ///
+    /// Returns the distance from (`x`, `y`) to the nearest obstacle in direction (`dx`, `dy`); in a bounded world
+    /// the area beyond the edge counts as an obstacle. If there is no obstacle in this direction in a toroidal
+    /// world, returns `WORLD_SIZE`.
+    ///
+    fn obstacle_distance(&self, topology: WorldTopology, x: i32, y: i32, dx: i32, dy: i32) -> i32 {
+        let (mut cx, mut cy) = (x, y);
+        for distance in 1..WORLD_SIZE as i32 {
+            match (topology.moved(cx, dx), topology.moved(cy, dy)) {
+                (Some(new_x), Some(new_y)) if !self.obstacles.contains(&(new_x, new_y)) => { cx = new_x; cy = new_y; },
+                _ => return distance
+            }
+        }
+
+        WORLD_SIZE as i32
+    }
+}
+
+fn generate_initial_population(num_programs: usize, rng: &mut rand_xorshift::XorShiftRng) -> utils::SortedEvaluatedPrograms {
+    let seed_programs: Vec<vm::Program> = SEED_PROGRAM_FILES.iter().map(|file_name| {
+        let text = std::fs::read_to_string(file_name).unwrap_or_else(|_| panic!("Could not read {}.", file_name));
+        utils::parse_vmasm(&text, NUM_PROG_DATA_SLOTS, ALLOW_CROSSING_BLOCKS)
+            .unwrap_or_else(|err| panic!("Could not parse {}: {:?}.", file_name, err))
+    }).collect();
+
+    let programs = utils::seed_population(&seed_programs, num_programs, |count| utils::generate_random_programs(
+        count,
         MIN_INITIAL_PROG_LEN,
         MAX_INITIAL_PROG_LEN,
         NUM_PROG_DATA_SLOTS,
-        get_allowed_instructions(),
+        &get_allowed_instructions(CONNECTIVITY, NUM_OBSTACLES != 0),
         None,
-        rng);
+        ALLOW_CROSSING_BLOCKS,
+        ALLOW_GUARDED_CONTROL_FLOW,
+        rng
+    ));
+
+    utils::SortedEvaluatedPrograms::new(programs, vec![utils::WORST_FITNESS; num_programs])
+}
+
+/// Agent moving on the grid, controlled by a VM program.
+struct Agent<'a> {
+    // current position
+    pub x: i32,
+    pub y: i32,
+    // target position
+    pub tx: i32,
+    pub ty: i32,
+    pub distance_travelled: i32,
+    pub connectivity: Connectivity,
+    pub topology: WorldTopology,
+    pub world: &'a World,
+    /// If set, positions after each move are appended.
+    pub trajectory: Option<Vec<(i32, i32)>>
+}
+
+impl<'a> Agent<'a> {
+    /// Returns an agent at the test case's start location.
+    fn for_test_case(test_case: &TestCase, world: &'a World) -> Agent<'a> {
+        Agent{
+            x: test_case.pos_x,
+            y: test_case.pos_y,
+            tx: test_case.target_x,
+            ty: test_case.target_y,
+            distance_travelled: 0,
+            connectivity: CONNECTIVITY,
+            topology: TOPOLOGY,
+            world,
+            trajectory: None
+        }
+    }
+}
+
+impl<'a> vm::InputOutputHandler for Agent<'a> {
+    fn input(&mut self, input_num: i32) -> vm::RegValue {
+        match input_num {
+            inputs::POS_X => self.x as vm::RegValue,
+            inputs::POS_Y => self.y as vm::RegValue,
+            inputs::TARGET_X => self.tx as vm::RegValue,
+            inputs::TARGET_Y => self.ty as vm::RegValue,
+            inputs::OBSTACLE_POS_X => self.world.obstacle_distance(self.topology, self.x, self.y, 1, 0) as vm::RegValue,
+            inputs::OBSTACLE_NEG_X => self.world.obstacle_distance(self.topology, self.x, self.y, -1, 0) as vm::RegValue,
+            inputs::OBSTACLE_POS_Y => self.world.obstacle_distance(self.topology, self.x, self.y, 0, 1) as vm::RegValue,
+            inputs::OBSTACLE_NEG_Y => self.world.obstacle_distance(self.topology, self.x, self.y, 0, -1) as vm::RegValue,
+            _ => 0.0
+        }
+    }
 
-    utils::SortedEvaluatedPrograms::new(programs, vec![utils::WORST_FITNESS; NUM_PROGRAMS])
+    /// Moves the agent; moves into an obstacle or which would leave a bounded world are ignored.
+    fn output(&mut self, output_num: i32, _output_val: vm::RegValue) {
+        let diagonal = self.connectivity == Connectivity::Eight;
+
+        let (dx, dy) = match output_num {
+            outputs::INC_X => (1, 0),
+            outputs::DEC_X => (-1, 0),
+            outputs::INC_Y => (0, 1),
+            outputs::DEC_Y => (0, -1),
+            outputs::INC_X_INC_Y if diagonal => (1, 1),
+            outputs::INC_X_DEC_Y if diagonal => (1, -1),
+            outputs::DEC_X_INC_Y if diagonal => (-1, 1),
+            outputs::DEC_X_DEC_Y if diagonal => (-1, -1),
+            _ => (0, 0)
+        };
+
+        if dx != 0 || dy != 0 {
+            if let (Some(new_x), Some(new_y)) = (self.topology.moved(self.x, dx), self.topology.moved(self.y, dy)) {
+                if self.world.obstacles.contains(&(new_x, new_y)) {
+                    return;
+                }
+                self.x = new_x;
+                self.y = new_y;
+                self.distance_travelled += 1;
+                if let Some(ref mut trajectory) = self.trajectory {
+                    trajectory.push((new_x, new_y));
+                }
+            }
+        }
+    }
+
+    fn check_end_condition(&self, _num_execd_instructions: usize) -> bool {
+        self.x == self.tx && self.y == self.ty
+    }
 }
 
 /// Evaluates genetic program's fitness.
@@ -182,79 +462,35 @@ fn generate_initial_population(rng: &mut rand_xorshift::XorShiftRng) -> utils::S
 ///     2 - target.x
 ///     3 - target.y
 ///
+/// and, if there are obstacles, the distance to the nearest obstacle (see `World::obstacle_distance`):
+///     4 - in the direction of increasing agent.x
+///     5 - in the direction of decreasing agent.x
+///     6 - in the direction of increasing agent.y
+///     7 - in the direction of decreasing agent.y
+///
 /// Writing to outputs (`reg_v` value is irrelevant) determines agent actions:
 ///     0 - increment agent.x by 1
 ///     1 - decrement agent.x by 1
 ///     2 - increment agent.y by 1
 ///     3 - decrement agent.y by 1
 ///
-/// Returns (fitness, whether the program reached the target).
+/// and, with `Connectivity::Eight` (a diagonal move counts as a single step of travelled distance):
+///     4 - increment agent.x and agent.y by 1
+///     5 - increment agent.x by 1, decrement agent.y by 1
+///     6 - decrement agent.x by 1, increment agent.y by 1
+///     7 - decrement agent.x and agent.y by 1
+///
+/// Returns fitness and whether the program reached the target.
 ///
 fn evaluate_fitness(
     program: &vm::Program,
-    test_case: &TestCase
-) -> (utils::Fitness, bool) {
-
-    macro_rules! sqr{ ($x:expr) => { ($x) * ($x) }; }
-
-    struct Agent {
-        // current position
-        pub x: i32,
-        pub y: i32,
-        // target position
-        pub tx: i32,
-        pub ty: i32,
-        pub distance_travelled: i32
-    }
-
-    impl vm::InputOutputHandler for Agent {
-        fn input(&mut self, input_num: i32) -> vm::RegValue {
-            match input_num {
-                inputs::POS_X => self.x as vm::RegValue,
-                inputs::POS_Y => self.y as vm::RegValue,
-                inputs::TARGET_X => self.tx as vm::RegValue,
-                inputs::TARGET_Y => self.ty as vm::RegValue,
-                _ => 0.0
-            }
-        }
-
-        fn output(&mut self, output_num: i32, _output_val: vm::RegValue) {
-            let old_x = self.x;
-            let old_y = self.y;
+    test_case: &TestCase,
+    world: &World
+) -> utils::EvaluationOutcome {
+    let mut agent = Agent::for_test_case(test_case, world);
+    run_agent(program, &mut agent);
 
-            match output_num {
-                outputs::INC_X => if self.x < WORLD_SIZE as i32 - 1 { self.x += 1; },
-                outputs::DEC_X => if self.x > 0 { self.x -= 1; },
-                outputs::INC_Y => if self.y < WORLD_SIZE as i32 - 1 { self.y += 1; },
-                outputs::DEC_Y => if self.y > 0 { self.y -= 1; },
-                _ => ()
-            }
-
-            if self.x != old_x || self.y != old_y {
-                self.distance_travelled += 1;
-            }
-        }
-
-        fn check_end_condition(&self, _num_execd_instructions: usize) -> bool {
-            self.x == self.tx && self.y == self.ty
-        }
-    }
-
-    let mut agent = Agent{
-        x: test_case.pos_x,
-        y: test_case.pos_y,
-        tx: test_case.target_x,
-        ty: test_case.target_y,
-        distance_travelled: 0
-    };
-
-    {
-        let opt_program = program.get_optimized();
-        let mut vm = vm::VirtualMachine::new(&opt_program, Some(&mut agent));
-        vm.run(Some(MAX_EXEC_INSTRUCTIONS), true, true);
-    }
-
-    let final_dist = f64::sqrt(sqr!(agent.x - agent.tx) as f64 + sqr!(agent.y - agent.ty) as f64);
+    let final_dist = agent.topology.distance(DISTANCE_METRIC, agent.x, agent.y, agent.tx, agent.ty);
     let reached_target = final_dist == 0.0;
 
     // fitness penalty for taking too long to reach the target
@@ -262,39 +498,53 @@ fn evaluate_fitness(
 
     if reached_target {
         // reduce the penalty if the program used a shorter path
-        penalty = penalty * (1.0 - f64::exp(-1.0*agent.distance_travelled as f64));
+        penalty *= 1.0 - f64::exp(-(agent.distance_travelled as f64));
     }
 
-    return (penalty + final_dist, reached_target)
+    utils::EvaluationOutcome{ fitness: penalty + final_dist, solved: reached_target }
+}
+
+/// Runs `program` controlling `agent` (see `evaluate_fitness`).
+fn run_agent(program: &vm::Program, agent: &mut Agent) -> vm::EndReason {
+    let opt_program = program.get_optimized();
+    let mut vm = vm::VirtualMachine::with_handler(&opt_program, Some(agent));
+    // the agent moves only on output, so a loop without output cannot change the result
+    vm.run_with_options(&vm::RunOptions{
+        num_exec_instructions: Some(MAX_EXEC_INSTRUCTIONS),
+        instruction_cost: None,
+        mode: vm::RunMode::LoopForever,
+        check_end_condition: true,
+        termination: vm::TerminationPolicy::default(),
+        detect_loops: true,
+        max_duration: None
+    })
+}
+
+///
+/// Runs `program` for `test_case` like `evaluate_fitness`; returns the agent's positions after each move
+/// (excluding the start location) and the reason for ending the run. Can be used to draw the agent's path.
+///
+#[cfg_attr(not(test), allow(dead_code))]
+fn replay(program: &vm::Program, test_case: &TestCase, world: &World) -> (Vec<(i32, i32)>, vm::EndReason) {
+    let mut agent = Agent{ trajectory: Some(vec![]), ..Agent::for_test_case(test_case, world) };
+    let end_reason = run_agent(program, &mut agent);
+    (agent.trajectory.unwrap(), end_reason)
 }
 
 ///
-/// Evaluates fitness of `programs`.
+/// Evaluates fitness of `programs`; evaluation of a program is aborted once its fitness exceeds `fitness_cap`.
 ///
 /// Returns list of evaluated programs (sorted by fitness) and a flag indicating if any program solved all test cases.
 ///
-fn evaluate_programs(programs: Vec<vm::Program>, test_cases: &[TestCase]) -> (utils::SortedEvaluatedPrograms, bool) {
-    // fitness of each program
-    let mut fitness = vec![0.0; programs.len()];
-    // indicates if any program reached all targets
-    let all_targets_reached = std::sync::atomic::AtomicBool::new(false);
-
-    // runs in parallel using `RAYON_NUM_THREADS` CPU cores
-    fitness.par_iter_mut().enumerate().for_each(
-        |(i, f)| {
-            let mut prog_fitness = 0.0;
-            let mut prog_all_targets_reached = true;
-            for test_case in test_cases.iter() {
-                let (tcase_fitness, tcase_target_reached) = evaluate_fitness(&programs[i], test_case);
-                prog_fitness += tcase_fitness;
-                prog_all_targets_reached = prog_all_targets_reached && tcase_target_reached;
-            }
-            *f = prog_fitness;
-            all_targets_reached.fetch_or(prog_all_targets_reached, std::sync::atomic::Ordering::Relaxed);
-        }
-    );
-
-    (utils::SortedEvaluatedPrograms::new(programs, fitness), all_targets_reached.into_inner())
+fn evaluate_programs(
+    programs: Vec<vm::Program>,
+    test_cases: &[TestCase],
+    world: &World,
+    fitness_cap: Option<utils::Fitness>
+) -> (utils::SortedEvaluatedPrograms, bool) {
+    utils::evaluate_population(programs, fitness_cap, |program, fitness_cap| {
+        utils::evaluate_test_cases(test_cases, fitness_cap, |test_case| evaluate_fitness(program, test_case, world))
+    })
 }
 
 struct EvolutionState {
@@ -312,6 +562,19 @@ impl EvolutionState {
     const NUM_PLATEAU_MITIGATION_STEPS: usize = 30;
     const NUM_PLATEAU_DETECTION_STEPS: usize = 16;
 
+    pub fn new() -> EvolutionState {
+        EvolutionState{
+            mutation_probability: MUTATION_PROBABILITY,
+            best_prog_fraction: BEST_PROG_FRACTION,
+            num_mutations: NUM_MUTATIONS,
+
+            mitigating_plateau: false,
+            mitigation_step: 0,
+            plateau_steps: 0,
+            best_fitness: utils::WORST_FITNESS
+        }
+    }
+
     pub fn end_plateau_mitigation(&mut self) {
         self.mitigating_plateau = false;
         self.mutation_probability = MUTATION_PROBABILITY;
@@ -336,6 +599,7 @@ impl EvolutionState {
 fn evaluate_and_reproduce_best_programs(
     programs: utils::SortedEvaluatedPrograms,
     test_cases: &[TestCase],
+    world: &World,
     evolution: &mut EvolutionState,
     rng: &mut rand_xorshift::XorShiftRng
 ) -> (utils::SortedEvaluatedPrograms, bool) {
@@ -345,26 +609,35 @@ fn evaluate_and_reproduce_best_programs(
     //
     let new_population = utils::create_new_population(
         programs,
-
-        evolution.mutation_probability,
-        evolution.num_mutations,
-        evolution.best_prog_fraction,
-        get_allowed_instructions(),
-        MIN_CROSSOVER_SEG_LENGTH,
-        MAX_CROSSOVER_SEG_LENGTH,
-        MAX_PROGRAM_LENGTH,
-        NUM_PROG_DATA_SLOTS,
+        &utils::BreedingParameters{
+            mutation_probability: evolution.mutation_probability,
+            num_mutations: evolution.num_mutations,
+            mutation_weights: MUTATION_WEIGHTS,
+            selection: utils::SelectionStrategy::TopFraction{ fraction: evolution.best_prog_fraction },
+            elitism_count: ELITISM_COUNT,
+            crossover: CROSSOVER_KIND,
+            allowed_instructions: get_allowed_instructions(CONNECTIVITY, NUM_OBSTACLES != 0),
+            min_crossover_seg_length: MIN_CROSSOVER_SEG_LENGTH,
+            max_crossover_seg_length: MAX_CROSSOVER_SEG_LENGTH,
+            min_program_length: MIN_PROGRAM_LENGTH,
+            max_program_length: MAX_PROGRAM_LENGTH,
+            allow_crossing_blocks: ALLOW_CROSSING_BLOCKS,
+            preserve_blocks: PRESERVE_BLOCKS,
+            allow_guarded_control_flow: ALLOW_GUARDED_CONTROL_FLOW,
+            enforce_uniqueness: ENFORCE_UNIQUENESS
+        },
         rng);
 
     //
     // 2) Evaluate fitness of the new population by running the programs for all test cases.
     //
-    let (sorted_new_programs, all_targets_reached) = evaluate_programs(new_population, &test_cases);
+    let fitness_cap = EARLY_ABORT_MARGIN.map(|margin| evolution.best_fitness + margin);
+    let (sorted_new_programs, all_targets_reached) = evaluate_programs(new_population, test_cases, world, fitness_cap);
 
     //
     // 3) Print statistics and mitigate a plateau if needed.
     //
-    let best_fitness = sorted_new_programs.get_programs()[0].fitness;
+    let best_fitness = sorted_new_programs.best().expect("population is empty").fitness;
 
     if best_fitness < evolution.best_fitness {
         evolution.best_fitness = best_fitness;
@@ -397,54 +670,489 @@ fn evaluate_and_reproduce_best_programs(
     (sorted_new_programs, all_targets_reached)
 }
 
-fn main() {
-    let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(RND_SEED);
+/// Experiment parameters which can be changed without recompiling (e.g. in tests).
+struct ExperimentConfig {
+    pub rnd_seed: u64,
+    pub num_programs: usize,
+    pub num_test_cases: usize,
+    pub max_num_iterations: usize,
+    /// If set, a checkpoint is saved to this file every `checkpoint_interval` generations.
+    pub checkpoint_file: Option<std::path::PathBuf>,
+    pub checkpoint_interval: usize
+}
 
-    let mut evolution = EvolutionState{
-        mutation_probability: MUTATION_PROBABILITY,
-        best_prog_fraction: BEST_PROG_FRACTION,
-        num_mutations: NUM_MUTATIONS,
+impl Default for ExperimentConfig {
+    fn default() -> ExperimentConfig {
+        ExperimentConfig{
+            rnd_seed: RND_SEED,
+            num_programs: NUM_PROGRAMS,
+            num_test_cases: NUM_TEST_CASES,
+            max_num_iterations: MAX_NUM_ITERATIONS,
+            checkpoint_file: Some(CHECKPOINT_FILE.into()),
+            checkpoint_interval: CHECKPOINT_INTERVAL
+        }
+    }
+}
 
-        mitigating_plateau: false,
-        mitigation_step: 0,
-        plateau_steps: 0,
-        best_fitness: utils::WORST_FITNESS
-    };
+struct ExperimentResult {
+    /// Final population (used only by tests).
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub programs: utils::SortedEvaluatedPrograms,
+    /// The best program of all generations.
+    pub hall_of_fame: utils::HallOfFame,
+    /// True if a program solving all the test cases emerged.
+    pub all_targets_reached: bool,
+    pub generation_log: utils::GenerationLog
+}
+
+/// Returns the seeker-specific part of a checkpoint (`utils::EvolutionCheckpoint::user_data`).
+fn encode_user_data(
+    evolution: &EvolutionState,
+    hall_of_fame: &utils::HallOfFame,
+    log_rng: &rand_xorshift::XorShiftRng,
+    generation_log: &utils::GenerationLog
+) -> Vec<u8> {
+    let mut result = vec![];
+    // the remaining fields of `EvolutionState` are determined by `mitigating_plateau`
+    result.push(if evolution.mitigating_plateau { 1 } else { 0 });
+    result.extend_from_slice(&(evolution.mitigation_step as u64).to_le_bytes());
+    result.extend_from_slice(&(evolution.plateau_steps as u64).to_le_bytes());
+    result.extend_from_slice(&evolution.best_fitness.to_bits().to_le_bytes());
+    result.extend_from_slice(&(hall_of_fame.get_programs().len() as u64).to_le_bytes());
+    for program in hall_of_fame.get_programs() {
+        let prog_data = program.prog.to_bytes();
+        result.extend_from_slice(&program.fitness.to_bits().to_le_bytes());
+        result.extend_from_slice(&(prog_data.len() as u64).to_le_bytes());
+        result.extend_from_slice(&prog_data);
+    }
+    result.extend_from_slice(&utils::rng_state(log_rng));
+    for (generation, mean_fitness, diversity) in generation_log.get_entries() {
+        result.extend_from_slice(&(*generation as u64).to_le_bytes());
+        result.extend_from_slice(&mean_fitness.to_bits().to_le_bytes());
+        result.extend_from_slice(&diversity.to_bits().to_le_bytes());
+    }
 
-    let test_cases = generate_test_cases(NUM_TEST_CASES, WORLD_SIZE, &mut rng);
+    result
+}
 
-    let mut programs = generate_initial_population(&mut rng);
+/// Removes `count` bytes from the beginning of `data` and returns them.
+fn take_bytes<'a>(data: &mut &'a [u8], count: usize) -> Result<&'a [u8], ParseError> {
+    if data.len() < count {
+        return Err(ParseError::UnexpectedEnd);
+    }
+    let (value, rest) = data.split_at(count);
+    *data = rest;
+    Ok(value)
+}
+
+fn take_u64(data: &mut &[u8]) -> Result<u64, ParseError> {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(take_bytes(data, 8)?);
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Evolution restored from a checkpoint (see `load_checkpoint`).
+struct ResumedExperiment {
+    checkpoint: utils::EvolutionCheckpoint,
+    evolution: EvolutionState,
+    hall_of_fame: utils::HallOfFame,
+    log_rng: rand_xorshift::XorShiftRng,
+    generation_log: utils::GenerationLog
+}
+
+/// Restores the state saved by `encode_user_data`.
+fn decode_user_data(checkpoint: utils::EvolutionCheckpoint) -> Result<ResumedExperiment, ParseError> {
+    let mut data = &checkpoint.user_data[..];
+
+    let mut evolution = EvolutionState::new();
+    if take_bytes(&mut data, 1)?[0] != 0 {
+        evolution.enable_plateau_mitigation();
+    }
+    evolution.mitigation_step = take_u64(&mut data)? as usize;
+    evolution.plateau_steps = take_u64(&mut data)? as usize;
+    evolution.best_fitness = utils::Fitness::from_bits(take_u64(&mut data)?);
+
+    let mut hall_of_fame = utils::HallOfFame::new(1);
+    let num_hall_of_fame_programs = take_u64(&mut data)?;
+    for _ in 0..num_hall_of_fame_programs {
+        let fitness = utils::Fitness::from_bits(take_u64(&mut data)?);
+        let prog_data_len = take_u64(&mut data)? as usize;
+        let prog = vm::Program::from_bytes(take_bytes(&mut data, prog_data_len)?)?;
+        hall_of_fame.consider(&utils::SortedEvaluatedPrograms::new(vec![prog], vec![fitness]));
+    }
 
-    for i in 0..MAX_NUM_ITERATIONS {
+    let mut seed = [0u8; 16];
+    for chunk in seed.chunks_mut(8) {
+        chunk.copy_from_slice(&take_u64(&mut data)?.to_le_bytes());
+    }
+    let log_rng = rand_xorshift::XorShiftRng::from_seed(seed);
+
+    let mut entries = vec![];
+    while !data.is_empty() {
+        let generation = take_u64(&mut data)? as usize;
+        let mean_fitness = f64::from_bits(take_u64(&mut data)?);
+        let diversity = f64::from_bits(take_u64(&mut data)?);
+        entries.push((generation, mean_fitness, diversity));
+    }
+
+    Ok(ResumedExperiment{
+        checkpoint,
+        evolution,
+        hall_of_fame,
+        log_rng,
+        generation_log: utils::GenerationLog::with_entries(entries)
+    })
+}
+
+/// Loads a checkpoint saved by `run_experiment`, including the seeker-specific part (see `decode_user_data`).
+fn load_checkpoint<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<ResumedExperiment> {
+    let checkpoint = utils::EvolutionCheckpoint::load(path)?;
+    decode_user_data(checkpoint).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+///
+/// Runs the evolution until a program solving all the test cases emerges or `config.max_num_iterations` is reached.
+///
+/// If `resumed` is specified, the evolution continues from it (giving the same results as an uninterrupted run
+/// with the same `config`) instead of starting from a random initial population.
+///
+fn run_experiment(config: &ExperimentConfig, resumed: Option<ResumedExperiment>) -> ExperimentResult {
+    let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(config.rnd_seed);
+
+    let test_cases = generate_test_cases(config.num_test_cases, WORLD_SIZE, &mut rng);
+    let world = World::generate(NUM_OBSTACLES, &test_cases, &mut rng);
+
+    let (mut programs, mut evolution, mut hall_of_fame, mut generation_log, mut log_rng, first_generation) = match resumed {
+        Some(resumed) => {
+            rng = resumed.checkpoint.rng;
+            (
+                resumed.checkpoint.programs,
+                resumed.evolution,
+                resumed.hall_of_fame,
+                resumed.generation_log,
+                resumed.log_rng,
+                resumed.checkpoint.generation
+            )
+        },
+
+        None => (
+            generate_initial_population(config.num_programs, &mut rng),
+            EvolutionState::new(),
+            utils::HallOfFame::new(1),
+            utils::GenerationLog::new(),
+            // separate generator, so that logging does not affect the course of evolution
+            rand_xorshift::XorShiftRng::seed_from_u64(config.rnd_seed),
+            0
+        )
+    };
+
+    let mut all_targets_reached = false;
+    for i in first_generation..config.max_num_iterations {
         print!("{}: ", i);
 
-        let (new_programs, all_targets_reached) = evaluate_and_reproduce_best_programs(programs, &test_cases, &mut evolution, &mut rng);
-        if all_targets_reached {
-            let optimized_best_prog = new_programs.get_programs()[0].prog.get_optimized();
-
-            let output_vmasm = "program.vmasm";
-            let output_jsvm = "src/bin/seeker/demo/program.js";
-            println!("\nSaving the best program as:\n  \
-                          - {} (VM assembly)\n  \
-                          - {} (JavaScript virtual machine)", output_vmasm, output_jsvm);
-            std::fs::write(
-                output_vmasm,
-                utils::pretty_print(
-                    &optimized_best_prog,
-                    Some("*"),
-                    false,
-                    Some(2)
-                )
-            ).expect(&format!("Could not write to {}.", output_vmasm));
-
-            std::fs::write(
-                output_jsvm,
-                genetic::transpile::javascript_vm::program_to_javascript_vm(&optimized_best_prog)
-            ).expect(&format!("Could not write to {}.", output_jsvm));
+        let (new_programs, targets_reached) = evaluate_and_reproduce_best_programs(programs, &test_cases, &world, &mut evolution, &mut rng);
+        generation_log.record(i, &new_programs, NUM_DIVERSITY_SAMPLES, &mut log_rng);
+        hall_of_fame.consider(&new_programs);
+        programs = new_programs;
 
+        if targets_reached {
+            all_targets_reached = true;
             break;
         }
 
-        programs = new_programs;
+        if let Some(ref checkpoint_file) = config.checkpoint_file {
+            if (i + 1).is_multiple_of(config.checkpoint_interval) {
+                let checkpoint = utils::EvolutionCheckpoint{
+                    programs,
+                    rng: rng.clone(),
+                    generation: i + 1,
+                    user_data: encode_user_data(&evolution, &hall_of_fame, &log_rng, &generation_log)
+                };
+                checkpoint.save(checkpoint_file).unwrap_or_else(|_| panic!("Could not write to {}.", checkpoint_file.display()));
+                programs = checkpoint.programs;
+            }
+        }
+    }
+
+    ExperimentResult{ programs, hall_of_fame, all_targets_reached, generation_log }
+}
+
+/// Returns VM assembly of the optimized best program.
+fn champion_vmasm(hall_of_fame: &utils::HallOfFame) -> String {
+    utils::pretty_print(
+        &hall_of_fame.best().prog.get_optimized(),
+        Some("*"),
+        false,
+        Some(2)
+    )
+}
+
+fn main() {
+    let checkpoint = if std::env::args().any(|arg| arg == "--resume") {
+        println!("Resuming from {}.", CHECKPOINT_FILE);
+        Some(load_checkpoint(CHECKPOINT_FILE).unwrap_or_else(|e| panic!("Could not load {}: {}.", CHECKPOINT_FILE, e)))
+    } else {
+        None
+    };
+
+    let result = run_experiment(&ExperimentConfig::default(), checkpoint);
+
+    if result.all_targets_reached {
+        let output_vmasm = "program.vmasm";
+        let output_jsvm = "src/bin/seeker/demo/program.js";
+        println!("\nSaving the best program as:\n  \
+                      - {} (VM assembly)\n  \
+                      - {} (JavaScript virtual machine)", output_vmasm, output_jsvm);
+        std::fs::write(
+            output_vmasm,
+            champion_vmasm(&result.hall_of_fame)
+        ).unwrap_or_else(|_| panic!("Could not write to {}.", output_vmasm));
+
+        std::fs::write(
+            output_jsvm,
+            genetic::transpile::javascript_vm::program_to_javascript_vm(&result.hall_of_fame.best().prog.get_optimized())
+        ).unwrap_or_else(|_| panic!("Could not write to {}.", output_jsvm));
+    }
+
+    result.generation_log.write_csv(GENERATION_LOG_FILE).unwrap_or_else(|_| panic!("Could not write to {}.", GENERATION_LOG_FILE));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genetic::vm::InputOutputHandler;
+
+    fn agent_at(x: i32, y: i32, connectivity: Connectivity, world: &World) -> Agent<'_> {
+        Agent{ x, y, tx: 0, ty: 0, distance_travelled: 0, connectivity, topology: WorldTopology::Bounded, world, trajectory: None }
+    }
+
+    fn empty_world() -> World { World{ obstacles: HashSet::new() } }
+
+    #[test]
+    fn replay_straight_line() {
+        let world = empty_world();
+        let program = vm::Program::new(&[vm::OpCode::Output(outputs::INC_X)], 0, false);
+
+        let test_case = TestCase{ pos_x: 10, pos_y: 10, target_x: 13, target_y: 10 };
+        let (trajectory, end_reason) = replay(&program, &test_case, &world);
+        assert_eq!(vec![(11, 10), (12, 10), (13, 10)], trajectory);
+        assert_eq!(vm::EndReason::EndConditionMet, end_reason);
+
+        // moves past the world's edge are not recorded
+        let test_case = TestCase{ pos_x: WORLD_SIZE as i32 - 3, pos_y: 0, target_x: 0, target_y: 0 };
+        let (trajectory, end_reason) = replay(&program, &test_case, &world);
+        assert_eq!(vec![(WORLD_SIZE as i32 - 2, 0), (WORLD_SIZE as i32 - 1, 0)], trajectory);
+        assert_eq!(vm::EndReason::NumExecInstructions, end_reason);
+    }
+
+    #[test]
+    fn diagonal_move() {
+        let world = empty_world();
+        let mut agent = agent_at(10, 10, Connectivity::Eight, &world);
+
+        agent.output(outputs::INC_X_INC_Y, 0.0);
+        assert_eq!((11, 11), (agent.x, agent.y));
+        agent.output(outputs::INC_X_DEC_Y, 0.0);
+        assert_eq!((12, 10), (agent.x, agent.y));
+        agent.output(outputs::DEC_X_INC_Y, 0.0);
+        assert_eq!((11, 11), (agent.x, agent.y));
+        agent.output(outputs::DEC_X_DEC_Y, 0.0);
+        assert_eq!((10, 10), (agent.x, agent.y));
+        assert_eq!(4, agent.distance_travelled);
+    }
+
+    #[test]
+    fn diagonal_move_rejected_at_boundary() {
+        let world = empty_world();
+        let max = WORLD_SIZE as i32 - 1;
+
+        let mut agent = agent_at(0, 5, Connectivity::Eight, &world);
+        agent.output(outputs::DEC_X_INC_Y, 0.0);
+        assert_eq!((0, 5), (agent.x, agent.y));
+
+        let mut agent = agent_at(5, max, Connectivity::Eight, &world);
+        agent.output(outputs::INC_X_INC_Y, 0.0);
+        assert_eq!((5, max), (agent.x, agent.y));
+
+        let mut agent = agent_at(max, 0, Connectivity::Eight, &world);
+        agent.output(outputs::INC_X_DEC_Y, 0.0);
+        assert_eq!((max, 0), (agent.x, agent.y));
+        assert_eq!(0, agent.distance_travelled);
+    }
+
+    #[test]
+    fn diagonal_move_ignored_with_four_connectivity() {
+        let world = empty_world();
+        let mut agent = agent_at(10, 10, Connectivity::Four, &world);
+        agent.output(outputs::INC_X_INC_Y, 0.0);
+        assert_eq!((10, 10), (agent.x, agent.y));
+
+        agent.output(outputs::INC_X, 0.0);
+        assert_eq!((11, 10), (agent.x, agent.y));
+    }
+
+    #[test]
+    fn toroidal_move_wraps_around() {
+        let world = empty_world();
+        let max = WORLD_SIZE as i32 - 1;
+
+        let mut agent = Agent{ topology: WorldTopology::Toroidal, ..agent_at(max, 0, Connectivity::Eight, &world) };
+        agent.output(outputs::INC_X_DEC_Y, 0.0);
+        assert_eq!((0, max), (agent.x, agent.y));
+        agent.output(outputs::DEC_X, 0.0);
+        assert_eq!((max, max), (agent.x, agent.y));
+        assert_eq!(2, agent.distance_travelled);
+    }
+
+    #[test]
+    fn move_into_obstacle_rejected() {
+        let world = World{ obstacles: [(11, 10), (10, 9)].iter().cloned().collect() };
+        let program = vm::Program::new(&[
+            vm::OpCode::Output(outputs::INC_X),
+            vm::OpCode::Output(outputs::DEC_Y),
+            vm::OpCode::Output(outputs::INC_Y)
+        ], 0, false);
+
+        let mut agent = agent_at(10, 10, Connectivity::Four, &world);
+        vm::VirtualMachine::with_handler(&program, Some(&mut agent)).run(None, false, false);
+        assert_eq!((10, 11), (agent.x, agent.y));
+        assert_eq!(1, agent.distance_travelled);
+    }
+
+    #[test]
+    fn obstacle_sensing() {
+        let world = World{ obstacles: [(13, 10), (10, 2)].iter().cloned().collect() };
+        let mut agent = agent_at(10, 10, Connectivity::Four, &world);
+
+        assert_eq!(3.0, agent.input(inputs::OBSTACLE_POS_X));
+        assert_eq!(11.0, agent.input(inputs::OBSTACLE_NEG_X)); // the edge
+        assert_eq!(WORLD_SIZE as f32 - 10.0, agent.input(inputs::OBSTACLE_POS_Y));
+        assert_eq!(8.0, agent.input(inputs::OBSTACLE_NEG_Y));
+
+        agent.topology = WorldTopology::Toroidal;
+        assert_eq!(WORLD_SIZE as f32 - 3.0, agent.input(inputs::OBSTACLE_NEG_X)); // across the edge
+        assert_eq!(WORLD_SIZE as f32 - 8.0, agent.input(inputs::OBSTACLE_POS_Y));
+        assert_eq!(WORLD_SIZE as f32, World{ obstacles: HashSet::new() }.obstacle_distance(WorldTopology::Toroidal, 0, 0, 1, 0) as f32);
+    }
+
+    #[test]
+    fn obstacles_avoid_test_cases() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let test_cases = generate_test_cases(8, WORLD_SIZE, &mut rng);
+        let world = World::generate(1000, &test_cases, &mut rng);
+
+        assert_eq!(1000, world.obstacles.len());
+        for t in &test_cases {
+            assert!(!world.obstacles.contains(&(t.pos_x, t.pos_y)));
+            assert!(!world.obstacles.contains(&(t.target_x, t.target_y)));
+        }
+    }
+
+    #[test]
+    fn toroidal_distance() {
+        let max = WORLD_SIZE as i32 - 1;
+
+        assert_eq!(f64::sqrt(2.0), WorldTopology::Toroidal.distance(DistanceMetric::Euclidean, 0, 0, max, max));
+        assert_eq!(f64::sqrt(2.0), WorldTopology::Toroidal.distance(DistanceMetric::Euclidean, max, 0, 0, max));
+        assert_eq!(f64::sqrt(2.0) * max as f64, WorldTopology::Bounded.distance(DistanceMetric::Euclidean, 0, 0, max, max));
+        assert_eq!(WORLD_SIZE as f64 / 2.0, WorldTopology::Toroidal.distance(DistanceMetric::Euclidean, 0, 3, WORLD_SIZE as i32 / 2, 3));
+        assert_eq!(4.0, WorldTopology::Toroidal.distance(DistanceMetric::Manhattan, 1, 1, max, max));
+    }
+
+    #[test]
+    fn distance_metrics() {
+        assert_eq!(5.0, WorldTopology::Bounded.distance(DistanceMetric::Euclidean, 1, 2, 4, 6));
+        assert_eq!(7.0, WorldTopology::Bounded.distance(DistanceMetric::Manhattan, 1, 2, 4, 6));
+        assert_eq!(4.0, WorldTopology::Bounded.distance(DistanceMetric::Chebyshev, 1, 2, 4, 6));
+        assert_eq!(7.0, WorldTopology::Bounded.distance(DistanceMetric::Manhattan, 4, 6, 1, 2));
+    }
+
+    #[test]
+    fn allowed_instructions() {
+        let four = get_allowed_instructions(Connectivity::Four, false);
+        let eight = get_allowed_instructions(Connectivity::Eight, false);
+        assert!(!four.contains(&vm::OpCode::Output(outputs::INC_X_INC_Y)));
+        assert!(eight.contains(&vm::OpCode::Output(outputs::DEC_X_DEC_Y)));
+        assert_eq!(four.len() + 4, eight.len());
+    }
+
+    /// Champion of `golden_config()`; to update after an intentional change, run the test with `UPDATE_GOLDEN=1`.
+    const GOLDEN_CHAMPION_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/bin/seeker/golden_champion.vmasm");
+
+    fn golden_config() -> ExperimentConfig {
+        ExperimentConfig{
+            rnd_seed: 1,
+            num_programs: 16,
+            num_test_cases: 4,
+            max_num_iterations: 20,
+            checkpoint_file: None,
+            checkpoint_interval: 0
+        }
+    }
+
+    #[test]
+    fn golden_champion() {
+        let champion = champion_vmasm(&run_experiment(&golden_config(), None).hall_of_fame);
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(GOLDEN_CHAMPION_FILE, &champion).unwrap();
+        }
+        let expected = std::fs::read_to_string(GOLDEN_CHAMPION_FILE).unwrap();
+        assert!(
+            expected == champion,
+            "champion differs from {} (if the change is intentional, rerun with UPDATE_GOLDEN=1):\n{}",
+            GOLDEN_CHAMPION_FILE,
+            champion
+        );
+    }
+
+    #[test]
+    fn corrupt_checkpoint() {
+        let mut hall_of_fame = utils::HallOfFame::new(1);
+        let program = vm::Program::new(&[vm::OpCode::Nop], NUM_PROG_DATA_SLOTS, ALLOW_CROSSING_BLOCKS);
+        hall_of_fame.consider(&utils::SortedEvaluatedPrograms::new(vec![program], vec![1.0]));
+        let log_rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let user_data = encode_user_data(&EvolutionState::new(), &hall_of_fame, &log_rng, &utils::GenerationLog::new());
+        let checkpoint = |user_data: Vec<u8>| utils::EvolutionCheckpoint{
+            programs: utils::SortedEvaluatedPrograms::new(vec![], vec![]),
+            rng: rand_xorshift::XorShiftRng::seed_from_u64(2),
+            generation: 0,
+            user_data
+        };
+
+        assert!(decode_user_data(checkpoint(user_data.clone())).is_ok());
+        assert_eq!(Some(ParseError::UnexpectedEnd), decode_user_data(checkpoint(user_data[..30].to_vec())).err());
+        // a corrupted program (its magic number follows the fitness and length)
+        let mut corrupted = user_data.clone();
+        corrupted[49] = b'X';
+        assert_eq!(Some(ParseError::InvalidHeader), decode_user_data(checkpoint(corrupted)).err());
+    }
+
+    #[test]
+    fn resume_from_checkpoint() {
+        // unique name, so that concurrent test runs do not interfere
+        let checkpoint_file = std::env::temp_dir().join(format!("seeker_checkpoint_test_{}.bin", std::process::id()));
+
+        let uninterrupted = run_experiment(&golden_config(), None);
+
+        let interrupted_config = ExperimentConfig{
+            max_num_iterations: 10,
+            checkpoint_file: Some(checkpoint_file.clone()),
+            checkpoint_interval: 5,
+            ..golden_config()
+        };
+        run_experiment(&interrupted_config, None);
+        let checkpoint = load_checkpoint(&checkpoint_file).unwrap();
+        std::fs::remove_file(&checkpoint_file).unwrap();
+        assert_eq!(10, checkpoint.checkpoint.generation);
+
+        let resumed = run_experiment(&golden_config(), Some(checkpoint));
+
+        let instructions = |programs: &utils::SortedEvaluatedPrograms| -> Vec<Vec<vm::OpCode>> {
+            programs.get_programs().iter().map(|p| p.prog.get_instr().to_vec()).collect()
+        };
+        assert_eq!(instructions(&uninterrupted.programs), instructions(&resumed.programs));
+        assert_eq!(uninterrupted.hall_of_fame.best().prog.get_instr(), resumed.hall_of_fame.best().prog.get_instr());
+        assert_eq!(uninterrupted.generation_log.get_entries(), resumed.generation_log.get_entries());
     }
 }
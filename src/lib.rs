@@ -10,8 +10,12 @@
 //   Library: linear genetic programming.
 //
 
+
 pub mod utils;
 pub mod vm;
+pub mod serialization;
 pub mod transpile;
 
-extern crate rand;
\ No newline at end of file
+extern crate libm;
+extern crate rand;
+extern crate rayon;
\ No newline at end of file
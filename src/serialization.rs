@@ -0,0 +1,915 @@
+//
+// genetic - genetic programming experiments
+// Copyright (c) 2019 Filip Szczerek <ga.software@yahoo.com>
+//
+// This project is licensed under the terms of the MIT license
+// (see the LICENSE file for details).
+//
+//
+// File description:
+//   Module: saving and loading of VM programs.
+//
+
+use std::convert::TryFrom;
+use vm::{ArithmeticMode, IndexMode, OpCode, Program};
+
+/// Error encountered when loading a program.
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// Data do not start with the expected magic number.
+    InvalidHeader,
+    /// Unsupported format version.
+    UnsupportedVersion(u8),
+    /// Data end prematurely.
+    UnexpectedEnd,
+    /// Unknown opcode encoding.
+    UnknownOpCode(u8),
+    /// Unknown `IndexMode` encoding.
+    UnknownIndexMode(u8),
+    /// Unknown `ArithmeticMode` encoding.
+    UnknownArithmeticMode(u8),
+    /// Data slots without any data bank, or their total number (of all banks) does not fit in `usize`.
+    InconsistentDataSlots{ num_data_slots: usize, num_data_banks: usize },
+    /// Data contain bytes after the last instruction.
+    TrailingData,
+    /// Unknown instruction mnemonic at the specified line (1-based).
+    UnknownMnemonic{ line: usize, mnemonic: String },
+    /// Missing, unexpected or malformed instruction operand at the specified line (1-based).
+    InvalidOperand{ line: usize, operand: String },
+    /// Malformed JSON at the specified byte offset.
+    InvalidJson{ offset: usize },
+    /// Missing JSON field, or its value has unexpected type or range.
+    InvalidJsonField(&'static str),
+    /// Unknown instruction name at the specified instruction index (0-based) in JSON.
+    UnknownOp{ index: usize, op: String },
+    /// Missing, unexpected or malformed `arg` of the instruction at the specified index (0-based) in JSON.
+    InvalidArg{ index: usize }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::InvalidHeader => write!(f, "invalid header"),
+            ParseError::UnsupportedVersion(v) => write!(f, "unsupported format version: {}", v),
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of data"),
+            ParseError::UnknownOpCode(b) => write!(f, "unknown opcode: {}", b),
+            ParseError::UnknownIndexMode(b) => write!(f, "unknown index mode: {}", b),
+            ParseError::UnknownArithmeticMode(b) => write!(f, "unknown arithmetic mode: {}", b),
+            ParseError::InconsistentDataSlots{ num_data_slots, num_data_banks } =>
+                write!(f, "inconsistent data size: {} slots in {} banks", num_data_slots, num_data_banks),
+            ParseError::TrailingData => write!(f, "trailing data after the last instruction"),
+            ParseError::UnknownMnemonic{ line, mnemonic } => write!(f, "line {}: unknown mnemonic \"{}\"", line, mnemonic),
+            ParseError::InvalidOperand{ line, operand } => write!(f, "line {}: invalid operand \"{}\"", line, operand),
+            ParseError::InvalidJson{ offset } => write!(f, "invalid JSON at offset {}", offset),
+            ParseError::InvalidJsonField(name) => write!(f, "missing or invalid field \"{}\"", name),
+            ParseError::UnknownOp{ index, op } => write!(f, "instruction {}: unknown op \"{}\"", index, op),
+            ParseError::InvalidArg{ index } => write!(f, "instruction {}: missing or invalid arg", index)
+        }
+    }
+}
+
+impl std::error::Error for ParseError { }
+
+/// Magic number starting the binary representation of a program.
+const MAGIC: &[u8] = b"GVMP";
+
+///
+/// Version of the binary format. Older versions can also be loaded: 1 (without index and arithmetic modes,
+/// number of data banks, `Cmp` tolerance), 2 (without arithmetic mode, number of data banks, `Cmp` tolerance),
+/// 3 (without number of data banks, `Cmp` tolerance), 4 (without `Cmp` tolerance).
+///
+const VERSION: u8 = 5;
+
+///
+/// Returns the stable binary encoding of an opcode (without operand).
+///
+/// Values must never change once assigned, so that saved programs remain loadable.
+///
+fn opcode_to_byte(opcode: OpCode) -> u8 {
+    match opcode {
+        OpCode::SetI(_)   => 0,
+        OpCode::Input(_)  => 1,
+        OpCode::Output(_) => 2,
+        OpCode::ItoV      => 3,
+        OpCode::VtoI      => 4,
+        OpCode::IncV      => 5,
+        OpCode::DecV      => 6,
+        OpCode::IncI      => 7,
+        OpCode::DecI      => 8,
+        OpCode::Load      => 9,
+        OpCode::Store     => 10,
+        OpCode::Swap      => 11,
+        OpCode::EndGoTo   => 12,
+        OpCode::GoToIfP   => 13,
+        OpCode::JumpIfN   => 14,
+        OpCode::EndJump   => 15,
+        OpCode::IfP       => 16,
+        OpCode::IfN       => 17,
+        OpCode::Cmp       => 18,
+        OpCode::Add       => 19,
+        OpCode::Sub       => 20,
+        OpCode::Mul       => 21,
+        OpCode::Div       => 22,
+        OpCode::Abs       => 23,
+        OpCode::Neg       => 24,
+        OpCode::Sqrt      => 25,
+        OpCode::Nop       => 26,
+        OpCode::RandV     => 27,
+        OpCode::SetBank(_) => 28,
+        OpCode::Pow       => 29,
+        OpCode::Log       => 30,
+        OpCode::Sin       => 31,
+        OpCode::Cos       => 32,
+        OpCode::Halt      => 33
+    }
+}
+
+/// Reads data sequentially, failing with `ParseError::UnexpectedEnd` if there are not enough bytes.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader{ data, pos: 0 }
+    }
+
+    pub(crate) fn is_at_end(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    pub(crate) fn bytes(&mut self, count: usize) -> Result<&'a [u8], ParseError> {
+        if self.data.len() - self.pos < count {
+            return Err(ParseError::UnexpectedEnd);
+        }
+        let result = &self.data[self.pos..self.pos + count];
+        self.pos += count;
+        Ok(result)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, ParseError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, ParseError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn i32(&mut self) -> Result<i32, ParseError> {
+        let b = self.bytes(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, ParseError> {
+        let b = self.bytes(8)?;
+        Ok(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+}
+
+impl Program {
+    ///
+    /// Returns binary representation of the program.
+    ///
+    /// Format (multi-byte values are little-endian):
+    ///
+    /// * magic number `GVMP`
+    /// * format version (1 byte)
+    /// * `allow_crossing_blocks` flag (1 byte)
+    /// * index mode (1 byte; 0 - `Ignore`, 1 - `Wrap`, 2 - `Clamp`)
+    /// * arithmetic mode (1 byte; 0 - `Ieee`, 1 - `Saturating` followed by `min`, `max` as `f64`)
+    /// * number of data slots (`u32`)
+    /// * number of data banks (`u32`)
+    /// * `Cmp` tolerance (`f64`)
+    /// * number of instructions (`u32`)
+    /// * instructions: opcode (1 byte), followed by `i32` operand for `SetI`, `Input`, `Output`, `SetBank`
+    ///
+    /// The jump table is not saved; it is recomputed by `from_bytes`.
+    ///
+    /// Panics if the number of data slots, data banks or instructions does not fit in `u32`.
+    ///
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = MAGIC.to_vec();
+        result.push(VERSION);
+        result.push(if self.get_allow_crossing_blocks() { 1 } else { 0 });
+        result.push(match self.get_index_mode() {
+            IndexMode::Ignore => 0,
+            IndexMode::Wrap => 1,
+            IndexMode::Clamp => 2
+        });
+        match self.get_arithmetic_mode() {
+            ArithmeticMode::Ieee => result.push(0),
+            ArithmeticMode::Saturating{ min, max } => {
+                result.push(1);
+                result.extend_from_slice(&min.to_bits().to_le_bytes());
+                result.extend_from_slice(&max.to_bits().to_le_bytes());
+            }
+        }
+        result.extend_from_slice(&u32::try_from(self.get_num_data_slots()).expect("too many data slots").to_le_bytes());
+        result.extend_from_slice(&u32::try_from(self.get_num_data_banks()).expect("too many data banks").to_le_bytes());
+        result.extend_from_slice(&self.get_cmp_epsilon().to_bits().to_le_bytes());
+        result.extend_from_slice(&u32::try_from(self.get_instr().len()).expect("too many instructions").to_le_bytes());
+
+        for opcode in self.get_instr() {
+            result.push(opcode_to_byte(*opcode));
+            match opcode {
+                OpCode::SetI(i) | OpCode::Input(i) | OpCode::Output(i) | OpCode::SetBank(i) =>
+                    result.extend_from_slice(&i.to_le_bytes()),
+                _ => ()
+            }
+        }
+
+        result
+    }
+
+    /// Creates a program from its binary representation (see `to_bytes`).
+    pub fn from_bytes(data: &[u8]) -> Result<Program, ParseError> {
+        let mut reader = Reader::new(data);
+
+        if reader.bytes(MAGIC.len()).map_err(|_| ParseError::InvalidHeader)? != MAGIC {
+            return Err(ParseError::InvalidHeader);
+        }
+        let version = reader.u8()?;
+        if version == 0 || version > VERSION {
+            return Err(ParseError::UnsupportedVersion(version));
+        }
+        let allow_crossing_blocks = reader.u8()? != 0;
+        let index_mode = if version == 1 {
+            IndexMode::Ignore
+        } else {
+            match reader.u8()? {
+                0 => IndexMode::Ignore,
+                1 => IndexMode::Wrap,
+                2 => IndexMode::Clamp,
+                b => return Err(ParseError::UnknownIndexMode(b))
+            }
+        };
+        let arithmetic_mode = if version < 3 {
+            ArithmeticMode::Ieee
+        } else {
+            match reader.u8()? {
+                0 => ArithmeticMode::Ieee,
+                1 => ArithmeticMode::Saturating{ min: f64::from_bits(reader.u64()?), max: f64::from_bits(reader.u64()?) },
+                b => return Err(ParseError::UnknownArithmeticMode(b))
+            }
+        };
+        let num_data_slots = reader.u32()? as usize;
+        let num_data_banks = if version < 4 { 1 } else { reader.u32()? as usize };
+        if (num_data_slots > 0 && num_data_banks == 0) || num_data_slots.checked_mul(num_data_banks).is_none() {
+            return Err(ParseError::InconsistentDataSlots{ num_data_slots, num_data_banks });
+        }
+        let cmp_epsilon = if version < 5 { 0.0 } else { f64::from_bits(reader.u64()?) };
+        let num_instructions = reader.u32()? as usize;
+
+        let mut instructions = Vec::with_capacity(std::cmp::min(num_instructions, data.len()));
+        for _ in 0..num_instructions {
+            let opcode = match reader.u8()? {
+                0  => OpCode::SetI(reader.i32()?),
+                1  => OpCode::Input(reader.i32()?),
+                2  => OpCode::Output(reader.i32()?),
+                3  => OpCode::ItoV,
+                4  => OpCode::VtoI,
+                5  => OpCode::IncV,
+                6  => OpCode::DecV,
+                7  => OpCode::IncI,
+                8  => OpCode::DecI,
+                9  => OpCode::Load,
+                10 => OpCode::Store,
+                11 => OpCode::Swap,
+                12 => OpCode::EndGoTo,
+                13 => OpCode::GoToIfP,
+                14 => OpCode::JumpIfN,
+                15 => OpCode::EndJump,
+                16 => OpCode::IfP,
+                17 => OpCode::IfN,
+                18 => OpCode::Cmp,
+                19 => OpCode::Add,
+                20 => OpCode::Sub,
+                21 => OpCode::Mul,
+                22 => OpCode::Div,
+                23 => OpCode::Abs,
+                24 => OpCode::Neg,
+                25 => OpCode::Sqrt,
+                26 => OpCode::Nop,
+                27 => OpCode::RandV,
+                28 => OpCode::SetBank(reader.i32()?),
+                29 => OpCode::Pow,
+                30 => OpCode::Log,
+                31 => OpCode::Sin,
+                32 => OpCode::Cos,
+                33 => OpCode::Halt,
+                b  => return Err(ParseError::UnknownOpCode(b))
+            };
+            instructions.push(opcode);
+        }
+
+        if !reader.is_at_end() {
+            return Err(ParseError::TrailingData);
+        }
+
+        let mut program = Program::new(&instructions, num_data_slots, allow_crossing_blocks);
+        program.set_index_mode(index_mode);
+        program.set_arithmetic_mode(arithmetic_mode);
+        program.set_num_data_banks(num_data_banks);
+        program.set_cmp_epsilon(cmp_epsilon);
+        Ok(program)
+    }
+}
+
+/// Parsed JSON value (see `Program::from_json`).
+enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>)
+}
+
+impl JsonValue {
+    fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None
+        }
+    }
+}
+
+/// Maximum nesting depth of JSON arrays and objects (limits recursion of `JsonParser`).
+const MAX_JSON_DEPTH: usize = 64;
+
+///
+/// Minimal JSON parser; fails with `ParseError::InvalidJson` at the offset of the first malformed value
+/// (or of the first array or object nested deeper than `MAX_JSON_DEPTH`).
+///
+struct JsonParser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    /// Number of arrays and objects containing the current position.
+    depth: usize
+}
+
+impl<'a> JsonParser<'a> {
+    fn parse(text: &'a str) -> Result<JsonValue, ParseError> {
+        let mut parser = JsonParser{ text: text.as_bytes(), pos: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.text.len() {
+            return Err(parser.error());
+        }
+        Ok(value)
+    }
+
+    fn error(&self) -> ParseError {
+        ParseError::InvalidJson{ offset: self.pos }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.text.len() && b" \t\r\n".contains(&self.text[self.pos]) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, token: &[u8]) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.text[self.pos..].starts_with(token) {
+            self.pos += token.len();
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn value(&mut self) -> Result<JsonValue, ParseError> {
+        match self.peek() {
+            Some(b'n') => self.expect(b"null").map(|_| JsonValue::Null),
+            Some(b't') => self.expect(b"true").map(|_| JsonValue::Bool(true)),
+            Some(b'f') => self.expect(b"false").map(|_| JsonValue::Bool(false)),
+            Some(b'"') => self.string().map(JsonValue::String),
+            Some(opening @ b'[') | Some(opening @ b'{') => {
+                if self.depth == MAX_JSON_DEPTH {
+                    return Err(self.error());
+                }
+                self.depth += 1;
+                let result = if opening == b'[' { self.array() } else { self.object() };
+                self.depth -= 1;
+                result
+            },
+            Some(b'-') | Some(b'0'..=b'9') => {
+                let start = self.pos;
+                while self.pos < self.text.len() && b"+-.eE0123456789".contains(&self.text[self.pos]) {
+                    self.pos += 1;
+                }
+                match std::str::from_utf8(&self.text[start..self.pos]).unwrap().parse::<f64>() {
+                    Ok(number) => Ok(JsonValue::Number(number)),
+                    Err(_) => { self.pos = start; Err(self.error()) }
+                }
+            },
+            _ => Err(self.error())
+        }
+    }
+
+    /// Parses an array; `self.pos` must point at the opening bracket.
+    fn array(&mut self) -> Result<JsonValue, ParseError> {
+        self.pos += 1;
+        let mut elements = vec![];
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(JsonValue::Array(elements));
+        }
+        loop {
+            elements.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => { self.pos += 1; return Ok(JsonValue::Array(elements)); },
+                _ => return Err(self.error())
+            }
+        }
+    }
+
+    /// Parses an object; `self.pos` must point at the opening brace.
+    fn object(&mut self) -> Result<JsonValue, ParseError> {
+        self.pos += 1;
+        let mut members = vec![];
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(JsonValue::Object(members));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error());
+            }
+            let key = self.string()?;
+            self.expect(b":")?;
+            members.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => { self.pos += 1; return Ok(JsonValue::Object(members)); },
+                _ => return Err(self.error())
+            }
+        }
+    }
+
+    /// Parses a string literal; `self.pos` must point at the opening quote.
+    fn string(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let mut bytes = vec![];
+        loop {
+            match self.text.get(self.pos).cloned() {
+                None => return Err(self.error()),
+                Some(b'"') => { self.pos += 1; break; },
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.text.get(self.pos).cloned() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            let code = self.text.get(self.pos + 1..self.pos + 5)
+                                .and_then(|hex| std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .and_then(std::char::from_u32);
+                            match code {
+                                Some(c) => { self.pos += 4; c },
+                                None => return Err(self.error())
+                            }
+                        },
+                        _ => return Err(self.error())
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(escaped.encode_utf8(&mut buf).as_bytes());
+                    self.pos += 1;
+                },
+                Some(b) => { bytes.push(b); self.pos += 1; }
+            }
+        }
+
+        String::from_utf8(bytes).map_err(|_| ParseError::InvalidJson{ offset: start })
+    }
+}
+
+impl Program {
+    ///
+    /// Returns JSON representation of the program, e.g.:
+    ///
+    /// ```text
+    /// {
+    ///   "num_data_slots": 2,
+    ///   "num_data_banks": 1,
+    ///   "allow_crossing_blocks": false,
+    ///   "index_mode": "ignore",
+    ///   "arithmetic_mode": {"saturating": {"min": -1000.0, "max": "Infinity"}},
+    ///   "cmp_epsilon": 0.0,
+    ///   "instructions": [
+    ///     {"op": "seti", "arg": 3},
+    ///     {"op": "load"}
+    ///   ]
+    /// }
+    /// ```
+    ///
+    /// Instructions are named by their VM assembly mnemonics. The index mode is one of `"ignore"`, `"wrap"`,
+    /// `"clamp"`; the arithmetic mode is `"ieee"` or `{"saturating": {"min": ..., "max": ...}}`. Non-finite
+    /// numbers are written as strings `"Infinity"`, `"-Infinity"`, `"NaN"`.
+    ///
+    pub fn to_json(&self) -> String {
+        let mut result = String::from("{\n");
+        result += &format!("  \"num_data_slots\": {},\n", self.get_num_data_slots());
+        result += &format!("  \"num_data_banks\": {},\n", self.get_num_data_banks());
+        result += &format!("  \"allow_crossing_blocks\": {},\n", self.get_allow_crossing_blocks());
+        result += &format!("  \"index_mode\": \"{}\",\n", match self.get_index_mode() {
+            IndexMode::Ignore => "ignore",
+            IndexMode::Wrap => "wrap",
+            IndexMode::Clamp => "clamp"
+        });
+        result += &match self.get_arithmetic_mode() {
+            ArithmeticMode::Ieee => "  \"arithmetic_mode\": \"ieee\",\n".to_string(),
+            ArithmeticMode::Saturating{ min, max } => format!(
+                "  \"arithmetic_mode\": {{\"saturating\": {{\"min\": {}, \"max\": {}}}}},\n", json_number(min), json_number(max)
+            )
+        };
+        result += &format!("  \"cmp_epsilon\": {},\n", json_number(self.get_cmp_epsilon()));
+        result += "  \"instructions\": [";
+        for (i, opcode) in self.get_instr().iter().enumerate() {
+            result += if i == 0 { "\n" } else { ",\n" };
+            match opcode.operand() {
+                Some(arg) => result += &format!("    {{\"op\": \"{}\", \"arg\": {}}}", opcode.mnemonic(), arg),
+                None => result += &format!("    {{\"op\": \"{}\"}}", opcode.mnemonic())
+            }
+        }
+        result += if self.get_instr().is_empty() { "]\n" } else { "\n  ]\n" };
+        result += "}\n";
+
+        result
+    }
+
+    ///
+    /// Creates a program from its JSON representation (see `to_json`). Unknown fields are ignored.
+    ///
+    /// `num_data_banks`, `index_mode`, `arithmetic_mode` and `cmp_epsilon` are optional (defaults: 1,
+    /// `"ignore"`, `"ieee"`, 0), so that JSON saved without them can be loaded.
+    ///
+    pub fn from_json(json: &str) -> Result<Program, ParseError> {
+        let root = JsonParser::parse(json)?;
+
+        let num_data_slots = match root.get("num_data_slots") {
+            Some(JsonValue::Number(n)) if n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64 => *n as usize,
+            _ => return Err(ParseError::InvalidJsonField("num_data_slots"))
+        };
+        let num_data_banks = match root.get("num_data_banks") {
+            None => 1,
+            Some(JsonValue::Number(n)) if n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64 => *n as usize,
+            _ => return Err(ParseError::InvalidJsonField("num_data_banks"))
+        };
+        if (num_data_slots > 0 && num_data_banks == 0) || num_data_slots.checked_mul(num_data_banks).is_none() {
+            return Err(ParseError::InconsistentDataSlots{ num_data_slots, num_data_banks });
+        }
+        let allow_crossing_blocks = match root.get("allow_crossing_blocks") {
+            Some(JsonValue::Bool(b)) => *b,
+            _ => return Err(ParseError::InvalidJsonField("allow_crossing_blocks"))
+        };
+        let index_mode = match root.get("index_mode") {
+            None => IndexMode::Ignore,
+            Some(JsonValue::String(mode)) if mode == "ignore" => IndexMode::Ignore,
+            Some(JsonValue::String(mode)) if mode == "wrap" => IndexMode::Wrap,
+            Some(JsonValue::String(mode)) if mode == "clamp" => IndexMode::Clamp,
+            _ => return Err(ParseError::InvalidJsonField("index_mode"))
+        };
+        let arithmetic_mode = match root.get("arithmetic_mode") {
+            None => ArithmeticMode::Ieee,
+            Some(JsonValue::String(mode)) if mode == "ieee" => ArithmeticMode::Ieee,
+            Some(mode) => {
+                let saturating = mode.get("saturating");
+                match (saturating.and_then(|s| s.get("min")).and_then(from_json_number),
+                       saturating.and_then(|s| s.get("max")).and_then(from_json_number)) {
+                    (Some(min), Some(max)) => ArithmeticMode::Saturating{ min, max },
+                    _ => return Err(ParseError::InvalidJsonField("arithmetic_mode"))
+                }
+            }
+        };
+        let cmp_epsilon = match root.get("cmp_epsilon") {
+            None => 0.0,
+            Some(value) => match from_json_number(value) {
+                Some(cmp_epsilon) => cmp_epsilon,
+                None => return Err(ParseError::InvalidJsonField("cmp_epsilon"))
+            }
+        };
+        let elements = match root.get("instructions") {
+            Some(JsonValue::Array(elements)) => elements,
+            _ => return Err(ParseError::InvalidJsonField("instructions"))
+        };
+
+        let mut instructions = Vec::with_capacity(elements.len());
+        for (index, element) in elements.iter().enumerate() {
+            let op = match element.get("op") {
+                Some(JsonValue::String(op)) => op,
+                _ => return Err(ParseError::InvalidJsonField("op"))
+            };
+            let opcode = match OpCode::ALL.iter().find(|o| o.mnemonic() == op) {
+                Some(opcode) => *opcode,
+                None => return Err(ParseError::UnknownOp{ index, op: op.clone() })
+            };
+            let opcode = match (opcode.operand(), element.get("arg")) {
+                (None, None) => opcode,
+                (Some(_), Some(JsonValue::Number(arg)))
+                    if arg.fract() == 0.0 && *arg >= i32::MIN as f64 && *arg <= i32::MAX as f64 =>
+                    opcode.with_operand(*arg as i32),
+                _ => return Err(ParseError::InvalidArg{ index })
+            };
+            instructions.push(opcode);
+        }
+
+        let mut program = Program::new(&instructions, num_data_slots, allow_crossing_blocks);
+        program.set_index_mode(index_mode);
+        program.set_arithmetic_mode(arithmetic_mode);
+        program.set_num_data_banks(num_data_banks);
+        program.set_cmp_epsilon(cmp_epsilon);
+        Ok(program)
+    }
+}
+
+/// Returns JSON representation of `value`; non-finite values are written as strings (see `Program::to_json`).
+fn json_number(value: f64) -> String {
+    if value.is_nan() {
+        "\"NaN\"".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "\"Infinity\"".to_string() } else { "\"-Infinity\"".to_string() }
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+/// Returns the number represented by `value` (see `json_number`), if any.
+fn from_json_number(value: &JsonValue) -> Option<f64> {
+    match value {
+        JsonValue::Number(n) => Some(*n),
+        JsonValue::String(s) if s == "NaN" => Some(f64::NAN),
+        JsonValue::String(s) if s == "Infinity" => Some(f64::INFINITY),
+        JsonValue::String(s) if s == "-Infinity" => Some(f64::NEG_INFINITY),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod binary_tests {
+    use rand::SeedableRng;
+    use serialization::ParseError;
+    use utils;
+    use vm::{ArithmeticMode, IndexMode, OpCode, Program};
+
+    fn all_opcodes() -> Vec<OpCode> {
+        vec![
+            OpCode::SetI(0), OpCode::SetI(-7), OpCode::SetI(i32::MAX),
+            OpCode::Input(3), OpCode::Output(-2),
+            OpCode::ItoV, OpCode::VtoI, OpCode::IncV, OpCode::DecV, OpCode::IncI, OpCode::DecI,
+            OpCode::Load, OpCode::Store, OpCode::Swap,
+            OpCode::EndGoTo, OpCode::GoToIfP, OpCode::JumpIfN, OpCode::EndJump, OpCode::IfP, OpCode::IfN,
+            OpCode::Cmp, OpCode::Add, OpCode::Sub, OpCode::Mul, OpCode::Div,
+            OpCode::Abs, OpCode::Neg, OpCode::Sqrt, OpCode::Pow, OpCode::Log, OpCode::Sin, OpCode::Cos, OpCode::RandV,
+            OpCode::SetBank(2), OpCode::SetBank(-3), OpCode::Halt, OpCode::Nop
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let programs = utils::generate_random_programs(100, 1, 64, 5, &all_opcodes(), None, false, true, &mut rng);
+
+        for program in programs.iter() {
+            let loaded = Program::from_bytes(&program.to_bytes()).unwrap();
+            assert!(program.get_instr() == loaded.get_instr());
+            assert!(program.get_jump_table() == loaded.get_jump_table());
+            assert_eq!(program.get_num_data_slots(), loaded.get_num_data_slots());
+            assert_eq!(program.get_allow_crossing_blocks(), loaded.get_allow_crossing_blocks());
+        }
+    }
+
+    #[test]
+    fn index_mode() {
+        for index_mode in [IndexMode::Ignore, IndexMode::Wrap, IndexMode::Clamp].iter() {
+            let mut program = Program::new(&[OpCode::Load], 2, false);
+            program.set_index_mode(*index_mode);
+            assert_eq!(*index_mode, Program::from_bytes(&program.to_bytes()).unwrap().get_index_mode());
+        }
+
+        let mut unknown_mode = Program::new(&[], 2, false).to_bytes();
+        unknown_mode[6] = 3;
+        assert_eq!(Err(ParseError::UnknownIndexMode(3)), Program::from_bytes(&unknown_mode).map(|_| ()));
+    }
+
+    #[test]
+    fn arithmetic_mode() {
+        for arithmetic_mode in [ArithmeticMode::Ieee, ArithmeticMode::Saturating{ min: -1.5, max: 1.0e+6 }].iter() {
+            let mut program = Program::new(&[OpCode::Mul], 2, false);
+            program.set_arithmetic_mode(*arithmetic_mode);
+            assert_eq!(*arithmetic_mode, Program::from_bytes(&program.to_bytes()).unwrap().get_arithmetic_mode());
+        }
+
+        let mut unknown_mode = Program::new(&[], 2, false).to_bytes();
+        unknown_mode[7] = 2;
+        assert_eq!(Err(ParseError::UnknownArithmeticMode(2)), Program::from_bytes(&unknown_mode).map(|_| ()));
+    }
+
+    #[test]
+    fn version_1() {
+        // `seti 3`, `load` saved in format version 1
+        let bytes = b"GVMP\x01\x00\x02\x00\x00\x00\x02\x00\x00\x00\x00\x03\x00\x00\x00\x09";
+        let program = Program::from_bytes(bytes).unwrap();
+        assert!(program.get_instr() == [OpCode::SetI(3), OpCode::Load]);
+        assert_eq!(2, program.get_num_data_slots());
+        assert_eq!(IndexMode::Ignore, program.get_index_mode());
+        assert_eq!(ArithmeticMode::Ieee, program.get_arithmetic_mode());
+    }
+
+    #[test]
+    fn version_2() {
+        // `seti 3`, `load` saved in format version 2 with `IndexMode::Wrap`
+        let bytes = b"GVMP\x02\x00\x01\x02\x00\x00\x00\x02\x00\x00\x00\x00\x03\x00\x00\x00\x09";
+        let program = Program::from_bytes(bytes).unwrap();
+        assert!(program.get_instr() == [OpCode::SetI(3), OpCode::Load]);
+        assert_eq!(IndexMode::Wrap, program.get_index_mode());
+        assert_eq!(ArithmeticMode::Ieee, program.get_arithmetic_mode());
+        assert_eq!(1, program.get_num_data_banks());
+    }
+
+    #[test]
+    fn data_banks() {
+        let mut program = Program::new(&[OpCode::SetBank(1), OpCode::Store], 2, false);
+        program.set_num_data_banks(3);
+        let loaded = Program::from_bytes(&program.to_bytes()).unwrap();
+        assert_eq!(3, loaded.get_num_data_banks());
+        assert_eq!(2, loaded.get_num_data_slots());
+        assert!(loaded.get_instr() == program.get_instr());
+    }
+
+    #[test]
+    fn cmp_epsilon() {
+        let mut program = Program::new(&[OpCode::Cmp], 1, false);
+        assert_eq!(0.0, Program::from_bytes(&program.to_bytes()).unwrap().get_cmp_epsilon());
+        program.set_cmp_epsilon(0.25);
+        assert_eq!(0.25, Program::from_bytes(&program.to_bytes()).unwrap().get_cmp_epsilon());
+    }
+
+    #[test]
+    fn inconsistent_data_slots() {
+        let mut program = Program::new(&[OpCode::Load], 2, false);
+        program.set_num_data_banks(0);
+        assert_eq!(Err(ParseError::InconsistentDataSlots{ num_data_slots: 2, num_data_banks: 0 }),
+            Program::from_bytes(&program.to_bytes()).map(|_| ()));
+
+        let mut no_data = Program::new(&[OpCode::Load], 0, false);
+        no_data.set_num_data_banks(0);
+        assert!(Program::from_bytes(&no_data.to_bytes()).is_ok());
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let program = Program::new(&[], 3, true);
+        let loaded = Program::from_bytes(&program.to_bytes()).unwrap();
+        assert!(loaded.get_instr().is_empty());
+        assert_eq!(3, loaded.get_num_data_slots());
+        assert!(loaded.get_allow_crossing_blocks());
+    }
+
+    #[test]
+    fn invalid_data() {
+        let program = Program::new(&[OpCode::SetI(1), OpCode::Nop], 1, false);
+        let bytes = program.to_bytes();
+
+        assert_eq!(Err(ParseError::InvalidHeader), Program::from_bytes(b"GV").map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidHeader), Program::from_bytes(b"XXXX\x01").map(|_| ()));
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] = 99;
+        assert_eq!(Err(ParseError::UnsupportedVersion(99)), Program::from_bytes(&wrong_version).map(|_| ()));
+
+        assert_eq!(Err(ParseError::UnexpectedEnd), Program::from_bytes(&bytes[..bytes.len() - 1]).map(|_| ()));
+
+        let mut unknown_opcode = bytes.clone();
+        *unknown_opcode.last_mut().unwrap() = 200;
+        assert_eq!(Err(ParseError::UnknownOpCode(200)), Program::from_bytes(&unknown_opcode).map(|_| ()));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(Err(ParseError::TrailingData), Program::from_bytes(&trailing).map(|_| ()));
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    #[should_panic(expected = "too many data slots")]
+    fn too_many_data_slots() {
+        Program::new(&[OpCode::Nop], u32::MAX as usize + 1, false).to_bytes();
+    }
+}
+
+#[cfg(test)]
+mod json_tests {
+    use rand::SeedableRng;
+    use serialization::ParseError;
+    use utils;
+    use vm::{ArithmeticMode, IndexMode, OpCode, Program};
+
+    #[test]
+    fn round_trip() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let mut programs = utils::generate_random_programs(100, 1, 64, 5, &OpCode::ALL, None, false, true, &mut rng);
+        programs.push(Program::new(&[OpCode::SetI(i32::MIN), OpCode::Output(i32::MAX)], 0, true));
+        programs.push(Program::new(&[], 3, false));
+        let mut with_settings = Program::new(&[OpCode::SetBank(1), OpCode::Cmp], 3, false);
+        with_settings.set_num_data_banks(2);
+        with_settings.set_index_mode(IndexMode::Clamp);
+        with_settings.set_arithmetic_mode(ArithmeticMode::Saturating{ min: f64::NEG_INFINITY, max: 1.0e-7 });
+        with_settings.set_cmp_epsilon(0.1);
+        programs.push(with_settings);
+
+        for program in programs.iter() {
+            let loaded = Program::from_json(&program.to_json()).unwrap();
+            // the binary representation includes all settings
+            assert_eq!(program.to_bytes(), loaded.to_bytes());
+            assert!(program.get_jump_table() == loaded.get_jump_table());
+        }
+    }
+
+    #[test]
+    fn format() {
+        let program = Program::new(&[OpCode::SetI(3), OpCode::Load], 2, false);
+        assert_eq!("{\n  \"num_data_slots\": 2,\n  \"num_data_banks\": 1,\n  \"allow_crossing_blocks\": false,\n  \
+            \"index_mode\": \"ignore\",\n  \"arithmetic_mode\": \"ieee\",\n  \"cmp_epsilon\": 0.0,\n  \"instructions\": [\n    \
+            {\"op\": \"seti\", \"arg\": 3},\n    {\"op\": \"load\"}\n  ]\n}\n", program.to_json());
+
+        let mut saturating = program.clone();
+        saturating.set_arithmetic_mode(ArithmeticMode::Saturating{ min: -1000.0, max: f64::INFINITY });
+        assert!(saturating.to_json().contains("\n  \"arithmetic_mode\": {\"saturating\": {\"min\": -1000.0, \"max\": \"Infinity\"}},\n"));
+
+        let compact = r#"{"instructions":[{"arg":-1,"op":"input"},{"op":"halt"}],"comment":"A\n",
+            "allow_crossing_blocks":true,"num_data_slots":0}"#;
+        let loaded = Program::from_json(compact).unwrap();
+        assert!(loaded.get_instr() == [OpCode::Input(-1), OpCode::Halt]);
+        assert!(loaded.get_allow_crossing_blocks());
+        // defaults of the optional fields
+        assert_eq!(1, loaded.get_num_data_banks());
+        assert_eq!(IndexMode::Ignore, loaded.get_index_mode());
+        assert_eq!(ArithmeticMode::Ieee, loaded.get_arithmetic_mode());
+        assert_eq!(0.0, loaded.get_cmp_epsilon());
+    }
+
+    #[test]
+    fn unknown_op() {
+        let json = r#"{"num_data_slots": 1, "allow_crossing_blocks": false,
+            "instructions": [{"op": "load"}, {"op": "jump", "arg": 2}]}"#;
+        let error = Program::from_json(json).map(|_| ()).unwrap_err();
+        assert_eq!(ParseError::UnknownOp{ index: 1, op: "jump".to_string() }, error);
+        assert_eq!("instruction 1: unknown op \"jump\"", error.to_string());
+    }
+
+    #[test]
+    fn invalid_json() {
+        let valid = r#"{"num_data_slots": 1, "allow_crossing_blocks": false, "instructions": [{"op": "seti", "arg": 3}]}"#;
+        assert!(Program::from_json(valid).is_ok());
+
+        assert_eq!(Err(ParseError::InvalidJson{ offset: 22 }),
+            Program::from_json(&valid.replace("1,", "1 ,,")).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidJson{ offset: valid.len() - 1 }),
+            Program::from_json(&valid[..valid.len() - 1]).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidJsonField("num_data_slots")),
+            Program::from_json(&valid.replace("1,", "-1,")).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidJsonField("allow_crossing_blocks")),
+            Program::from_json(&valid.replace("false", "0")).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidArg{ index: 0 }),
+            Program::from_json(&valid.replace("3", "3.5")).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidArg{ index: 0 }),
+            Program::from_json(&valid.replace(", \"arg\": 3", "")).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidArg{ index: 0 }),
+            Program::from_json(&valid.replace("seti", "load")).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidJsonField("index_mode")),
+            Program::from_json(&valid.replace("1,", "1, \"index_mode\": \"modulo\",")).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidJsonField("arithmetic_mode")),
+            Program::from_json(&valid.replace("1,", "1, \"arithmetic_mode\": {\"saturating\": {\"min\": 0}},")).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidJsonField("cmp_epsilon")),
+            Program::from_json(&valid.replace("1,", "1, \"cmp_epsilon\": \"small\",")).map(|_| ()));
+        assert_eq!(Err(ParseError::InconsistentDataSlots{ num_data_slots: 1, num_data_banks: 0 }),
+            Program::from_json(&valid.replace("1,", "1, \"num_data_banks\": 0,")).map(|_| ()));
+    }
+
+    #[test]
+    fn nesting_depth_limited() {
+        let valid = r#"{"num_data_slots": 1, "allow_crossing_blocks": false, "instructions": [], "comment": "#;
+        let nested = |depth: usize| format!("{}{}{}}}", valid, "[".repeat(depth), "]".repeat(depth));
+
+        // the root object is at depth 1
+        assert!(Program::from_json(&nested(63)).is_ok());
+        assert_eq!(Err(ParseError::InvalidJson{ offset: valid.len() + 63 }), Program::from_json(&nested(64)).map(|_| ()));
+        assert_eq!(Err(ParseError::InvalidJson{ offset: valid.len() + 63 }), Program::from_json(&nested(1_000_000)).map(|_| ()));
+    }
+}
@@ -0,0 +1,337 @@
+//
+// genetic - genetic programming experiments
+// Copyright (c) 2019 Filip Szczerek <ga.software@yahoo.com>
+//
+// This project is licensed under the terms of the MIT license
+// (see the LICENSE file for details).
+//
+//
+// File description:
+//   Module: transpiling to an ANSI C function.
+//
+
+use transpile::{generate_c_like_data_index, is_saturated, uses_data_banks};
+use vm;
+
+///
+/// Creates ANSI C source code of a function running `program`.
+///
+/// The output defines `void fn_name(unsigned long numInstructions, float (*input)(int), void (*output)(int, float))`,
+/// which executes the specified number of instructions (restarting from the beginning after the last instruction,
+/// like a looped `VirtualMachine::run`). The VM state (`regV`, `regI`, `data`, instruction pointer) is kept
+/// in static variables of the function, so subsequent calls resume execution where it stopped.
+/// Executing `Halt` returns immediately (and so will subsequent calls).
+///
+/// Like in the Rust VM, conversion of an out-of-range `regV` to `regI` saturates (by a `static` helper function
+/// `fn_name_toInt`, emitted if the program contains `VtoI`) and `regI` wraps around on overflow (computed
+/// in `unsigned` arithmetic; converting the result back to `int` is implementation-defined in C,
+/// but wraps around with all common compilers). `RandV` uses a simple linear congruential generator (`rngState`), so its values
+/// differ from those of the Rust VM. With `vm::ArithmeticMode::Saturating`, results of arithmetic instructions
+/// are clamped by a `static` helper function `fn_name_saturate`; `Cmp` uses the program's tolerance
+/// (`vm::Program::set_cmp_epsilon`).
+///
+pub fn program_to_c(program: &vm::Program, fn_name: &str) -> String {
+    let num_instr = program.get_instr().len();
+    let num_data_slots = program.get_num_data_slots();
+    let uses_banks = uses_data_banks(program);
+
+    let mut result = format!(
+        "/* Generated from a VM program ({} instructions, {} data slots). */\n\n", num_instr, num_data_slots
+    );
+    result += "#include <math.h>\n\n";
+    if program.get_instr().contains(&vm::OpCode::VtoI) {
+        result += &generate_float_to_int(fn_name);
+    }
+    if let vm::ArithmeticMode::Saturating{ min, max } = program.get_arithmetic_mode() {
+        result += &generate_saturate(fn_name, min, max);
+    }
+    result += &format!(
+        "void {}(unsigned long numInstructions, float (*input)(int), void (*output)(int, float))\n{{\n", fn_name
+    );
+
+    if num_instr == 0 {
+        result += "    (void)numInstructions;\n";
+        result += "    (void)input;\n";
+        result += "    (void)output;\n";
+        result += "}\n";
+        return result;
+    }
+
+    // zero-length arrays are not allowed in C
+    result += &format!("    static float data[{}];\n", std::cmp::max(1, num_data_slots * program.get_num_data_banks()));
+    result += &generate_jump_table(program);
+    result += "    static float regV = 0.0f;\n";
+    result += "    static int regI = 0;\n";
+    if uses_banks {
+        result += "    static int regBank = 0;\n";
+    }
+    result += "    static int iptr = 0;\n";
+    if program.get_instr().contains(&vm::OpCode::RandV) {
+        result += "    static unsigned long rngState = 1UL;\n";
+    }
+    result += "    unsigned long i;\n";
+    result += "    int dataIndex;\n";
+    if uses_banks {
+        result += "    int bankIndex;\n";
+    }
+    result += "    int next;\n\n";
+
+    result += "    for (i = 0; i < numInstructions; i++) {\n";
+    result += &generate_c_like_data_index(program, "        ");
+    result += "        next = iptr + 1;\n";
+    result += "        switch (iptr) {\n";
+    result += &generate_instructions(program, fn_name);
+    result += "        }\n";
+    result += &format!("        iptr = (next >= {}) ? 0 : next;\n", num_instr);
+    result += "    }\n";
+    result += "}\n";
+
+    result
+}
+
+/// Generates the conversion of `regV` to `regI` (used by `VtoI`); out-of-range values saturate, like in the Rust VM.
+fn generate_float_to_int(fn_name: &str) -> String {
+    // `x != x` is true only for NaN; 2147483647.0f is rounded to 2^31
+    format!(
+        "static int {}_toInt(float value)\n{{\n    \
+         if (value != value) {{ return 0; }}\n    \
+         if (value >= 2147483647.0f) {{ return 2147483647; }}\n    \
+         if (value <= -2147483648.0f) {{ return -2147483647 - 1; }}\n    \
+         return (int)value;\n}}\n\n",
+        fn_name
+    )
+}
+
+/// Generates the clamping of `regV` used with `vm::ArithmeticMode::Saturating` (NaN becomes 0, like in the Rust VM).
+fn generate_saturate(fn_name: &str, min: f64, max: f64) -> String {
+    format!(
+        "static float {0}_saturate(float value)\n{{\n    \
+         if (value != value) {{ return 0.0f; }}\n    \
+         return (value < {1}) ? {1} : ((value > {2}) ? {2} : value);\n}}\n\n",
+        fn_name, c_float(min), c_float(max)
+    )
+}
+
+/// Returns C literal of `value` (as `float`).
+fn c_float(value: f64) -> String {
+    let value = value as f32;
+    if value.is_nan() {
+        "(0.0f / 0.0f)".to_string()
+    } else if value.is_infinite() {
+        // ANSI C has no `INFINITY`; `HUGE_VAL` (from <math.h>) is infinite with IEEE arithmetic
+        if value > 0.0 { "(float)HUGE_VAL".to_string() } else { "(float)-HUGE_VAL".to_string() }
+    } else {
+        format!("{:?}f", value)
+    }
+}
+
+/// Number of jump table items per line in the output C code.
+const ITEMS_PER_LINE: usize = 8;
+
+/// Generates the `jumpTable` array's definition; `-1` means no jump target.
+fn generate_jump_table(program: &vm::Program) -> String {
+    let mut result = "    static const int jumpTable[] = {\n        ".to_string();
+    let jump_table = program.get_jump_table();
+    for (i, jitem) in jump_table.iter().enumerate() {
+        match jitem {
+            Some(jmp_target) => result += &format!("{}", jmp_target),
+            None             => result +=         "-1"
+        }
+        if i + 1 < jump_table.len() {
+            result += ", ";
+            if (i + 1) % ITEMS_PER_LINE == 0 { result += "\n        "; }
+        }
+    }
+    result += "\n    };\n";
+
+    result
+}
+
+/// Generates the `case` labels executing each instruction.
+fn generate_instructions(program: &vm::Program, fn_name: &str) -> String {
+    let cmp_equal = if program.get_cmp_epsilon() == 0.0 {
+        "regV == dval".to_string()
+    } else {
+        format!("regV == dval || fabs((double)regV - dval) <= {:?}", program.get_cmp_epsilon())
+    };
+    let saturating = program.get_arithmetic_mode() != vm::ArithmeticMode::Ieee;
+    let mut result = String::new();
+
+    for (i, instr) in program.get_instr().iter().enumerate() {
+        let mut code = match instr {
+            vm::OpCode::SetI(n)   => format!("regI = {};", n),
+            vm::OpCode::SetBank(n) => format!("regBank = {};", n),
+            vm::OpCode::Input(n)  => format!("regV = input({});", n),
+            vm::OpCode::Output(n) => format!("output({}, regV);", n),
+            vm::OpCode::ItoV      => "regV = (float)regI;".to_string(),
+            vm::OpCode::VtoI      => format!("regI = {}_toInt(regV);", fn_name),
+            vm::OpCode::IncV      => "regV += 1.0f;".to_string(),
+            vm::OpCode::DecV      => "regV -= 1.0f;".to_string(),
+            vm::OpCode::IncI      => "regI = (int)((unsigned int)regI + 1u);".to_string(),
+            vm::OpCode::DecI      => "regI = (int)((unsigned int)regI - 1u);".to_string(),
+            vm::OpCode::Load      => "if (dataIndex >= 0) { regV = data[dataIndex]; }".to_string(),
+            vm::OpCode::Store     => "if (dataIndex >= 0) { data[dataIndex] = regV; }".to_string(),
+            vm::OpCode::Swap      => "if (dataIndex >= 0) { float tmp = data[dataIndex]; data[dataIndex] = regV; regV = tmp; }".to_string(),
+            vm::OpCode::GoToIfP   => "if (regV >= 0.0f && jumpTable[iptr] != -1) { next = jumpTable[iptr]; }".to_string(),
+            vm::OpCode::JumpIfN   => "if (regV < 0.0f && jumpTable[iptr] != -1) { next = jumpTable[iptr]; }".to_string(),
+            vm::OpCode::EndGoTo |
+            vm::OpCode::EndJump |
+            vm::OpCode::Nop       => "".to_string(),
+            vm::OpCode::IfP       => "if (regV < 0.0f) { next++; }".to_string(),
+            vm::OpCode::IfN       => "if (regV >= 0.0f) { next++; }".to_string(),
+            vm::OpCode::Cmp       => format!("if (dataIndex >= 0) {{ float dval = data[dataIndex]; \
+                                              if ({}) {{ regV = 0.0f; }} \
+                                              else if (regV < dval) {{ regV = -1.0f; }} \
+                                              else if (regV > dval) {{ regV = 1.0f; }} }}", cmp_equal),
+            vm::OpCode::Add       => "if (dataIndex >= 0) { regV += data[dataIndex]; }".to_string(),
+            vm::OpCode::Sub       => "if (dataIndex >= 0) { regV -= data[dataIndex]; }".to_string(),
+            vm::OpCode::Mul       => "if (dataIndex >= 0) { regV *= data[dataIndex]; }".to_string(),
+            vm::OpCode::Div       => "if (dataIndex >= 0 && data[dataIndex] != 0.0f) { regV /= data[dataIndex]; }".to_string(),
+            vm::OpCode::Abs       => "regV = (float)fabs(regV);".to_string(),
+            vm::OpCode::Neg       => "regV = -regV;".to_string(),
+            vm::OpCode::Sqrt      => "regV = (regV >= 0.0f) ? (float)sqrt(regV) : 0.0f;".to_string(),
+            // `x != x` is true only for NaN
+            vm::OpCode::Pow       => "if (dataIndex >= 0) { float dval = data[dataIndex]; float p = (float)pow(regV, dval); \
+                                      regV = (regV != regV || dval != dval || p != p) ? 0.0f : p; }".to_string(),
+            vm::OpCode::Log       => "regV = (regV > 0.0f) ? (float)log(regV) : 0.0f;".to_string(),
+            vm::OpCode::Sin       => "regV = (float)sin(regV);".to_string(),
+            vm::OpCode::Cos       => "regV = (float)cos(regV);".to_string(),
+            vm::OpCode::RandV     => "rngState = (rngState * 1103515245UL + 12345UL) & 0x7fffffffUL; \
+                                      regV = (float)(rngState >> 7) / 16777216.0f;".to_string(),
+            // `iptr` stays at `Halt`
+            vm::OpCode::Halt      => "return;".to_string()
+        };
+        if saturating && is_saturated(instr) {
+            code += &format!(" regV = {}_saturate(regV);", fn_name);
+        }
+        if code.is_empty() {
+            result += &format!("            case {}: break;\n", i);
+        } else {
+            result += &format!("            case {}: {} break;\n", i, code);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use transpile::c::program_to_c;
+    use vm;
+    use vm::OpCode;
+
+    #[test]
+    fn declarations() {
+        let program = vm::Program::new(&[
+            OpCode::EndGoTo,
+                OpCode::Input(0),
+                OpCode::SetI(2),
+                OpCode::Div,
+                OpCode::Sqrt,
+                OpCode::Output(1),
+            OpCode::GoToIfP,
+            OpCode::JumpIfN
+        ], 3, false);
+
+        let code = program_to_c(&program, "controller");
+
+        assert!(code.contains("void controller(unsigned long numInstructions, float (*input)(int), void (*output)(int, float))"));
+        assert!(code.contains("static float data[3];"));
+        assert!(code.contains("static const int jumpTable[] = {\n        6, -1, -1, -1, -1, -1, 0, -1\n    };"));
+        assert!(code.contains("static float regV = 0.0f;"));
+        assert!(code.contains("static int regI = 0;"));
+        assert!(code.contains("dataIndex = (regI >= 0 && regI < 3) ? regI : -1;"));
+        assert!(code.contains("case 3: if (dataIndex >= 0 && data[dataIndex] != 0.0f) { regV /= data[dataIndex]; } break;"));
+        assert!(code.contains("case 4: regV = (regV >= 0.0f) ? (float)sqrt(regV) : 0.0f; break;"));
+        assert!(code.contains("iptr = (next >= 8) ? 0 : next;"));
+        assert!(!code.contains("//"));
+        assert!(!code.contains("rngState"));
+    }
+
+    #[test]
+    fn index_mode() {
+        let mut program = vm::Program::new(&[OpCode::Load], 3, false);
+        program.set_index_mode(vm::IndexMode::Wrap);
+        assert!(program_to_c(&program, "f").contains("dataIndex = regI % 3; if (dataIndex < 0) { dataIndex += 3; }"));
+        program.set_index_mode(vm::IndexMode::Clamp);
+        assert!(program_to_c(&program, "f").contains("dataIndex = (regI < 0) ? 0 : ((regI >= 3) ? 2 : regI);"));
+        assert!(program_to_c(&vm::Program::new(&[OpCode::Load], 0, false), "f").contains("dataIndex = -1;"));
+    }
+
+    #[test]
+    fn data_banks() {
+        let mut program = vm::Program::new(&[OpCode::SetBank(1), OpCode::Store], 3, false);
+        program.set_num_data_banks(2);
+        let code = program_to_c(&program, "banks");
+        assert!(code.contains("static float data[6];"));
+        assert!(code.contains("static int regBank = 0;"));
+        assert!(code.contains("bankIndex = (regBank >= 0 && regBank < 2) ? regBank : -1;"));
+        assert!(code.contains("if (dataIndex >= 0) { dataIndex = (bankIndex >= 0) ? bankIndex * 3 + dataIndex : -1; }"));
+        assert!(code.contains("case 0: regBank = 1; break;"));
+
+        // without `SetBank`, only bank 0 is accessed
+        program = program.with_instructions(&[OpCode::Store]);
+        assert!(!program_to_c(&program, "banks").contains("regBank"));
+    }
+
+    #[test]
+    fn integer_conversions() {
+        let program = vm::Program::new(&[OpCode::VtoI, OpCode::IncI, OpCode::DecI], 0, false);
+        let code = program_to_c(&program, "conv");
+        assert!(code.contains("static int conv_toInt(float value)"));
+        assert!(code.contains("if (value >= 2147483647.0f) { return 2147483647; }"));
+        assert!(code.contains("case 0: regI = conv_toInt(regV); break;"));
+        assert!(code.contains("case 1: regI = (int)((unsigned int)regI + 1u); break;"));
+        assert!(code.contains("case 2: regI = (int)((unsigned int)regI - 1u); break;"));
+
+        assert!(!program_to_c(&vm::Program::new(&[OpCode::IncI], 0, false), "conv").contains("toInt"));
+    }
+
+    #[test]
+    fn saturating_arithmetic() {
+        let mut program = vm::Program::new(&[OpCode::Mul, OpCode::Load, OpCode::Sqrt], 1, false);
+        program.set_arithmetic_mode(vm::ArithmeticMode::Saturating{ min: f64::NEG_INFINITY, max: 1000.0 });
+        let code = program_to_c(&program, "sat");
+        assert!(code.contains("static float sat_saturate(float value)"));
+        assert!(code.contains("return (value < (float)-HUGE_VAL) ? (float)-HUGE_VAL : ((value > 1000.0f) ? 1000.0f : value);"));
+        assert!(code.contains("case 0: if (dataIndex >= 0) { regV *= data[dataIndex]; } regV = sat_saturate(regV); break;"));
+        assert!(code.contains("case 1: if (dataIndex >= 0) { regV = data[dataIndex]; } break;"));
+        assert!(code.contains("case 2: regV = (regV >= 0.0f) ? (float)sqrt(regV) : 0.0f; regV = sat_saturate(regV); break;"));
+
+        program.set_arithmetic_mode(vm::ArithmeticMode::Ieee);
+        assert!(!program_to_c(&program, "sat").contains("saturate"));
+    }
+
+    #[test]
+    fn cmp_epsilon() {
+        let mut program = vm::Program::new(&[OpCode::Cmp], 1, false);
+        assert!(program_to_c(&program, "f").contains(
+            "case 0: if (dataIndex >= 0) { float dval = data[dataIndex]; if (regV == dval) { regV = 0.0f; } \
+             else if (regV < dval) { regV = -1.0f; } else if (regV > dval) { regV = 1.0f; } } break;"
+        ));
+        program.set_cmp_epsilon(0.25);
+        assert!(program_to_c(&program, "f").contains("if (regV == dval || fabs((double)regV - dval) <= 0.25) { regV = 0.0f; }"));
+    }
+
+    #[test]
+    fn halt() {
+        let code = program_to_c(&vm::Program::new(&[OpCode::IncV, OpCode::Halt], 0, false), "halting");
+        assert!(code.contains("case 1: return; break;"));
+    }
+
+    #[test]
+    fn random_value() {
+        let code = program_to_c(&vm::Program::new(&[OpCode::RandV, OpCode::Output(0)], 0, false), "random");
+        assert!(code.contains("static unsigned long rngState = 1UL;"));
+        assert!(code.contains("case 0: rngState = (rngState * 1103515245UL + 12345UL) & 0x7fffffffUL;"));
+    }
+
+    #[test]
+    fn empty_program() {
+        let code = program_to_c(&vm::Program::new(&[], 0, false), "empty");
+        assert!(code.contains("void empty(unsigned long numInstructions, float (*input)(int), void (*output)(int, float))"));
+        assert!(!code.contains("jumpTable"));
+        assert!(!code.contains("data["));
+        assert!(!code.contains("switch"));
+    }
+}
@@ -0,0 +1,287 @@
+//
+// genetic - genetic programming experiments
+// Copyright (c) 2019 Filip Szczerek <ga.software@yahoo.com>
+//
+// This project is licensed under the terms of the MIT license
+// (see the LICENSE file for details).
+//
+//
+// File description:
+//   Module: transpiling to a C# class (e.g. for use in Unity).
+//
+
+use transpile::{generate_c_like_data_index, is_saturated, uses_data_banks};
+use vm;
+
+///
+/// Creates C# source code of a class `class_name` running `program`.
+///
+/// The class has public fields `regV`, `regI`, `data`, `iptr` and `halted`, a constructor taking the input
+/// and output delegates (`Func<int, float>` called for `Input`, `Action<int, float>` called for `Output`;
+/// either may be `null`, like a missing `vm::InputOutputHandler`), and a method `Run(int numInstructions)`
+/// which executes the specified number of instructions (restarting from the beginning after the last instruction,
+/// like a looped `VirtualMachine::run`). Subsequent calls resume execution where it stopped; executing `Halt`
+/// sets `halted` and returns immediately (and so will subsequent calls).
+///
+/// Index and arithmetic modes and the `Cmp` tolerance are supported. `RandV` uses the same linear congruential
+/// generator as the C transpiler (see `transpile::c`), so its values differ from those of the Rust VM.
+///
+pub fn program_to_csharp(program: &vm::Program, class_name: &str) -> String {
+    let num_instr = program.get_instr().len();
+    let num_data_slots = program.get_num_data_slots();
+    let uses_banks = uses_data_banks(program);
+
+    let mut result = format!(
+        "// Generated from a VM program ({} instructions, {} data slots).\n\n", num_instr, num_data_slots
+    );
+    result += "using System;\n\n";
+    result += &format!("public class {}\n{{\n", class_name);
+
+    result += "    public float regV = 0.0f;\n";
+    result += "    public int regI = 0;\n";
+    if uses_banks {
+        result += "    public int regBank = 0;\n";
+    }
+    result += &format!("    public float[] data = new float[{}];\n", num_data_slots * program.get_num_data_banks());
+    result += "    public int iptr = 0;\n";
+    result += "    public bool halted = false;\n";
+    if program.get_instr().contains(&vm::OpCode::RandV) {
+        result += "    private uint rngState = 1u;\n";
+    }
+    result += &generate_jump_table(program);
+    result += "    private readonly Func<int, float> input;\n";
+    result += "    private readonly Action<int, float> output;\n\n";
+
+    result += &format!("    public {}(Func<int, float> input, Action<int, float> output)\n    {{\n", class_name);
+    result += "        this.input = input;\n";
+    result += "        this.output = output;\n";
+    result += "    }\n\n";
+
+    result += "    /// <summary>Executes the specified number of instructions (or until `Halt`).</summary>\n";
+    result += "    public void Run(int numInstructions)\n    {\n";
+    if num_instr > 0 {
+        result += "        for (int i = 0; i < numInstructions; i++)\n        {\n";
+        result += "            int dataIndex;\n";
+        if uses_banks {
+            result += "            int bankIndex;\n";
+        }
+        result += &generate_c_like_data_index(program, "            ");
+        result += "            int next = iptr + 1;\n";
+        result += "            switch (iptr)\n            {\n";
+        result += &generate_instructions(program);
+        result += "            }\n";
+        result += &format!("            iptr = (next >= {}) ? 0 : next;\n", num_instr);
+        result += "        }\n";
+    }
+    result += "    }\n";
+
+    result += FLOAT_TO_INT;
+    if let vm::ArithmeticMode::Saturating{ min, max } = program.get_arithmetic_mode() {
+        result += &format!(
+            "\n    private static float Saturate(float value)\n    {{\n        \
+             if (float.IsNaN(value)) {{ return 0.0f; }}\n        \
+             return (value < {0}) ? {0} : ((value > {1}) ? {1} : value);\n    }}\n",
+            cs_float(min), cs_float(max)
+        );
+    }
+    result += "}\n";
+
+    result
+}
+
+/// Conversion of `regV` to `regI` (used by `VtoI`); out-of-range values saturate, like in the Rust VM.
+const FLOAT_TO_INT: &str = r#"
+    private static int ToInt(float value)
+    {
+        if (float.IsNaN(value)) { return 0; }
+        if (value >= 2147483647.0f) { return int.MaxValue; }
+        if (value <= -2147483648.0f) { return int.MinValue; }
+        return (int)value;
+    }
+"#;
+
+/// Returns C# literal of `value` (as `float`).
+fn cs_float(value: f64) -> String {
+    let value = value as f32;
+    if value.is_nan() {
+        "float.NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "float.PositiveInfinity".to_string() } else { "float.NegativeInfinity".to_string() }
+    } else {
+        format!("{:?}f", value)
+    }
+}
+
+/// Number of jump table items per line in the output C# code.
+const ITEMS_PER_LINE: usize = 8;
+
+/// Generates the `jumpTable` array's definition; `null` means no jump target.
+fn generate_jump_table(program: &vm::Program) -> String {
+    let jump_table = program.get_jump_table();
+    let mut result = format!("    private static readonly int?[] jumpTable = new int?[{}] {{", jump_table.len());
+    if !jump_table.is_empty() {
+        result += "\n        ";
+    }
+    for (i, jitem) in jump_table.iter().enumerate() {
+        match jitem {
+            Some(jmp_target) => result += &format!("{}", jmp_target),
+            None             => result +=         "null"
+        }
+        if i + 1 < jump_table.len() {
+            result += ", ";
+            if (i + 1) % ITEMS_PER_LINE == 0 { result += "\n        "; }
+        } else {
+            result += "\n    ";
+        }
+    }
+    result += "};\n";
+
+    result
+}
+
+/// Generates the `case` labels executing each instruction.
+fn generate_instructions(program: &vm::Program) -> String {
+    let mut result = String::new();
+
+    let cmp_equal = if program.get_cmp_epsilon() == 0.0 {
+        "regV == dval".to_string()
+    } else {
+        format!("regV == dval || Math.Abs((double)regV - dval) <= {:?}", program.get_cmp_epsilon())
+    };
+    let saturating = program.get_arithmetic_mode() != vm::ArithmeticMode::Ieee;
+
+    for (i, instr) in program.get_instr().iter().enumerate() {
+        let mut code = match instr {
+            vm::OpCode::SetI(n)   => format!("regI = {};", n),
+            vm::OpCode::SetBank(n) => format!("regBank = {};", n),
+            vm::OpCode::Input(n)  => format!("if (input != null) {{ regV = input({}); }}", n),
+            vm::OpCode::Output(n) => format!("if (output != null) {{ output({}, regV); }}", n),
+            vm::OpCode::ItoV      => "regV = (float)regI;".to_string(),
+            vm::OpCode::VtoI      => "regI = ToInt(regV);".to_string(),
+            vm::OpCode::IncV      => "regV += 1.0f;".to_string(),
+            vm::OpCode::DecV      => "regV -= 1.0f;".to_string(),
+            vm::OpCode::IncI      => "regI = unchecked(regI + 1);".to_string(),
+            vm::OpCode::DecI      => "regI = unchecked(regI - 1);".to_string(),
+            vm::OpCode::Load      => "if (dataIndex >= 0) { regV = data[dataIndex]; }".to_string(),
+            vm::OpCode::Store     => "if (dataIndex >= 0) { data[dataIndex] = regV; }".to_string(),
+            vm::OpCode::Swap      => "if (dataIndex >= 0) { float tmp = data[dataIndex]; data[dataIndex] = regV; regV = tmp; }".to_string(),
+            vm::OpCode::GoToIfP   => "if (regV >= 0.0f && jumpTable[iptr] != null) { next = jumpTable[iptr].Value; }".to_string(),
+            vm::OpCode::JumpIfN   => "if (regV < 0.0f && jumpTable[iptr] != null) { next = jumpTable[iptr].Value; }".to_string(),
+            vm::OpCode::EndGoTo |
+            vm::OpCode::EndJump |
+            vm::OpCode::Nop       => "".to_string(),
+            vm::OpCode::IfP       => "if (regV < 0.0f) { next++; }".to_string(),
+            vm::OpCode::IfN       => "if (regV >= 0.0f) { next++; }".to_string(),
+            vm::OpCode::Cmp       => format!("if (dataIndex >= 0) {{ float dval = data[dataIndex]; \
+                                              if ({}) {{ regV = 0.0f; }} \
+                                              else if (regV < dval) {{ regV = -1.0f; }} \
+                                              else if (regV > dval) {{ regV = 1.0f; }} }}", cmp_equal),
+            vm::OpCode::Add       => "if (dataIndex >= 0) { regV += data[dataIndex]; }".to_string(),
+            vm::OpCode::Sub       => "if (dataIndex >= 0) { regV -= data[dataIndex]; }".to_string(),
+            vm::OpCode::Mul       => "if (dataIndex >= 0) { regV *= data[dataIndex]; }".to_string(),
+            vm::OpCode::Div       => "if (dataIndex >= 0 && data[dataIndex] != 0.0f) { regV /= data[dataIndex]; }".to_string(),
+            vm::OpCode::Abs       => "regV = Math.Abs(regV);".to_string(),
+            vm::OpCode::Neg       => "regV = -regV;".to_string(),
+            vm::OpCode::Sqrt      => "regV = (regV >= 0.0f) ? (float)Math.Sqrt(regV) : 0.0f;".to_string(),
+            vm::OpCode::Pow       => "if (dataIndex >= 0) { float dval = data[dataIndex]; float p = (float)Math.Pow(regV, dval); \
+                                      regV = (float.IsNaN(regV) || float.IsNaN(dval) || float.IsNaN(p)) ? 0.0f : p; }".to_string(),
+            vm::OpCode::Log       => "regV = (regV > 0.0f) ? (float)Math.Log(regV) : 0.0f;".to_string(),
+            vm::OpCode::Sin       => "regV = (float)Math.Sin(regV);".to_string(),
+            vm::OpCode::Cos       => "regV = (float)Math.Cos(regV);".to_string(),
+            vm::OpCode::RandV     => "rngState = unchecked(rngState * 1103515245u + 12345u) & 0x7fffffffu; \
+                                      regV = (float)(rngState >> 7) / 16777216.0f;".to_string(),
+            // `iptr` stays at `Halt`
+            vm::OpCode::Halt      => "halted = true; return;".to_string()
+        };
+        if saturating && is_saturated(instr) {
+            code += " regV = Saturate(regV);";
+        }
+
+        if code.is_empty() {
+            result += &format!("                case {}: break;\n", i);
+        } else if code.ends_with("return;") {
+            // `break` would be unreachable
+            result += &format!("                case {}: {}\n", i, code);
+        } else {
+            result += &format!("                case {}: {} break;\n", i, code);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use transpile::csharp::program_to_csharp;
+    use vm;
+    use vm::OpCode;
+
+    #[test]
+    fn declarations() {
+        let program = vm::Program::new(&[
+            OpCode::EndGoTo,
+                OpCode::Input(0),
+                OpCode::SetI(2),
+                OpCode::Div,
+                OpCode::Sqrt,
+                OpCode::Output(1),
+            OpCode::GoToIfP,
+            OpCode::JumpIfN
+        ], 3, false);
+
+        let code = program_to_csharp(&program, "Controller");
+
+        assert!(code.contains("public class Controller\n{\n"));
+        assert!(code.contains("    public Controller(Func<int, float> input, Action<int, float> output)\n"));
+        assert!(code.contains("    public void Run(int numInstructions)\n"));
+        assert!(code.contains("    public float regV = 0.0f;\n"));
+        assert!(code.contains("    public int regI = 0;\n"));
+        assert!(code.contains("    public float[] data = new float[3];\n"));
+        assert!(code.contains(
+            "    private static readonly int?[] jumpTable = new int?[8] {\n        6, null, null, null, null, null, 0, null\n    };\n"
+        ));
+        assert!(code.contains("dataIndex = (regI >= 0 && regI < 3) ? regI : -1;"));
+        assert!(code.contains("case 1: if (input != null) { regV = input(0); } break;"));
+        assert!(code.contains("case 3: if (dataIndex >= 0 && data[dataIndex] != 0.0f) { regV /= data[dataIndex]; } break;"));
+        assert!(code.contains("case 6: if (regV >= 0.0f && jumpTable[iptr] != null) { next = jumpTable[iptr].Value; } break;"));
+        assert!(code.contains("iptr = (next >= 8) ? 0 : next;"));
+        assert!(!code.contains("regBank"));
+        assert!(!code.contains("rngState"));
+        assert!(!code.contains("Saturate"));
+        assert_eq!(code.matches('{').count(), code.matches('}').count());
+    }
+
+    #[test]
+    fn data_banks() {
+        let mut program = vm::Program::new(&[OpCode::SetBank(1), OpCode::Store], 3, false);
+        program.set_num_data_banks(2);
+        program.set_index_mode(vm::IndexMode::Wrap);
+        let code = program_to_csharp(&program, "Banks");
+        assert!(code.contains("public float[] data = new float[6];"));
+        assert!(code.contains("public int regBank = 0;"));
+        assert!(code.contains("dataIndex = regI % 3; if (dataIndex < 0) { dataIndex += 3; }"));
+        assert!(code.contains("bankIndex = regBank % 2; if (bankIndex < 0) { bankIndex += 2; }"));
+        assert!(code.contains("case 0: regBank = 1; break;"));
+    }
+
+    #[test]
+    fn arithmetic_mode_and_cmp_epsilon() {
+        let mut program = vm::Program::new(&[OpCode::Cmp, OpCode::Mul, OpCode::Halt], 1, false);
+        program.set_arithmetic_mode(vm::ArithmeticMode::Saturating{ min: f64::NEG_INFINITY, max: 1000.0 });
+        program.set_cmp_epsilon(0.125);
+        let code = program_to_csharp(&program, "Saturating");
+        assert!(code.contains("if (regV == dval || Math.Abs((double)regV - dval) <= 0.125) { regV = 0.0f; }"));
+        assert!(code.contains("case 1: if (dataIndex >= 0) { regV *= data[dataIndex]; } regV = Saturate(regV); break;"));
+        assert!(code.contains("return (value < float.NegativeInfinity) ? float.NegativeInfinity : ((value > 1000.0f) ? 1000.0f : value);"));
+        assert!(code.contains("case 2: halted = true; return;\n"));
+    }
+
+    #[test]
+    fn empty_program() {
+        let code = program_to_csharp(&vm::Program::new(&[], 0, false), "Empty");
+        assert!(code.contains("public class Empty\n"));
+        assert!(code.contains("public float[] data = new float[0];"));
+        assert!(code.contains("private static readonly int?[] jumpTable = new int?[0] {};"));
+        assert!(!code.contains("switch"));
+    }
+}
@@ -0,0 +1,97 @@
+//
+// genetic - genetic programming experiments
+// Copyright (c) 2019 Filip Szczerek <ga.software@yahoo.com>
+//
+// This project is licensed under the terms of the MIT license
+// (see the LICENSE file for details).
+//
+//
+// File description:
+//   Module: control-flow graph in Graphviz DOT format.
+//
+
+use utils;
+use vm;
+
+///
+/// Creates a Graphviz digraph of the control flow of `program`.
+///
+/// Nodes are instructions (named `i<index>`, labeled with the index and VM assembly mnemonic). Edges lead
+/// to the next instruction (except from `Halt` and the last instruction), from active `GoToIfP` and `JumpIfN`
+/// to their targets (labeled "jump"), and from `IfP`, `IfN` past the next instruction (labeled "skip").
+/// Inactive `GoToIfP`, `EndGoTo`, `JumpIfN`, `EndJump` instructions are drawn with dashed outlines.
+///
+pub fn program_to_dot(program: &vm::Program) -> String {
+    let instr = program.get_instr();
+    let jmp_tbl = program.get_jump_table();
+
+    let mut result = String::from("digraph program {\n");
+    result += "    node [shape=box];\n";
+
+    for (i, opcode) in instr.iter().enumerate() {
+        let style = if utils::is_block_instruction(*opcode) && jmp_tbl[i].is_none() { ", style=dashed" } else { "" };
+        result += &format!("    i{} [label=\"{}: {}\"{}];\n", i, i, utils::mnemonic(*opcode), style);
+    }
+
+    for (i, opcode) in instr.iter().enumerate() {
+        if *opcode != vm::OpCode::Halt && i + 1 < instr.len() {
+            result += &format!("    i{} -> i{};\n", i, i + 1);
+        }
+        match opcode {
+            vm::OpCode::GoToIfP | vm::OpCode::JumpIfN => if let Some(target) = jmp_tbl[i] {
+                result += &format!("    i{} -> i{} [label=\"jump\"];\n", i, target);
+            },
+            vm::OpCode::IfP | vm::OpCode::IfN if i + 2 < instr.len() => {
+                result += &format!("    i{} -> i{} [label=\"skip\"];\n", i, i + 2);
+            },
+            _ => ()
+        }
+    }
+
+    result += "}\n";
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use transpile::dot::program_to_dot;
+    use vm;
+    use vm::OpCode;
+
+    #[test]
+    fn active_goto() {
+        let program = vm::Program::new(&[
+            OpCode::EndGoTo,
+            OpCode::DecV,
+            OpCode::GoToIfP,
+            OpCode::Output(1)
+        ], 0, false);
+
+        let dot = program_to_dot(&program);
+
+        assert!(dot.starts_with("digraph program {\n"));
+        assert_eq!(4, dot.lines().filter(|line| line.contains("[label=\"") && !line.contains("->")).count());
+        assert!(dot.contains("    i3 [label=\"3: output 1\"];\n"));
+        assert!(dot.contains("    i2 -> i0 [label=\"jump\"];\n"));
+        assert!(dot.contains("    i0 -> i1;\n"));
+        assert!(!dot.contains("dashed"));
+    }
+
+    #[test]
+    fn inactive_and_skip() {
+        let program = vm::Program::new(&[
+            OpCode::IfN,
+            OpCode::GoToIfP,
+            OpCode::Halt,
+            OpCode::IncV
+        ], 0, false);
+
+        let dot = program_to_dot(&program);
+
+        assert!(dot.contains("    i1 [label=\"1: gotoifp\", style=dashed];\n"));
+        assert!(dot.contains("    i0 -> i2 [label=\"skip\"];\n"));
+        assert!(!dot.contains("jump"));
+        assert!(!dot.contains("i2 -> i3"));
+    }
+}
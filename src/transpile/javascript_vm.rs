@@ -10,127 +10,362 @@
 //   Module: transpiling to JavaScript virtual machine with an embedded program.
 //
 
+use utils;
 use vm;
 
-/// Creates a virtual machine in JavaScript with `program` embedded in it.
+impl vm::OpCode {
+    ///
+    /// Returns the name of the JavaScript class representing the opcode in the output code.
+    ///
+    /// The class is declared by `generate_opcode_classes` and must be handled in `handleInstruction` (see `THIRD_PART`).
+    ///
+    pub fn js_class_name(&self) -> &'static str {
+        match self {
+            vm::OpCode::SetI(_) =>    "SetI",
+            vm::OpCode::Input(_) =>   "Input",
+            vm::OpCode::Output(_) =>  "Output",
+            vm::OpCode::ItoV =>       "ItoV",
+            vm::OpCode::VtoI =>       "VtoI",
+            vm::OpCode::IncV =>       "IncV",
+            vm::OpCode::DecV =>       "DecV",
+            vm::OpCode::IncI =>       "IncI",
+            vm::OpCode::DecI =>       "DecI",
+            vm::OpCode::Load =>       "Load",
+            vm::OpCode::Store =>      "Store",
+            vm::OpCode::Swap =>       "Swap",
+            vm::OpCode::EndGoTo =>    "EndGoTo",
+            vm::OpCode::GoToIfP =>    "GoToIfP",
+            vm::OpCode::JumpIfN =>    "JumpIfN",
+            vm::OpCode::EndJump =>    "EndJump",
+            vm::OpCode::IfP =>        "IfP",
+            vm::OpCode::IfN =>        "IfN",
+            vm::OpCode::Cmp =>        "Cmp",
+            vm::OpCode::Add =>        "Add",
+            vm::OpCode::Sub =>        "Sub",
+            vm::OpCode::Mul =>        "Mul",
+            vm::OpCode::Div =>        "Div",
+            vm::OpCode::Abs =>        "Abs",
+            vm::OpCode::Neg =>        "Neg",
+            vm::OpCode::Sqrt =>       "Sqrt",
+            vm::OpCode::Pow =>        "Pow",
+            vm::OpCode::Log =>        "Log",
+            vm::OpCode::Sin =>        "Sin",
+            vm::OpCode::Cos =>        "Cos",
+            vm::OpCode::RandV =>      "RandV",
+            vm::OpCode::SetBank(_) => "SetBank",
+            vm::OpCode::Halt =>       "Halt",
+            vm::OpCode::Nop =>        "Nop"
+        }
+    }
+}
+
+/// Formatting options of the output JavaScript code.
+#[derive(Clone, Copy, Debug)]
+pub struct JsTranspileOptions {
+    /// Number of jump table, instruction and data slot items per line.
+    pub items_per_line: usize,
+    /// If true, each instruction is emitted on a separate line with a trailing comment
+    /// containing its index and mnemonic (e.g. `// 12: gotoifp`).
+    pub include_source_comments: bool,
+    /// If true, indentation, blank lines and comment-only lines are removed.
+    pub minify: bool,
+    ///
+    /// If true, the VM's constructor takes an additional `endCondition` callback, which `run` calls
+    /// after `Output` instructions (see `generate_run`). Otherwise `run` does not check any end condition.
+    ///
+    pub end_condition: bool,
+    ///
+    /// If true, `regV` is rounded to `f32` (with `Math.fround`) after each instruction computing it, so that
+    /// the results of `Add`, `Sub`, `Mul`, `Div` and `Sqrt` are the same as those of a `VirtualMachine::<f32>`
+    /// (see `vm::VirtualMachine::set_strict_math`). Otherwise all values are `f64`, as in a `VirtualMachine::<f64>`.
+    ///
+    pub f32_arithmetic: bool
+}
+
+impl Default for JsTranspileOptions {
+    fn default() -> JsTranspileOptions {
+        JsTranspileOptions{ items_per_line: 8, include_source_comments: false, minify: false, end_condition: false, f32_arithmetic: false }
+    }
+}
+
+///
+/// Creates a virtual machine in JavaScript with `program` embedded in it (using default `JsTranspileOptions`).
+///
+/// `RandV` uses `Math.random()`, which cannot be seeded; the values differ from those of the Rust
+/// virtual machine, between runs and between JavaScript engines.
+///
 pub fn program_to_javascript_vm(program: &vm::Program) -> String {
-    FIRST_PART.to_string() +
-        &generate_instruction_list(program) +
-        &generate_jump_table(program) +
-        &generate_data_slots(program) +
-        &SECOND_PART.to_string()
+    program_to_javascript_vm_with_options(program, &JsTranspileOptions::default())
+}
+
+/// Creates a virtual machine in JavaScript with `program` embedded in it (see `program_to_javascript_vm`).
+pub fn program_to_javascript_vm_with_options(program: &vm::Program, options: &JsTranspileOptions) -> String {
+    let items_per_line = std::cmp::max(1, options.items_per_line);
+
+    let code = generate_header(program) +
+        "\n\"use strict\";\n\n" +
+        &generate_opcode_classes() +
+        FIRST_PART +
+        &generate_constructor(options.end_condition) +
+        &generate_instruction_list(program, items_per_line, options.include_source_comments) +
+        &generate_jump_table(program, items_per_line) +
+        &generate_data_slots(program, items_per_line) +
+        &generate_index_mode(program) +
+        &generate_arithmetic_mode(program, options.f32_arithmetic) +
+        &format!("        this.cmpEpsilon = {};\n", js_number(program.get_cmp_epsilon())) +
+        SECOND_PART +
+        &generate_run(options.end_condition) +
+        THIRD_PART;
+
+    if options.minify { minify(&code) } else { code }
+}
+
+///
+/// Removes indentation, trailing whitespace, blank lines and lines containing only comments.
+///
+/// Line breaks are preserved, so that no automatic semicolon insertion issues arise.
+///
+fn minify(code: &str) -> String {
+    let mut output = String::new();
+    for line in code.lines().map(|line| line.trim()) {
+        let comment_only = line.starts_with("//") || line.starts_with("/*") || line.starts_with('*');
+        if !line.is_empty() && !comment_only {
+            output += line;
+            output += "\n";
+        }
+    }
+
+    output
+}
+
+///
+/// Generates the comment block describing the embedded program: number of instructions and data slots,
+/// number of active and inactive `GoToIfP`, `EndGoTo`, `JumpIfN`, `EndJump` instructions, and the crate version.
+///
+fn generate_header(program: &vm::Program) -> String {
+    let jmp_tbl = program.get_jump_table();
+    let (active, inactive) = program.get_instr().iter().enumerate()
+        .filter(|(_, opcode)| utils::is_block_instruction(**opcode))
+        .fold((0, 0), |(active, inactive), (i, _)| if jmp_tbl[i].is_some() { (active + 1, inactive) } else { (active, inactive + 1) });
+
+    let mut header = format!("// Generated by genetic {}.\n", env!("CARGO_PKG_VERSION"));
+    header += &format!("//   instructions: {}\n", program.get_instr().len());
+    header += &format!("//   data slots: {} (banks: {})\n", program.get_num_data_slots(), program.get_num_data_banks());
+    header += &format!("//   control flow instructions: {} active, {} inactive\n", active, inactive);
+
+    header
+}
+
+/// Generates the definitions of JavaScript classes representing opcodes (in the order of `vm::OpCode::ALL`).
+fn generate_opcode_classes() -> String {
+    let mut classes = "// virtual machine instruction opcodes\n".to_string();
+    for opcode in vm::OpCode::ALL.iter() {
+        classes += &if opcode.operand().is_some() {
+            format!("class {} {{ constructor(i) {{ this.i = i; }} }};\n", opcode.js_class_name())
+        } else {
+            format!("class {} {{ }};\n", opcode.js_class_name())
+        };
+    }
+
+    classes
+}
+
+/// Generates the beginning of the VM's constructor (up to the opening brace).
+fn generate_constructor(end_condition: bool) -> String {
+    let mut constructor = "    /**\n".to_string();
+    constructor += "     * @callback {VmInputHander} inputHandler - Called for `Input` instructions. May be `null`.\n";
+    constructor += "     * @callback {VmOutputHander} outputHandler - Called for `Output` instructions. May be `null`.\n";
+    if end_condition {
+        constructor += "     * @callback {VmEndCondition} endCondition - Called by `run` after `Output` instructions if requested.\n";
+    }
+    constructor += "     */\n";
+    constructor += if end_condition {
+        "    constructor(inputHandler, outputHandler, endCondition) {\n"
+    } else {
+        "    constructor(inputHandler, outputHandler) {\n"
+    };
+
+    constructor
+}
+
+///
+/// Generates the end of the VM's constructor (after `SECOND_PART`) and the `run` method.
+///
+/// If `end_condition` is true, `run(numInstructions, checkEndCondition)` mirrors `vm::VirtualMachine::run`
+/// with `check_end_condition` for a looped program: after each executed `Output` instruction,
+/// `endCondition(instructionCount)` is called with the number of instructions executed so far by this
+/// `run` call (including the `Output`), and execution stops if it returns `true`. `run` then returns
+/// `true` if the end condition was met, `false` if `numInstructions` have been executed.
+///
+fn generate_run(end_condition: bool) -> String {
+    if end_condition { RUN_WITH_END_CONDITION.to_string() } else { RUN.to_string() }
 }
 
-/// Number of jump table and instruction items per line in the output JS code.
-const ITEMS_PER_LINE: usize = 8;
+/// End of the VM's constructor and the `run` method without end condition checking.
+const RUN: &str = r#"    }
 
-/// Generates the data slots array's definition.
-fn generate_data_slots(program: &vm::Program) -> String {
-    let mut result = "        this.data = [\n            ".to_string();
-    for i in 1..=program.get_num_data_slots() {
-        result += &"0.0, ";
-        if i % ITEMS_PER_LINE == 0 { result += &"\n            "; }
+    /** Executes the specified number of instructions (or until `Halt`). Subsequent calls resume execution where it stopped. */
+    run(num_instructions) {
+        let icounter = 0;
+        while (icounter < num_instructions) {
+            if (this.instructions[this.iptr] instanceof Halt) {
+                this.halted = true;
+                return;
+            }
+            if (this.handleInstruction(this.instructions[this.iptr])) {
+                this.iptr += 1;
+            }
+            icounter += 1;
+            if (this.iptr >= this.instructions.length) {
+                this.iptr = 0;
+            }
+        }
+    }
+"#;
+
+/// End of the VM's constructor and the `run` method with end condition checking (see `generate_run`).
+const RUN_WITH_END_CONDITION: &str = r#"
+        this.endCondition = endCondition;
     }
-    result += &"\n        ];\n";
 
-    result
+    /**
+     * Executes the specified number of instructions (or until `Halt`). Subsequent calls resume execution where it stopped.
+     *
+     * If `checkEndCondition` is `true`, `endCondition` is called after every `Output` instruction with the number
+     * of instructions executed so far by this call (including the `Output`); if it returns `true`, execution ends.
+     *
+     * @returns {boolean} - `true` if execution ended because of the end condition.
+     */
+    run(numInstructions, checkEndCondition) {
+        let icounter = 0;
+        while (icounter < numInstructions) {
+            let instr = this.instructions[this.iptr];
+            if (instr instanceof Halt) {
+                this.halted = true;
+                return false;
+            }
+            if (this.handleInstruction(instr)) {
+                this.iptr += 1;
+            }
+            icounter += 1;
+            if (this.iptr >= this.instructions.length) {
+                this.iptr = 0;
+            }
+            if (checkEndCondition && instr instanceof Output && this.endCondition(icounter)) {
+                return true;
+            }
+        }
+        return false;
+    }
+"#;
+
+/// Generates the definition of the data banks array (each element is an array of data slots).
+fn generate_data_slots(program: &vm::Program, items_per_line: usize) -> String {
+    let num_data_slots = program.get_num_data_slots();
+    let num_data_banks = program.get_num_data_banks();
+    if num_data_banks == 0 {
+        return "        this.data = [];\n".to_string();
+    }
+
+    let mut bank = "            [".to_string();
+    if num_data_slots > 0 {
+        bank += "\n";
+        for line_start in (0..num_data_slots).step_by(items_per_line) {
+            let num_items = std::cmp::min(items_per_line, num_data_slots - line_start);
+            bank += "                ";
+            bank += &vec!["0.0"; num_items].join(", ");
+            bank += if line_start + num_items < num_data_slots { ",\n" } else { "\n" };
+        }
+        bank += "            ";
+    }
+    bank += "]";
+
+    format!("        this.data = [\n{}\n        ];\n", vec![bank; num_data_banks].join(",\n"))
+}
+
+/// Generates the definition of `this.indexMode` (see `vm::IndexMode`).
+fn generate_index_mode(program: &vm::Program) -> String {
+    let index_mode = match program.get_index_mode() {
+        vm::IndexMode::Ignore => "ignore",
+        vm::IndexMode::Wrap => "wrap",
+        vm::IndexMode::Clamp => "clamp"
+    };
+    format!("        this.indexMode = \"{}\";\n", index_mode)
+}
+
+///
+/// Generates the definitions of `this.saturation` (see `vm::ArithmeticMode`): `null` or the range of `regV`,
+/// and `this.f32Arithmetic` (see `JsTranspileOptions::f32_arithmetic`).
+///
+fn generate_arithmetic_mode(program: &vm::Program, f32_arithmetic: bool) -> String {
+    // the bounds are converted to the VM's number type, like in `vm::ArithmeticMode::apply`
+    let bound = |value: f64| if f32_arithmetic { js_number(value as f32 as f64) } else { js_number(value) };
+    let saturation = match program.get_arithmetic_mode() {
+        vm::ArithmeticMode::Ieee => "        this.saturation = null;\n".to_string(),
+        vm::ArithmeticMode::Saturating{ min, max } =>
+            format!("        this.saturation = {{ min: {}, max: {} }};\n", bound(min), bound(max))
+    };
+
+    saturation + &format!("        this.f32Arithmetic = {};\n", f32_arithmetic)
+}
+
+/// Returns JavaScript literal of `value`.
+fn js_number(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "Infinity".to_string() } else { "-Infinity".to_string() }
+    } else {
+        format!("{:?}", value)
+    }
 }
 
 /// Generates the contents of the VM's `this.jumpTable` array.
-fn generate_jump_table(program: &vm::Program) -> String {
+fn generate_jump_table(program: &vm::Program, items_per_line: usize) -> String {
     let mut jump_table = "        this.jumpTable = [\n            ".to_string();
     for (i, jitem) in program.get_jump_table().iter().enumerate() {
         match jitem {
             Some(jmp_target) => jump_table += &format!("{}, ", jmp_target),
-            None             => jump_table +=         &"null, "
+            None             => jump_table +=         "null, "
         }
-        if (i+1) % ITEMS_PER_LINE == 0 { jump_table += &"\n            "; }
+        if (i+1) % items_per_line == 0 { jump_table += "\n            "; }
     }
     jump_table += "\n        ];\n";
 
     jump_table
 }
 
+///
 /// Generates the contents of the VM's `this.instructions` array.
-fn generate_instruction_list(program: &vm::Program) -> String {
+///
+/// If `source_comments` is true, emits one instruction per line, followed by a comment with its index and mnemonic.
+///
+fn generate_instruction_list(program: &vm::Program, items_per_line: usize, source_comments: bool) -> String {
     let mut instructions = "        this.instructions = [\n            ".to_string();
     for (i, instr) in program.get_instr().iter().enumerate() {
-        let instr_str =
-            match instr {
-                vm::OpCode::SetI(i)   => format!("new SetI({}), ", i),
-                vm::OpCode::Input(i)  => format!("new Input({}), ", i),
-                vm::OpCode::Output(i) => format!("new Output({}), ", i),
-                vm::OpCode::ItoV              => "new ItoV, ".to_string(),
-                vm::OpCode::VtoI              => "new VtoI, ".to_string(),
-                vm::OpCode::IncV              => "new IncV, ".to_string(),
-                vm::OpCode::DecV              => "new DecV, ".to_string(),
-                vm::OpCode::IncI              => "new IncI, ".to_string(),
-                vm::OpCode::DecI              => "new DecI, ".to_string(),
-                vm::OpCode::Load              => "new Load, ".to_string(),
-                vm::OpCode::Store             => "new Store, ".to_string(),
-                vm::OpCode::Swap              => "new Swap, ".to_string(),
-                vm::OpCode::EndGoTo           => "new EndGoTo, ".to_string(),
-                vm::OpCode::GoToIfP           => "new GoToIfP, ".to_string(),
-                vm::OpCode::JumpIfN           => "new JumpIfN, ".to_string(),
-                vm::OpCode::EndJump           => "new EndJump, ".to_string(),
-                vm::OpCode::IfP               => "new IfP, ".to_string(),
-                vm::OpCode::IfN               => "new IfN, ".to_string(),
-                vm::OpCode::Cmp               => "new Cmp, ".to_string(),
-                vm::OpCode::Add               => "new Add, ".to_string(),
-                vm::OpCode::Sub               => "new Sub, ".to_string(),
-                vm::OpCode::Mul               => "new Mul, ".to_string(),
-                vm::OpCode::Div               => "new Div, ".to_string(),
-                vm::OpCode::Abs               => "new Abs, ".to_string(),
-                vm::OpCode::Neg               => "new Neg, ".to_string(),
-                vm::OpCode::Sqrt              => "new Sqrt, ".to_string(),
-                vm::OpCode::Nop               => "new Nop, ".to_string(),
-            };
+        let instr_str = match instr.operand() {
+            Some(operand) => format!("new {}({}), ", instr.js_class_name(), operand),
+            None => format!("new {}, ", instr.js_class_name())
+        };
         instructions += &instr_str;
-        if (i+1) % ITEMS_PER_LINE == 0 { instructions += &"\n            "; }
+        if source_comments {
+            instructions += &format!("// {}: {}\n            ", i, utils::mnemonic(*instr));
+        } else if (i+1) % items_per_line == 0 {
+            instructions += "\n            ";
+        }
     }
-    instructions += &"\n        ];\n";
+    instructions += "\n        ];\n";
 
     instructions
 }
 
 ///
-/// First part of the output JavaScript code.
+/// First part of the output JavaScript code (preceded by the header, "use strict" and the string returned by `generate_opcode_classes`).
 ///
-/// Strings returned by `generate_instruction_list`, 'generate_data_slots`
-/// and `generate_jump_table` are inserted between `FIRST_PART` and `SECOND_PART`.
+/// Strings returned by `generate_constructor`, `generate_instruction_list`, 'generate_data_slots`, `generate_index_mode`,
+/// `generate_arithmetic_mode` and `generate_jump_table` are inserted between `FIRST_PART` and `SECOND_PART`.
 ///
 const FIRST_PART: &str =r#"
-"use strict";
-
-// virtual machine instruction opcodes
-class SetI { constructor(i) { this.i = i; } };
-class Input { constructor(i) { this.i = i; } };
-class Output { constructor(i) { this.i = i; } };
-class ItoV { };
-class VtoI { };
-class IncV { };
-class DecV { };
-class IncI { };
-class DecI { };
-class Load { };
-class Store { };
-class Swap { };
-class EndGoTo { };
-class GoToIfP { };
-class JumpIfN { };
-class EndJump { };
-class IfP { };
-class IfN { };
-class Cmp { };
-class Add { };
-class Sub { };
-class Mul { };
-class Div { };
-class Abs { };
-class Neg { };
-class Sqrt { };
-class Nop { };
-
 /**
  * @callback VmInputHandler
  * @param {number} inputNumber - Input number (integer).
@@ -143,48 +378,48 @@ class Nop { };
  * @param {number} outputValue
  */
 
+/**
+ * @callback VmEndCondition
+ * @param {number} instructionCount - Number of instructions executed so far by the current `run` call.
+ * @returns {boolean} - `true` if execution is to end.
+ */
+
 /** Virtual machine running a hard-coded program. */
 class VM {
-    /**
-     * @callback {VmInputHander} inputHandler - Called for `Input` instructions. May be `null`.
-     * @callback {VmOutputHander} outputHandler - Called for `Output` instructions. May be `null`.
-     */
-    constructor(inputHandler, outputHandler) {
 "#;
 
 ///
-/// Second (and the last) part of the output JavaScript code.
+/// Second part of the output JavaScript code.
 ///
-/// Strings returned by `generate_instruction_list`, 'generate_data_slots`
-/// and `generate_jump_table` are inserted between `FIRST_PART` and `SECOND_PART`.
+/// Strings returned by `generate_constructor`, `generate_instruction_list`, 'generate_data_slots`, `generate_index_mode`,
+/// `generate_arithmetic_mode` and `generate_jump_table` are inserted between `FIRST_PART` and `SECOND_PART`.
 ///
 const SECOND_PART: &str = r#"
 
         this.iptr = 0;
         this.regI = 0;
+        this.regBank = 0;
         this.regV = 0.0;
+        /** Set to `true` when execution is ended by a `Halt` instruction (which is not passed), see `run`, `runUntil`. */
+        this.halted = false;
 
         this.inputHandler = inputHandler;
         this.outputHandler = outputHandler;
-    }
-
-    /** Executes the specified number of instructions. Subsequent calls resume execution where it stopped. */
-    run(num_instructions) {
-        let icounter = 0;
-        while (icounter < num_instructions) {
-            if (this.handleInstruction(this.instructions[this.iptr])) {
-                this.iptr += 1;
-            }
-            icounter += 1;
-            if (this.iptr >= this.instructions.length) {
-                this.iptr = 0;
-            }
-        }
-    }
+"#;
 
-    /** Executes the program until the `end_condition` function returns `true`. Subsequent calls resume execution where it stopped. */
+///
+/// Third (and the last) part of the output JavaScript code.
+///
+/// The string returned by `generate_run` is inserted between `SECOND_PART` and `THIRD_PART`.
+///
+const THIRD_PART: &str = r#"
+    /** Executes the program until the `end_condition` function returns `true` (or until `Halt`). Subsequent calls resume execution where it stopped. */
     runUntil(end_condition) {
         while (!end_condition()) {
+            if (this.instructions[this.iptr] instanceof Halt) {
+                this.halted = true;
+                return;
+            }
             if (this.handleInstruction(this.instructions[this.iptr])) {
                 this.iptr += 1;
             }
@@ -194,28 +429,62 @@ const SECOND_PART: &str = r#"
         }
     }
 
-    isDataIndex() {
-        return this.regI >= 0 && this.regI < this.data.length;
+    /** Returns `reg` as an index less than `n` (depending on `indexMode`), or -1 if there is none. */
+    index(reg, n) {
+        if (reg >= 0 && reg < n) return reg;
+        if (n == 0) return -1;
+        if (this.indexMode == "wrap") return ((reg % n) + n) % n;
+        if (this.indexMode == "clamp") return (reg < 0) ? 0 : n - 1;
+        return -1;
+    }
+
+    /** Returns the data bank (array of data slots) pointed to by `regBank`, or `null` if there is none. */
+    dataBank() {
+        let b = this.index(this.regBank, this.data.length);
+        return (b >= 0) ? this.data[b] : null;
+    }
+
+    /** Returns the index in `bank` (returned by `dataBank`) of the data slot pointed to by `regI`, or -1 if there is none. */
+    dataIndex(bank) {
+        return (bank != null) ? this.index(this.regI, bank.length) : -1;
+    }
+
+    /** Returns `value` rounded to the nearest `f32` value if `f32Arithmetic` is set. */
+    round(value) {
+        return this.f32Arithmetic ? Math.fround(value) : value;
+    }
+
+    /**
+     * Rounds `regV` (see `round`) and, if `saturation` is set, clamps it to its range (replacing NaN with 0);
+     * called after arithmetic instructions.
+     */
+    roundAndSaturate() {
+        this.regV = this.round(this.regV);
+        if (this.saturation == null) return;
+        if (Number.isNaN(this.regV)) this.regV = 0.0;
+        this.regV = Math.min(Math.max(this.regV, this.saturation.min), this.saturation.max);
     }
 
     /** Handles `instr`; returns `true` if instruction pointer is to be incremented by the caller afterwards. */
     handleInstruction(instr) {
         if (instr instanceof SetI) { this.regI = instr.i; }
-        else if (instr instanceof Input) { if (this.inputHandler != null) this.regV = this.inputHandler(instr.i); }
+        else if (instr instanceof SetBank) { this.regBank = instr.i; }
+        else if (instr instanceof Input) { if (this.inputHandler != null) this.regV = this.round(this.inputHandler(instr.i)); }
         else if (instr instanceof Output) { if (this.outputHandler != null) this.outputHandler(instr.i, this.regV); }
-        else if (instr instanceof ItoV) { this.regV = this.regI; }
+        else if (instr instanceof ItoV) { this.regV = this.round(this.regI); }
         else if (instr instanceof VtoI) { this.regI = Math.trunc(this.regV); }
-        else if (instr instanceof IncV) { this.regV += 1.0 }
-        else if (instr instanceof DecV) { this.regV -= 1.0 }
+        else if (instr instanceof IncV) { this.regV += 1.0; this.roundAndSaturate(); }
+        else if (instr instanceof DecV) { this.regV -= 1.0; this.roundAndSaturate(); }
         else if (instr instanceof IncI) { this.regI += 1; if (this.regI >= 0x80000000) this.regI = -1; }
         else if (instr instanceof DecI) { this.regI -= 1; if (this.regI < -0x80000000) this.regI = 0x7FFFFFFF; }
-        else if (instr instanceof Load) { if (this.isDataIndex()) this.regV = this.data[this.regI]; }
-        else if (instr instanceof Store) { if (this.isDataIndex()) this.data[this.regI] = this.regV; }
+        else if (instr instanceof Load) { let bank = this.dataBank(), idx = this.dataIndex(bank); if (idx >= 0) this.regV = bank[idx]; }
+        else if (instr instanceof Store) { let bank = this.dataBank(), idx = this.dataIndex(bank); if (idx >= 0) bank[idx] = this.regV; }
         else if (instr instanceof Swap) {
-            if (this.isDataIndex()) {
+            let bank = this.dataBank(), idx = this.dataIndex(bank);
+            if (idx >= 0) {
                 let tmp = this.regV;
-                this.regV = this.data[this.regI];
-                this.data[this.regI] = tmp;
+                this.regV = bank[idx];
+                bank[idx] = tmp;
             }
         }
         else if (instr instanceof EndGoTo) { }
@@ -235,23 +504,240 @@ const SECOND_PART: &str = r#"
         else if (instr instanceof IfP) { if (this.regV < 0.0) this.iptr += 1; }
         else if (instr instanceof IfN) { if (this.regV >= 0.0) this.iptr += 1; }
         else if (instr instanceof Cmp) {
-            if (this.isDataIndex()) {
-                let dval = this.data[this.regI];
-                if (this.regV < dval) this.regV = -1.0;
-                else if (this.regV == dval) this.regV = 0.0;
+            let bank = this.dataBank(), idx = this.dataIndex(bank);
+            if (idx >= 0) {
+                let dval = bank[idx];
+                if (this.regV == dval || Math.abs(this.regV - dval) <= this.cmpEpsilon) this.regV = 0.0;
+                else if (this.regV < dval) this.regV = -1.0;
                 else if (this.regV > dval) this.regV = 1.0;
             }
         }
-        else if (instr instanceof Add) { if (this.isDataIndex()) this.regV += this.data[this.regI]; }
-        else if (instr instanceof Sub) { if (this.isDataIndex()) this.regV -= this.data[this.regI]; }
-        else if (instr instanceof Mul) { if (this.isDataIndex()) this.regV *= this.data[this.regI]; }
-        else if (instr instanceof Div) { if (this.isDataIndex() && this.data[this.regI] != 0.0) this.regV /= this.data[this.regI]; }
-        else if (instr instanceof Abs) { this.regV = Math.abs(this.regV); }
-        else if (instr instanceof Neg) { this.regV = -this.regV; }
-        else if (instr instanceof Sqrt) { if (this.regV >= 0.0) this.regV = Math.sqrt(this.regV); else this.regV = 0.0; }
+        else if (instr instanceof Add) { let bank = this.dataBank(), idx = this.dataIndex(bank); if (idx >= 0) this.regV += bank[idx]; this.roundAndSaturate(); }
+        else if (instr instanceof Sub) { let bank = this.dataBank(), idx = this.dataIndex(bank); if (idx >= 0) this.regV -= bank[idx]; this.roundAndSaturate(); }
+        else if (instr instanceof Mul) { let bank = this.dataBank(), idx = this.dataIndex(bank); if (idx >= 0) this.regV *= bank[idx]; this.roundAndSaturate(); }
+        else if (instr instanceof Div) { let bank = this.dataBank(), idx = this.dataIndex(bank); if (idx >= 0 && bank[idx] != 0.0) this.regV /= bank[idx]; this.roundAndSaturate(); }
+        else if (instr instanceof Abs) { this.regV = Math.abs(this.regV); this.roundAndSaturate(); }
+        else if (instr instanceof Neg) { this.regV = -this.regV; this.roundAndSaturate(); }
+        else if (instr instanceof Sqrt) { if (this.regV >= 0.0) this.regV = Math.sqrt(this.regV); else this.regV = 0.0; this.roundAndSaturate(); }
+        else if (instr instanceof Pow) {
+            let bank = this.dataBank(), idx = this.dataIndex(bank);
+            if (idx >= 0) {
+                let p = Math.pow(this.regV, bank[idx]);
+                this.regV = (Number.isNaN(this.regV) || Number.isNaN(bank[idx]) || Number.isNaN(p)) ? 0.0 : p;
+            }
+            this.roundAndSaturate();
+        }
+        else if (instr instanceof Log) { this.regV = (this.regV > 0.0) ? Math.log(this.regV) : 0.0; this.roundAndSaturate(); }
+        else if (instr instanceof Sin) { this.regV = Math.sin(this.regV); this.roundAndSaturate(); }
+        else if (instr instanceof Cos) { this.regV = Math.cos(this.regV); this.roundAndSaturate(); }
+        else if (instr instanceof RandV) { this.regV = this.round(Math.random()); }
+        else if (instr instanceof Halt) { return false; } // handled by `run`, `runUntil`
         else if (instr instanceof Nop) { }
 
         return true;
     }
 }
-"#;
\ No newline at end of file
+"#;
+#[cfg(test)]
+mod tests {
+    use transpile::javascript_vm::{
+        generate_data_slots,
+        generate_header,
+        program_to_javascript_vm,
+        program_to_javascript_vm_with_options,
+        JsTranspileOptions
+    };
+    use vm;
+
+    fn data_slots(num_data_slots: usize, num_data_banks: usize) -> String {
+        let mut program = vm::Program::new(&[vm::OpCode::Nop], num_data_slots, false);
+        program.set_num_data_banks(num_data_banks);
+        generate_data_slots(&program, JsTranspileOptions::default().items_per_line)
+    }
+
+    #[test]
+    fn data_slots_definition() {
+        assert_eq!("        this.data = [\n            []\n        ];\n", data_slots(0, 1));
+        assert_eq!("        this.data = [\n            [\n                0.0\n            ]\n        ];\n", data_slots(1, 1));
+        assert_eq!(
+            "        this.data = [\n            [\n                \
+             0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,\n                \
+             0.0\n            \
+             ]\n        \
+             ];\n",
+            data_slots(9, 1)
+        );
+        assert_eq!(
+            "        this.data = [\n            [\n                0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0\n            ]\n        ];\n",
+            data_slots(8, 1)
+        );
+    }
+
+    #[test]
+    fn data_banks_definition() {
+        assert_eq!("        this.data = [];\n", data_slots(2, 0));
+        assert_eq!(
+            "        this.data = [\n            [\n                0.0, 0.0\n            ],\n            [\n                0.0, 0.0\n            ]\n        ];\n",
+            data_slots(2, 2)
+        );
+    }
+
+    #[test]
+    fn header() {
+        let program = vm::Program::new(&[
+            vm::OpCode::EndGoTo,
+            vm::OpCode::IncV,
+            vm::OpCode::GoToIfP,
+            vm::OpCode::JumpIfN,
+            vm::OpCode::Output(0)
+        ], 3, false);
+
+        let code = program_to_javascript_vm(&program);
+        assert!(code.starts_with(&generate_header(&program)));
+        assert!(code.starts_with(&format!("// Generated by genetic {}.\n", env!("CARGO_PKG_VERSION"))));
+        assert!(code.contains("\n//   instructions: 5\n"));
+        assert!(code.contains("\n//   data slots: 3 (banks: 1)\n"));
+        assert!(code.contains("\n//   control flow instructions: 2 active, 1 inactive\n"));
+    }
+
+    #[test]
+    fn index_mode() {
+        let mut program = vm::Program::new(&[vm::OpCode::Load], 2, false);
+        assert!(program_to_javascript_vm(&program).contains("        this.indexMode = \"ignore\";\n"));
+        program.set_index_mode(vm::IndexMode::Wrap);
+        assert!(program_to_javascript_vm(&program).contains("        this.indexMode = \"wrap\";\n"));
+        program.set_index_mode(vm::IndexMode::Clamp);
+        assert!(program_to_javascript_vm(&program).contains("        this.indexMode = \"clamp\";\n"));
+    }
+
+    #[test]
+    fn arithmetic_mode() {
+        let mut program = vm::Program::new(&[vm::OpCode::Mul], 2, false);
+        assert!(program_to_javascript_vm(&program).contains("        this.saturation = null;\n"));
+        program.set_arithmetic_mode(vm::ArithmeticMode::Saturating{ min: -1.0e+6, max: 1000.0 });
+        assert!(program_to_javascript_vm(&program).contains("        this.saturation = { min: -1000000.0, max: 1000.0 };\n"));
+        program.set_arithmetic_mode(vm::ArithmeticMode::Saturating{ min: f64::NEG_INFINITY, max: 0.5 });
+        assert!(program_to_javascript_vm(&program).contains("        this.saturation = { min: -Infinity, max: 0.5 };\n"));
+    }
+
+    #[test]
+    fn f32_arithmetic() {
+        let mut program = vm::Program::new(&[vm::OpCode::Input(0), vm::OpCode::Add, vm::OpCode::Sqrt], 2, false);
+        program.set_arithmetic_mode(vm::ArithmeticMode::Saturating{ min: -0.1, max: 1.0e10 });
+
+        let code = program_to_javascript_vm(&program);
+        assert!(code.contains("        this.saturation = { min: -0.1, max: 10000000000.0 };\n        this.f32Arithmetic = false;\n"));
+
+        let options = JsTranspileOptions{ f32_arithmetic: true, ..Default::default() };
+        let code = program_to_javascript_vm_with_options(&program, &options);
+        // bounds rounded to `f32`
+        assert!(code.contains("        this.saturation = { min: -0.10000000149011612, max: 10000000000.0 };\n        this.f32Arithmetic = true;\n"));
+        assert!(code.contains("        return this.f32Arithmetic ? Math.fround(value) : value;\n"));
+        assert!(code.contains("        this.regV = this.round(this.regV);\n"));
+        assert!(code.contains("this.regV = this.round(this.inputHandler(instr.i));"));
+        assert!(code.contains("if (idx >= 0) this.regV += bank[idx]; this.roundAndSaturate(); }"));
+        assert!(code.contains("(instr instanceof Sqrt) { if (this.regV >= 0.0) this.regV = Math.sqrt(this.regV); else this.regV = 0.0; this.roundAndSaturate(); }"));
+    }
+
+    #[test]
+    fn cmp_epsilon() {
+        let mut program = vm::Program::new(&[vm::OpCode::Cmp], 2, false);
+        assert!(program_to_javascript_vm(&program).contains("        this.cmpEpsilon = 0.0;\n"));
+        program.set_cmp_epsilon(0.125);
+        let code = program_to_javascript_vm(&program);
+        assert!(code.contains("        this.cmpEpsilon = 0.125;\n"));
+        assert!(code.contains("if (this.regV == dval || Math.abs(this.regV - dval) <= this.cmpEpsilon) this.regV = 0.0;"));
+    }
+
+    #[test]
+    fn opcode_classes() {
+        let program = vm::Program::new(&vm::OpCode::ALL, 2, false);
+        let code = program_to_javascript_vm(&program);
+
+        for opcode in vm::OpCode::ALL.iter() {
+            let class_name = opcode.js_class_name();
+            assert!(!class_name.is_empty());
+            assert!(code.contains(&format!("\nclass {} {{", class_name)));
+            assert!(code.contains(&format!("new {}", class_name)));
+            assert!(code.contains(&format!("(instr instanceof {})", class_name)));
+        }
+    }
+
+    #[test]
+    fn trigonometric() {
+        let program = vm::Program::new(&[vm::OpCode::Sin, vm::OpCode::Cos], 2, false);
+        let code = program_to_javascript_vm(&program);
+        assert!(code.contains("new Sin, new Cos,"));
+        assert!(code.contains("(instr instanceof Sin) { this.regV = Math.sin(this.regV);"));
+        assert!(code.contains("(instr instanceof Cos) { this.regV = Math.cos(this.regV);"));
+    }
+
+    #[test]
+    fn halt() {
+        let program = vm::Program::new(&[vm::OpCode::Output(0), vm::OpCode::Halt], 2, false);
+        let halt_check = concat!(
+            "            if (this.instructions[this.iptr] instanceof Halt) {\n",
+            "                this.halted = true;\n",
+            "                return;\n"
+        );
+
+        let code = program_to_javascript_vm(&program);
+        assert!(code.contains("new Output(0), new Halt,"));
+        assert!(code.contains("        this.halted = false;\n"));
+        assert_eq!(2, code.matches(halt_check).count()); // `run` and `runUntil`
+
+        let options = JsTranspileOptions{ end_condition: true, ..Default::default() };
+        let code = program_to_javascript_vm_with_options(&program, &options);
+        assert_eq!(1, code.matches(halt_check).count());
+        assert!(code.contains("            if (instr instanceof Halt) {\n                this.halted = true;\n                return false;\n"));
+    }
+
+    #[test]
+    fn source_comments() {
+        let program = vm::Program::new(&[vm::OpCode::SetI(3), vm::OpCode::EndGoTo, vm::OpCode::GoToIfP], 2, false);
+
+        let code = program_to_javascript_vm(&program);
+        assert!(!code.contains("// 0: "));
+
+        let options = JsTranspileOptions{ include_source_comments: true, ..Default::default() };
+        let code = program_to_javascript_vm_with_options(&program, &options);
+        assert!(code.contains("            new SetI(3), // 0: seti 3\n"));
+        assert!(code.contains("            new EndGoTo, // 1: endgoto\n"));
+        assert!(code.contains("            new GoToIfP, // 2: gotoifp\n"));
+    }
+
+    #[test]
+    fn minify() {
+        let program = vm::Program::new(&[vm::OpCode::SetI(3), vm::OpCode::EndGoTo, vm::OpCode::GoToIfP], 2, false);
+        let options = JsTranspileOptions{ minify: true, ..Default::default() };
+        let code = program_to_javascript_vm_with_options(&program, &options);
+
+        assert!(code.contains("\nthis.instructions = [\nnew SetI(3), new EndGoTo, new GoToIfP,\n];\n"));
+        assert!(code.lines().all(|line| !line.is_empty() && line.trim() == line && !line.starts_with("//")));
+    }
+
+    #[test]
+    fn end_condition() {
+        let program = vm::Program::new(&[vm::OpCode::Output(0)], 0, false);
+
+        let code = program_to_javascript_vm(&program);
+        assert!(code.contains("    constructor(inputHandler, outputHandler) {\n"));
+        assert!(code.contains("    run(num_instructions) {\n"));
+        assert!(!code.contains("endCondition("));
+
+        let options = JsTranspileOptions{ end_condition: true, ..Default::default() };
+        let code = program_to_javascript_vm_with_options(&program, &options);
+        assert!(code.contains("    constructor(inputHandler, outputHandler, endCondition) {\n"));
+        assert!(code.contains("        this.endCondition = endCondition;\n"));
+        assert!(code.contains("    run(numInstructions, checkEndCondition) {\n"));
+        assert!(code.contains(
+            "            icounter += 1;\n            \
+             if (this.iptr >= this.instructions.length) {\n                \
+             this.iptr = 0;\n            \
+             }\n            \
+             if (checkEndCondition && instr instanceof Output && this.endCondition(icounter)) {\n                \
+             return true;\n            \
+             }\n"
+        ));
+    }
+}
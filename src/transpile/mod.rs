@@ -10,4 +10,55 @@
 //   Module: transpiling VM programs to other languages.
 //
 
-pub mod javascript_vm;
\ No newline at end of file
+pub mod c;
+pub mod csharp;
+pub mod dot;
+pub mod javascript_vm;
+pub mod rust;
+
+use vm;
+
+/// Returns true if the program uses data banks other than 0 (i.e. contains `SetBank`).
+pub(crate) fn uses_data_banks(program: &vm::Program) -> bool {
+    program.get_instr().iter().any(|opcode| matches!(opcode, vm::OpCode::SetBank(_)))
+}
+
+/// Returns true if `ArithmeticMode::Saturating` is applied to `regV` after executing `opcode` (like in the Rust VM).
+pub(crate) fn is_saturated(opcode: &vm::OpCode) -> bool {
+    matches!(opcode,
+        vm::OpCode::IncV | vm::OpCode::DecV | vm::OpCode::Add | vm::OpCode::Sub | vm::OpCode::Mul | vm::OpCode::Div |
+        vm::OpCode::Abs | vm::OpCode::Neg | vm::OpCode::Sqrt | vm::OpCode::Pow | vm::OpCode::Log | vm::OpCode::Sin |
+        vm::OpCode::Cos
+    )
+}
+
+///
+/// Generates C-like (C, C#) statements setting `dataIndex` to the element of `data` pointed to by `regBank`
+/// and `regI` (-1 if none; see `vm::IndexMode`). Each statement is prefixed with `indent`.
+///
+pub(crate) fn generate_c_like_data_index(program: &vm::Program, indent: &str) -> String {
+    let num_slots = program.get_num_data_slots();
+    let num_banks = program.get_num_data_banks();
+    if num_slots == 0 || num_banks == 0 {
+        return format!("{}dataIndex = -1;\n", indent);
+    }
+
+    let mut result = generate_c_like_index("dataIndex", "regI", num_slots, program.get_index_mode(), indent);
+    if uses_data_banks(program) {
+        result += &generate_c_like_index("bankIndex", "regBank", num_banks, program.get_index_mode(), indent);
+        result += &format!(
+            "{}if (dataIndex >= 0) {{ dataIndex = (bankIndex >= 0) ? bankIndex * {} + dataIndex : -1; }}\n", indent, num_slots
+        );
+    }
+
+    result
+}
+
+/// Generates the C-like statement setting `var` to the index (less than `n`) pointed to by `reg` (-1 if none).
+fn generate_c_like_index(var: &str, reg: &str, n: usize, index_mode: vm::IndexMode, indent: &str) -> String {
+    indent.to_string() + &match index_mode {
+        vm::IndexMode::Ignore => format!("{0} = ({1} >= 0 && {1} < {2}) ? {1} : -1;\n", var, reg, n),
+        vm::IndexMode::Wrap => format!("{0} = {1} % {2}; if ({0} < 0) {{ {0} += {2}; }}\n", var, reg, n),
+        vm::IndexMode::Clamp => format!("{0} = ({1} < 0) ? 0 : (({1} >= {2}) ? {3} : {1});\n", var, reg, n, n - 1)
+    }
+}
@@ -0,0 +1,359 @@
+//
+// genetic - genetic programming experiments
+// Copyright (c) 2019 Filip Szczerek <ga.software@yahoo.com>
+//
+// This project is licensed under the terms of the MIT license
+// (see the LICENSE file for details).
+//
+//
+// File description:
+//   Module: transpiling to a standalone Rust function.
+//
+
+use transpile::{is_saturated, uses_data_banks};
+use vm;
+
+///
+/// Creates Rust source code of a function running `program`.
+///
+/// The output defines a state structure (`<FnName>State`, with `data`, `reg_i`, `reg_v`, `iptr`)
+/// and a function `fn_name(state, num_instructions, input, output)` which executes the specified
+/// number of instructions (restarting from the beginning after the last instruction, like a looped
+/// `VirtualMachine::run`); `input` and `output` are closures handling `Input` and `Output` instructions.
+/// The generated code does not depend on any crate. If the program contains `RandV`, the state structure
+/// also contains `rng`, the state of a xorshift32 generator (which produces different values than the Rust VM).
+/// With `vm::ArithmeticMode::Saturating`, results of arithmetic instructions are clamped (by a local closure `saturate`).
+/// `Cmp` uses the program's tolerance (`vm::Program::set_cmp_epsilon`).
+/// Executing `Halt` returns immediately (and so will subsequent calls).
+///
+pub fn program_to_rust_fn(program: &vm::Program, fn_name: &str) -> String {
+    let state_name = to_camel_case(fn_name) + "State";
+    let num_instr = program.get_instr().len();
+    let num_data_slots = program.get_num_data_slots();
+    let uses_rng = program.get_instr().contains(&vm::OpCode::RandV);
+    let uses_banks = uses_data_banks(program);
+
+    let mut result = format!(
+        "// Generated from a VM program ({} instructions, {} data slots).\n\n", num_instr, num_data_slots
+    );
+
+    result += &format!(r#"/// State of the virtual machine running `{fn_name}`.
+#[derive(Clone, Debug)]
+pub struct {state} {{
+    pub data: [f32; {slots}],
+    pub reg_i: i32,{bank_field}
+    pub reg_v: f32,
+    pub iptr: usize{rng_field}
+}}
+
+impl Default for {state} {{
+    fn default() -> {state} {{
+        {state}{{ data: [0.0; {slots}], reg_i: 0,{bank_init} reg_v: 0.0, iptr: 0{rng_init} }}
+    }}
+}}
+
+/// Executes `num_instructions` instructions. Subsequent calls resume execution where it stopped.
+#[allow(unused_mut, unused_variables, clippy::all)]
+pub fn {fn_name}<I: FnMut(i32) -> f32, O: FnMut(i32, f32)>(
+    state: &mut {state},
+    num_instructions: usize,
+    mut input: I,
+    mut output: O
+) {{
+"#,
+        fn_name = fn_name,
+        state = state_name,
+        slots = num_data_slots * program.get_num_data_banks(),
+        bank_field = if uses_banks { "\n    pub reg_bank: i32," } else { "" },
+        bank_init = if uses_banks { " reg_bank: 0," } else { "" },
+        rng_field = if uses_rng { ",\n    /// State of the generator used by `RandV` (must be non-zero).\n    pub rng: u32" } else { "" },
+        rng_init = if uses_rng { ", rng: 1" } else { "" }
+    );
+
+    if num_instr > 0 {
+        if let vm::ArithmeticMode::Saturating{ min, max } = program.get_arithmetic_mode() {
+            // NaN becomes 0, like in the Rust VM
+            result += &format!(
+                "    let saturate = |value: f32| if value.is_nan() {{ 0.0 }} else if value < {0} {{ {0} }} \
+                 else if value > {1} {{ {1} }} else {{ value }};\n",
+                rust_float(min), rust_float(max)
+            );
+        }
+        result += "    for _ in 0..num_instructions {\n";
+        result += &generate_data_index(program);
+        result += "        let mut next = state.iptr + 1;\n";
+        result += "        match state.iptr {\n";
+        result += &generate_instructions(program);
+        result += "            _ => unreachable!()\n";
+        result += "        }\n";
+        result += &format!("        state.iptr = if next >= {} {{ 0 }} else {{ next }};\n", num_instr);
+        result += "    }\n";
+    }
+    result += "}\n";
+
+    result
+}
+
+/// Returns Rust literal of `value` (as `f32`).
+fn rust_float(value: f64) -> String {
+    let value = value as f32;
+    if value.is_nan() {
+        "f32::NAN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "f32::INFINITY".to_string() } else { "f32::NEG_INFINITY".to_string() }
+    } else {
+        format!("{:?}f32", value)
+    }
+}
+
+/// Converts e.g. `seeker_program` to `SeekerProgram`.
+fn to_camel_case(name: &str) -> String {
+    name.split('_').map(|word| {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().collect::<String>() + chars.as_str(),
+            None => String::new()
+        }
+    }).collect()
+}
+
+///
+/// Generates the definition of `data_index`: the element of `state.data` pointed to by `reg_bank` and `reg_i`,
+/// if any (see `vm::IndexMode`).
+///
+fn generate_data_index(program: &vm::Program) -> String {
+    let num_slots = program.get_num_data_slots();
+    let num_banks = program.get_num_data_banks();
+    if num_slots == 0 || num_banks == 0 {
+        return "        let data_index: Option<usize> = None;\n".to_string();
+    }
+
+    let mut result = generate_index("data_index", "state.reg_i", num_slots, program.get_index_mode());
+    if uses_data_banks(program) {
+        result += &generate_index("bank_index", "state.reg_bank", num_banks, program.get_index_mode());
+        result += &format!(
+            "        let data_index = match (bank_index, data_index) {{ (Some(b), Some(i)) => Some(b * {} + i), _ => None }};\n",
+            num_slots
+        );
+    }
+
+    result
+}
+
+/// Generates the definition of `var`: the index (less than `n`) pointed to by `reg`, if any.
+fn generate_index(var: &str, reg: &str, n: usize, index_mode: vm::IndexMode) -> String {
+    match index_mode {
+        vm::IndexMode::Ignore =>
+            format!("        let {0} = if {1} >= 0 && ({1} as usize) < {2} {{ Some({1} as usize) }} else {{ None }};\n", var, reg, n),
+        vm::IndexMode::Wrap =>
+            format!("        let {} = Some({}.rem_euclid({}) as usize);\n", var, reg, n),
+        vm::IndexMode::Clamp =>
+            format!("        let {0} = Some(if {1} < 0 {{ 0 }} else {{ std::cmp::min({1} as usize, {2}) }});\n", var, reg, n - 1)
+    }
+}
+
+/// Generates the `match` arms executing each instruction.
+fn generate_instructions(program: &vm::Program) -> String {
+    const DATA: &str = "state.data[i]";
+
+    let cmp_equal = if program.get_cmp_epsilon() == 0.0 {
+        "state.reg_v == dval".to_string()
+    } else {
+        format!("state.reg_v == dval || (state.reg_v as f64 - dval as f64).abs() <= {:?}", program.get_cmp_epsilon())
+    };
+    let saturating = program.get_arithmetic_mode() != vm::ArithmeticMode::Ieee;
+    let jump_table = program.get_jump_table();
+    let mut result = String::new();
+
+    for (i, instr) in program.get_instr().iter().enumerate() {
+        let mut code = match instr {
+            vm::OpCode::SetI(n)   => format!("state.reg_i = {};", n),
+            vm::OpCode::SetBank(n) => format!("state.reg_bank = {};", n),
+            vm::OpCode::Input(n)  => format!("state.reg_v = input({});", n),
+            vm::OpCode::Output(n) => format!("output({}, state.reg_v);", n),
+            vm::OpCode::ItoV      => "state.reg_v = state.reg_i as f32;".to_string(),
+            vm::OpCode::VtoI      => "state.reg_i = state.reg_v as i32;".to_string(),
+            vm::OpCode::IncV      => "state.reg_v += 1.0;".to_string(),
+            vm::OpCode::DecV      => "state.reg_v -= 1.0;".to_string(),
+            vm::OpCode::IncI      => "state.reg_i = state.reg_i.wrapping_add(1);".to_string(),
+            vm::OpCode::DecI      => "state.reg_i = state.reg_i.wrapping_sub(1);".to_string(),
+            vm::OpCode::Load      => format!("if let Some(i) = data_index {{ state.reg_v = {}; }}", DATA),
+            vm::OpCode::Store     => format!("if let Some(i) = data_index {{ {} = state.reg_v; }}", DATA),
+            vm::OpCode::Swap      => format!("if let Some(i) = data_index {{ std::mem::swap(&mut {}, &mut state.reg_v); }}", DATA),
+            vm::OpCode::GoToIfP   => match jump_table[i] {
+                Some(target) => format!("if state.reg_v >= 0.0 {{ next = {}; }}", target),
+                None => "/* inactive */".to_string()
+            },
+            vm::OpCode::JumpIfN   => match jump_table[i] {
+                Some(target) => format!("if state.reg_v < 0.0 {{ next = {}; }}", target),
+                None => "/* inactive */".to_string()
+            },
+            vm::OpCode::EndGoTo |
+            vm::OpCode::EndJump |
+            vm::OpCode::Nop       => "".to_string(),
+            vm::OpCode::IfP       => "if state.reg_v < 0.0 { next += 1; }".to_string(),
+            vm::OpCode::IfN       => "if state.reg_v >= 0.0 { next += 1; }".to_string(),
+            vm::OpCode::Cmp       => format!(
+                "if let Some(i) = data_index {{ let dval = {}; \
+                 if {} {{ state.reg_v = 0.0; }} \
+                 else if state.reg_v < dval {{ state.reg_v = -1.0; }} \
+                 else if state.reg_v > dval {{ state.reg_v = 1.0; }} }}", DATA, cmp_equal),
+            vm::OpCode::Add       => format!("if let Some(i) = data_index {{ state.reg_v += {}; }}", DATA),
+            vm::OpCode::Sub       => format!("if let Some(i) = data_index {{ state.reg_v -= {}; }}", DATA),
+            vm::OpCode::Mul       => format!("if let Some(i) = data_index {{ state.reg_v *= {}; }}", DATA),
+            vm::OpCode::Div       => format!("if let Some(i) = data_index {{ if {0} != 0.0 {{ state.reg_v /= {0}; }} }}", DATA),
+            vm::OpCode::Abs       => "state.reg_v = state.reg_v.abs();".to_string(),
+            vm::OpCode::Neg       => "state.reg_v = -state.reg_v;".to_string(),
+            vm::OpCode::Sqrt      => "state.reg_v = if state.reg_v >= 0.0 { state.reg_v.sqrt() } else { 0.0 };".to_string(),
+            vm::OpCode::Pow       => format!(
+                "if let Some(i) = data_index {{ let dval = {}; let p = state.reg_v.powf(dval); \
+                 state.reg_v = if state.reg_v.is_nan() || dval.is_nan() || p.is_nan() {{ 0.0 }} else {{ p }}; }}", DATA),
+            vm::OpCode::Log       => "state.reg_v = if state.reg_v > 0.0 { state.reg_v.ln() } else { 0.0 };".to_string(),
+            vm::OpCode::Sin       => "state.reg_v = state.reg_v.sin();".to_string(),
+            vm::OpCode::Cos       => "state.reg_v = state.reg_v.cos();".to_string(),
+            vm::OpCode::RandV     => "state.rng ^= state.rng << 13; state.rng ^= state.rng >> 17; state.rng ^= state.rng << 5; \
+                                      state.reg_v = (state.rng >> 8) as f32 / 16777216.0;".to_string(),
+            // `state.iptr` stays at `Halt`
+            vm::OpCode::Halt      => "return;".to_string()
+        };
+        if saturating && is_saturated(instr) {
+            code += " state.reg_v = saturate(state.reg_v);";
+        }
+        if code.is_empty() {
+            result += &format!("            {} => {{}}\n", i);
+        } else {
+            result += &format!("            {} => {{ {} }}\n", i, code);
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use transpile::rust::program_to_rust_fn;
+    use vm;
+    use vm::OpCode;
+
+    // Output of `program_to_rust_fn(&fixture_program(), "fixture")`; compiled as part of the tests.
+    include!("rust_fixture.rs");
+
+    fn fixture_program() -> vm::Program {
+        vm::Program::new(&[
+            OpCode::Input(0),
+            OpCode::SetI(1),
+            OpCode::Store,
+            OpCode::EndGoTo,
+                OpCode::DecV,
+                OpCode::Output(1),
+                OpCode::IfN,
+                    OpCode::Div,
+                OpCode::Sqrt,
+                OpCode::JumpIfN,
+                    OpCode::Swap,
+                    OpCode::Cmp,
+                OpCode::EndJump,
+            OpCode::GoToIfP,
+            OpCode::EndJump,
+            OpCode::IncI,
+            OpCode::Mul,
+            OpCode::GoToIfP
+        ], 2, false)
+    }
+
+    #[test]
+    fn matches_fixture() {
+        assert_eq!(include_str!("rust_fixture.rs"), program_to_rust_fn(&fixture_program(), "fixture"));
+    }
+
+    #[test]
+    fn empty_program() {
+        let code = program_to_rust_fn(&vm::Program::new(&[], 0, false), "empty");
+        assert!(code.contains("pub struct EmptyState"));
+        assert!(code.contains("pub fn empty<"));
+        assert!(!code.contains("match"));
+        assert!(!code.contains("rng"));
+    }
+
+    #[test]
+    fn random_value() {
+        let code = program_to_rust_fn(&vm::Program::new(&[OpCode::RandV], 0, false), "random");
+        assert!(code.contains("    pub iptr: usize,\n    /// State of the generator used by `RandV` (must be non-zero).\n    pub rng: u32\n}"));
+        assert!(code.contains("RandomState{ data: [0.0; 0], reg_i: 0, reg_v: 0.0, iptr: 0, rng: 1 }"));
+        assert!(code.contains("state.reg_v = (state.rng >> 8) as f32 / 16777216.0;"));
+    }
+
+    #[test]
+    fn saturating_arithmetic() {
+        let mut program = vm::Program::new(&[OpCode::Mul, OpCode::Load, OpCode::Sqrt], 1, false);
+        program.set_arithmetic_mode(vm::ArithmeticMode::Saturating{ min: f64::NEG_INFINITY, max: 1000.0 });
+        let code = program_to_rust_fn(&program, "sat");
+        assert!(code.contains("let saturate = |value: f32| if value.is_nan() { 0.0 } else if value < f32::NEG_INFINITY { f32::NEG_INFINITY } \
+                               else if value > 1000.0f32 { 1000.0f32 } else { value };"));
+        assert!(code.contains("0 => { if let Some(i) = data_index { state.reg_v *= state.data[i]; } state.reg_v = saturate(state.reg_v); }"));
+        assert!(code.contains("1 => { if let Some(i) = data_index { state.reg_v = state.data[i]; } }"));
+        assert!(code.contains("state.reg_v = saturate(state.reg_v); }\n            _ => unreachable!()"));
+
+        program.set_arithmetic_mode(vm::ArithmeticMode::Ieee);
+        assert!(!program_to_rust_fn(&program, "sat").contains("saturate"));
+    }
+
+    #[test]
+    fn cmp_epsilon() {
+        let mut program = vm::Program::new(&[OpCode::Cmp], 1, false);
+        assert!(program_to_rust_fn(&program, "f").contains("if state.reg_v == dval { state.reg_v = 0.0; }"));
+        program.set_cmp_epsilon(0.25);
+        assert!(program_to_rust_fn(&program, "f").contains(
+            "if state.reg_v == dval || (state.reg_v as f64 - dval as f64).abs() <= 0.25 { state.reg_v = 0.0; }"
+        ));
+    }
+
+    #[test]
+    fn halt() {
+        let code = program_to_rust_fn(&vm::Program::new(&[OpCode::IncV, OpCode::Halt], 0, false), "halting");
+        assert!(code.contains("            1 => { return; }\n"));
+    }
+
+    #[test]
+    fn data_banks() {
+        let mut program = vm::Program::new(&[OpCode::SetBank(1), OpCode::Store], 3, false);
+        program.set_num_data_banks(2);
+        let code = program_to_rust_fn(&program, "banks");
+        assert!(code.contains("    pub data: [f32; 6],\n    pub reg_i: i32,\n    pub reg_bank: i32,\n"));
+        assert!(code.contains("BanksState{ data: [0.0; 6], reg_i: 0, reg_bank: 0, reg_v: 0.0, iptr: 0 }"));
+        assert!(code.contains("let bank_index = if state.reg_bank >= 0 && (state.reg_bank as usize) < 2 { Some(state.reg_bank as usize) } else { None };"));
+        assert!(code.contains("let data_index = match (bank_index, data_index) { (Some(b), Some(i)) => Some(b * 3 + i), _ => None };"));
+        assert!(code.contains("0 => { state.reg_bank = 1; }"));
+    }
+
+    #[test]
+    fn same_behavior_as_vm() {
+        struct IoHandler { outputs: Vec<(i32, f32)> }
+        impl vm::InputOutputHandler for IoHandler {
+            fn input(&mut self, _: i32) -> vm::RegValue { 7.5 }
+            fn output(&mut self, output_num: i32, output_val: vm::RegValue) { self.outputs.push((output_num, output_val)); }
+            fn check_end_condition(&self, _: usize) -> bool { false }
+        }
+
+        const NUM_INSTRUCTIONS: usize = 300;
+
+        let program = fixture_program();
+        let mut io_handler = IoHandler{ outputs: vec![] };
+        let vm_state = {
+            let mut vm = vm::VirtualMachine::new(&program, Some(&mut io_handler));
+            vm.run(Some(NUM_INSTRUCTIONS), true, false);
+            vm.get_state().clone()
+        };
+
+        let mut state = FixtureState::default();
+        let mut outputs = vec![];
+        fixture(&mut state, NUM_INSTRUCTIONS, |_| 7.5, |n, v| outputs.push((n, v)));
+
+        assert!(vm_state.data == state.data);
+        assert_eq!(vm_state.reg_i, state.reg_i);
+        assert_eq!(vm_state.reg_v, state.reg_v);
+        assert_eq!(vm_state.iptr, state.iptr);
+        assert!(!outputs.is_empty());
+        assert_eq!(io_handler.outputs, outputs);
+    }
+}
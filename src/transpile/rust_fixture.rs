@@ -0,0 +1,52 @@
+// Generated from a VM program (18 instructions, 2 data slots).
+
+/// State of the virtual machine running `fixture`.
+#[derive(Clone, Debug)]
+pub struct FixtureState {
+    pub data: [f32; 2],
+    pub reg_i: i32,
+    pub reg_v: f32,
+    pub iptr: usize
+}
+
+impl Default for FixtureState {
+    fn default() -> FixtureState {
+        FixtureState{ data: [0.0; 2], reg_i: 0, reg_v: 0.0, iptr: 0 }
+    }
+}
+
+/// Executes `num_instructions` instructions. Subsequent calls resume execution where it stopped.
+#[allow(unused_mut, unused_variables, clippy::all)]
+pub fn fixture<I: FnMut(i32) -> f32, O: FnMut(i32, f32)>(
+    state: &mut FixtureState,
+    num_instructions: usize,
+    mut input: I,
+    mut output: O
+) {
+    for _ in 0..num_instructions {
+        let data_index = if state.reg_i >= 0 && (state.reg_i as usize) < 2 { Some(state.reg_i as usize) } else { None };
+        let mut next = state.iptr + 1;
+        match state.iptr {
+            0 => { state.reg_v = input(0); }
+            1 => { state.reg_i = 1; }
+            2 => { if let Some(i) = data_index { state.data[i] = state.reg_v; } }
+            3 => {}
+            4 => { state.reg_v -= 1.0; }
+            5 => { output(1, state.reg_v); }
+            6 => { if state.reg_v >= 0.0 { next += 1; } }
+            7 => { if let Some(i) = data_index { if state.data[i] != 0.0 { state.reg_v /= state.data[i]; } } }
+            8 => { state.reg_v = if state.reg_v >= 0.0 { state.reg_v.sqrt() } else { 0.0 }; }
+            9 => { if state.reg_v < 0.0 { next = 12; } }
+            10 => { if let Some(i) = data_index { std::mem::swap(&mut state.data[i], &mut state.reg_v); } }
+            11 => { if let Some(i) = data_index { let dval = state.data[i]; if state.reg_v == dval { state.reg_v = 0.0; } else if state.reg_v < dval { state.reg_v = -1.0; } else if state.reg_v > dval { state.reg_v = 1.0; } } }
+            12 => {}
+            13 => { if state.reg_v >= 0.0 { next = 3; } }
+            14 => {}
+            15 => { state.reg_i = state.reg_i.wrapping_add(1); }
+            16 => { if let Some(i) = data_index { state.reg_v *= state.data[i]; } }
+            17 => { /* inactive */ }
+            _ => unreachable!()
+        }
+        state.iptr = if next >= 18 { 0 } else { next };
+    }
+}
@@ -11,61 +11,327 @@
 //
 
 use rand::prelude::*;
+use rayon::prelude::*;
+use serialization;
+use serialization::ParseError;
+use std::convert::TryFrom;
 use vm;
 
+pub mod novelty;
+
 /// Represents fitness of a genetic program; lower values are better.
 pub type Fitness = f64;
 
 pub const WORST_FITNESS: Fitness = 99.0e+19;
 
-pub struct EvaluatedProgram {
+///
+/// Compares fitness values; NaN is considered worse than any other value (including `WORST_FITNESS`
+/// and infinity) and equal to itself, so that a faulty fitness function cannot break sorting.
+///
+pub fn compare_fitness(f1: Fitness, f2: Fitness) -> std::cmp::Ordering {
+    match (f1.is_nan(), f2.is_nan()) {
+        (true, true) => std::cmp::Ordering::Equal,
+        (true, false) => std::cmp::Ordering::Greater,
+        (false, true) => std::cmp::Ordering::Less,
+        (false, false) => f1.partial_cmp(&f2).unwrap()
+    }
+}
+
+pub struct EvaluatedProgram<Meta = ()> {
     pub fitness: Fitness,
-    pub prog: vm::Program
+    pub prog: vm::Program,
+    /// Arbitrary per-program data (e.g. per-test case results, novelty score); not used for sorting.
+    pub meta: Meta
 }
 
+///
 /// List of evaluated programs sorted (ascending) by fitness.
-pub struct SortedEvaluatedPrograms {
-    programs: Vec<EvaluatedProgram>
+///
+/// The order is established on creation and kept by all methods; fitness values can only be changed
+/// via `map_fitness`, which re-sorts the list.
+///
+pub struct SortedEvaluatedPrograms<Meta = ()> {
+    programs: Vec<EvaluatedProgram<Meta>>
 }
 
 impl SortedEvaluatedPrograms {
     /// Creates a list containing `programs` and `fitness` sorted (ascending) by fitness.
     pub fn new(programs: Vec<vm::Program>, fitness: Vec<Fitness>) -> SortedEvaluatedPrograms {
+        let num_programs = programs.len();
+        SortedEvaluatedPrograms::with_metadata(programs, fitness, vec![(); num_programs])
+    }
+}
+
+impl<Meta> SortedEvaluatedPrograms<Meta> {
+    ///
+    /// Creates a list containing `programs`, `fitness` and `metadata` sorted (ascending) by fitness.
+    ///
+    /// Each element of `metadata` stays attached to the same-index element of `programs`.
+    ///
+    pub fn with_metadata(
+        programs: Vec<vm::Program>,
+        fitness: Vec<Fitness>,
+        metadata: Vec<Meta>
+    ) -> SortedEvaluatedPrograms<Meta> {
+        SortedEvaluatedPrograms::with_length_penalty(programs, fitness, metadata, 0.0)
+    }
+
+    ///
+    /// Creates a list like `with_metadata`, but sorted (ascending) by `fitness + length_penalty * program_length`
+    /// (parsimony pressure); e.g. of programs with equal fitness, the shortest come first.
+    ///
+    /// The `fitness` of each element stays unchanged, so the first element need not have the best fitness.
+    /// `SelectionStrategy::TopFraction` and `Tournament` select parents by position, i.e. including the penalty;
+    /// `Roulette` uses only the `fitness` values. Programs with `WORST_FITNESS` remain at the end if `length_penalty * max_program_length` is
+    /// negligible compared to `WORST_FITNESS`.
+    ///
+    pub fn with_length_penalty(
+        programs: Vec<vm::Program>,
+        fitness: Vec<Fitness>,
+        metadata: Vec<Meta>,
+        length_penalty: f64
+    ) -> SortedEvaluatedPrograms<Meta> {
         assert!(programs.len() == fitness.len());
-        let mut sorted_programs: Vec<EvaluatedProgram> = vec![];
-        for (prog, fitness) in programs.into_iter().zip(fitness.into_iter()) {
-            sorted_programs.push(EvaluatedProgram{ fitness, prog });
+        assert!(programs.len() == metadata.len());
+        let mut sorted_programs: Vec<EvaluatedProgram<Meta>> = vec![];
+        for ((prog, fitness), meta) in programs.into_iter().zip(fitness).zip(metadata) {
+            sorted_programs.push(EvaluatedProgram{ fitness, prog, meta });
         }
-        sorted_programs.sort();
+        let sort_key = |p: &EvaluatedProgram<Meta>| p.fitness + length_penalty * p.prog.get_instr().len() as Fitness;
+        sorted_programs.sort_by(|p1, p2| compare_fitness(sort_key(p1), sort_key(p2)));
 
         SortedEvaluatedPrograms{ programs: sorted_programs }
     }
 
     pub fn len(&self) -> usize { self.programs.len() }
 
-    pub fn get_programs(&self) -> &[EvaluatedProgram] { &self.programs }
+    pub fn is_empty(&self) -> bool { self.programs.is_empty() }
+
+    pub fn get_programs(&self) -> &[EvaluatedProgram<Meta>] { &self.programs }
+
+    /// Returns an iterator over the programs in sorted order.
+    pub fn iter(&self) -> std::slice::Iter<'_, EvaluatedProgram<Meta>> { self.programs.iter() }
+
+    /// Returns the first program in sorted order (e.g. not necessarily of the best fitness if sorted with a length penalty).
+    pub fn best(&self) -> Option<&EvaluatedProgram<Meta>> { self.programs.first() }
+
+    /// Returns the last program in sorted order.
+    pub fn worst(&self) -> Option<&EvaluatedProgram<Meta>> { self.programs.last() }
+
+    /// Returns the program at position `n` (0-based) in sorted order.
+    pub fn nth_best(&self, n: usize) -> Option<&EvaluatedProgram<Meta>> { self.programs.get(n) }
+
+    ///
+    /// Replaces the fitness of each program with `f(program)` (e.g. for fitness sharing) and re-sorts the list
+    /// (ascending) by the new fitness; programs of equal new fitness keep their relative order.
+    ///
+    /// Any length penalty of `with_length_penalty` is not applied; it can be included in `f`.
+    ///
+    pub fn map_fitness<F: Fn(&EvaluatedProgram<Meta>) -> Fitness>(&mut self, f: F) {
+        for program in self.programs.iter_mut() {
+            program.fitness = f(program);
+        }
+        self.programs.sort_by(|p1, p2| compare_fitness(p1.fitness, p2.fitness));
+    }
+
+    ///
+    /// Removes programs whose instruction list is identical to that of a preceding (better) program.
+    ///
+    /// Returns the number of removed programs.
+    ///
+    pub fn dedupe(&mut self) -> usize {
+        let num_programs = self.programs.len();
+        let mut seen = std::collections::HashSet::new();
+        self.programs.retain(|p| seen.insert(p.prog.get_instr().to_vec()));
+        num_programs - self.programs.len()
+    }
+}
+
+impl<Meta> std::cmp::PartialEq for EvaluatedProgram<Meta> {
+    fn eq(&self, other: &EvaluatedProgram<Meta>) -> bool {
+        compare_fitness(self.fitness, other.fitness) == std::cmp::Ordering::Equal
+    }
+}
+
+impl<Meta> Eq for EvaluatedProgram<Meta> { }
+
+impl<Meta> std::cmp::PartialOrd for EvaluatedProgram<Meta> {
+    fn partial_cmp(&self, other: &EvaluatedProgram<Meta>) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<Meta> Ord for EvaluatedProgram<Meta> {
+    fn cmp(&self, other: &EvaluatedProgram<Meta>) -> std::cmp::Ordering {
+        compare_fitness(self.fitness, other.fitness)
+    }
+}
+
+///
+/// The best programs (by fitness) seen across generations.
+///
+/// Programs whose instruction list is identical to that of an already held program are not added again.
+///
+pub struct HallOfFame<Meta = ()> {
+    capacity: usize,
+    /// Sorted (ascending) by fitness.
+    programs: Vec<EvaluatedProgram<Meta>>
+}
+
+impl<Meta: Clone> HallOfFame<Meta> {
+    /// Creates an empty hall of fame holding at most `capacity` (> 0) programs.
+    pub fn new(capacity: usize) -> HallOfFame<Meta> {
+        assert!(capacity > 0);
+        HallOfFame{ capacity, programs: vec![] }
+    }
+
+    ///
+    /// Adds copies of those of `programs` which are better than the held ones.
+    ///
+    /// Of programs with equal fitness, those held earlier are kept.
+    ///
+    pub fn consider(&mut self, programs: &SortedEvaluatedPrograms<Meta>) {
+        for p in programs.get_programs() {
+            if self.programs.len() == self.capacity &&
+               compare_fitness(p.fitness, self.programs.last().unwrap().fitness) != std::cmp::Ordering::Less {
+                continue;
+            }
+            if self.programs.iter().any(|held| held.prog.get_instr() == p.prog.get_instr()) {
+                continue;
+            }
+            let pos = self.programs.iter().position(|held| compare_fitness(p.fitness, held.fitness) == std::cmp::Ordering::Less)
+                .unwrap_or(self.programs.len());
+            self.programs.insert(pos, EvaluatedProgram{ fitness: p.fitness, prog: p.prog.clone(), meta: p.meta.clone() });
+            self.programs.truncate(self.capacity);
+        }
+    }
+
+    /// Returns the best program seen. Panics if no programs have been considered.
+    pub fn best(&self) -> &EvaluatedProgram<Meta> {
+        self.programs.first().expect("hall of fame is empty")
+    }
+
+    /// Returns the held programs, sorted (ascending) by fitness.
+    pub fn get_programs(&self) -> &[EvaluatedProgram<Meta>] { &self.programs }
+}
+
+/// Result of evaluating a program.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EvaluationOutcome {
+    pub fitness: Fitness,
+    /// True if the program solved the problem (e.g. passed all test cases).
+    pub solved: bool
+}
+
+///
+/// Evaluates `programs` in parallel (using `RAYON_NUM_THREADS` CPU cores).
+///
+/// `eval` receives `fitness_cap`, so that it can abort evaluation of a program once its fitness exceeds the cap
+/// (see `evaluate_test_cases`). Fitness above `fitness_cap` is reported as `fitness_cap`.
+///
+/// Returns list of evaluated programs (sorted by fitness) and a flag indicating if any program solved the problem.
+///
+pub fn evaluate_population<F>(programs: Vec<vm::Program>, fitness_cap: Option<Fitness>, eval: F) -> (SortedEvaluatedPrograms, bool)
+where F: Fn(&vm::Program, Option<Fitness>) -> EvaluationOutcome + Sync {
+    let outcomes: Vec<EvaluationOutcome> = programs.par_iter().map(|program| eval(program, fitness_cap)).collect();
+    let any_solved = outcomes.iter().any(|outcome| outcome.solved);
+    let fitness = outcomes.into_iter().map(|outcome| match fitness_cap {
+        Some(cap) if outcome.fitness > cap => cap,
+        _ => outcome.fitness
+    }).collect();
+
+    (SortedEvaluatedPrograms::new(programs, fitness), any_solved)
 }
 
-impl std::cmp::PartialEq for EvaluatedProgram {
-    fn eq(&self, other: &EvaluatedProgram) -> bool {
-        self.fitness == other.fitness
+///
+/// Evaluates a program for `test_cases` (in order) by summing the fitness of each case; the program has solved
+/// the problem if it solved all cases.
+///
+/// If the accumulated fitness exceeds `fitness_cap`, the remaining cases are skipped and the result is `fitness_cap`
+/// (not solved), which saves time evaluating obviously bad programs.
+///
+pub fn evaluate_test_cases<T, F>(test_cases: &[T], fitness_cap: Option<Fitness>, mut eval: F) -> EvaluationOutcome
+where F: FnMut(&T) -> EvaluationOutcome {
+    let mut outcome = EvaluationOutcome{ fitness: 0.0, solved: true };
+    for test_case in test_cases.iter() {
+        let tcase_outcome = eval(test_case);
+        outcome.fitness += tcase_outcome.fitness;
+        outcome.solved = outcome.solved && tcase_outcome.solved;
+
+        if let Some(cap) = fitness_cap {
+            if outcome.fitness > cap {
+                return EvaluationOutcome{ fitness: cap, solved: false };
+            }
+        }
     }
+
+    outcome
+}
+
+///
+/// Fitness combined from several named objectives (e.g. distance, path length, instruction count)
+/// as a weighted sum. `breakdown` can be used for logging the contribution of each objective.
+///
+#[derive(Clone, Debug, Default)]
+pub struct CompositeFitness {
+    /// Name, weight and value of each term.
+    terms: Vec<(String, f64, Fitness)>
 }
 
-impl Eq for EvaluatedProgram { }
+impl CompositeFitness {
+    pub fn new() -> CompositeFitness { CompositeFitness::default() }
+
+    /// Adds a term contributing `weight * value` to the total.
+    pub fn add_term(&mut self, name: &str, weight: f64, value: Fitness) -> &mut CompositeFitness {
+        self.terms.push((name.to_string(), weight, value));
+        self
+    }
+
+    /// Returns the weighted sum of all terms.
+    pub fn total(&self) -> Fitness {
+        self.terms.iter().map(|(_, weight, value)| weight * value).sum()
+    }
 
-impl std::cmp::PartialOrd for EvaluatedProgram {
-    fn partial_cmp(&self, other: &EvaluatedProgram) -> Option<std::cmp::Ordering> {
-        self.fitness.partial_cmp(&other.fitness)
+    /// Returns the names and contributions (`weight * value`) of all terms, in order of adding.
+    pub fn breakdown(&self) -> Vec<(&str, Fitness)> {
+        self.terms.iter().map(|(name, weight, value)| (name.as_str(), weight * value)).collect()
     }
 }
 
-impl Ord for EvaluatedProgram {
-    fn cmp(&self, other: &EvaluatedProgram) -> std::cmp::Ordering {
-        self.fitness.partial_cmp(&other.fitness).unwrap()
+///
+/// Cache of program fitness, allowing to skip re-evaluation of unchanged programs
+/// (e.g. copied by elitism or not mutated).
+///
+/// Programs are identified by their binary representation (`Program::to_bytes`), so that different programs
+/// never share an entry (unlike with a hash such as `Program::stable_hash`, which may collide).
+///
+#[derive(Default)]
+pub struct FitnessCache {
+    fitness: std::collections::HashMap<Vec<u8>, Fitness>
+}
+
+impl FitnessCache {
+    pub fn new() -> FitnessCache { FitnessCache::default() }
+
+    /// Returns the cached fitness of `prog`; if not present, calls `f` and caches its result.
+    pub fn get_or_compute<F: FnOnce() -> Fitness>(&mut self, prog: &vm::Program, f: F) -> Fitness {
+        *self.fitness.entry(prog.to_bytes()).or_insert_with(f)
     }
+
+    pub fn len(&self) -> usize { self.fitness.len() }
+
+    pub fn is_empty(&self) -> bool { self.fitness.is_empty() }
+
+    pub fn clear(&mut self) { self.fitness.clear(); }
 }
 
+/// Returns the VM assembly mnemonic of `opcode` (including the operand, if any), as used by `pretty_print`.
+pub fn mnemonic(opcode: vm::OpCode) -> String {
+    match opcode.operand() {
+        Some(operand) => format!("{} {}", opcode.mnemonic(), operand),
+        None => opcode.mnemonic().to_string()
+    }
+}
 
 ///
 /// Returns textual representation of program.
@@ -74,7 +340,7 @@ impl Ord for EvaluatedProgram {
 ///
 /// * `program` - The program to print.
 /// * `inactive_jumps_marker` - If `Some`, wil be used to mark inactive
-/// `GoToIfP`, `EndGoTo`, `JumpIfN`, `EndJump` instructions.
+///   `GoToIfP`, `EndGoTo`, `JumpIfN`, `EndJump` instructions.
 /// * `instr_numbers` - If true, print instruction numbers.
 /// * `indentation_width` - Number of spaces per indendation level.
 ///
@@ -110,7 +376,7 @@ pub fn pretty_print(
 
         if indent {
             // additional identation (only for the current instruction) if the previous opcode was `IfP` or `IfN`
-            let mut actual_lvl = indent_level + if prev_opcode == vm::OpCode::IfN || prev_opcode == vm::OpCode::IfP { 1 } else { 0 };
+            let actual_lvl = indent_level + if prev_opcode == vm::OpCode::IfN || prev_opcode == vm::OpCode::IfP { 1 } else { 0 };
             output += &" ".repeat(actual_lvl * indentation_width.unwrap());
         }
 
@@ -118,36 +384,7 @@ pub fn pretty_print(
             indent_level += 1;
         }
 
-        let instr_mnemonic;
-        match opcode {
-            vm::OpCode::SetI(i) =>   instr_mnemonic = format!("seti {}", i),
-            vm::OpCode::Input(i) =>  instr_mnemonic = format!("input {}", i),
-            vm::OpCode::Output(i) => instr_mnemonic = format!("output {}", i),
-            vm::OpCode::ItoV =>      instr_mnemonic = "itov".to_string(),
-            vm::OpCode::VtoI =>      instr_mnemonic = "vtoi".to_string(),
-            vm::OpCode::IncV =>      instr_mnemonic = "incv".to_string(),
-            vm::OpCode::DecV =>      instr_mnemonic = "decv".to_string(),
-            vm::OpCode::IncI =>      instr_mnemonic = "inci".to_string(),
-            vm::OpCode::DecI =>      instr_mnemonic = "deci".to_string(),
-            vm::OpCode::Load =>      instr_mnemonic = "load".to_string(),
-            vm::OpCode::Store =>     instr_mnemonic = "store".to_string(),
-            vm::OpCode::Swap =>      instr_mnemonic = "swap".to_string(),
-            vm::OpCode::EndGoTo =>   instr_mnemonic = "endgoto".to_string(),
-            vm::OpCode::GoToIfP =>   instr_mnemonic = "gotoifp".to_string(),
-            vm::OpCode::JumpIfN =>   instr_mnemonic = "jumpifn".to_string(),
-            vm::OpCode::EndJump =>   instr_mnemonic = "endjump".to_string(),
-            vm::OpCode::IfP =>       instr_mnemonic = "ifp".to_string(),
-            vm::OpCode::IfN =>       instr_mnemonic = "ifn".to_string(),
-            vm::OpCode::Cmp =>       instr_mnemonic = "cmp".to_string(),
-            vm::OpCode::Add =>       instr_mnemonic = "add".to_string(),
-            vm::OpCode::Sub =>       instr_mnemonic = "sub".to_string(),
-            vm::OpCode::Mul =>       instr_mnemonic = "mul".to_string(),
-            vm::OpCode::Div =>       instr_mnemonic = "div".to_string(),
-            vm::OpCode::Abs =>       instr_mnemonic = "abs".to_string(),
-            vm::OpCode::Neg =>       instr_mnemonic = "neg".to_string(),
-            vm::OpCode::Sqrt =>      instr_mnemonic = "sqrt".to_string(),
-            vm::OpCode::Nop =>       instr_mnemonic = "nop".to_string()
-        }
+        let instr_mnemonic = mnemonic(*opcode);
 
         if jmp_tbl[i].is_none() &&
            (*opcode == vm::OpCode::EndGoTo ||
@@ -164,9 +401,136 @@ pub fn pretty_print(
     output
 }
 
+///
+/// Creates a program from its textual representation (VM assembly).
+///
+/// Each non-empty line contains an optional instruction number, a mnemonic (optionally prefixed with
+/// an inactive jump marker) and an integer operand for `seti`, `input`, `output`, `setbank`. Indentation
+/// and `//` comments are ignored. Accepts the output of `pretty_print`.
+///
+/// # Parameters
+///
+/// * `text` - VM assembly.
+/// * `num_data_slots` - Number of virtual machine data slots used by program.
+/// * `allow_crossing_blocks` - See `vm::Program::new`.
+///
+pub fn parse_vmasm(text: &str, num_data_slots: usize, allow_crossing_blocks: bool) -> Result<vm::Program, ParseError> {
+    let mut instructions = vec![];
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_num = line_idx + 1;
+        let code = match line.find("//") {
+            Some(pos) => &line[..pos],
+            None => line
+        };
+
+        let mut tokens: Vec<&str> = code.split_whitespace().collect();
+        if tokens.is_empty() {
+            continue;
+        }
+        // skip the instruction number
+        if tokens.len() > 1 && tokens[0].parse::<usize>().is_ok() {
+            tokens.remove(0);
+        }
+        // skip the inactive jump marker
+        let mnemonic = tokens[0].trim_start_matches(|c: char| !c.is_ascii_alphabetic());
+
+        let operand = |tokens: &[&str]| -> Result<i32, ParseError> {
+            match tokens.len() {
+                1 => Err(ParseError::InvalidOperand{ line: line_num, operand: "".to_string() }),
+                2 => tokens[1].parse::<i32>().map_err(|_| ParseError::InvalidOperand{ line: line_num, operand: tokens[1].to_string() }),
+                _ => Err(ParseError::InvalidOperand{ line: line_num, operand: tokens[2..].join(" ") })
+            }
+        };
+
+        let opcode = match vm::OpCode::ALL.iter().find(|opcode| opcode.mnemonic() == mnemonic) {
+            Some(opcode) => *opcode,
+            None => return Err(ParseError::UnknownMnemonic{ line: line_num, mnemonic: tokens[0].to_string() })
+        };
+
+        let opcode = if opcode.operand().is_some() {
+            opcode.with_operand(operand(&tokens)?)
+        } else if tokens.len() > 1 {
+            return Err(ParseError::InvalidOperand{ line: line_num, operand: tokens[1..].join(" ") });
+        } else {
+            opcode
+        };
+
+        instructions.push(opcode);
+    }
+
+    Ok(vm::Program::new(&instructions, num_data_slots, allow_crossing_blocks))
+}
+
+///
+/// Builds a list of allowed instructions (e.g. for `generate_random_programs`, `mutate`, `create_new_population`),
+/// expanding operand ranges of `SetI`, `Input`, `Output` and `SetBank` into individual opcodes.
+///
+/// Instructions are listed in order of adding; duplicates are ignored.
+///
+#[derive(Clone, Debug, Default)]
+pub struct InstructionSetBuilder {
+    instructions: Vec<vm::OpCode>
+}
+
+impl InstructionSetBuilder {
+    pub fn new() -> InstructionSetBuilder { InstructionSetBuilder::default() }
+
+    /// Adds `SetI(i)` for each `i` in `range`.
+    pub fn set_i_range(&mut self, range: std::ops::RangeInclusive<i32>) -> &mut InstructionSetBuilder {
+        self.enable_with_operands(vm::OpCode::SetI(0), range)
+    }
+
+    /// Adds `Input(i)` for each `i` in `range`.
+    pub fn inputs(&mut self, range: std::ops::RangeInclusive<i32>) -> &mut InstructionSetBuilder {
+        self.enable_with_operands(vm::OpCode::Input(0), range)
+    }
+
+    /// Adds `Output(i)` for each `i` in `range`.
+    pub fn outputs(&mut self, range: std::ops::RangeInclusive<i32>) -> &mut InstructionSetBuilder {
+        self.enable_with_operands(vm::OpCode::Output(0), range)
+    }
+
+    /// Adds `SetBank(i)` for each `i` in `range`.
+    pub fn set_bank_range(&mut self, range: std::ops::RangeInclusive<i32>) -> &mut InstructionSetBuilder {
+        self.enable_with_operands(vm::OpCode::SetBank(0), range)
+    }
+
+    /// Adds `opcode` (including its operand, if any).
+    pub fn enable(&mut self, opcode: vm::OpCode) -> &mut InstructionSetBuilder {
+        if !self.instructions.contains(&opcode) {
+            self.instructions.push(opcode);
+        }
+        self
+    }
+
+    /// Adds each of `opcodes`.
+    pub fn enable_all(&mut self, opcodes: &[vm::OpCode]) -> &mut InstructionSetBuilder {
+        for opcode in opcodes {
+            self.enable(*opcode);
+        }
+        self
+    }
+
+    /// Returns the list of allowed instructions.
+    pub fn build(&self) -> Vec<vm::OpCode> {
+        self.instructions.clone()
+    }
+
+    fn enable_with_operands(&mut self, opcode: vm::OpCode, range: std::ops::RangeInclusive<i32>) -> &mut InstructionSetBuilder {
+        for operand in range {
+            self.enable(opcode.with_operand(operand));
+        }
+        self
+    }
+}
+
 ///
 /// Generates a set of random programs.
 ///
+/// The programs have the default settings of `vm::Program::new` (e.g. one data bank); other settings
+/// can be applied with `vm::Program::with_instructions`.
+///
 /// # Parameters
 ///
 /// * `num_programs` - Number of programs to generate.
@@ -174,23 +538,29 @@ pub fn pretty_print(
 /// * `max_length` - Max. program length.
 /// * `num_data_slots` - Number of virtual machine data slots each program will use.
 /// * `allowed_instructions` - List of allowed instructions.
-/// * `rel_probability` - Relative probability of each instruction in `allowed_instructions`.
-/// If `None`, each instruction is equally probable.
+/// * `rel_probability` - Relative probability of each instruction in `allowed_instructions`
+///   (see also `rel_probability_by_mnemonic`). If `None`, each instruction is equally probable.
+/// * `allow_crossing_blocks` - See `vm::Program::new`.
+/// * `allow_guarded_control_flow` - If false, `IfP`/`IfN` directly preceding a control flow instruction are removed
+///   (see `repair_guarded_control_flow`), so programs may be shorter than `min_length`.
 /// * `rng` - Random number generator to use.
 ///
-pub fn generate_random_programs(
+#[allow(clippy::too_many_arguments)] // each parameter is documented above
+pub fn generate_random_programs<R: Rng>(
     num_programs: usize,
     min_length: usize,
     max_length: usize,
     num_data_slots: usize,
     allowed_instructions: &[vm::OpCode],
     rel_probability: Option<&[f64]>,
-    rng: &mut rand_xorshift::XorShiftRng)
+    allow_crossing_blocks: bool,
+    allow_guarded_control_flow: bool,
+    rng: &mut R)
 -> Vec<vm::Program> {
     assert!(min_length > 0 && max_length >= min_length);
-    assert!(allowed_instructions.len() > 0);
-    if rel_probability.is_some() {
-        assert!(allowed_instructions.len() == rel_probability.unwrap().len());
+    assert!(!allowed_instructions.is_empty(), "allowed_instructions must be non-empty");
+    if let Some(rel_probability) = rel_probability {
+        assert!(allowed_instructions.len() == rel_probability.len());
     }
 
     let mut cumulative_probability = vec![0.0];
@@ -202,8 +572,8 @@ pub fn generate_random_programs(
             cumulative_probability.push(current_cumulative);
         };
 
-        if rel_probability.is_some() {
-            for p in rel_probability.unwrap() {
+        if let Some(rel_probability) = rel_probability {
+            for p in rel_probability {
                 prob_adder(*p);
             }
         } else {
@@ -224,191 +594,3271 @@ pub fn generate_random_programs(
         for _ in 0..prog_len {
             let f: f64 = rng.gen_range(0.0, rel_prob_sum);
 
-            let mut opcode_loc;
-            match cumulative_probability.binary_search_by(|x| x.partial_cmp(&f).unwrap()) {
-                Ok(x) => opcode_loc = x,
-                Err(x) => opcode_loc = x - 1
-            }
+            // the last instruction whose range starts at or below `f` (so that instructions with zero probability,
+            // i.e. with empty ranges, are never chosen)
+            let opcode_loc = cumulative_probability.partition_point(|x| *x <= f) - 1;
 
             instructions.push(allowed_instructions[opcode_loc]);
         }
+        if !allow_guarded_control_flow {
+            repair_guarded_control_flow(&mut instructions);
+        }
+
+        result.push(vm::Program::new(&instructions, num_data_slots, allow_crossing_blocks));
+    }
+
+    result
+}
+
+///
+/// Returns relative probabilities of `allowed_instructions` (to be passed to `generate_random_programs`)
+/// specified by mnemonic (see `vm::OpCode::mnemonic`); instructions whose mnemonic is not in `weights`
+/// get 1.0. E.g. `{"nop": 0.1, "add": 4.0}` makes `Nop` rare and `Add` common.
+///
+/// Panics if `weights` contains a key which is not a mnemonic of any instruction.
+///
+pub fn rel_probability_by_mnemonic(
+    allowed_instructions: &[vm::OpCode],
+    weights: &std::collections::HashMap<&str, f64>
+) -> Vec<f64> {
+    for mnemonic in weights.keys() {
+        assert!(vm::OpCode::ALL.iter().any(|o| o.mnemonic() == *mnemonic), "unknown mnemonic: {}", mnemonic);
+    }
 
-        result.push(vm::Program::new(&instructions, num_data_slots, false));
+    allowed_instructions.iter().map(|o| *weights.get(o.mnemonic()).unwrap_or(&1.0)).collect()
+}
+
+///
+/// Creates an initial population of `num_programs` programs starting with (copies of) `seed_programs`,
+/// e.g. known partial solutions; the remaining programs are created by `generate_random(count)`,
+/// typically a closure calling `generate_random_programs`.
+///
+/// Panics if there are more seed programs than `num_programs`, if `generate_random` does not return
+/// `count` programs, or if the programs do not all use the same number of data slots.
+///
+pub fn seed_population<F: FnOnce(usize) -> Vec<vm::Program>>(
+    seed_programs: &[vm::Program],
+    num_programs: usize,
+    generate_random: F
+) -> Vec<vm::Program> {
+    assert!(seed_programs.len() <= num_programs, "too many seed programs");
+
+    let count = num_programs - seed_programs.len();
+    let mut result = seed_programs.to_vec();
+    result.extend(generate_random(count));
+    assert_eq!(num_programs, result.len(), "expected {} random programs", count);
+    if let Some(first) = result.first() {
+        let num_data_slots = first.get_num_data_slots();
+        assert!(result.iter().all(|p| p.get_num_data_slots() == num_data_slots),
+            "all programs must use {} data slots", num_data_slots);
     }
 
     result
 }
 
+///
+/// Removes unmatched control flow instructions (`GoToIfP`, `EndGoTo`, `JumpIfN`, `EndJump`).
+///
+/// Can be applied to offspring produced by recombination before creating a `vm::Program`
+/// (with `allow_crossing_blocks` = true), so that all control flow instructions are active.
+///
+pub fn repair_control_flow(instr: &mut Vec<vm::OpCode>) {
+    let jump_table = vm::Program::new(instr, 0, true).get_jump_table().to_vec();
+
+    let mut index = 0;
+    instr.retain(|opcode| {
+        let keep = match opcode {
+            vm::OpCode::GoToIfP |
+            vm::OpCode::EndGoTo |
+            vm::OpCode::JumpIfN |
+            vm::OpCode::EndJump => jump_table[index].is_some(),
+            _ => true
+        };
+        index += 1;
+        keep
+    });
+}
+
+///
+/// Removes `IfP`, `IfN` directly preceding a control flow instruction (`GoToIfP`, `EndGoTo`, `JumpIfN`, `EndJump`),
+/// so that control flow instructions are never skipped and the remaining `IfP`, `IfN` guard only
+/// other instructions. Control flow instructions themselves are kept, so their matching does not change.
+///
+pub fn repair_guarded_control_flow(instr: &mut Vec<vm::OpCode>) {
+    let mut result = Vec::with_capacity(instr.len());
+    let mut next_is_block_instruction = false;
+    for opcode in instr.iter().rev() {
+        if next_is_block_instruction && [vm::OpCode::IfP, vm::OpCode::IfN].contains(opcode) {
+            continue;
+        }
+        next_is_block_instruction = is_block_instruction(*opcode);
+        result.push(*opcode);
+    }
+    result.reverse();
+    *instr = result;
+}
+
+///
 /// Returns the greatest length (up to `length`) of a code segment from `start` which does not cross a control flow block boundary.
+///
+/// A closing instruction (`GoToIfP`, `EndJump`) without its opening counterpart inside the segment may belong
+/// to a block starting before `start`, so the segment ends before it.
+///
 fn limit_length_to_not_crossing(program: &[vm::OpCode], start: usize, length: usize) -> usize {
-    let mut result = length;
-
     let mut jump_level = 0;
     let mut goto_level = 0;
 
-    let mut pos = start;
-    let mut last_zero_pos = pos; // last position where both levels were zero
+    let mut last_zero_end = start; // last position after which both levels were zero
 
-    while pos < start + length {
-        match program[pos] {
+    for (pos, opcode) in program.iter().enumerate().skip(start).take(length) {
+        match opcode {
             vm::OpCode::EndGoTo => goto_level += 1,
-            vm::OpCode::GoToIfP => if goto_level > 0 { goto_level -= 1; }, // if already zero, this is an unmatched (ineffective) instruction
+            vm::OpCode::GoToIfP => if goto_level > 0 { goto_level -= 1; } else { break; },
             vm::OpCode::JumpIfN => jump_level += 1,
-            vm::OpCode::EndJump => if jump_level > 0 { jump_level -= 1; }, // if already zero, this is an unmatched (ineffective) instruction
+            vm::OpCode::EndJump => if jump_level > 0 { jump_level -= 1; } else { break; },
             _ => ()
         }
 
         if goto_level == 0 && jump_level == 0 {
-            last_zero_pos = pos;
+            last_zero_end = pos + 1;
         }
-        pos += 1;
     }
 
-    // if specified `length` crosses a block, limit it to the last non-crossing position
-    if jump_level != 0 || goto_level != 0 {
-        result = last_zero_pos - start;
-    }
+    last_zero_end - start
+}
 
-    result
+/// Method of exchanging code between programs (see `recombine_programs`).
+#[derive(Clone, Copy, Debug, Default)]
+pub enum CrossoverKind {
+    /// A randomly positioned and sized segment of each program is swapped with the segment in the other program.
+    #[default]
+    SingleSegment,
+    /// Like `SingleSegment`, but two non-overlapping segments of each program are swapped.
+    TwoPoint,
+    ///
+    /// Instructions at the same position in both programs are swapped with probability `rate`
+    /// (up to the length of the shorter program).
+    ///
+    Uniform{ rate: f64 },
+    ///
+    /// The programs are first aligned: of the offsets (position in the second program minus position in the first one)
+    /// from `-window` to `window`, the one with the most matching instructions is chosen (see `alignment_offset`).
+    /// Then a randomly positioned and sized segment is swapped with the aligned segment of the same length
+    /// in the other program, so that program lengths and the positions of the other instructions stay unchanged.
+    ///
+    Homologous{ window: usize }
 }
 
 ///
-/// Exchanges randomly chosen segments between programs.
+/// Returns position and length of a randomly chosen segment of `program` within `range_start..range_end`.
+///
+/// The segment is shortened if it would extend past `range_end`. If the range is shorter than `min_seg_len`,
+/// the whole range is used. See `recombine_programs` for the description of the other parameters.
 ///
-/// For each program, a randomly positioned and sized segment is chosen,
-/// and swapped with the segment in the other program.
+fn choose_segment<R: Rng>(
+    program: &[vm::OpCode],
+    range_start: usize,
+    range_end: usize,
+    min_seg_len: usize,
+    max_seg_len: usize,
+    allow_control_flow_block_xing: bool,
+    rng: &mut R
+) -> (usize, usize) {
+    if range_start >= range_end { return (range_end, 0); }
+
+    let (pos, mut len) = if range_end - range_start < min_seg_len {
+        (range_start, range_end - range_start)
+    } else {
+        let pos: usize = rng.gen_range(range_start, range_end);
+        (pos, std::cmp::min(rng.gen_range(min_seg_len, max_seg_len + 1), range_end - pos))
+    };
+
+    if !allow_control_flow_block_xing {
+        len = limit_length_to_not_crossing(program, pos, len);
+    }
+
+    (pos, len)
+}
+
+///
+/// Exchanges randomly chosen code between programs.
 ///
 /// # Parameters
 ///
 /// * `prog1` - First program to recombine.
 /// * `prog2` - Second program to recombine.
-/// * `min_seg_len` - Min. segment length.
-/// * `max_seg_len` - Max. segment length.
+/// * `kind` - Crossover method.
+/// * `min_seg_len` - Min. segment length (used by segment-based crossover methods).
+/// * `max_seg_len` - Max. segment length (used by segment-based crossover methods).
 /// * `allow_control_flow_block_xing` - If true, segments are allowed to cross control flow blocks
-/// (`GoToIfP`/`EndGoTo` and `JumpIfN`/`EndJump` pairs).
+///   (`GoToIfP`/`EndGoTo` and `JumpIfN`/`EndJump` pairs).
 /// * `rng` - Random number generator to use.
 ///
-pub fn recombine_programs(
+pub fn recombine_programs<R: Rng>(
     prog1: &mut Vec<vm::OpCode>,
     prog2: &mut Vec<vm::OpCode>,
+    kind: CrossoverKind,
     min_seg_len: usize,
     max_seg_len: usize,
     allow_control_flow_block_xing: bool,
-    rng: &mut rand_xorshift::XorShiftRng
+    rng: &mut R
 ) {
     assert!(max_seg_len >= min_seg_len);
 
-    let exchg_pos_1: usize = rng.gen_range(0, prog1.len());
-    let mut exchg_len_1: usize = std::cmp::min(rng.gen_range(min_seg_len, max_seg_len + 1), prog1.len() - exchg_pos_1);
-
-    let exchg_pos_2: usize = rng.gen_range(0, prog2.len());
-    let mut exchg_len_2: usize = std::cmp::min(rng.gen_range(min_seg_len, max_seg_len + 1), prog2.len() - exchg_pos_2);
+    match kind {
+        CrossoverKind::SingleSegment => {
+            let seg1 = choose_segment(prog1, 0, prog1.len(), min_seg_len, max_seg_len, allow_control_flow_block_xing, rng);
+            let seg2 = choose_segment(prog2, 0, prog2.len(), min_seg_len, max_seg_len, allow_control_flow_block_xing, rng);
+            exchange_segments(prog1, prog2, &[seg1], &[seg2]);
+        },
+
+        CrossoverKind::TwoPoint => {
+            let seg1a = choose_segment(prog1, 0, prog1.len(), min_seg_len, max_seg_len, allow_control_flow_block_xing, rng);
+            let seg1b = choose_segment(prog1, seg1a.0 + seg1a.1, prog1.len(), min_seg_len, max_seg_len, allow_control_flow_block_xing, rng);
+            let seg2a = choose_segment(prog2, 0, prog2.len(), min_seg_len, max_seg_len, allow_control_flow_block_xing, rng);
+            let seg2b = choose_segment(prog2, seg2a.0 + seg2a.1, prog2.len(), min_seg_len, max_seg_len, allow_control_flow_block_xing, rng);
+            exchange_segments(prog1, prog2, &[seg1a, seg1b], &[seg2a, seg2b]);
+        },
+
+        CrossoverKind::Uniform{ rate } => {
+            for i in 0..std::cmp::min(prog1.len(), prog2.len()) {
+                if rng.gen::<f64>() < rate {
+                    std::mem::swap(&mut prog1[i], &mut prog2[i]);
+                }
+            }
+        },
+
+        CrossoverKind::Homologous{ window } => {
+            let offset = alignment_offset(prog1, prog2, window);
+            // positions in `prog1` which have an aligned counterpart in `prog2` (a nonzero offset
+            // is chosen only if some instructions match, so the range is empty only for an empty program)
+            let range_start = std::cmp::max(0, -offset) as usize;
+            let range_end = std::cmp::max(0, std::cmp::min(prog1.len() as isize, prog2.len() as isize - offset)) as usize;
+
+            let (pos1, mut len) = choose_segment(prog1, range_start, range_end, min_seg_len, max_seg_len, allow_control_flow_block_xing, rng);
+            let pos2 = (pos1 as isize + offset) as usize;
+            if !allow_control_flow_block_xing {
+                loop {
+                    let limited = std::cmp::min(
+                        limit_length_to_not_crossing(prog1, pos1, len),
+                        limit_length_to_not_crossing(prog2, pos2, len)
+                    );
+                    if limited == len { break; }
+                    len = limited;
+                }
+            }
+            exchange_segments(prog1, prog2, &[(pos1, len)], &[(pos2, len)]);
+        }
+    }
+}
 
-    if !allow_control_flow_block_xing {
-        exchg_len_1 = limit_length_to_not_crossing(prog1, exchg_pos_1, exchg_len_1);
-        exchg_len_2 = limit_length_to_not_crossing(prog2, exchg_pos_2, exchg_len_2);
+///
+/// Returns the offset (position in `prog2` minus position in `prog1`) from `-window` to `window`
+/// at which the most instructions of both programs match. Of equally good offsets, the one closest to 0
+/// (and then the negative one) is chosen.
+///
+fn alignment_offset(prog1: &[vm::OpCode], prog2: &[vm::OpCode], window: usize) -> isize {
+    let num_matches = |offset: isize| {
+        prog1.iter().enumerate().filter(|&(i, opcode)| {
+            let j = i as isize + offset;
+            j >= 0 && (j as usize) < prog2.len() && prog2[j as usize] == *opcode
+        }).count()
+    };
+
+    let mut best_offset = 0;
+    let mut best_num_matches = num_matches(0);
+    for distance in 1..=window as isize {
+        for &offset in &[-distance, distance] {
+            let n = num_matches(offset);
+            if n > best_num_matches {
+                best_offset = offset;
+                best_num_matches = n;
+            }
+        }
     }
 
-    let mut new_prog1: Vec<vm::OpCode> = vec![];
-    let mut new_prog2: Vec<vm::OpCode> = vec![];
+    best_offset
+}
+
+///
+/// Swaps each segment (position, length) in `segments1` of `prog1` with the corresponding one
+/// in `segments2` of `prog2`. Segments must be sorted and non-overlapping.
+///
+fn exchange_segments(
+    prog1: &mut Vec<vm::OpCode>,
+    prog2: &mut Vec<vm::OpCode>,
+    segments1: &[(usize, usize)],
+    segments2: &[(usize, usize)]
+) {
+    assert!(segments1.len() == segments2.len());
+
+    // copies `dest_prog` with its segments replaced by those of `src_prog`
+    let exchange = |dest_prog: &[vm::OpCode], dest_segments: &[(usize, usize)], src_prog: &[vm::OpCode], src_segments: &[(usize, usize)]| {
+        let mut result: Vec<vm::OpCode> = vec![];
+        let mut copied_up_to = 0;
+        for (&(dest_pos, dest_len), &(src_pos, src_len)) in dest_segments.iter().zip(src_segments) {
+            result.extend(dest_prog[copied_up_to..dest_pos].iter());
+            result.extend(src_prog[src_pos .. src_pos + src_len].iter());
+            copied_up_to = dest_pos + dest_len;
+        }
+        result.extend(dest_prog[copied_up_to..].iter());
 
-    new_prog1.extend(prog1[0..exchg_pos_1].iter());
-    new_prog1.extend(prog2[exchg_pos_2 .. exchg_pos_2 + exchg_len_2].iter());
-    new_prog1.extend(prog1[exchg_pos_1 + exchg_len_1 ..].iter());
+        result
+    };
 
-    new_prog2.extend(prog2[0..exchg_pos_2].iter());
-    new_prog2.extend(prog1[exchg_pos_1 .. exchg_pos_1 + exchg_len_1].iter());
-    new_prog2.extend(prog2[exchg_pos_2 + exchg_len_2 ..].iter());
+    let new_prog1 = exchange(prog1, segments1, prog2, segments2);
+    let new_prog2 = exchange(prog2, segments2, prog1, segments1);
 
     *prog1 = new_prog1;
     *prog2 = new_prog2;
 }
 
-pub fn mutate(
-    program: &mut Vec<vm::OpCode>,
-    num_mutations: usize,
-    allowed_instructions: &[vm::OpCode],
-    rng: &mut rand_xorshift::XorShiftRng
-) {
-    if program.len() == 0 { return; }
-
-    let actual_num_mutations: usize = rng.gen_range(1, num_mutations+1);
+/// Relative probabilities of mutation types (see `mutate`); need not be normalized.
+#[derive(Clone, Copy, Debug)]
+pub struct MutationWeights {
+    pub insert: f64,
+    pub delete: f64,
+    pub substitute: f64,
+    pub transpose: f64,
+    /// If set, enables operand perturbation (with its own weight).
+    pub operand_perturbation: Option<OperandPerturbation>
+}
 
-    for _ in 0..actual_num_mutations {
-        let f: f64 = rng.gen(); // selector of mutation type
+impl Default for MutationWeights {
+    /// All mutation types equally probable; no operand perturbation.
+    fn default() -> MutationWeights {
+        MutationWeights{ insert: 1.0, delete: 1.0, substitute: 1.0, transpose: 1.0, operand_perturbation: None }
+    }
+}
 
-        let mut pos: usize = rng.gen_range(0, program.len());
+///
+/// Operand perturbation mutation: changes the operand of `SetI`, `Input` or `Output` by a random non-zero delta
+/// of at most `max_delta` (>= 1), clamped to the operand's range (inclusive). Other instructions are not changed.
+///
+/// Unlike substitution, it can produce operands not listed in the allowed instructions.
+///
+#[derive(Clone, Copy, Debug)]
+pub struct OperandPerturbation {
+    /// Relative probability (see `MutationWeights`).
+    pub weight: f64,
+    pub max_delta: i32,
+    pub set_i_range: (i32, i32),
+    pub input_range: (i32, i32),
+    pub output_range: (i32, i32)
+}
 
-        let new_opcode = allowed_instructions[rng.gen_range(0, allowed_instructions.len())];
+impl OperandPerturbation {
+    /// Returns `opcode` with perturbed operand.
+    fn apply<R: Rng>(&self, opcode: vm::OpCode, rng: &mut R) -> vm::OpCode {
+        assert!(self.max_delta >= 1);
+        let mut perturb = |operand: i32, (min, max): (i32, i32)| {
+            let delta = rng.gen_range(1, self.max_delta as i64 + 1);
+            let perturbed = if rng.gen::<bool>() { operand as i64 + delta } else { operand as i64 - delta };
+            std::cmp::max(min as i64, std::cmp::min(max as i64, perturbed)) as i32
+        };
 
-        if f < 1.0/4.0 {
-            // insertion
-            program.insert(pos, new_opcode);
-        } else if f < 2.0/4.0 && program.len() > 1 {
-            // deletion
-            program.remove(pos);
-        } else if f < 3.0/4.0 {
-            // substitution
-            program[pos] = new_opcode;
-        } else if program.len() >= 2 {
-            // transposition
-            if pos == 0 { pos = 1 };
-            program.swap(pos, pos - 1);
+        match opcode {
+            vm::OpCode::SetI(i) => vm::OpCode::SetI(perturb(i, self.set_i_range)),
+            vm::OpCode::Input(i) => vm::OpCode::Input(perturb(i, self.input_range)),
+            vm::OpCode::Output(i) => vm::OpCode::Output(perturb(i, self.output_range)),
+            _ => opcode
         }
     }
 }
 
-/// Returns a new population created by recombining and mutating the best of `programs`.
-pub fn create_new_population(
-    programs: SortedEvaluatedPrograms,
-    mutation_probability: f64,
+///
+/// Performs a random number (1 to `num_mutations`) of random mutations of `program`.
+///
+/// Mutation type (insertion, deletion, substitution, transposition, operand perturbation) is chosen according to `weights`.
+/// Deletion of the only instruction is replaced by substitution; transposition of the only instruction is skipped.
+///
+/// If `preserve_blocks` is true, mutations do not create unmatched control flow instructions (`GoToIfP`, `EndGoTo`,
+/// `JumpIfN`, `EndJump`; matched as with `allow_crossing_blocks` = true, see `vm::Program::new`):
+///
+///   - inserting one of them inserts a matched pair around a random span of complete blocks,
+///   - deleting one of them also deletes its counterpart (if any),
+///   - they are neither substituted nor transposed, nor substituted for other instructions.
+///
+/// If `allow_guarded_control_flow` is false, `IfP`/`IfN` directly preceding a control flow instruction are removed
+/// after mutation (see `repair_guarded_control_flow`).
+///
+/// Panics if `allowed_instructions` is empty.
+///
+pub fn mutate<R: Rng>(
+    program: &mut Vec<vm::OpCode>,
     num_mutations: usize,
-    best_prog_fraction: f64,
+    weights: &MutationWeights,
     allowed_instructions: &[vm::OpCode],
-    min_crossover_seg_length: usize,
-    max_crossover_seg_length: usize,
-    max_program_length: usize,
-    num_program_data_slots: usize,
-    rng: &mut rand_xorshift::XorShiftRng
-) -> Vec<vm::Program> {
-    let num_best_programs = (programs.len() as f64 * best_prog_fraction) as usize;
-    let best_programs: Vec<&EvaluatedProgram> = programs.get_programs().iter().take(num_best_programs).collect();
-
-    let mut new_population: Vec<vm::Program> = vec![];
+    preserve_blocks: bool,
+    allow_guarded_control_flow: bool,
+    rng: &mut R
+) {
+    assert!(!allowed_instructions.is_empty(), "allowed_instructions must be non-empty");
+    if program.is_empty() { return; }
+
+    let perturb_weight = weights.operand_perturbation.map_or(0.0, |perturbation| perturbation.weight);
+    let total_weight = weights.insert + weights.delete + weights.substitute + weights.transpose + perturb_weight;
+    assert!(total_weight > 0.0);
+    // upper bounds of the mutation type selector's ranges
+    let insert_limit = weights.insert / total_weight;
+    let delete_limit = (weights.insert + weights.delete) / total_weight;
+    let substitute_limit = (weights.insert + weights.delete + weights.substitute) / total_weight;
+    let transpose_limit = (weights.insert + weights.delete + weights.substitute + weights.transpose) / total_weight;
 
-    for _ in 0 .. programs.len()/2 {
+    let actual_num_mutations: usize = rng.gen_range(1, num_mutations+1);
 
-        let index1: usize = rng.gen_range(0, best_programs.len());
-        let index2: usize = rng.gen_range(0, best_programs.len());
+    for _ in 0..actual_num_mutations {
+        let f: f64 = rng.gen(); // selector of mutation type
 
-        let mut prog1 = vec![]; prog1.extend_from_slice(best_programs[index1].prog.get_instr());
-        let mut prog2 = vec![]; prog2.extend_from_slice(best_programs[index2].prog.get_instr());
+        let mut pos: usize = rng.gen_range(0, program.len());
 
-        recombine_programs(&mut prog1, &mut prog2, min_crossover_seg_length, max_crossover_seg_length, true, rng);
+        let new_opcode = allowed_instructions[rng.gen_range(0, allowed_instructions.len())];
 
-        if prog1.len() > max_program_length {
-            prog1.truncate(max_program_length);
-        }
-        if prog2.len() > max_program_length {
-            prog2.truncate(max_program_length);
+        if !preserve_blocks {
+            if f < insert_limit {
+                // insertion
+                program.insert(pos, new_opcode);
+            } else if f < delete_limit && program.len() > 1 {
+                // deletion
+                program.remove(pos);
+            } else if f < substitute_limit {
+                // substitution
+                program[pos] = new_opcode;
+            } else if f < transpose_limit {
+                // transposition
+                if program.len() >= 2 {
+                    if pos == 0 { pos = 1 };
+                    program.swap(pos, pos - 1);
+                }
+            } else if let Some(perturbation) = weights.operand_perturbation {
+                program[pos] = perturbation.apply(program[pos], rng);
+            }
+            continue;
         }
 
-        if rng.gen::<f64>() <= mutation_probability {
-            mutate(&mut prog1, num_mutations, allowed_instructions, rng);
-        }
+        let jump_table = vm::Program::new(program, 0, true).get_jump_table().to_vec();
+        let partner = if is_block_instruction(program[pos]) { jump_table[pos] } else { None };
 
-        if rng.gen::<f64>() <= mutation_probability {
-            mutate(&mut prog2, num_mutations, allowed_instructions, rng);
+        if f < insert_limit {
+            if is_block_instruction(new_opcode) {
+                insert_block(program, &jump_table, pos, new_opcode, rng);
+            } else {
+                program.insert(pos, new_opcode);
+            }
+        } else if f < delete_limit && program.len() > 1 + partner.iter().count() {
+            if let Some(partner) = partner {
+                program.remove(std::cmp::max(pos, partner));
+                program.remove(std::cmp::min(pos, partner));
+            } else {
+                program.remove(pos);
+            }
+        } else if f < substitute_limit {
+            if !is_block_instruction(program[pos]) && !is_block_instruction(new_opcode) {
+                program[pos] = new_opcode;
+            }
+        } else if f < transpose_limit {
+            if program.len() >= 2 {
+                if pos == 0 { pos = 1 };
+                if !is_block_instruction(program[pos]) && !is_block_instruction(program[pos - 1]) {
+                    program.swap(pos, pos - 1);
+                }
+            }
+        } else if let Some(perturbation) = weights.operand_perturbation {
+            program[pos] = perturbation.apply(program[pos], rng);
         }
-
-        new_population.push(vm::Program::new(&prog1, num_program_data_slots, true));
-        new_population.push(vm::Program::new(&prog2, num_program_data_slots, true));
     }
 
-    // if the number of programs is odd, just copy one of the best ones without recombining
-    if programs.len() % 2 == 1 {
-        new_population.push(best_programs[rng.gen_range(0, best_programs.len())].prog.clone());
+    if !allow_guarded_control_flow {
+        repair_guarded_control_flow(program);
     }
+}
+
+/// Returns true for `GoToIfP`, `EndGoTo`, `JumpIfN`, `EndJump`.
+pub(crate) fn is_block_instruction(opcode: vm::OpCode) -> bool {
+    matches!(opcode, vm::OpCode::GoToIfP | vm::OpCode::EndGoTo | vm::OpCode::JumpIfN | vm::OpCode::EndJump)
+}
+
+///
+/// Inserts a matched pair of block instructions of the same kind as `opcode` (`EndGoTo`/`GoToIfP`
+/// or `JumpIfN`/`EndJump`) around a randomly chosen span starting at `start`.
+///
+/// The span contains only complete blocks (according to `jump_table`, i.e. each block instruction in it
+/// has its counterpart in it too), so that the new pair matches.
+///
+fn insert_block<R: Rng>(
+    program: &mut Vec<vm::OpCode>,
+    jump_table: &[Option<usize>],
+    start: usize,
+    opcode: vm::OpCode,
+    rng: &mut R
+) {
+    // possible span ends (exclusive)
+    let mut ends = vec![start];
+    let mut num_unmatched = 0; // number of block instructions in the span without their counterpart
+    for (pos, opcode) in program.iter().enumerate().skip(start) {
+        if is_block_instruction(*opcode) {
+            match jump_table[pos] {
+                Some(partner) if partner > pos => num_unmatched += 1,
+                Some(partner) if partner >= start => num_unmatched -= 1,
+                // the block begins before the span, or the instruction is inactive
+                _ => break
+            }
+        }
+        if num_unmatched == 0 {
+            ends.push(pos + 1);
+        }
+    }
+    let end = ends[rng.gen_range(0, ends.len())];
+
+    let (opening, closing) = match opcode {
+        vm::OpCode::EndGoTo | vm::OpCode::GoToIfP => (vm::OpCode::EndGoTo, vm::OpCode::GoToIfP),
+        _ => (vm::OpCode::JumpIfN, vm::OpCode::EndJump)
+    };
+    program.insert(end, closing);
+    program.insert(start, opening);
+}
+
+/// Method of choosing parents for breeding the new generation (see `create_new_population`).
+#[derive(Clone, Copy, Debug)]
+pub enum SelectionStrategy {
+    /// Each parent is chosen uniformly from the best `fraction` of the population.
+    TopFraction{ fraction: f64 },
+    /// Each parent is the fittest of `size` programs chosen uniformly from the best `fraction_eligible` of the population.
+    Tournament{ size: usize, fraction_eligible: f64 },
+    ///
+    /// Each parent is chosen from the whole population with probability proportional to
+    /// `1 / (1 + fitness - best_fitness)`, i.e. the inverse of fitness relative to the best program
+    /// (the lowest non-NaN fitness, regardless of the population's order).
+    ///
+    /// Programs with NaN or infinite fitness are never chosen, unless no program has a finite fitness;
+    /// then all are equally probable.
+    ///
+    Roulette
+}
+
+/// Chooses parents according to a `SelectionStrategy`.
+enum ParentSelector {
+    Uniform{ num_eligible: usize },
+    Tournament{ size: usize, num_eligible: usize },
+    Roulette(rand::distributions::WeightedIndex<f64>)
+}
+
+impl ParentSelector {
+    fn new<Meta>(strategy: SelectionStrategy, programs: &SortedEvaluatedPrograms<Meta>) -> ParentSelector {
+        let num_eligible = |fraction: f64| std::cmp::max(1, (programs.len() as f64 * fraction) as usize);
+
+        match strategy {
+            SelectionStrategy::TopFraction{ fraction } => ParentSelector::Uniform{ num_eligible: num_eligible(fraction) },
+
+            SelectionStrategy::Tournament{ size, fraction_eligible } => {
+                assert!(size > 0);
+                ParentSelector::Tournament{ size, num_eligible: num_eligible(fraction_eligible) }
+            },
+
+            SelectionStrategy::Roulette => {
+                // not necessarily the first program (e.g. if sorted with a length penalty)
+                let best_fitness = programs.iter().map(|p| p.fitness).filter(|f| !f.is_nan()).fold(Fitness::INFINITY, Fitness::min);
+                let mut weights: Vec<f64> = programs.iter().map(|p| {
+                    let weight = 1.0 / (1.0 + p.fitness - best_fitness);
+                    if weight.is_finite() { weight.max(0.0) } else { 0.0 }
+                }).collect();
+                if weights.iter().all(|w| *w == 0.0) {
+                    weights = vec![1.0; weights.len()];
+                }
+                ParentSelector::Roulette(rand::distributions::WeightedIndex::new(weights).unwrap())
+            }
+        }
+    }
+
+    /// Returns index of the chosen parent in the sorted population.
+    fn select<R: Rng>(&self, rng: &mut R) -> usize {
+        match self {
+            ParentSelector::Uniform{ num_eligible } => rng.gen_range(0, *num_eligible),
+
+            // programs are sorted by fitness, so the fittest contestant is the one with the lowest index
+            ParentSelector::Tournament{ size, num_eligible } =>
+                (0..*size).map(|_| rng.gen_range(0, *num_eligible)).min().unwrap(),
+
+            ParentSelector::Roulette(distribution) => rng.sample(distribution)
+        }
+    }
+}
+
+/// Exchanges code between two programs (see `recombine_programs`).
+pub trait Recombinator: Sync {
+    fn recombine(&self, prog1: &mut Vec<vm::OpCode>, prog2: &mut Vec<vm::OpCode>, rng: &mut dyn RngCore);
+}
+
+/// Mutates a program (see `mutate`).
+pub trait Mutator: Sync {
+    fn mutate(&self, program: &mut Vec<vm::OpCode>, rng: &mut dyn RngCore);
+}
+
+/// Recombinator calling `recombine_programs` with the specified parameters.
+#[derive(Clone, Copy, Debug)]
+pub struct DefaultRecombinator {
+    pub kind: CrossoverKind,
+    pub min_seg_len: usize,
+    pub max_seg_len: usize,
+    pub allow_control_flow_block_xing: bool
+}
+
+impl Recombinator for DefaultRecombinator {
+    fn recombine(&self, prog1: &mut Vec<vm::OpCode>, prog2: &mut Vec<vm::OpCode>, mut rng: &mut dyn RngCore) {
+        recombine_programs(
+            prog1, prog2, self.kind, self.min_seg_len, self.max_seg_len, self.allow_control_flow_block_xing, &mut rng
+        );
+    }
+}
+
+/// Mutator calling `mutate` with the specified parameters.
+#[derive(Clone, Copy, Debug)]
+pub struct DefaultMutator<'a> {
+    pub num_mutations: usize,
+    pub weights: MutationWeights,
+    pub allowed_instructions: &'a [vm::OpCode],
+    pub preserve_blocks: bool,
+    pub allow_guarded_control_flow: bool
+}
+
+impl<'a> Mutator for DefaultMutator<'a> {
+    fn mutate(&self, program: &mut Vec<vm::OpCode>, mut rng: &mut dyn RngCore) {
+        mutate(
+            program,
+            self.num_mutations,
+            &self.weights,
+            self.allowed_instructions,
+            self.preserve_blocks,
+            self.allow_guarded_control_flow,
+            &mut rng
+        );
+    }
+}
+
+/// Parameters of breeding a new population (see `create_new_population`).
+#[derive(Clone, Debug)]
+pub struct BreedingParameters {
+    pub mutation_probability: f64,
+    pub num_mutations: usize,
+    pub mutation_weights: MutationWeights,
+    pub selection: SelectionStrategy,
+    pub elitism_count: usize,
+    pub crossover: CrossoverKind,
+    pub allowed_instructions: Vec<vm::OpCode>,
+    pub min_crossover_seg_length: usize,
+    pub max_crossover_seg_length: usize,
+    pub min_program_length: usize,
+    pub max_program_length: usize,
+    pub allow_crossing_blocks: bool,
+    pub preserve_blocks: bool,
+    pub allow_guarded_control_flow: bool,
+    pub enforce_uniqueness: bool
+}
+
+///
+/// Returns a new population created by recombining and mutating programs chosen according to `selection`.
+///
+/// The parameters named below are fields of `breeding`.
+///
+/// The best `elitism_count` programs are copied unchanged into the new population;
+/// the rest is filled with offspring, so that the population size stays the same.
+///
+/// If `enforce_uniqueness` is true, duplicate programs are removed first (see `SortedEvaluatedPrograms::dedupe`)
+/// and replaced by random programs (with lengths within the range of the population's program lengths),
+/// so that a population collapsed to copies of the same program regains diversity.
+///
+/// Offspring keep the settings of their parents (data slots and banks, crossing of blocks, index and arithmetic
+/// modes, `Cmp` tolerance; see `vm::Program::with_instructions`); random programs replacing duplicates get those
+/// of the best program. `allow_crossing_blocks` is used for recombination (see `recombine_programs`),
+/// so it should be the same as for the initial population (`generate_random_programs`).
+/// `preserve_blocks` is passed to `mutate`; `allow_guarded_control_flow` applies to mutation, recombined offspring
+/// (see `create_new_population_with_operators`) and random programs replacing duplicates.
+///
+/// After recombination, mutation and repair (see `allow_guarded_control_flow`), offspring longer
+/// than `max_program_length` are truncated and offspring shorter than `min_program_length` are padded with `Nop`s.
+///
+/// Pairs of offspring are created in parallel, each using its own random number generator seeded from `rng`,
+/// so the result does not depend on the number of threads.
+///
+/// Panics if `allowed_instructions` is empty or `min_program_length` is greater than `max_program_length`.
+///
+pub fn create_new_population<Meta: Sync, R: Rng>(
+    programs: SortedEvaluatedPrograms<Meta>,
+    breeding: &BreedingParameters,
+    rng: &mut R
+) -> Vec<vm::Program> {
+    let recombinator = DefaultRecombinator{
+        kind: breeding.crossover,
+        min_seg_len: breeding.min_crossover_seg_length,
+        max_seg_len: breeding.max_crossover_seg_length,
+        allow_control_flow_block_xing: breeding.allow_crossing_blocks
+    };
+    let mutator = DefaultMutator{
+        num_mutations: breeding.num_mutations,
+        weights: breeding.mutation_weights,
+        allowed_instructions: &breeding.allowed_instructions,
+        preserve_blocks: breeding.preserve_blocks,
+        allow_guarded_control_flow: breeding.allow_guarded_control_flow
+    };
+
+    create_new_population_with_operators(programs, breeding, &recombinator, &mutator, rng)
+}
+
+///
+/// Returns a new population like `create_new_population`, but using `recombinator` and `mutator`
+/// (e.g. domain-specific ones) instead of `recombine_programs` and `mutate`.
+///
+/// The fields of `breeding` configuring those (`num_mutations`, `mutation_weights`, `crossover`,
+/// `min_crossover_seg_length`, `max_crossover_seg_length`, `allow_crossing_blocks`, `preserve_blocks`) are ignored.
+/// `allowed_instructions` is used only for random programs replacing duplicates if `enforce_uniqueness` is true.
+/// If `allow_guarded_control_flow` is false, it also applies to the offspring, which are repaired
+/// (see `repair_guarded_control_flow`) after recombination and mutation.
+///
+pub fn create_new_population_with_operators<Meta: Sync, R: Rng>(
+    programs: SortedEvaluatedPrograms<Meta>,
+    breeding: &BreedingParameters,
+    recombinator: &dyn Recombinator,
+    mutator: &dyn Mutator,
+    rng: &mut R
+) -> Vec<vm::Program> {
+    let BreedingParameters{
+        mutation_probability,
+        selection,
+        elitism_count,
+        ref allowed_instructions,
+        min_program_length,
+        max_program_length,
+        allow_guarded_control_flow,
+        enforce_uniqueness,
+        ..
+    } = *breeding;
+    assert!(!allowed_instructions.is_empty(), "allowed_instructions must be non-empty");
+    assert!(min_program_length <= max_program_length, "min_program_length must not exceed max_program_length");
+
+    let mut programs = programs;
+    let mut random_programs = vec![];
+    if enforce_uniqueness {
+        let lengths = || programs.get_programs().iter().map(|p| p.prog.get_instr().len());
+        let min_length = std::cmp::max(1, lengths().min().unwrap_or(1));
+        let max_length = std::cmp::max(min_length, lengths().max().unwrap_or(1));
+        let num_duplicates = programs.dedupe();
+        if num_duplicates > 0 {
+            // the random programs get the settings (data slots and banks, modes etc.) of the best program
+            let template = &programs.get_programs()[0].prog;
+            random_programs = generate_random_programs(
+                num_duplicates,
+                min_length,
+                max_length,
+                template.get_num_data_slots(),
+                allowed_instructions,
+                None,
+                template.get_allow_crossing_blocks(),
+                allow_guarded_control_flow,
+                rng
+            ).iter().map(|p| template.with_instructions(p.get_instr())).collect();
+        }
+    }
+
+    let selector = ParentSelector::new(selection, &programs);
+    let parents = programs.get_programs();
+
+    let elitism_count = std::cmp::min(elitism_count, programs.len());
+    let mut new_population: Vec<vm::Program> = parents.iter().take(elitism_count).map(|p| p.prog.clone()).collect();
+    let num_offspring = programs.len() - elitism_count;
+
+    let pair_seeds: Vec<u64> = (0 .. num_offspring/2).map(|_| rng.gen()).collect();
+
+    let offspring: Vec<(vm::Program, vm::Program)> = pair_seeds.par_iter().map(|seed| {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(*seed);
+
+        let index1: usize = selector.select(&mut rng);
+        let index2: usize = selector.select(&mut rng);
+
+        let mut prog1 = vec![]; prog1.extend_from_slice(parents[index1].prog.get_instr());
+        let mut prog2 = vec![]; prog2.extend_from_slice(parents[index2].prog.get_instr());
+
+        recombinator.recombine(&mut prog1, &mut prog2, &mut rng);
+
+        if rng.gen::<f64>() <= mutation_probability {
+            mutator.mutate(&mut prog1, &mut rng);
+        }
+
+        if rng.gen::<f64>() <= mutation_probability {
+            mutator.mutate(&mut prog2, &mut rng);
+        }
+
+        // recombination may splice an `IfP`/`IfN` directly before a control flow instruction
+        if !allow_guarded_control_flow {
+            repair_guarded_control_flow(&mut prog1);
+            repair_guarded_control_flow(&mut prog2);
+        }
+
+        // mutation may insert instructions; truncation does not create new guarded control flow instructions
+        if prog1.len() > max_program_length {
+            prog1.truncate(max_program_length);
+        }
+        if prog2.len() > max_program_length {
+            prog2.truncate(max_program_length);
+        }
+        if prog1.len() < min_program_length {
+            prog1.resize(min_program_length, vm::OpCode::Nop);
+        }
+        if prog2.len() < min_program_length {
+            prog2.resize(min_program_length, vm::OpCode::Nop);
+        }
+
+        // each offspring keeps the settings (data slots and banks, modes etc.) of the parent it was copied from
+        (parents[index1].prog.with_instructions(&prog1), parents[index2].prog.with_instructions(&prog2))
+    }).collect();
+
+    for (prog1, prog2) in offspring {
+        new_population.push(prog1);
+        new_population.push(prog2);
+    }
+
+    // if the number of offspring is odd, just copy one of the selected ones without recombining
+    if num_offspring % 2 == 1 {
+        new_population.push(parents[selector.select(rng)].prog.clone());
+    }
+
+    new_population.extend(random_programs);
 
     new_population
 }
+
+///
+/// Island model: several populations (islands) evolving independently, exchanging programs periodically.
+///
+/// Every `migration_interval` generations, copies of the best `migration_count` programs of each island
+/// replace the worst programs of the next island (ring topology).
+///
+pub struct IslandModel {
+    islands: Vec<SortedEvaluatedPrograms>,
+    breeding: BreedingParameters,
+    migration_interval: usize,
+    migration_count: usize,
+    /// Number of generations since creation.
+    generation: usize
+}
+
+impl IslandModel {
+    pub fn new(
+        islands: Vec<SortedEvaluatedPrograms>,
+        breeding: BreedingParameters,
+        migration_interval: usize,
+        migration_count: usize
+    ) -> IslandModel {
+        assert!(migration_interval > 0);
+        assert!(islands.iter().all(|island| island.len() >= migration_count));
+        IslandModel{ islands, breeding, migration_interval, migration_count, generation: 0 }
+    }
+
+    pub fn get_islands(&self) -> &[SortedEvaluatedPrograms] { &self.islands }
+
+    pub fn get_generation(&self) -> usize { self.generation }
+
+    /// Creates and evaluates (with `evaluate`) the next generation on each island; performs migration when due.
+    pub fn step<F: Fn(&vm::Program) -> Fitness, R: Rng>(&mut self, evaluate: F, rng: &mut R) {
+        let breeding = &self.breeding;
+        self.islands = self.islands.drain(..).map(|island| {
+            let new_population = create_new_population(island, breeding, rng);
+            let fitness = new_population.iter().map(&evaluate).collect();
+            SortedEvaluatedPrograms::new(new_population, fitness)
+        }).collect();
+
+        self.generation += 1;
+        if self.generation.is_multiple_of(self.migration_interval) {
+            self.migrate();
+        }
+    }
+
+    fn migrate(&mut self) {
+        let num_islands = self.islands.len();
+        if num_islands < 2 { return; }
+
+        let emigrants: Vec<Vec<(vm::Program, Fitness)>> = self.islands.iter().map(|island|
+            island.get_programs().iter().take(self.migration_count).map(|p| (p.prog.clone(), p.fitness)).collect()
+        ).collect();
+
+        for (i, emigrants) in emigrants.into_iter().enumerate() {
+            let dest = &mut self.islands[(i + 1) % num_islands];
+            let num_remaining = dest.len() - emigrants.len();
+
+            let mut programs = vec![];
+            let mut fitness = vec![];
+            for p in dest.programs.drain(..).take(num_remaining) {
+                programs.push(p.prog);
+                fitness.push(p.fitness);
+            }
+            for (prog, prog_fitness) in emigrants {
+                programs.push(prog);
+                fitness.push(prog_fitness);
+            }
+
+            *dest = SortedEvaluatedPrograms::new(programs, fitness);
+        }
+    }
+}
+
+/// Returns the edit (Levenshtein) distance between two instruction lists.
+pub fn edit_distance(instr1: &[vm::OpCode], instr2: &[vm::OpCode]) -> usize {
+    let mut prev_row: Vec<usize> = (0..=instr2.len()).collect();
+    let mut curr_row = vec![0; instr2.len() + 1];
+
+    for (i, opcode1) in instr1.iter().enumerate() {
+        curr_row[0] = i + 1;
+        for (j, opcode2) in instr2.iter().enumerate() {
+            let substitution_cost = if opcode1 == opcode2 { 0 } else { 1 };
+            curr_row[j + 1] = std::cmp::min(
+                prev_row[j] + substitution_cost,
+                std::cmp::min(prev_row[j + 1], curr_row[j]) + 1
+            );
+        }
+        std::mem::swap(&mut prev_row, &mut curr_row);
+    }
+
+    prev_row[instr2.len()]
+}
+
+///
+/// Returns the mean edit distance between `num_samples` randomly chosen pairs of programs
+/// (a measure of population diversity).
+///
+pub fn sampled_mean_edit_distance<Meta, R: Rng>(
+    programs: &SortedEvaluatedPrograms<Meta>,
+    num_samples: usize,
+    rng: &mut R
+) -> f64 {
+    if programs.len() < 2 || num_samples == 0 { return 0.0; }
+
+    let mut sum = 0;
+    for _ in 0..num_samples {
+        let index1 = rng.gen_range(0, programs.len());
+        let mut index2 = rng.gen_range(0, programs.len() - 1);
+        if index2 >= index1 { index2 += 1; }
+        sum += edit_distance(programs.programs[index1].prog.get_instr(), programs.programs[index2].prog.get_instr());
+    }
+
+    sum as f64 / num_samples as f64
+}
+
+///
+/// Fitness sharing (niching): multiplies each program's fitness by 1 + the number of other programs within
+/// edit distance `sigma` (see `edit_distance`), then re-sorts `programs`.
+///
+/// Penalizes crowded regions of the search space, so that distinct "species" of solutions can coexist.
+/// Modifies the stored `fitness` values (a previously applied length penalty order is not preserved).
+/// Assumes non-negative fitness; a fitness of 0 (perfect) stays unchanged. Takes O(n^2) edit distance computations.
+///
+pub fn apply_fitness_sharing<Meta>(programs: &mut SortedEvaluatedPrograms<Meta>, sigma: usize) {
+    let num_neighbors: Vec<usize> = programs.programs.iter().enumerate().map(|(i, p1)|
+        programs.programs.iter().enumerate()
+            .filter(|(j, p2)| i != *j && edit_distance(p1.prog.get_instr(), p2.prog.get_instr()) <= sigma)
+            .count()
+    ).collect();
+
+    for (p, n) in programs.programs.iter_mut().zip(num_neighbors) {
+        p.fitness *= (1 + n) as Fitness;
+    }
+    programs.programs.sort_by(|p1, p2| compare_fitness(p1.fitness, p2.fitness));
+}
+
+/// Per-generation log of (generation, mean fitness, diversity) for analyzing exploration vs. exploitation.
+#[derive(Default)]
+pub struct GenerationLog {
+    entries: Vec<(usize, Fitness, f64)>
+}
+
+impl GenerationLog {
+    pub fn new() -> GenerationLog { GenerationLog::default() }
+
+    /// Creates a log containing previously recorded `entries` (see `get_entries`).
+    pub fn with_entries(entries: Vec<(usize, Fitness, f64)>) -> GenerationLog { GenerationLog{ entries } }
+
+    ///
+    /// Appends an entry for `generation`; diversity is the sampled mean edit distance
+    /// (see `sampled_mean_edit_distance`).
+    ///
+    pub fn record<Meta, R: Rng>(
+        &mut self,
+        generation: usize,
+        programs: &SortedEvaluatedPrograms<Meta>,
+        num_diversity_samples: usize,
+        rng: &mut R
+    ) {
+        let mean_fitness = if programs.is_empty() {
+            WORST_FITNESS
+        } else {
+            programs.get_programs().iter().map(|p| p.fitness).sum::<Fitness>() / programs.len() as Fitness
+        };
+        let diversity = sampled_mean_edit_distance(programs, num_diversity_samples, rng);
+        self.entries.push((generation, mean_fitness, diversity));
+    }
+
+    pub fn get_entries(&self) -> &[(usize, Fitness, f64)] { &self.entries }
+
+    /// Saves the log as CSV with columns `generation,mean_fitness,diversity`.
+    pub fn write_csv<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut contents = "generation,mean_fitness,diversity\n".to_string();
+        for (generation, mean_fitness, diversity) in &self.entries {
+            contents += &format!("{},{},{}\n", generation, mean_fitness, diversity);
+        }
+        std::fs::write(path, contents)
+    }
+}
+
+/// Summary statistics of a single generation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GenerationStats {
+    pub best_fitness: Fitness,
+    pub worst_fitness: Fitness,
+    pub mean_fitness: Fitness,
+    pub median_fitness: Fitness,
+    /// Number of programs in the population.
+    pub num_programs: usize,
+    /// Mean number of instructions.
+    pub mean_length: f64,
+    /// Max. number of instructions.
+    pub max_length: usize,
+    /// Number of distinct instruction lists (a simple measure of diversity).
+    pub num_distinct: usize
+}
+
+impl<'a, Meta> From<&'a SortedEvaluatedPrograms<Meta>> for GenerationStats {
+    /// For an empty population, fitness values are `WORST_FITNESS` and the remaining values are 0.
+    fn from(programs: &'a SortedEvaluatedPrograms<Meta>) -> GenerationStats {
+        if programs.is_empty() {
+            return GenerationStats{
+                best_fitness: WORST_FITNESS,
+                worst_fitness: WORST_FITNESS,
+                mean_fitness: WORST_FITNESS,
+                median_fitness: WORST_FITNESS,
+                num_programs: 0,
+                mean_length: 0.0,
+                max_length: 0,
+                num_distinct: 0
+            };
+        }
+
+        let num_programs = programs.len();
+
+        // not taken from the list's ends, as it may have been sorted with a length penalty
+        let mut fitness: Vec<Fitness> = programs.get_programs().iter().map(|p| p.fitness).collect();
+        fitness.sort_by(|f1, f2| compare_fitness(*f1, *f2));
+        let median_fitness = if num_programs % 2 == 1 {
+            fitness[num_programs / 2]
+        } else {
+            (fitness[num_programs / 2 - 1] + fitness[num_programs / 2]) / 2.0
+        };
+
+        let lengths = programs.get_programs().iter().map(|p| p.prog.get_instr().len());
+        let distinct: std::collections::HashSet<&[vm::OpCode]> =
+            programs.get_programs().iter().map(|p| p.prog.get_instr()).collect();
+
+        GenerationStats{
+            best_fitness: fitness[0],
+            worst_fitness: fitness[num_programs - 1],
+            mean_fitness: fitness.iter().sum::<Fitness>() / num_programs as Fitness,
+            median_fitness,
+            num_programs,
+            mean_length: lengths.clone().sum::<usize>() as f64 / num_programs as f64,
+            max_length: lengths.max().unwrap(),
+            num_distinct: distinct.len()
+        }
+    }
+}
+
+/// Per-generation log of `GenerationStats`.
+#[derive(Default)]
+pub struct StatsLog {
+    entries: Vec<GenerationStats>
+}
+
+impl StatsLog {
+    pub fn new() -> StatsLog { StatsLog::default() }
+
+    pub fn record<Meta>(&mut self, programs: &SortedEvaluatedPrograms<Meta>) {
+        self.entries.push(GenerationStats::from(programs));
+    }
+
+    pub fn get_entries(&self) -> &[GenerationStats] { &self.entries }
+
+    ///
+    /// Returns the log as CSV with columns `generation,best_fitness,worst_fitness,mean_fitness,median_fitness,`
+    /// `mean_length,max_length,num_distinct` (generations are numbered from 0 in order of recording).
+    ///
+    pub fn to_csv(&self) -> String {
+        let mut result =
+            "generation,best_fitness,worst_fitness,mean_fitness,median_fitness,mean_length,max_length,num_distinct\n".to_string();
+        for (generation, stats) in self.entries.iter().enumerate() {
+            result += &format!(
+                "{},{},{},{},{},{},{},{}\n",
+                generation,
+                stats.best_fitness,
+                stats.worst_fitness,
+                stats.mean_fitness,
+                stats.median_fitness,
+                stats.mean_length,
+                stats.max_length,
+                stats.num_distinct
+            );
+        }
+        result
+    }
+}
+
+///
+/// Adjusts mutation strength to population diversity: the fewer distinct programs, the higher the mutation
+/// probability and number of mutations.
+///
+/// Diversity is `(num_distinct - 1) / (num_programs - 1)` (see `GenerationStats`), i.e. 0 if all programs
+/// are the same and 1 if all are different.
+///
+/// A smooth alternative to switching between fixed mutation settings when evolution stalls.
+///
+#[derive(Clone, Debug)]
+pub struct AdaptiveMutation {
+    min_probability: f64,
+    max_probability: f64,
+    min_num_mutations: usize,
+    max_num_mutations: usize,
+    /// Diversity at and above which mutation is the weakest.
+    target_diversity: f64,
+    /// Fraction (in (0, 1]) of the distance to the diversity-dependent strength covered by each `adjust`.
+    smoothing: f64,
+    /// Current mutation strength; 0 corresponds to the minimum, 1 to the maximum settings.
+    strength: f64
+}
+
+impl AdaptiveMutation {
+    ///
+    /// Creates a controller starting with the weakest mutation (`min_probability`, `min_num_mutations`).
+    ///
+    /// # Parameters
+    ///
+    /// * `probability` - Range of mutation probability.
+    /// * `num_mutations` - Range of the number of mutations.
+    /// * `target_diversity` - Diversity (in (0, 1]) at and above which mutation is the weakest;
+    ///   below it, strength grows linearly, reaching the maximum when all programs are the same.
+    /// * `smoothing` - Value in (0, 1]; 1 means mutation strength follows the current diversity immediately,
+    ///   smaller values make it change gradually over generations.
+    ///
+    pub fn new(
+        probability: std::ops::RangeInclusive<f64>,
+        num_mutations: std::ops::RangeInclusive<usize>,
+        target_diversity: f64,
+        smoothing: f64
+    ) -> AdaptiveMutation {
+        assert!(probability.start() <= probability.end());
+        assert!(num_mutations.start() <= num_mutations.end());
+        assert!(target_diversity > 0.0 && target_diversity <= 1.0);
+        assert!(smoothing > 0.0 && smoothing <= 1.0);
+
+        AdaptiveMutation{
+            min_probability: *probability.start(),
+            max_probability: *probability.end(),
+            min_num_mutations: *num_mutations.start(),
+            max_num_mutations: *num_mutations.end(),
+            target_diversity,
+            smoothing,
+            strength: 0.0
+        }
+    }
+
+    /// Returns the current mutation strength (0 - weakest, 1 - strongest).
+    pub fn get_strength(&self) -> f64 { self.strength }
+
+    ///
+    /// Updates mutation strength using the diversity of the just evaluated generation; returns the mutation
+    /// probability and number of mutations to be used when creating the next one.
+    ///
+    /// An empty population counts as having no diversity.
+    ///
+    pub fn adjust(&mut self, stats: &GenerationStats) -> (f64, usize) {
+        let diversity = if stats.num_programs == 0 {
+            0.0
+        } else {
+            (stats.num_distinct - 1) as f64 / std::cmp::max(stats.num_programs - 1, 1) as f64
+        };
+        let desired_strength = (1.0 - diversity / self.target_diversity).max(0.0);
+        self.strength += self.smoothing * (desired_strength - self.strength);
+
+        let probability = self.min_probability + self.strength * (self.max_probability - self.min_probability);
+        let num_mutations = self.min_num_mutations +
+            (self.strength * (self.max_num_mutations - self.min_num_mutations) as f64).round() as usize;
+
+        (probability, num_mutations)
+    }
+}
+
+/// Inverts `x ^ (x >> shift)`.
+fn invert_xor_shr(value: u32, shift: u32) -> u32 {
+    let mut result = value;
+    for _ in 0..32 / shift {
+        result = value ^ (result >> shift);
+    }
+    result
+}
+
+/// Inverts `x ^ (x << shift)`.
+fn invert_xor_shl(value: u32, shift: u32) -> u32 {
+    let mut result = value;
+    for _ in 0..32 / shift {
+        result = value ^ (result << shift);
+    }
+    result
+}
+
+///
+/// Returns the seed which makes `XorShiftRng::from_seed` create a generator identical to `rng`
+/// (i.e. producing the same sequence of numbers from now on).
+///
+pub fn rng_state(rng: &rand_xorshift::XorShiftRng) -> [u8; 16] {
+    // The generator's state (x, y, z, w) is not accessible; after 4 steps it consists of the 4 generated values.
+    // Each step is: t = x ^ (x << 11); (x, y, z) = (y, z, w); w = w ^ (w >> 19) ^ t ^ (t >> 8).
+    let mut future_rng = rng.clone();
+    let mut state = [0u32; 4];
+    for s in state.iter_mut() {
+        *s = future_rng.next_u32();
+    }
+
+    for _ in 0..4 {
+        let [x, y, z, w] = state;
+        let t = invert_xor_shr(w ^ z ^ (z >> 19), 8);
+        state = [invert_xor_shl(t, 11), x, y, z];
+    }
+
+    let mut seed = [0u8; 16];
+    for (i, s) in state.iter().enumerate() {
+        seed[4 * i..4 * i + 4].copy_from_slice(&s.to_le_bytes());
+    }
+    seed
+}
+
+/// Magic number starting the binary representation of an evolution checkpoint.
+const CHECKPOINT_MAGIC: &[u8] = b"GEVC";
+
+/// Version of the checkpoint binary format.
+const CHECKPOINT_VERSION: u8 = 1;
+
+///
+/// Complete state of an evolution run, allowing to resume it later with identical results
+/// as if it was not interrupted.
+///
+pub struct EvolutionCheckpoint {
+    pub programs: SortedEvaluatedPrograms,
+    pub rng: rand_xorshift::XorShiftRng,
+    /// Number of generations created so far.
+    pub generation: usize,
+    /// Arbitrary experiment-specific state (e.g. plateau detection counters).
+    pub user_data: Vec<u8>
+}
+
+impl EvolutionCheckpoint {
+    ///
+    /// Returns binary representation of the checkpoint.
+    ///
+    /// Format (multi-byte values are little-endian):
+    ///
+    /// * magic number `GEVC`
+    /// * format version (1 byte)
+    /// * generation (`u64`)
+    /// * random number generator state (16 bytes, see `rng_state`)
+    /// * number of programs (`u32`)
+    /// * programs (in sorted order): fitness (`f64`), length of program data (`u32`), program data (see `Program::to_bytes`)
+    /// * length of user data (`u32`), user data
+    ///
+    /// Panics if the number of programs or the length of program or user data does not fit in `u32`.
+    ///
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = CHECKPOINT_MAGIC.to_vec();
+        result.push(CHECKPOINT_VERSION);
+        result.extend_from_slice(&(self.generation as u64).to_le_bytes());
+        result.extend_from_slice(&rng_state(&self.rng));
+        result.extend_from_slice(&u32::try_from(self.programs.len()).expect("too many programs").to_le_bytes());
+        for program in self.programs.get_programs() {
+            let prog_data = program.prog.to_bytes();
+            result.extend_from_slice(&program.fitness.to_bits().to_le_bytes());
+            result.extend_from_slice(&u32::try_from(prog_data.len()).expect("program data too long").to_le_bytes());
+            result.extend_from_slice(&prog_data);
+        }
+        result.extend_from_slice(&u32::try_from(self.user_data.len()).expect("user data too long").to_le_bytes());
+        result.extend_from_slice(&self.user_data);
+
+        result
+    }
+
+    /// Creates a checkpoint from its binary representation (see `to_bytes`).
+    pub fn from_bytes(data: &[u8]) -> Result<EvolutionCheckpoint, ParseError> {
+        let mut reader = serialization::Reader::new(data);
+
+        if reader.bytes(CHECKPOINT_MAGIC.len()).map_err(|_| ParseError::InvalidHeader)? != CHECKPOINT_MAGIC {
+            return Err(ParseError::InvalidHeader);
+        }
+        let version = reader.u8()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(ParseError::UnsupportedVersion(version));
+        }
+        let generation = reader.u64()? as usize;
+        let mut seed = [0u8; 16];
+        seed.copy_from_slice(reader.bytes(16)?);
+        let rng = rand_xorshift::XorShiftRng::from_seed(seed);
+
+        let num_programs = reader.u32()? as usize;
+        let mut programs = Vec::with_capacity(std::cmp::min(num_programs, data.len()));
+        for _ in 0..num_programs {
+            let fitness = Fitness::from_bits(reader.u64()?);
+            let prog_data_len = reader.u32()? as usize;
+            let prog = vm::Program::from_bytes(reader.bytes(prog_data_len)?)?;
+            programs.push(EvaluatedProgram{ fitness, prog, meta: () });
+        }
+
+        let user_data_len = reader.u32()? as usize;
+        let user_data = reader.bytes(user_data_len)?.to_vec();
+
+        if !reader.is_at_end() {
+            return Err(ParseError::TrailingData);
+        }
+
+        // the saved order is kept as-is (it may have been sorted e.g. with a length penalty)
+        Ok(EvolutionCheckpoint{ programs: SortedEvaluatedPrograms{ programs }, rng, generation, user_data })
+    }
+
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<EvolutionCheckpoint> {
+        let data = std::fs::read(path)?;
+        EvolutionCheckpoint::from_bytes(&data).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod evaluated_programs_tests {
+    use utils::{evaluate_population, evaluate_test_cases, EvaluationOutcome, Fitness, SortedEvaluatedPrograms, WORST_FITNESS};
+    use vm::{OpCode, Program};
+
+    #[test]
+    fn metadata_stays_with_program() {
+        let programs = vec![
+            Program::new(&[OpCode::SetI(0)], 1, false),
+            Program::new(&[OpCode::SetI(1)], 1, false),
+            Program::new(&[OpCode::SetI(2)], 1, false)
+        ];
+        let sorted = SortedEvaluatedPrograms::with_metadata(
+            programs,
+            vec![3.0, 1.0, 2.0],
+            vec!["a", "b", "c"]
+        );
+
+        let result: Vec<(f64, OpCode, &str)> = sorted.get_programs().iter()
+            .map(|p| (p.fitness, p.prog.get_instr()[0], p.meta))
+            .collect();
+
+        assert_eq!(vec![
+            (1.0, OpCode::SetI(1), "b"),
+            (2.0, OpCode::SetI(2), "c"),
+            (3.0, OpCode::SetI(0), "a")
+        ], result);
+    }
+
+    #[test]
+    fn map_fitness() {
+        let programs = vec![
+            Program::new(&[OpCode::SetI(0)], 1, false),
+            Program::new(&[OpCode::SetI(1)], 1, false),
+            Program::new(&[OpCode::SetI(2)], 1, false),
+            Program::new(&[OpCode::SetI(3)], 1, false)
+        ];
+        let mut sorted = SortedEvaluatedPrograms::with_metadata(programs, vec![1.0, 2.0, 3.0, 4.0], vec![4.0, 0.5, 2.0, 0.5]);
+
+        // fitness divided by metadata (e.g. a niche count)
+        sorted.map_fitness(|p| p.fitness / p.meta);
+
+        let result: Vec<(f64, OpCode)> = sorted.iter().map(|p| (p.fitness, p.prog.get_instr()[0])).collect();
+        assert_eq!(vec![(0.25, OpCode::SetI(0)), (1.5, OpCode::SetI(2)), (4.0, OpCode::SetI(1)), (8.0, OpCode::SetI(3))], result);
+    }
+
+    #[test]
+    fn accessors() {
+        let programs = vec![
+            Program::new(&[OpCode::SetI(0)], 1, false),
+            Program::new(&[OpCode::SetI(1)], 1, false),
+            Program::new(&[OpCode::SetI(2)], 1, false)
+        ];
+        let sorted = SortedEvaluatedPrograms::new(programs, vec![2.0, 3.0, 1.0]);
+
+        assert_eq!(vec![1.0, 2.0, 3.0], sorted.iter().map(|p| p.fitness).collect::<Vec<Fitness>>());
+        assert_eq!(OpCode::SetI(2), sorted.best().unwrap().prog.get_instr()[0]);
+        assert_eq!(OpCode::SetI(1), sorted.worst().unwrap().prog.get_instr()[0]);
+        assert_eq!(OpCode::SetI(0), sorted.nth_best(1).unwrap().prog.get_instr()[0]);
+        assert!(sorted.nth_best(3).is_none());
+    }
+
+    #[test]
+    fn accessors_of_empty_population() {
+        let sorted = SortedEvaluatedPrograms::new(vec![], vec![]);
+
+        assert_eq!(0, sorted.iter().count());
+        assert!(sorted.best().is_none());
+        assert!(sorted.worst().is_none());
+        assert!(sorted.nth_best(0).is_none());
+    }
+
+    #[test]
+    fn length_penalty() {
+        let programs = || vec![
+            Program::new(&[OpCode::SetI(0), OpCode::Nop, OpCode::Nop], 1, false),
+            Program::new(&[OpCode::SetI(1)], 1, false)
+        ];
+
+        let sorted = SortedEvaluatedPrograms::with_length_penalty(programs(), vec![2.0, 2.0], vec![(), ()], 0.0);
+        assert_eq!(3, sorted.get_programs()[0].prog.get_instr().len());
+
+        let sorted = SortedEvaluatedPrograms::with_length_penalty(programs(), vec![2.0, 2.0], vec![(), ()], 0.01);
+        assert_eq!(1, sorted.get_programs()[0].prog.get_instr().len());
+        assert!(sorted.get_programs().iter().all(|p| p.fitness == 2.0));
+
+        // penalty outweighing a fitness difference
+        let sorted = SortedEvaluatedPrograms::with_length_penalty(programs(), vec![1.9, 2.0], vec![(), ()], 0.1);
+        assert_eq!(OpCode::SetI(1), sorted.get_programs()[0].prog.get_instr()[0]);
+        assert_eq!(2.0, sorted.get_programs()[0].fitness);
+    }
+
+    #[test]
+    fn length_penalty_roulette_breeding() {
+        use rand::SeedableRng;
+        use utils::{create_new_population, BreedingParameters, CrossoverKind, MutationWeights, SelectionStrategy};
+
+        let programs = (0..8).map(|i| Program::new(&vec![OpCode::IncV; 8 - i], 1, false)).collect();
+        // the shortest programs (with the worst fitness) come first
+        let sorted = SortedEvaluatedPrograms::with_length_penalty(programs, (0..8).map(|i| i as Fitness).collect(), vec![(); 8], 2.0);
+        assert!(sorted.get_programs()[0].fitness > sorted.get_programs()[7].fitness);
+
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let new_population = create_new_population(
+            sorted,
+            &BreedingParameters{
+                mutation_probability: 0.5,
+                num_mutations: 2,
+                mutation_weights: MutationWeights::default(),
+                selection: SelectionStrategy::Roulette,
+                elitism_count: 1,
+                crossover: CrossoverKind::SingleSegment,
+                allowed_instructions: vec![OpCode::IncV, OpCode::DecV],
+                min_crossover_seg_length: 1,
+                max_crossover_seg_length: 3,
+                min_program_length: 1,
+                max_program_length: 16,
+                allow_crossing_blocks: false,
+                preserve_blocks: false,
+                allow_guarded_control_flow: true,
+                enforce_uniqueness: false
+            },
+            &mut rng
+        );
+        assert_eq!(8, new_population.len());
+    }
+
+    #[test]
+    fn parallel_evaluation() {
+        let programs: Vec<Program> = (0..20).map(|i| Program::new(&vec![OpCode::Nop; 20 - i], 1, false)).collect();
+        let length = |p: &Program| p.get_instr().len() as f64;
+
+        let (sorted, any_solved) = evaluate_population(programs.clone(), None, |p, _| EvaluationOutcome{ fitness: length(p), solved: false });
+        assert!(!any_solved);
+        let fitness: Vec<f64> = sorted.get_programs().iter().map(|p| p.fitness).collect();
+        assert_eq!((1..=20).map(|i| i as f64).collect::<Vec<f64>>(), fitness);
+        assert!(sorted.get_programs().iter().all(|p| p.fitness == length(&p.prog)));
+
+        let (_, any_solved) = evaluate_population(programs, None, |p, _| EvaluationOutcome{ fitness: length(p), solved: p.get_instr().len() == 7 });
+        assert!(any_solved);
+    }
+
+    #[test]
+    fn any_solved() {
+        let programs: Vec<Program> = (0..10).map(|i| Program::new(&[OpCode::SetI(i)], 1, false)).collect();
+        let solved_sets: [&[i32]; 4] = [&[], &[0], &[9], &[2, 3, 4]];
+
+        for solved_set in solved_sets.iter() {
+            let (sorted, any_solved) = evaluate_population(programs.clone(), None, |p, _| {
+                let i = match p.get_instr()[0] { OpCode::SetI(i) => i, _ => unreachable!() };
+                EvaluationOutcome{ fitness: i as Fitness, solved: solved_set.contains(&i) }
+            });
+            assert_eq!(!solved_set.is_empty(), any_solved);
+            assert_eq!(programs.len(), sorted.len());
+        }
+    }
+
+    #[test]
+    fn early_abort() {
+        let test_cases = [50.0, 1.0, 1.0];
+        let mut num_evaluated = 0;
+        let outcome = evaluate_test_cases(&test_cases, Some(10.0), |tc| {
+            num_evaluated += 1;
+            EvaluationOutcome{ fitness: *tc, solved: true }
+        });
+        assert_eq!(1, num_evaluated);
+        assert_eq!(EvaluationOutcome{ fitness: 10.0, solved: false }, outcome);
+
+        let outcome = evaluate_test_cases(&test_cases, None, |tc| EvaluationOutcome{ fitness: *tc, solved: true });
+        assert_eq!(EvaluationOutcome{ fitness: 52.0, solved: true }, outcome);
+
+        let programs = vec![Program::new(&[OpCode::SetI(0)], 1, false), Program::new(&[OpCode::SetI(1)], 1, false)];
+        let (sorted, _) = evaluate_population(programs, Some(10.0), |p, fitness_cap| {
+            assert_eq!(Some(10.0), fitness_cap);
+            EvaluationOutcome{ fitness: if p.get_instr()[0] == OpCode::SetI(0) { 5.0 } else { 20.0 }, solved: false }
+        });
+        assert_eq!(vec![5.0, 10.0], sorted.iter().map(|p| p.fitness).collect::<Vec<Fitness>>());
+    }
+
+    #[test]
+    fn nan_fitness_sorts_last() {
+        let programs = vec![
+            Program::new(&[OpCode::SetI(0)], 1, false),
+            Program::new(&[OpCode::SetI(1)], 1, false),
+            Program::new(&[OpCode::SetI(2)], 1, false)
+        ];
+        let sorted = SortedEvaluatedPrograms::new(programs, vec![WORST_FITNESS, f64::NAN, 1.0]);
+
+        assert_eq!(1.0, sorted.get_programs()[0].fitness);
+        assert_eq!(WORST_FITNESS, sorted.get_programs()[1].fitness);
+        assert!(sorted.get_programs()[2].fitness.is_nan());
+        assert_eq!(OpCode::SetI(1), sorted.get_programs()[2].prog.get_instr()[0]);
+    }
+}
+
+#[cfg(test)]
+mod hall_of_fame_tests {
+    use utils::{Fitness, HallOfFame, SortedEvaluatedPrograms};
+    use vm::{OpCode, Program};
+
+    fn generation(opcodes: &[OpCode], fitness: Vec<Fitness>) -> SortedEvaluatedPrograms {
+        SortedEvaluatedPrograms::new(opcodes.iter().map(|opcode| Program::new(&[*opcode], 0, false)).collect(), fitness)
+    }
+
+    fn held(hall_of_fame: &HallOfFame) -> Vec<(Fitness, Vec<OpCode>)> {
+        hall_of_fame.get_programs().iter().map(|p| (p.fitness, p.prog.get_instr().to_vec())).collect()
+    }
+
+    #[test]
+    fn keeps_best_of_earlier_generation() {
+        let mut hall_of_fame = HallOfFame::new(2);
+
+        hall_of_fame.consider(&generation(&[OpCode::IncV, OpCode::DecV, OpCode::Nop], vec![2.0, 1.0, 5.0]));
+        assert_eq!(vec![(1.0, vec![OpCode::DecV]), (2.0, vec![OpCode::IncV])], held(&hall_of_fame));
+
+        // a worse generation
+        hall_of_fame.consider(&generation(&[OpCode::Neg, OpCode::Abs], vec![3.0, 4.0]));
+        assert_eq!(1.0, hall_of_fame.best().fitness);
+        assert_eq!(vec![OpCode::DecV], hall_of_fame.best().prog.get_instr());
+
+        hall_of_fame.consider(&generation(&[OpCode::Neg, OpCode::Abs], vec![1.5, 0.5]));
+        assert_eq!(vec![(0.5, vec![OpCode::Abs]), (1.0, vec![OpCode::DecV])], held(&hall_of_fame));
+    }
+
+    #[test]
+    fn no_duplicates() {
+        let mut hall_of_fame = HallOfFame::new(3);
+
+        hall_of_fame.consider(&generation(&[OpCode::IncV, OpCode::IncV], vec![1.0, 1.0]));
+        hall_of_fame.consider(&generation(&[OpCode::IncV, OpCode::DecV], vec![1.0, 2.0]));
+        assert_eq!(vec![(1.0, vec![OpCode::IncV]), (2.0, vec![OpCode::DecV])], held(&hall_of_fame));
+    }
+}
+
+#[cfg(test)]
+mod vmasm_tests {
+    use rand::SeedableRng;
+    use serialization::ParseError;
+    use utils::{generate_random_programs, parse_vmasm, pretty_print};
+    use vm::OpCode;
+
+    #[test]
+    fn hand_written() {
+        let text = "
+            // computes data[0] * 2
+            seti 0
+            load       // reg_v := data[0]
+            add
+            jumpifn
+              output -1
+            endjump
+        ";
+        let program = parse_vmasm(text, 1, false).unwrap();
+
+        assert!(program.get_instr() == [
+            OpCode::SetI(0),
+            OpCode::Load,
+            OpCode::Add,
+            OpCode::JumpIfN,
+            OpCode::Output(-1),
+            OpCode::EndJump
+        ]);
+        assert_eq!(1, program.get_num_data_slots());
+        assert!(program.get_jump_table()[3] == Some(5));
+    }
+
+    #[test]
+    fn pretty_print_round_trip() {
+        let allowed_instructions = [
+            OpCode::SetI(0), OpCode::SetI(-3), OpCode::Input(1), OpCode::Output(2),
+            OpCode::ItoV, OpCode::VtoI, OpCode::IncV, OpCode::DecV, OpCode::IncI, OpCode::DecI,
+            OpCode::Load, OpCode::Store, OpCode::Swap,
+            OpCode::EndGoTo, OpCode::GoToIfP, OpCode::JumpIfN, OpCode::EndJump, OpCode::IfP, OpCode::IfN,
+            OpCode::Cmp, OpCode::Add, OpCode::Sub, OpCode::Mul, OpCode::Div,
+            OpCode::Abs, OpCode::Neg, OpCode::Sqrt, OpCode::Pow, OpCode::Log, OpCode::Sin, OpCode::Cos, OpCode::RandV,
+            OpCode::SetBank(-2), OpCode::Halt, OpCode::Nop
+        ];
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let programs = generate_random_programs(50, 1, 100, 4, &allowed_instructions, None, false, true, &mut rng);
+
+        for program in programs.iter() {
+            for (marker, instr_numbers, indentation) in &[(None, false, None), (Some("*"), true, Some(2)), (Some("~~"), false, Some(4))] {
+                let text = pretty_print(program, *marker, *instr_numbers, *indentation);
+                let parsed = parse_vmasm(&text, 4, false).unwrap();
+                assert!(program.get_instr() == parsed.get_instr());
+                assert_eq!(text, pretty_print(&parsed, *marker, *instr_numbers, *indentation));
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Err(ParseError::UnknownMnemonic{ line: 2, mnemonic: "jump".to_string() }),
+            parse_vmasm("nop\njump\n", 1, false).map(|_| ())
+        );
+        assert_eq!(
+            Err(ParseError::InvalidOperand{ line: 1, operand: "x".to_string() }),
+            parse_vmasm("seti x", 1, false).map(|_| ())
+        );
+        assert_eq!(
+            Err(ParseError::InvalidOperand{ line: 3, operand: "".to_string() }),
+            parse_vmasm("\n\ninput", 1, false).map(|_| ())
+        );
+        assert_eq!(
+            Err(ParseError::InvalidOperand{ line: 1, operand: "5".to_string() }),
+            parse_vmasm("add 5", 1, false).map(|_| ())
+        );
+    }
+}
+
+#[cfg(test)]
+mod repair_tests {
+    use rand::SeedableRng;
+    use utils::{
+        create_new_population_with_operators, generate_random_programs, is_block_instruction, mutate, repair_control_flow,
+        repair_guarded_control_flow, BreedingParameters, CrossoverKind, DefaultMutator, DefaultRecombinator, MutationWeights,
+        SelectionStrategy, SortedEvaluatedPrograms
+    };
+    use vm::{OpCode, Program};
+
+    #[test]
+    fn unbalanced_splice() {
+        let mut instr = vec![
+            OpCode::GoToIfP, // unmatched
+            OpCode::EndGoTo, // unmatched
+            OpCode::JumpIfN,
+                OpCode::JumpIfN,
+                    OpCode::Add,
+                OpCode::EndJump,
+                OpCode::EndGoTo,
+                    OpCode::IncV,
+                OpCode::GoToIfP,
+            OpCode::EndJump
+        ];
+        repair_control_flow(&mut instr);
+
+        assert_eq!(vec![
+            OpCode::JumpIfN,
+                OpCode::JumpIfN,
+                    OpCode::Add,
+                OpCode::EndJump,
+                OpCode::EndGoTo,
+                    OpCode::IncV,
+                OpCode::GoToIfP,
+            OpCode::EndJump
+        ], instr);
+
+        let program = Program::new(&instr, 1, true);
+        for (i, opcode) in program.get_instr().iter().enumerate() {
+            match opcode {
+                OpCode::GoToIfP | OpCode::EndGoTo | OpCode::JumpIfN | OpCode::EndJump => assert!(program.get_jump_table()[i].is_some()),
+                _ => ()
+            }
+        }
+    }
+
+    #[test]
+    fn balanced_unchanged() {
+        let original = vec![OpCode::JumpIfN, OpCode::EndGoTo, OpCode::EndJump, OpCode::GoToIfP];
+        let mut instr = original.clone();
+        repair_control_flow(&mut instr);
+        assert_eq!(original, instr);
+    }
+
+    #[test]
+    fn guarded_control_flow() {
+        let mut instr = vec![
+            OpCode::IfP,
+                OpCode::IncV,
+            OpCode::IfN,     // removed
+            OpCode::IfP,     // removed
+                OpCode::JumpIfN,
+            OpCode::IfN,
+                OpCode::IfP,
+                    OpCode::Add,
+            OpCode::IfN,     // removed
+                OpCode::EndJump
+        ];
+        repair_guarded_control_flow(&mut instr);
+
+        assert_eq!(vec![
+            OpCode::IfP, OpCode::IncV, OpCode::JumpIfN, OpCode::IfN, OpCode::IfP, OpCode::Add, OpCode::EndJump
+        ], instr);
+    }
+
+    /// Returns true if no `IfP`/`IfN` directly precedes a control flow instruction.
+    fn no_guarded_control_flow(instr: &[OpCode]) -> bool {
+        instr.windows(2).all(|w| ![OpCode::IfP, OpCode::IfN].contains(&w[0]) || !is_block_instruction(w[1]))
+    }
+
+    #[test]
+    fn generation_and_mutation_constraint() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let allowed_instructions = [
+            OpCode::IfP, OpCode::IfN, OpCode::GoToIfP, OpCode::EndGoTo, OpCode::JumpIfN, OpCode::EndJump, OpCode::DecV
+        ];
+
+        let unconstrained = generate_random_programs(100, 10, 30, 0, &allowed_instructions, None, true, true, &mut rng);
+        assert!(unconstrained.iter().any(|p| !no_guarded_control_flow(p.get_instr())));
+
+        let programs = generate_random_programs(100, 10, 30, 0, &allowed_instructions, None, true, false, &mut rng);
+        for program in &programs {
+            assert!(no_guarded_control_flow(program.get_instr()));
+
+            let mut instr = program.get_instr().to_vec();
+            for &preserve_blocks in &[false, true] {
+                mutate(&mut instr, 5, &MutationWeights::default(), &allowed_instructions, preserve_blocks, false, &mut rng);
+                assert!(no_guarded_control_flow(&instr));
+            }
+        }
+    }
+
+    #[test]
+    fn breeding_constraint() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let allowed_instructions = [
+            OpCode::IfP, OpCode::IfN, OpCode::GoToIfP, OpCode::EndGoTo, OpCode::JumpIfN, OpCode::EndJump, OpCode::DecV
+        ];
+        let programs = generate_random_programs(100, 10, 30, 0, &allowed_instructions, None, true, false, &mut rng);
+        let recombinator = DefaultRecombinator{
+            kind: CrossoverKind::SingleSegment, min_seg_len: 1, max_seg_len: 10, allow_control_flow_block_xing: true
+        };
+        // no mutation, so only recombination may guard control flow instructions
+        let mutator = DefaultMutator{
+            num_mutations: 0,
+            weights: MutationWeights::default(),
+            allowed_instructions: &allowed_instructions,
+            preserve_blocks: false,
+            allow_guarded_control_flow: false
+        };
+
+        let breed = |allow_guarded_control_flow: bool| {
+            let breeding = BreedingParameters{
+                mutation_probability: 0.0,
+                num_mutations: 0,
+                mutation_weights: MutationWeights::default(),
+                selection: SelectionStrategy::TopFraction{ fraction: 1.0 },
+                elitism_count: 0,
+                crossover: CrossoverKind::SingleSegment,
+                allowed_instructions: allowed_instructions.to_vec(),
+                min_crossover_seg_length: 1,
+                max_crossover_seg_length: 10,
+                min_program_length: 1,
+                max_program_length: 40,
+                allow_crossing_blocks: true,
+                preserve_blocks: false,
+                allow_guarded_control_flow,
+                enforce_uniqueness: false
+            };
+            let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(2);
+            create_new_population_with_operators(
+                SortedEvaluatedPrograms::new(programs.clone(), vec![1.0; programs.len()]), &breeding, &recombinator, &mutator, &mut rng
+            )
+        };
+
+        assert!(breed(true).iter().any(|p| !no_guarded_control_flow(p.get_instr())));
+
+        let new_population = breed(false);
+        assert_eq!(programs.len(), new_population.len());
+        assert!(new_population.iter().all(|p| no_guarded_control_flow(p.get_instr())));
+    }
+}
+
+#[cfg(test)]
+mod instruction_set_builder_tests {
+    use utils::*;
+    use vm::OpCode;
+
+    #[test]
+    fn expanded_ranges() {
+        let allowed = InstructionSetBuilder::new()
+            .set_i_range(0..=2)
+            .inputs(1..=2)
+            .outputs(0..=0)
+            .enable(OpCode::Add)
+            .enable_all(&[OpCode::Load, OpCode::Store])
+            .set_bank_range(-1..=0)
+            .build();
+
+        assert!(allowed == [
+            OpCode::SetI(0), OpCode::SetI(1), OpCode::SetI(2),
+            OpCode::Input(1), OpCode::Input(2),
+            OpCode::Output(0),
+            OpCode::Add, OpCode::Load, OpCode::Store,
+            OpCode::SetBank(-1), OpCode::SetBank(0)
+        ]);
+    }
+
+    #[test]
+    fn duplicates_and_empty_ranges() {
+        let mut builder = InstructionSetBuilder::new();
+        builder.enable(OpCode::Nop).set_i_range(0..=1).enable(OpCode::SetI(1)).enable(OpCode::Nop);
+        #[allow(clippy::reversed_empty_ranges)]
+        builder.outputs(3..=2);
+        assert!(builder.build() == [OpCode::Nop, OpCode::SetI(0), OpCode::SetI(1)]);
+    }
+}
+
+#[cfg(test)]
+mod seed_population_tests {
+    use rand::SeedableRng;
+    use utils::*;
+    use vm::{OpCode, Program};
+
+    #[test]
+    fn seeds_included() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let seeds = vec![
+            Program::new(&[OpCode::Input(0), OpCode::Output(1)], 2, false),
+            Program::new(&[OpCode::Load, OpCode::IncV, OpCode::Store], 2, false)
+        ];
+
+        let programs = seed_population(&seeds, 10, |count| {
+            assert_eq!(8, count);
+            generate_random_programs(count, 3, 5, 2, &[OpCode::IncV, OpCode::DecV], None, false, true, &mut rng)
+        });
+        assert_eq!(10, programs.len());
+        assert!(programs[0].get_instr() == seeds[0].get_instr());
+        assert!(programs[1].get_instr() == seeds[1].get_instr());
+        for program in &programs[2..] {
+            assert!(program.get_instr().len() >= 3 && program.get_instr().len() <= 5);
+            assert!(program.get_instr().iter().all(|opcode| *opcode == OpCode::IncV || *opcode == OpCode::DecV));
+        }
+    }
+
+    #[test]
+    fn no_seeds_same_as_random() {
+        let allowed_instructions = [OpCode::IncV, OpCode::DecV, OpCode::Nop];
+        let mut rng1 = rand_xorshift::XorShiftRng::seed_from_u64(5);
+        let mut rng2 = rand_xorshift::XorShiftRng::seed_from_u64(5);
+
+        let seeded = seed_population(&[], 8,
+            |count| generate_random_programs(count, 1, 10, 1, &allowed_instructions, None, false, true, &mut rng1));
+        let random = generate_random_programs(8, 1, 10, 1, &allowed_instructions, None, false, true, &mut rng2);
+        assert!(seeded.iter().map(|p| p.get_instr()).eq(random.iter().map(|p| p.get_instr())));
+    }
+
+    #[test]
+    #[should_panic(expected = "all programs must use 3 data slots")]
+    fn seed_data_slots_mismatch() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let seeds = vec![Program::new(&[OpCode::Load], 3, false)];
+        seed_population(&seeds, 4, |count| generate_random_programs(count, 1, 2, 2, &[OpCode::Nop], None, false, true, &mut rng));
+    }
+
+    #[test]
+    #[should_panic(expected = "expected 3 random programs")]
+    fn wrong_number_of_random_programs() {
+        let seeds = vec![Program::new(&[OpCode::Load], 1, false)];
+        seed_population(&seeds, 4, |_| vec![]);
+    }
+}
+
+#[cfg(test)]
+mod rel_probability_tests {
+    use rand::SeedableRng;
+    use std::collections::HashMap;
+    use utils::{generate_random_programs, rel_probability_by_mnemonic};
+    use vm::OpCode;
+
+    #[test]
+    fn weights_by_mnemonic() {
+        let allowed = [OpCode::SetI(1), OpCode::SetI(2), OpCode::Nop, OpCode::Add, OpCode::Neg];
+        let weights: HashMap<&str, f64> = [("nop", 0.0), ("add", 6.0), ("seti", 0.5)].iter().cloned().collect();
+        let rel_probability = rel_probability_by_mnemonic(&allowed, &weights);
+        assert_eq!(vec![0.5, 0.5, 0.0, 6.0, 1.0], rel_probability);
+
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let programs = generate_random_programs(100, 100, 100, 0, &allowed, Some(&rel_probability), false, true, &mut rng);
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for program in &programs {
+            for (mnemonic, count) in program.opcode_histogram() {
+                *counts.entry(mnemonic).or_insert(0) += count;
+            }
+        }
+        assert!(!counts.contains_key("nop"));
+        // expected fractions: `seti` 1/8, `add` 6/8, `neg` 1/8
+        let fraction = |mnemonic| counts[mnemonic] as f64 / 10000.0;
+        assert!((fraction("seti") - 0.125).abs() < 0.02);
+        assert!((fraction("add") - 0.75).abs() < 0.02);
+        assert!((fraction("neg") - 0.125).abs() < 0.02);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown mnemonic: nopp")]
+    fn unknown_mnemonic() {
+        let weights: HashMap<&str, f64> = [("nopp", 0.0)].iter().cloned().collect();
+        rel_probability_by_mnemonic(&[OpCode::Nop], &weights);
+    }
+}
+
+#[cfg(test)]
+mod diversity_tests {
+    use rand::SeedableRng;
+    use utils::*;
+    use vm::OpCode;
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(0, edit_distance(&[], &[]));
+        assert_eq!(2, edit_distance(&[OpCode::Add, OpCode::Sub], &[]));
+        assert_eq!(0, edit_distance(&[OpCode::Add, OpCode::SetI(1)], &[OpCode::Add, OpCode::SetI(1)]));
+        assert_eq!(1, edit_distance(&[OpCode::Add, OpCode::SetI(1)], &[OpCode::Add, OpCode::SetI(2)]));
+        assert_eq!(2, edit_distance(&[OpCode::Add, OpCode::Sub, OpCode::Mul], &[OpCode::Sub, OpCode::Mul, OpCode::Div]));
+    }
+
+    #[test]
+    fn fitness_sharing() {
+        let crowded = [
+            [OpCode::Add, OpCode::Sub, OpCode::Mul, OpCode::Div],
+            [OpCode::Add, OpCode::Sub, OpCode::Mul, OpCode::Nop],
+            [OpCode::Add, OpCode::Sub, OpCode::Nop, OpCode::Div],
+            [OpCode::Add, OpCode::Nop, OpCode::Mul, OpCode::Div]
+        ];
+        let sparse = [
+            [OpCode::IncV, OpCode::IncV, OpCode::Load, OpCode::Store],
+            [OpCode::IncV, OpCode::IncV, OpCode::Load, OpCode::Swap]
+        ];
+        let programs: Vec<vm::Program> = crowded.iter().chain(sparse.iter()).map(|instr| vm::Program::new(instr, 1, true)).collect();
+        let mut sorted = SortedEvaluatedPrograms::new(programs, vec![10.0, 10.0, 10.0, 10.0, 11.0, 11.0]);
+
+        apply_fitness_sharing(&mut sorted, 2);
+
+        let result: Vec<Fitness> = sorted.get_programs().iter().map(|p| p.fitness).collect();
+        assert_eq!(vec![22.0, 22.0, 40.0, 40.0, 40.0, 40.0], result);
+        assert!(sorted.get_programs()[..2].iter().all(|p| p.prog.get_instr()[0] == OpCode::IncV));
+
+        // no other programs within distance 0
+        let mut sorted = SortedEvaluatedPrograms::new(vec![vm::Program::new(&crowded[0], 1, true); 1], vec![3.0]);
+        apply_fitness_sharing(&mut sorted, 0);
+        assert_eq!(3.0, sorted.get_programs()[0].fitness);
+    }
+
+    #[test]
+    fn csv_has_row_per_generation() {
+        const NUM_GENERATIONS: usize = 5;
+        let allowed_instructions = [OpCode::Add, OpCode::Sub, OpCode::IncV, OpCode::Nop];
+
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let mut programs = generate_random_programs(20, 2, 10, 1, &allowed_instructions, None, false, true, &mut rng);
+        let mut log = GenerationLog::new();
+
+        for generation in 0..NUM_GENERATIONS {
+            let fitness = programs.iter().map(|p| p.get_instr().len() as Fitness).collect();
+            let sorted = SortedEvaluatedPrograms::new(programs, fitness);
+            log.record(generation, &sorted, 10, &mut rng);
+            programs = create_new_population(
+                sorted,
+                &BreedingParameters{
+                    mutation_probability: 0.5,
+                    num_mutations: 1,
+                    mutation_weights: MutationWeights::default(),
+                    selection: SelectionStrategy::TopFraction{ fraction: 0.5 },
+                    elitism_count: 0,
+                    crossover: CrossoverKind::SingleSegment,
+                    allowed_instructions: allowed_instructions.to_vec(),
+                    min_crossover_seg_length: 1,
+                    max_crossover_seg_length: 3,
+                    min_program_length: 0,
+                    max_program_length: 10,
+                    allow_crossing_blocks: true,
+                    preserve_blocks: false,
+                    allow_guarded_control_flow: true,
+                    enforce_uniqueness: false
+                },
+                &mut rng
+            );
+        }
+
+        let path = std::env::temp_dir().join("genetic_generation_log_test.csv");
+        log.write_csv(&path).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(NUM_GENERATIONS + 1, lines.len());
+        assert_eq!("generation,mean_fitness,diversity", lines[0]);
+        for (generation, line) in lines[1..].iter().enumerate() {
+            let columns: Vec<&str> = line.split(',').collect();
+            assert_eq!(3, columns.len());
+            assert_eq!(generation, columns[0].parse::<usize>().unwrap());
+            assert!(columns[1].parse::<f64>().unwrap() >= 0.0);
+            assert!(columns[2].parse::<f64>().unwrap() >= 0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod selection_tests {
+    use rand::SeedableRng;
+    use utils::*;
+    use vm::OpCode;
+
+    const NUM_PROGRAMS: usize = 10;
+    const NUM_SELECTIONS: usize = 10000;
+
+    /// Returns the number of times each program (sorted by fitness) was selected.
+    fn selection_counts(strategy: SelectionStrategy) -> Vec<usize> {
+        let programs = SortedEvaluatedPrograms::new(
+            (0..NUM_PROGRAMS).map(|_| vm::Program::new(&[OpCode::Nop], 0, false)).collect(),
+            (0..NUM_PROGRAMS).rev().map(|i| i as Fitness).collect()
+        );
+        let selector = ParentSelector::new(strategy, &programs);
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+
+        let mut counts = vec![0; NUM_PROGRAMS];
+        for _ in 0..NUM_SELECTIONS {
+            counts[selector.select(&mut rng)] += 1;
+        }
+
+        counts
+    }
+
+    #[test]
+    fn top_fraction() {
+        let counts = selection_counts(SelectionStrategy::TopFraction{ fraction: 0.3 });
+        assert!(counts[..3].iter().all(|&c| c > 0));
+        assert!(counts[3..].iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn tournament() {
+        let counts = selection_counts(SelectionStrategy::Tournament{ size: 3, fraction_eligible: 1.0 });
+        assert!(counts.windows(2).all(|w| w[0] > w[1]));
+        assert!(counts[NUM_PROGRAMS - 1] > 0);
+
+        let counts = selection_counts(SelectionStrategy::Tournament{ size: 3, fraction_eligible: 0.5 });
+        assert!(counts[5..].iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn roulette() {
+        let counts = selection_counts(SelectionStrategy::Roulette);
+        assert!(counts.windows(2).all(|w| w[0] > w[1]));
+        assert!(counts[NUM_PROGRAMS - 1] > 0);
+        // weights are 1, 1/2, 1/3, ...
+        assert!(counts[0] > 2 * counts[3]);
+    }
+
+    /// Returns the number of times each program of `programs` was selected by `SelectionStrategy::Roulette`.
+    fn roulette_counts(programs: &SortedEvaluatedPrograms) -> Vec<usize> {
+        let selector = ParentSelector::new(SelectionStrategy::Roulette, programs);
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+
+        let mut counts = vec![0; programs.len()];
+        for _ in 0..NUM_SELECTIONS {
+            counts[selector.select(&mut rng)] += 1;
+        }
+
+        counts
+    }
+
+    #[test]
+    fn roulette_best_not_first() {
+        // sorted as: 1 instruction (fitness 2.0), 20 instructions (fitness 1.0)
+        let programs = SortedEvaluatedPrograms::with_length_penalty(
+            vec![vm::Program::new(&[OpCode::Nop; 20], 0, false), vm::Program::new(&[OpCode::Nop], 0, false)],
+            vec![1.0, 2.0],
+            vec![(), ()],
+            1.0
+        );
+        assert_eq!(2.0, programs.get_programs()[0].fitness);
+
+        // weights are 1/2, 1
+        let counts = roulette_counts(&programs);
+        assert!(counts[0] > 0 && counts[1] > counts[0]);
+    }
+
+    #[test]
+    fn roulette_non_finite_fitness() {
+        let nops = |count| (0..count).map(|_| vm::Program::new(&[OpCode::Nop], 0, false)).collect::<Vec<_>>();
+
+        let all_infinite = SortedEvaluatedPrograms::new(nops(3), vec![Fitness::INFINITY; 3]);
+        assert!(roulette_counts(&all_infinite).iter().all(|&c| c > 0));
+
+        let all_nan = SortedEvaluatedPrograms::new(nops(3), vec![Fitness::NAN; 3]);
+        assert!(roulette_counts(&all_nan).iter().all(|&c| c > 0));
+
+        let mixed = SortedEvaluatedPrograms::new(nops(3), vec![Fitness::NAN, 1.0, Fitness::INFINITY]);
+        let counts = roulette_counts(&mixed);
+        assert_eq!(NUM_SELECTIONS, counts[mixed.get_programs().iter().position(|p| p.fitness == 1.0).unwrap()]);
+    }
+}
+
+#[cfg(test)]
+mod composite_fitness_tests {
+    use utils::CompositeFitness;
+
+    #[test]
+    fn total_and_breakdown() {
+        let mut fitness = CompositeFitness::new();
+        fitness
+            .add_term("distance", 1.0, 20.0)
+            .add_term("path length", 0.5, 8.0)
+            .add_term("coverage bonus", -2.0, 3.0);
+
+        assert_eq!(18.0, fitness.total());
+        assert_eq!(vec![("distance", 20.0), ("path length", 4.0), ("coverage bonus", -6.0)], fitness.breakdown());
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(0.0, CompositeFitness::new().total());
+        assert!(CompositeFitness::new().breakdown().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod elitism_tests {
+    use rand::SeedableRng;
+    use utils::*;
+    use vm::OpCode;
+
+    #[test]
+    fn best_program_survives() {
+        let allowed_instructions = [OpCode::Add, OpCode::Sub, OpCode::IncV, OpCode::Nop];
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+
+        for &num_programs in &[10, 11] {
+            let programs = generate_random_programs(num_programs, 5, 10, 1, &allowed_instructions, None, false, true, &mut rng);
+            let fitness = programs.iter().map(|p| p.get_instr().len() as Fitness).collect();
+            let sorted = SortedEvaluatedPrograms::new(programs, fitness);
+            let best_instr = sorted.get_programs()[0].prog.get_instr().to_vec();
+
+            let new_population = create_new_population(
+                sorted,
+                &BreedingParameters{
+                    mutation_probability: 1.0,
+                    num_mutations: 5,
+                    mutation_weights: MutationWeights::default(),
+                    selection: SelectionStrategy::TopFraction{ fraction: 0.5 },
+                    elitism_count: 1,
+                    crossover: CrossoverKind::SingleSegment,
+                    allowed_instructions: allowed_instructions.to_vec(),
+                    min_crossover_seg_length: 1,
+                    max_crossover_seg_length: 3,
+                    min_program_length: 0,
+                    max_program_length: 10,
+                    allow_crossing_blocks: true,
+                    preserve_blocks: false,
+                    allow_guarded_control_flow: true,
+                    enforce_uniqueness: false
+                },
+                &mut rng
+            );
+
+            assert_eq!(num_programs, new_population.len());
+            assert_eq!(best_instr, new_population[0].get_instr());
+        }
+    }
+    #[test]
+    fn duplicates_replaced_with_random_programs() {
+        let allowed_instructions = [OpCode::Add, OpCode::Sub, OpCode::IncV, OpCode::Load, OpCode::Store, OpCode::Nop];
+        let original = [OpCode::IncV, OpCode::Store, OpCode::Add, OpCode::Add, OpCode::Sub];
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+
+        let programs = vec![vm::Program::new(&original, 1, true); 10];
+        let sorted = SortedEvaluatedPrograms::new(programs, vec![1.0; 10]);
+
+        let new_population = create_new_population(
+            sorted,
+            &BreedingParameters{
+                mutation_probability: 1.0,
+                num_mutations: 5,
+                mutation_weights: MutationWeights::default(),
+                selection: SelectionStrategy::TopFraction{ fraction: 0.5 },
+                elitism_count: 1,
+                crossover: CrossoverKind::SingleSegment,
+                allowed_instructions: allowed_instructions.to_vec(),
+                min_crossover_seg_length: 1,
+                max_crossover_seg_length: 3,
+                min_program_length: 0,
+                max_program_length: 10,
+                allow_crossing_blocks: true,
+                preserve_blocks: false,
+                allow_guarded_control_flow: true,
+                enforce_uniqueness: true
+            },
+            &mut rng
+        );
+
+        assert_eq!(10, new_population.len());
+        assert_eq!(original, new_population[0].get_instr());
+        assert_eq!(1, new_population.iter().filter(|p| p.get_instr() == original).count());
+        assert!(new_population.iter().all(|p| p.get_instr().len() == original.len()));
+    }
+
+    #[test]
+    fn dedupe_keeps_best() {
+        let programs = vec![
+            vm::Program::new(&[OpCode::Add], 1, true),
+            vm::Program::new(&[OpCode::Sub], 1, true),
+            vm::Program::new(&[OpCode::Add], 1, true)
+        ];
+        let mut sorted = SortedEvaluatedPrograms::new(programs, vec![2.0, 3.0, 1.0]);
+
+        assert_eq!(1, sorted.dedupe());
+        let result: Vec<(Fitness, Vec<OpCode>)> = sorted.get_programs().iter().map(|p| (p.fitness, p.prog.get_instr().to_vec())).collect();
+        assert_eq!(vec![(1.0, vec![OpCode::Add]), (3.0, vec![OpCode::Sub])], result);
+    }
+}
+
+#[cfg(test)]
+mod parallel_breeding_tests {
+    use rand::SeedableRng;
+    use utils::*;
+    use vm::OpCode;
+
+    fn breed(num_threads: usize) -> Vec<Vec<OpCode>> {
+        let allowed_instructions = [OpCode::Add, OpCode::Sub, OpCode::IncV, OpCode::Load, OpCode::Store, OpCode::Nop];
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let programs = generate_random_programs(32, 5, 10, 1, &allowed_instructions, None, false, true, &mut rng);
+        let fitness = programs.iter().map(|p| p.get_instr().len() as Fitness).collect();
+        let sorted = SortedEvaluatedPrograms::new(programs, fitness);
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(num_threads).build().unwrap();
+        pool.install(|| create_new_population(
+            sorted,
+            &BreedingParameters{
+                mutation_probability: 0.5,
+                num_mutations: 3,
+                mutation_weights: MutationWeights::default(),
+                selection: SelectionStrategy::TopFraction{ fraction: 0.5 },
+                elitism_count: 1,
+                crossover: CrossoverKind::SingleSegment,
+                allowed_instructions: allowed_instructions.to_vec(),
+                min_crossover_seg_length: 1,
+                max_crossover_seg_length: 3,
+                min_program_length: 0,
+                max_program_length: 20,
+                allow_crossing_blocks: true,
+                preserve_blocks: false,
+                allow_guarded_control_flow: true,
+                enforce_uniqueness: false
+            },
+            &mut rng
+        )).iter().map(|p| p.get_instr().to_vec()).collect()
+    }
+
+    #[test]
+    fn same_result_regardless_of_thread_count() {
+        let serial = breed(1);
+        assert_eq!(32, serial.len());
+        assert_eq!(serial, breed(4));
+    }
+}
+
+#[cfg(test)]
+mod program_length_tests {
+    use rand::SeedableRng;
+    use utils::*;
+    use vm::OpCode;
+
+    ///
+    /// Returns offspring of 2-instruction programs. If `mutation_weights` is set, every offspring is mutated
+    /// (1 to 3 mutations); otherwise there is no mutation.
+    ///
+    fn offspring(
+        min_program_length: usize,
+        max_program_length: usize,
+        mutation_weights: Option<MutationWeights>
+    ) -> Vec<Vec<OpCode>> {
+        let allowed_instructions = [OpCode::Add, OpCode::Sub];
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let programs = vec![
+            vm::Program::new(&[OpCode::Add, OpCode::Add], 1, true),
+            vm::Program::new(&[OpCode::Sub, OpCode::Sub], 1, true),
+            vm::Program::new(&[OpCode::Add, OpCode::Sub], 1, true),
+            vm::Program::new(&[OpCode::Sub, OpCode::Add], 1, true)
+        ];
+        let sorted = SortedEvaluatedPrograms::new(programs, vec![1.0, 2.0, 3.0, 4.0]);
+
+        create_new_population(
+            sorted,
+            &BreedingParameters{
+                mutation_probability: if mutation_weights.is_some() { 1.0 } else { 0.0 },
+                num_mutations: 3,
+                mutation_weights: mutation_weights.unwrap_or_default(),
+                selection: SelectionStrategy::TopFraction{ fraction: 1.0 },
+                elitism_count: 0,
+                crossover: CrossoverKind::SingleSegment,
+                allowed_instructions: allowed_instructions.to_vec(),
+                min_crossover_seg_length: 1,
+                max_crossover_seg_length: 1,
+                min_program_length,
+                max_program_length,
+                allow_crossing_blocks: true,
+                preserve_blocks: false,
+                allow_guarded_control_flow: true,
+                enforce_uniqueness: false
+            },
+            &mut rng
+        ).iter().map(|p| p.get_instr().to_vec()).collect()
+    }
+
+    #[test]
+    fn short_offspring_padded() {
+        assert!(offspring(0, 10, None).iter().all(|instr| instr.len() == 2));
+
+        let padded = offspring(5, 10, None);
+        assert_eq!(4, padded.len());
+        for instr in &padded {
+            assert_eq!(5, instr.len());
+            assert!(instr[..2].iter().all(|&opcode| opcode != OpCode::Nop));
+            assert!(instr[2..].iter().all(|&opcode| opcode == OpCode::Nop));
+        }
+    }
+
+    #[test]
+    fn long_offspring_truncated() {
+        let insert_only = MutationWeights{ insert: 1.0, delete: 0.0, substitute: 0.0, transpose: 0.0, operand_perturbation: None };
+        let unlimited = offspring(0, 10, Some(insert_only));
+        assert!(unlimited.iter().all(|instr| instr.len() >= 3 && instr.len() <= 5));
+        assert!(unlimited.iter().any(|instr| instr.len() > 3));
+        assert!(offspring(0, 3, Some(insert_only)).iter().all(|instr| instr.len() == 3));
+        assert!(offspring(3, 3, Some(insert_only)).iter().all(|instr| instr.len() == 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "min_program_length must not exceed max_program_length")]
+    fn invalid_length_bounds() {
+        offspring(5, 4, None);
+    }
+}
+
+#[cfg(test)]
+mod operator_tests {
+    use rand::{RngCore, SeedableRng};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use utils::*;
+    use vm::OpCode;
+
+    struct NoRecombination;
+
+    impl Recombinator for NoRecombination {
+        fn recombine(&self, _prog1: &mut Vec<OpCode>, _prog2: &mut Vec<OpCode>, _rng: &mut dyn RngCore) { }
+    }
+
+    /// Counts calls, does not change programs.
+    struct NoMutation {
+        num_calls: AtomicUsize
+    }
+
+    impl Mutator for NoMutation {
+        fn mutate(&self, _program: &mut Vec<OpCode>, _rng: &mut dyn RngCore) {
+            self.num_calls.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn custom_operators() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let programs: Vec<vm::Program> = (0..6).map(|i| vm::Program::new(&[OpCode::SetI(i), OpCode::Add], 1, false)).collect();
+        let parents: Vec<Vec<OpCode>> = programs.iter().map(|p| p.get_instr().to_vec()).collect();
+        let sorted = SortedEvaluatedPrograms::new(programs, (0..6).map(|i| i as Fitness).collect());
+        let mutator = NoMutation{ num_calls: AtomicUsize::new(0) };
+
+        let breeding = BreedingParameters{
+            mutation_probability: 1.0,
+            num_mutations: 0,
+            mutation_weights: MutationWeights::default(),
+            selection: SelectionStrategy::TopFraction{ fraction: 1.0 },
+            elitism_count: 0,
+            crossover: CrossoverKind::SingleSegment,
+            allowed_instructions: vec![OpCode::Sub],
+            min_crossover_seg_length: 1,
+            max_crossover_seg_length: 1,
+            min_program_length: 0,
+            max_program_length: 10,
+            allow_crossing_blocks: false,
+            preserve_blocks: false,
+            allow_guarded_control_flow: true,
+            enforce_uniqueness: false
+        };
+        let new_population = create_new_population_with_operators(sorted, &breeding, &NoRecombination, &mutator, &mut rng);
+
+        assert_eq!(6, new_population.len());
+        assert_eq!(6, mutator.num_calls.load(Ordering::SeqCst));
+        assert!(new_population.iter().all(|p| parents.iter().any(|parent| p.get_instr() == &parent[..])));
+    }
+
+    #[test]
+    fn default_operators() {
+        let allowed_instructions = [OpCode::Add, OpCode::Sub, OpCode::Mul];
+        let population = |rng: &mut rand_xorshift::XorShiftRng| {
+            let programs = generate_random_programs(8, 4, 8, 1, &allowed_instructions, None, true, true, rng);
+            SortedEvaluatedPrograms::new(programs, (0..8).map(|i| i as Fitness).collect())
+        };
+
+        let breeding = BreedingParameters{
+            mutation_probability: 0.5,
+            num_mutations: 2,
+            mutation_weights: MutationWeights::default(),
+            selection: SelectionStrategy::TopFraction{ fraction: 0.5 },
+            elitism_count: 1,
+            crossover: CrossoverKind::SingleSegment,
+            allowed_instructions: allowed_instructions.to_vec(),
+            min_crossover_seg_length: 1,
+            max_crossover_seg_length: 3,
+            min_program_length: 0,
+            max_program_length: 10,
+            allow_crossing_blocks: true,
+            preserve_blocks: false,
+            allow_guarded_control_flow: true,
+            enforce_uniqueness: false
+        };
+
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let sorted = population(&mut rng);
+        let result = create_new_population(sorted, &breeding, &mut rng);
+
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let sorted = population(&mut rng);
+        let recombinator = DefaultRecombinator{
+            kind: CrossoverKind::SingleSegment, min_seg_len: 1, max_seg_len: 3, allow_control_flow_block_xing: true
+        };
+        let mutator = DefaultMutator{
+            num_mutations: 2,
+            weights: MutationWeights::default(),
+            allowed_instructions: &allowed_instructions,
+            preserve_blocks: false,
+            allow_guarded_control_flow: true
+        };
+        let result_with_operators = create_new_population_with_operators(sorted, &breeding, &recombinator, &mutator, &mut rng);
+
+        assert!(result.iter().map(|p| p.get_instr()).eq(result_with_operators.iter().map(|p| p.get_instr())));
+    }
+}
+
+#[cfg(test)]
+mod fitness_cache_tests {
+    use utils::FitnessCache;
+    use vm::{OpCode, Program};
+
+    #[test]
+    fn cache_hit() {
+        let mut cache = FitnessCache::new();
+        let prog = Program::new(&[OpCode::SetI(1), OpCode::Load, OpCode::Output(0)], 2, true);
+        let identical = prog.clone();
+
+        assert_eq!(5.0, cache.get_or_compute(&prog, || 5.0));
+        assert_eq!(5.0, cache.get_or_compute(&identical, || panic!("fitness should be cached")));
+        assert_eq!(1, cache.len());
+    }
+
+    #[test]
+    fn different_programs() {
+        let mut cache = FitnessCache::new();
+        let instr = [OpCode::SetI(1), OpCode::Load];
+
+        assert_eq!(1.0, cache.get_or_compute(&Program::new(&instr, 2, true), || 1.0));
+        assert_eq!(2.0, cache.get_or_compute(&Program::new(&instr, 3, true), || 2.0));
+        assert_eq!(3.0, cache.get_or_compute(&Program::new(&[OpCode::SetI(2), OpCode::Load], 2, true), || 3.0));
+        let mut with_epsilon = Program::new(&instr, 2, true);
+        with_epsilon.set_cmp_epsilon(0.5);
+        assert_eq!(4.0, cache.get_or_compute(&with_epsilon, || 4.0));
+        assert_eq!(4, cache.len());
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod crossover_tests {
+    use rand::SeedableRng;
+    use utils::*;
+    use vm::OpCode;
+
+    /// Returns a program consisting of `SetI(first)`, `SetI(first + 1)`, ...
+    fn numbered(first: i32, length: usize) -> Vec<OpCode> {
+        (0..length as i32).map(|i| OpCode::SetI(first + i)).collect()
+    }
+
+    fn operand(opcode: &OpCode) -> i32 {
+        match opcode { OpCode::SetI(i) => *i, _ => panic!() }
+    }
+
+    /// Returns the number of contiguous runs of instructions coming from the program numbered from `first`.
+    fn num_runs_from(program: &[OpCode], first: i32) -> usize {
+        let from = |opcode: &OpCode| operand(opcode) >= first && operand(opcode) < first + 100;
+        (0..program.len()).filter(|&i| from(&program[i]) && (i == 0 || !from(&program[i - 1]))).count()
+    }
+
+    /// Checks that instructions of both input programs are all present in the output programs.
+    fn check_preserved(prog1: &[OpCode], prog2: &[OpCode], len1: usize, len2: usize) {
+        assert_eq!(len1 + len2, prog1.len() + prog2.len());
+        let mut operands: Vec<i32> = prog1.iter().chain(prog2.iter()).map(operand).collect();
+        operands.sort();
+        let expected: Vec<i32> = (0..len1 as i32).chain(100..100 + len2 as i32).collect();
+        assert_eq!(expected, operands);
+    }
+
+    #[test]
+    fn single_segment() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let mut prog1 = numbered(0, 20);
+            let mut prog2 = numbered(100, 30);
+            recombine_programs(&mut prog1, &mut prog2, CrossoverKind::SingleSegment, 2, 6, true, &mut rng);
+
+            check_preserved(&prog1, &prog2, 20, 30);
+            assert!(num_runs_from(&prog1, 100) <= 1);
+            assert!(num_runs_from(&prog2, 0) <= 1);
+        }
+    }
+
+    #[test]
+    fn two_point() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let mut num_two_segment_exchanges = 0;
+        for _ in 0..100 {
+            let mut prog1 = numbered(0, 20);
+            let mut prog2 = numbered(100, 30);
+            recombine_programs(&mut prog1, &mut prog2, CrossoverKind::TwoPoint, 2, 6, true, &mut rng);
+
+            check_preserved(&prog1, &prog2, 20, 30);
+            assert!(num_runs_from(&prog1, 100) <= 2);
+            assert!(num_runs_from(&prog2, 0) <= 2);
+            // remaining own instructions keep their order
+            let own: Vec<i32> = prog1.iter().map(operand).filter(|&i| i < 100).collect();
+            assert!(own.windows(2).all(|w| w[0] < w[1]));
+
+            if num_runs_from(&prog1, 100) == 2 { num_two_segment_exchanges += 1; }
+        }
+        assert!(num_two_segment_exchanges > 0);
+    }
+
+    #[test]
+    fn segments_do_not_cross_blocks() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let block = [OpCode::EndGoTo, OpCode::Nop, OpCode::Nop, OpCode::GoToIfP];
+        for &kind in &[CrossoverKind::SingleSegment, CrossoverKind::TwoPoint] {
+            for _ in 0..100 {
+                let mut prog1: Vec<OpCode> = block.iter().cycle().take(16).cloned().collect();
+                let mut prog2 = prog1.clone();
+                recombine_programs(&mut prog1, &mut prog2, kind, 1, 8, false, &mut rng);
+
+                for prog in &[prog1, prog2] {
+                    let program = vm::Program::new(prog, 0, true);
+                    assert!(prog.iter().enumerate()
+                        .filter(|(_, opcode)| [OpCode::EndGoTo, OpCode::GoToIfP].contains(opcode))
+                        .all(|(i, _)| program.get_jump_table()[i].is_some()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn programs_shorter_than_min_segment() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        for &short_len in &[1, 2, 5] {
+            for &kind in &[CrossoverKind::SingleSegment, CrossoverKind::TwoPoint] {
+                for _ in 0..100 {
+                    let mut prog1 = numbered(0, short_len);
+                    let mut prog2 = numbered(100, 20);
+                    recombine_programs(&mut prog1, &mut prog2, kind, 6, 10, true, &mut rng);
+
+                    // the short program is exchanged as a whole
+                    assert!(!prog1.is_empty() && prog1.iter().all(|op| operand(op) >= 100));
+                    let pos = prog2.iter().position(|op| operand(op) < 100).unwrap();
+                    assert_eq!(numbered(0, short_len), prog2[pos .. pos + short_len].to_vec());
+
+                    let mut all: Vec<i32> = prog1.iter().chain(prog2.iter()).map(operand).collect();
+                    all.sort();
+                    assert_eq!((0..short_len as i32).chain(100..120).collect::<Vec<i32>>(), all);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn uniform() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+
+        let mut prog1 = numbered(0, 10);
+        let mut prog2 = numbered(100, 10);
+        recombine_programs(&mut prog1, &mut prog2, CrossoverKind::Uniform{ rate: 1.0 }, 1, 1, true, &mut rng);
+        assert_eq!(numbered(100, 10), prog1);
+        assert_eq!(numbered(0, 10), prog2);
+
+        recombine_programs(&mut prog1, &mut prog2, CrossoverKind::Uniform{ rate: 0.0 }, 1, 1, true, &mut rng);
+        assert_eq!(numbered(100, 10), prog1);
+
+        let mut prog1 = numbered(0, 10);
+        let mut prog2 = numbered(100, 25);
+        recombine_programs(&mut prog1, &mut prog2, CrossoverKind::Uniform{ rate: 0.5 }, 1, 1, true, &mut rng);
+        assert_eq!(10, prog1.len());
+        assert_eq!(25, prog2.len());
+        assert_eq!(numbered(110, 15), prog2[10..].to_vec());
+        for i in 0..10 {
+            let (op1, op2) = (operand(&prog1[i]), operand(&prog2[i]));
+            assert!((op1, op2) == (i as i32, 100 + i as i32) || (op1, op2) == (100 + i as i32, i as i32));
+        }
+        assert!(num_runs_from(&prog1, 100) > 0);
+        assert!(prog1.iter().any(|opcode| operand(opcode) < 100));
+    }
+
+    #[test]
+    fn homologous() {
+        let parent1 = numbered(0, 20);
+        // `parent1` shifted by 2, with one differing instruction
+        let mut parent2 = vec![OpCode::SetI(500), OpCode::SetI(501)];
+        parent2.extend_from_slice(&parent1);
+        parent2[12] = OpCode::SetI(110);
+
+        assert_eq!(2, alignment_offset(&parent1, &parent2, 4));
+        assert_eq!(-2, alignment_offset(&parent2, &parent1, 4));
+        assert_eq!(0, alignment_offset(&parent1, &parent2, 1));
+
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let mut num_swapped = 0;
+        for _ in 0..100 {
+            let mut prog1 = parent1.clone();
+            let mut prog2 = parent2.clone();
+            recombine_programs(&mut prog1, &mut prog2, CrossoverKind::Homologous{ window: 4 }, 2, 6, true, &mut rng);
+
+            // aligned segments are swapped, so only the differing instruction can move
+            assert_eq!(prog1 == parent1, prog2 == parent2);
+            assert_eq!(parent2[..2], prog2[..2]);
+            assert_eq!(parent1.len(), prog1.len());
+            assert_eq!(parent2.len(), prog2.len());
+            for i in 0..parent1.len() {
+                assert!(prog1[i] == parent1[i] || (i == 10 && prog1[i] == OpCode::SetI(110)));
+            }
+            if prog1 != parent1 { num_swapped += 1; }
+        }
+        assert!(num_swapped > 0);
+    }
+
+    #[test]
+    fn homologous_not_crossing_blocks() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let mut prog1 = vec![OpCode::IncV, OpCode::JumpIfN, OpCode::DecV, OpCode::EndJump, OpCode::IncV];
+            let mut prog2 = vec![OpCode::JumpIfN, OpCode::IncV, OpCode::DecV, OpCode::EndJump, OpCode::Nop];
+            recombine_programs(&mut prog1, &mut prog2, CrossoverKind::Homologous{ window: 0 }, 1, 5, false, &mut rng);
+
+            for prog in &[prog1, prog2] {
+                assert_eq!(1, prog.iter().filter(|&&opcode| opcode == OpCode::JumpIfN).count());
+                assert_eq!(1, prog.iter().filter(|&&opcode| opcode == OpCode::EndJump).count());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod mutation_tests {
+    use rand::SeedableRng;
+    use utils::*;
+    use vm::OpCode;
+
+    #[test]
+    fn substitutions_only() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let weights = MutationWeights{ insert: 0.0, delete: 0.0, substitute: 1.0, transpose: 0.0, operand_perturbation: None };
+        let original = vec![OpCode::Nop; 10];
+        let mut program = original.clone();
+
+        for _ in 0..1000 {
+            let previous = program.clone();
+            mutate(&mut program, 3, &weights, &[OpCode::IncV, OpCode::DecV, OpCode::Add], false, true, &mut rng);
+
+            assert_eq!(original.len(), program.len());
+            // substitutions never introduce `Nop`, so no position may change back to `Nop`
+            // (which would happen with a transposition)
+            for (old, new) in previous.iter().zip(program.iter()) {
+                assert!(*old == *new || *new != OpCode::Nop);
+            }
+        }
+        assert!(!program.contains(&OpCode::Nop));
+    }
+
+    #[test]
+    fn operand_perturbation() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let perturbation = OperandPerturbation{
+            weight: 1.0, max_delta: 2, set_i_range: (0, 9), input_range: (0, 3), output_range: (-1, 1)
+        };
+        let weights = MutationWeights{ insert: 0.0, delete: 0.0, substitute: 0.0, transpose: 0.0, operand_perturbation: Some(perturbation) };
+        let original = vec![OpCode::SetI(5), OpCode::Input(0), OpCode::Add, OpCode::Output(1)];
+        let mut program = original.clone();
+        let mut operands_changed = false;
+
+        for _ in 0..1000 {
+            mutate(&mut program, 3, &weights, &[OpCode::Nop], false, true, &mut rng);
+
+            assert_eq!(original.len(), program.len());
+            for (old, new) in original.iter().zip(program.iter()) {
+                assert_eq!(std::mem::discriminant(old), std::mem::discriminant(new));
+                operands_changed |= old != new;
+            }
+            match program[0] { OpCode::SetI(i) => assert!((0..=9).contains(&i)), _ => unreachable!() }
+            match program[1] { OpCode::Input(i) => assert!((0..=3).contains(&i)), _ => unreachable!() }
+            match program[3] { OpCode::Output(i) => assert!((-1..=1).contains(&i)), _ => unreachable!() }
+        }
+        assert!(operands_changed);
+    }
+
+    #[test]
+    fn deletions_keep_one_instruction() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let weights = MutationWeights{ insert: 0.0, delete: 5.0, substitute: 0.0, transpose: 0.0, operand_perturbation: None };
+        let mut program = vec![OpCode::Nop; 10];
+
+        for _ in 0..100 {
+            mutate(&mut program, 3, &weights, &[OpCode::IncV], false, true, &mut rng);
+            assert!(!program.is_empty());
+        }
+        assert_eq!(1, program.len());
+    }
+
+    #[test]
+    fn default_weights() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let mut program = vec![OpCode::Nop; 10];
+        let (mut num_longer, mut num_shorter) = (0, 0);
+
+        for _ in 0..1000 {
+            let length = program.len();
+            mutate(&mut program, 1, &MutationWeights::default(), &[OpCode::IncV], false, true, &mut rng);
+            if program.len() > length { num_longer += 1; }
+            if program.len() < length { num_shorter += 1; }
+        }
+        assert!(num_longer > 200 && num_longer < 300);
+        assert!(num_shorter > 200 && num_shorter < 300);
+    }
+
+    #[test]
+    fn other_generator() {
+        // any `Rng` can be used; the same seed gives the same results
+        let allowed = [OpCode::IncV, OpCode::DecV, OpCode::Add];
+        let run = |seed: u64| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            let programs = generate_random_programs(8, 4, 8, 1, &allowed, None, false, true, &mut rng);
+            let fitness = programs.iter().map(|p| p.get_instr().len() as Fitness).collect();
+            let new_population = create_new_population(
+                SortedEvaluatedPrograms::new(programs, fitness),
+                &BreedingParameters{
+                    mutation_probability: 1.0,
+                    num_mutations: 2,
+                    mutation_weights: MutationWeights::default(),
+                    selection: SelectionStrategy::Roulette,
+                    elitism_count: 1,
+                    crossover: CrossoverKind::SingleSegment,
+                    allowed_instructions: allowed.to_vec(),
+                    min_crossover_seg_length: 1,
+                    max_crossover_seg_length: 3,
+                    min_program_length: 0,
+                    max_program_length: 16,
+                    allow_crossing_blocks: true,
+                    preserve_blocks: false,
+                    allow_guarded_control_flow: true,
+                    enforce_uniqueness: false
+                },
+                &mut rng
+            );
+            new_population.iter().map(|p| p.get_instr().to_vec()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(8, run(1).len());
+        assert_eq!(run(1), run(1));
+    }
+
+    #[test]
+    fn founders_and_offspring_allow_crossing_blocks() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let allowed = [OpCode::IncV, OpCode::EndGoTo, OpCode::GoToIfP, OpCode::JumpIfN, OpCode::EndJump];
+        for &allow_crossing_blocks in &[false, true] {
+            let mut programs = generate_random_programs(16, 4, 12, 1, &allowed, None, allow_crossing_blocks, true, &mut rng);
+            // a duplicate, to be replaced by a random program
+            programs[1] = programs[0].clone();
+            assert!(programs.iter().all(|p| p.get_allow_crossing_blocks() == allow_crossing_blocks));
+
+            let fitness = (0..programs.len()).map(|i| i as Fitness).collect();
+            let new_population = create_new_population(
+                SortedEvaluatedPrograms::new(programs, fitness),
+                &BreedingParameters{
+                    mutation_probability: 1.0,
+                    num_mutations: 2,
+                    mutation_weights: MutationWeights::default(),
+                    selection: SelectionStrategy::Roulette,
+                    elitism_count: 1,
+                    crossover: CrossoverKind::SingleSegment,
+                    allowed_instructions: allowed.to_vec(),
+                    min_crossover_seg_length: 1,
+                    max_crossover_seg_length: 3,
+                    min_program_length: 0,
+                    max_program_length: 16,
+                    allow_crossing_blocks,
+                    preserve_blocks: false,
+                    allow_guarded_control_flow: true,
+                    enforce_uniqueness: true
+                },
+                &mut rng
+            );
+            assert_eq!(16, new_population.len());
+            assert!(new_population.iter().all(|p| p.get_allow_crossing_blocks() == allow_crossing_blocks));
+        }
+    }
+
+    #[test]
+    fn offspring_keep_program_settings() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let allowed = [OpCode::IncV, OpCode::SetBank(1), OpCode::SetI(3), OpCode::Store, OpCode::Cmp, OpCode::Mul];
+        let arithmetic_mode = vm::ArithmeticMode::Saturating{ min: -100.0, max: 100.0 };
+        let with_settings = |program: vm::Program| {
+            let mut program = program;
+            program.set_num_data_banks(2);
+            program.set_index_mode(vm::IndexMode::Wrap);
+            program.set_arithmetic_mode(arithmetic_mode);
+            program.set_cmp_epsilon(0.01);
+            program
+        };
+        let has_settings = |p: &vm::Program| {
+            p.get_num_data_slots() == 2 && p.get_num_data_banks() == 2 && p.get_index_mode() == vm::IndexMode::Wrap &&
+                p.get_arithmetic_mode() == arithmetic_mode && p.get_cmp_epsilon() == 0.01
+        };
+
+        let mut programs: Vec<vm::Program> =
+            generate_random_programs(15, 4, 12, 2, &allowed, None, true, true, &mut rng).into_iter().map(with_settings).collect();
+        // a duplicate, to be replaced by a random program
+        programs[1] = programs[0].clone();
+
+        let fitness = (0..programs.len()).map(|i| i as Fitness).collect();
+        let new_population = create_new_population(
+            SortedEvaluatedPrograms::new(programs, fitness),
+            &BreedingParameters{
+                mutation_probability: 1.0,
+                num_mutations: 2,
+                mutation_weights: MutationWeights::default(),
+                selection: SelectionStrategy::Roulette,
+                elitism_count: 2,
+                crossover: CrossoverKind::SingleSegment,
+                allowed_instructions: allowed.to_vec(),
+                min_crossover_seg_length: 1,
+                max_crossover_seg_length: 3,
+                min_program_length: 0,
+                max_program_length: 16,
+                allow_crossing_blocks: true,
+                preserve_blocks: false,
+                allow_guarded_control_flow: true,
+                enforce_uniqueness: true
+            },
+            &mut rng
+        );
+        assert_eq!(15, new_population.len());
+        assert!(new_population.iter().all(has_settings));
+    }
+
+    const BLOCK_TEST_INSTRUCTIONS: [OpCode; 6] =
+        [OpCode::EndGoTo, OpCode::GoToIfP, OpCode::JumpIfN, OpCode::EndJump, OpCode::IncV, OpCode::Nop];
+
+    /// Returns true if all control flow instructions of `program` are active.
+    fn all_blocks_active(program: &[OpCode]) -> bool {
+        vm::Program::new(program, 1, false).control_flow_report().iter().all(|(_, _, active)| *active)
+    }
+
+    #[test]
+    fn preserve_blocks() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let mut program = vec![
+            OpCode::EndGoTo, OpCode::JumpIfN, OpCode::IncV, OpCode::EndJump, OpCode::GoToIfP, OpCode::Nop
+        ];
+        let mut num_block_instructions = 0;
+
+        for _ in 0..2000 {
+            mutate(&mut program, 3, &MutationWeights::default(), &BLOCK_TEST_INSTRUCTIONS, true, true, &mut rng);
+            assert!(all_blocks_active(&program));
+            num_block_instructions += program.iter().filter(|opcode| is_block_instruction(**opcode)).count();
+        }
+        assert!(num_block_instructions > 0);
+    }
+
+    #[test]
+    fn without_preserving_blocks() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let mut program = vec![OpCode::Nop; 6];
+        let mut any_inactive = false;
+
+        for _ in 0..100 {
+            mutate(&mut program, 3, &MutationWeights::default(), &BLOCK_TEST_INSTRUCTIONS, false, true, &mut rng);
+            any_inactive |= !all_blocks_active(&program);
+        }
+        assert!(any_inactive);
+    }
+
+    #[test]
+    fn preserve_blocks_deletes_pairs() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let weights = MutationWeights{ insert: 0.0, delete: 1.0, substitute: 0.0, transpose: 0.0, operand_perturbation: None };
+        let original = vec![OpCode::IncV, OpCode::EndGoTo, OpCode::Nop, OpCode::GoToIfP, OpCode::IncV];
+
+        for _ in 0..100 {
+            let mut program = original.clone();
+            mutate(&mut program, 1, &weights, &[OpCode::Nop], true, true, &mut rng);
+            if program.contains(&OpCode::EndGoTo) {
+                assert_eq!(4, program.len());
+                assert!(all_blocks_active(&program));
+            } else {
+                assert!(program == [OpCode::IncV, OpCode::Nop, OpCode::IncV]);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "allowed_instructions must be non-empty")]
+    fn mutate_without_allowed_instructions() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        mutate(&mut vec![OpCode::Nop; 10], 3, &MutationWeights::default(), &[], false, true, &mut rng);
+    }
+
+    #[test]
+    #[should_panic(expected = "allowed_instructions must be non-empty")]
+    fn new_population_without_allowed_instructions() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let programs = vec![vm::Program::new(&[OpCode::Nop; 4], 1, false); 4];
+        create_new_population(
+            SortedEvaluatedPrograms::new(programs, vec![1.0; 4]),
+            &BreedingParameters{
+                mutation_probability: 1.0,
+                num_mutations: 2,
+                mutation_weights: MutationWeights::default(),
+                selection: SelectionStrategy::Roulette,
+                elitism_count: 1,
+                crossover: CrossoverKind::SingleSegment,
+                allowed_instructions: vec![],
+                min_crossover_seg_length: 1,
+                max_crossover_seg_length: 3,
+                min_program_length: 0,
+                max_program_length: 16,
+                allow_crossing_blocks: true,
+                preserve_blocks: false,
+                allow_guarded_control_flow: true,
+                enforce_uniqueness: false
+            },
+            &mut rng
+        );
+    }
+}
+
+#[cfg(test)]
+mod island_model_tests {
+    use rand::SeedableRng;
+    use utils::*;
+    use vm::OpCode;
+
+    #[test]
+    fn migration() {
+        const MIGRATION_INTERVAL: usize = 3;
+
+        let allowed_instructions = vec![OpCode::IncV, OpCode::DecV, OpCode::Nop];
+        let champion = [OpCode::Add, OpCode::Sub, OpCode::Mul];
+        let evaluate = |prog: &vm::Program| if prog.get_instr() == champion { 0.0 } else { 100.0 };
+
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let mut islands = vec![];
+        for i in 0..2 {
+            let mut programs = generate_random_programs(10, 2, 5, 1, &allowed_instructions, None, false, true, &mut rng);
+            if i == 0 {
+                programs[0] = vm::Program::new(&champion, 1, true);
+            }
+            let fitness = programs.iter().map(&evaluate).collect();
+            islands.push(SortedEvaluatedPrograms::new(programs, fitness));
+        }
+
+        let breeding = BreedingParameters{
+            mutation_probability: 1.0,
+            num_mutations: 2,
+            mutation_weights: MutationWeights::default(),
+            selection: SelectionStrategy::TopFraction{ fraction: 0.5 },
+            elitism_count: 1,
+            crossover: CrossoverKind::SingleSegment,
+            allowed_instructions: allowed_instructions.clone(),
+            min_crossover_seg_length: 1,
+            max_crossover_seg_length: 2,
+            min_program_length: 0,
+            max_program_length: 10,
+            allow_crossing_blocks: true,
+            preserve_blocks: false,
+            allow_guarded_control_flow: true,
+            enforce_uniqueness: false
+        };
+        let mut model = IslandModel::new(islands, breeding, MIGRATION_INTERVAL, 2);
+
+        let has_champion = |island: &SortedEvaluatedPrograms| island.get_programs().iter().any(|p| p.prog.get_instr() == champion);
+
+        for _ in 0..MIGRATION_INTERVAL - 1 {
+            model.step(evaluate, &mut rng);
+            assert!(has_champion(&model.get_islands()[0]));
+            assert!(!has_champion(&model.get_islands()[1]));
+        }
+
+        model.step(evaluate, &mut rng);
+        assert_eq!(MIGRATION_INTERVAL, model.get_generation());
+        assert!(has_champion(&model.get_islands()[0]));
+        assert!(has_champion(&model.get_islands()[1]));
+        assert_eq!(0.0, model.get_islands()[1].get_programs()[0].fitness);
+        assert!(model.get_islands().iter().all(|island| island.len() == 10));
+    }
+}
+
+#[cfg(test)]
+mod checkpoint_tests {
+    use rand::prelude::*;
+    use serialization::ParseError;
+    use utils::*;
+    use vm::OpCode;
+
+    #[test]
+    fn rng_state_round_trip() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(5);
+        for _ in 0..7 { rng.next_u32(); }
+
+        let mut restored = rand_xorshift::XorShiftRng::from_seed(rng_state(&rng));
+        for _ in 0..100 {
+            assert_eq!(rng.next_u64(), restored.next_u64());
+        }
+    }
+
+    fn next_generation(
+        programs: SortedEvaluatedPrograms,
+        rng: &mut rand_xorshift::XorShiftRng
+    ) -> SortedEvaluatedPrograms {
+        let allowed_instructions = [OpCode::IncV, OpCode::DecV, OpCode::Load, OpCode::Store, OpCode::Nop];
+        let new_population = create_new_population(
+            programs,
+            &BreedingParameters{
+                mutation_probability: 0.5,
+                num_mutations: 2,
+                mutation_weights: MutationWeights::default(),
+                selection: SelectionStrategy::Tournament{ size: 3, fraction_eligible: 1.0 },
+                elitism_count: 1,
+                crossover: CrossoverKind::TwoPoint,
+                allowed_instructions: allowed_instructions.to_vec(),
+                min_crossover_seg_length: 1,
+                max_crossover_seg_length: 4,
+                min_program_length: 0,
+                max_program_length: 30,
+                allow_crossing_blocks: true,
+                preserve_blocks: false,
+                allow_guarded_control_flow: true,
+                enforce_uniqueness: false
+            },
+            rng
+        );
+        // prefer programs of 12 instructions with many `IncV`s
+        let fitness = new_population.iter().map(|p|
+            (p.get_instr().len() as Fitness - 12.0).abs() - p.get_instr().iter().filter(|i| **i == OpCode::IncV).count() as Fitness
+        ).collect();
+        SortedEvaluatedPrograms::new(new_population, fitness)
+    }
+
+    fn instructions(programs: &SortedEvaluatedPrograms) -> Vec<(Fitness, Vec<OpCode>)> {
+        programs.get_programs().iter().map(|p| (p.fitness, p.prog.get_instr().to_vec())).collect()
+    }
+
+    #[test]
+    fn resume() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(3);
+        let initial = generate_random_programs(20, 4, 16, 2, &[OpCode::IncV, OpCode::DecV, OpCode::Nop], None, false, true, &mut rng);
+        let mut programs = SortedEvaluatedPrograms::new(initial, vec![WORST_FITNESS; 20]);
+        for _ in 0..5 {
+            programs = next_generation(programs, &mut rng);
+        }
+
+        let path = std::env::temp_dir().join("genetic_checkpoint_test.bin");
+        let checkpoint = EvolutionCheckpoint{ programs, rng, generation: 5, user_data: vec![1, 2, 3] };
+        checkpoint.save(&path).unwrap();
+        let resumed = EvolutionCheckpoint::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(5, resumed.generation);
+        assert_eq!(vec![1, 2, 3], resumed.user_data);
+        assert_eq!(instructions(&checkpoint.programs), instructions(&resumed.programs));
+
+        let mut rng = checkpoint.rng;
+        let mut resumed_rng = resumed.rng;
+        let uninterrupted = next_generation(checkpoint.programs, &mut rng);
+        let continued = next_generation(resumed.programs, &mut resumed_rng);
+        assert_eq!(instructions(&uninterrupted), instructions(&continued));
+        assert_eq!(rng.next_u64(), resumed_rng.next_u64());
+    }
+
+    #[test]
+    fn invalid_data() {
+        let checkpoint = EvolutionCheckpoint{
+            programs: SortedEvaluatedPrograms::new(vec![vm::Program::new(&[OpCode::IncV], 1, false)], vec![1.0]),
+            rng: rand_xorshift::XorShiftRng::seed_from_u64(1),
+            generation: 0,
+            user_data: vec![]
+        };
+        let data = checkpoint.to_bytes();
+
+        assert!(EvolutionCheckpoint::from_bytes(&data).is_ok());
+        assert!(EvolutionCheckpoint::from_bytes(&data[..data.len() - 1]).err() == Some(ParseError::UnexpectedEnd));
+        assert!(EvolutionCheckpoint::from_bytes(b"GVMP").err() == Some(ParseError::InvalidHeader));
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert!(EvolutionCheckpoint::from_bytes(&trailing).err() == Some(ParseError::TrailingData));
+    }
+}
+
+#[cfg(test)]
+mod generation_stats_tests {
+    use utils::*;
+    use vm::{OpCode, Program};
+
+    fn population() -> SortedEvaluatedPrograms {
+        SortedEvaluatedPrograms::new(
+            vec![
+                Program::new(&[OpCode::IncV], 1, false),
+                Program::new(&[OpCode::IncV, OpCode::DecV, OpCode::Nop], 1, false),
+                Program::new(&[OpCode::IncV], 1, false),
+                Program::new(&[OpCode::Load, OpCode::Store], 1, false)
+            ],
+            vec![4.0, 1.0, 10.0, 2.0]
+        )
+    }
+
+    #[test]
+    fn aggregates() {
+        let stats = GenerationStats::from(&population());
+        assert_eq!(1.0, stats.best_fitness);
+        assert_eq!(10.0, stats.worst_fitness);
+        assert_eq!(4.25, stats.mean_fitness);
+        assert_eq!(3.0, stats.median_fitness);
+        assert_eq!(4, stats.num_programs);
+        assert_eq!(1.75, stats.mean_length);
+        assert_eq!(3, stats.max_length);
+        assert_eq!(3, stats.num_distinct);
+    }
+
+    #[test]
+    fn odd_count_median() {
+        let programs = SortedEvaluatedPrograms::new(vec![Program::new(&[], 1, false); 3], vec![5.0, 1.0, 2.0]);
+        let stats = GenerationStats::from(&programs);
+        assert_eq!(2.0, stats.median_fitness);
+        assert_eq!(0.0, stats.mean_length);
+        assert_eq!(1, stats.num_distinct);
+    }
+
+    #[test]
+    fn empty() {
+        let stats = GenerationStats::from(&SortedEvaluatedPrograms::new(vec![], vec![]));
+        assert_eq!(WORST_FITNESS, stats.best_fitness);
+        assert_eq!(0, stats.max_length);
+        assert_eq!(0, stats.num_distinct);
+    }
+
+    #[test]
+    fn csv() {
+        let mut log = StatsLog::new();
+        log.record(&population());
+        log.record(&population());
+
+        let csv = log.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!("generation,best_fitness,worst_fitness,mean_fitness,median_fitness,mean_length,max_length,num_distinct", lines[0]);
+        assert_eq!("0,1,10,4.25,3,1.75,3,3", lines[1]);
+        assert_eq!("1,1,10,4.25,3,1.75,3,3", lines[2]);
+    }
+}
+
+#[cfg(test)]
+mod adaptive_mutation_tests {
+    use utils::*;
+
+    fn stats(num_programs: usize, num_distinct: usize) -> GenerationStats {
+        GenerationStats{
+            best_fitness: 0.0,
+            worst_fitness: 0.0,
+            mean_fitness: 0.0,
+            median_fitness: 0.0,
+            num_programs,
+            mean_length: 0.0,
+            max_length: 0,
+            num_distinct
+        }
+    }
+
+    #[test]
+    fn diverse_population() {
+        let mut adaptive = AdaptiveMutation::new(0.2..=1.0, 3..=16, 0.5, 1.0);
+        assert_eq!((0.2, 3), adaptive.adjust(&stats(101, 101)));
+        assert_eq!((0.2, 3), adaptive.adjust(&stats(101, 51)));
+    }
+
+    #[test]
+    fn low_diversity_increases_mutation() {
+        let mut adaptive = AdaptiveMutation::new(0.2..=1.0, 3..=16, 0.5, 1.0);
+        let (probability1, num_mutations1) = adaptive.adjust(&stats(101, 101));
+        let (probability2, num_mutations2) = adaptive.adjust(&stats(101, 26));
+        assert_eq!(0.5, adaptive.get_strength());
+        let (probability3, num_mutations3) = adaptive.adjust(&stats(101, 1));
+
+        assert!(probability1 < probability2 && probability2 < probability3);
+        assert!(num_mutations1 < num_mutations2 && num_mutations2 < num_mutations3);
+        assert_eq!(10, num_mutations2);
+        assert_eq!(1.0, probability3);
+        assert_eq!(16, num_mutations3);
+    }
+
+    #[test]
+    fn smoothing() {
+        let mut adaptive = AdaptiveMutation::new(0.0..=1.0, 0..=8, 1.0, 0.5);
+        let mut prev_probability = 0.0;
+        for _ in 0..5 {
+            let (probability, _) = adaptive.adjust(&stats(10, 1));
+            assert!(probability > prev_probability && probability < 1.0);
+            prev_probability = probability;
+        }
+        assert_eq!(1.0 - 0.5f64.powi(5), adaptive.get_strength());
+
+        // diversity restored: strength decreases gradually
+        let (probability, _) = adaptive.adjust(&stats(10, 10));
+        assert!(probability > 0.0 && probability < prev_probability);
+    }
+
+    #[test]
+    fn empty_population() {
+        let mut adaptive = AdaptiveMutation::new(0.1..=0.9, 1..=5, 1.0, 1.0);
+        assert_eq!((0.9, 5), adaptive.adjust(&stats(0, 0)));
+    }
+}
@@ -0,0 +1,128 @@
+//
+// genetic - genetic programming experiments
+// Copyright (c) 2019 Filip Szczerek <ga.software@yahoo.com>
+//
+// This project is licensed under the terms of the MIT license
+// (see the LICENSE file for details).
+//
+//
+// File description:
+//   Module: novelty search.
+//
+
+use vm;
+
+///
+/// Describes a program's behavior (e.g. the results of running it for each test case) as a feature vector;
+/// all vectors produced by a descriptor must have the same length.
+///
+pub trait BehaviorDescriptor {
+    fn describe(&self, program: &vm::Program) -> Vec<f64>;
+}
+
+/// Returns the Euclidean distance between feature vectors `a` and `b` (of equal length).
+fn distance(a: &[f64], b: &[f64]) -> f64 {
+    assert!(a.len() == b.len(), "feature vectors must have equal length");
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y) * (x - y)).sum::<f64>().sqrt()
+}
+
+///
+/// Returns the novelty of behavior `descriptor`: mean distance to its `k` (> 0) nearest neighbors in `archive`
+/// (or to all of them if there are fewer). Returns `f64::INFINITY` for an empty archive.
+///
+/// Higher is more novel; since lower fitness is better, use e.g. the negated score as fitness
+/// to reward novel programs.
+///
+pub fn novelty_score(descriptor: &[f64], archive: &[Vec<f64>], k: usize) -> f64 {
+    assert!(k > 0);
+    if archive.is_empty() {
+        return f64::INFINITY;
+    }
+
+    let mut distances: Vec<f64> = archive.iter().map(|other| distance(descriptor, other)).collect();
+    distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let num_neighbors = std::cmp::min(k, distances.len());
+
+    distances[..num_neighbors].iter().sum::<f64>() / num_neighbors as f64
+}
+
+///
+/// Archive of behaviors found novel so far. A behavior is added if its novelty (with respect to the archive)
+/// is at least `novelty_threshold`.
+///
+pub struct NoveltyArchive {
+    novelty_threshold: f64,
+    /// Number of nearest neighbors used by `novelty_score`.
+    k: usize,
+    descriptors: Vec<Vec<f64>>
+}
+
+impl NoveltyArchive {
+    pub fn new(novelty_threshold: f64, k: usize) -> NoveltyArchive {
+        assert!(k > 0);
+        NoveltyArchive{ novelty_threshold, k, descriptors: vec![] }
+    }
+
+    /// Returns the novelty of `descriptor` (see `novelty_score`).
+    pub fn novelty(&self, descriptor: &[f64]) -> f64 {
+        novelty_score(descriptor, &self.descriptors, self.k)
+    }
+
+    /// Returns the novelty of `descriptor` and adds it to the archive if the novelty reaches the threshold.
+    pub fn consider(&mut self, descriptor: Vec<f64>) -> f64 {
+        let novelty = self.novelty(&descriptor);
+        if novelty >= self.novelty_threshold {
+            self.descriptors.push(descriptor);
+        }
+        novelty
+    }
+
+    pub fn get_descriptors(&self) -> &[Vec<f64>] { &self.descriptors }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::novelty::{novelty_score, BehaviorDescriptor, NoveltyArchive};
+    use vm::{OpCode, Program};
+
+    #[test]
+    fn distant_behavior_is_novel() {
+        let archive = vec![vec![0.0, 0.0], vec![1.0, 0.0], vec![0.0, 1.0], vec![1.0, 1.0]];
+
+        let near = novelty_score(&[0.5, 0.5], &archive, 2);
+        let far = novelty_score(&[100.0, 100.0], &archive, 2);
+        assert!(far > 100.0);
+        assert!(near < 1.0);
+
+        assert_eq!(0.0, novelty_score(&[1.0, 0.0], &archive, 1));
+        // mean of distances to the 2 nearest neighbors: 0 and 1
+        assert_eq!(0.5, novelty_score(&[1.0, 0.0], &archive, 2));
+        // `k` larger than the archive
+        assert_eq!(novelty_score(&[3.0, 3.0], &archive, 4), novelty_score(&[3.0, 3.0], &archive, 10));
+        assert_eq!(f64::INFINITY, novelty_score(&[0.0, 0.0], &[], 3));
+    }
+
+    /// Describes a program by its length.
+    struct Length;
+
+    impl BehaviorDescriptor for Length {
+        fn describe(&self, program: &Program) -> Vec<f64> { vec![program.get_instr().len() as f64] }
+    }
+
+    #[test]
+    fn archive_insertion() {
+        let mut archive = NoveltyArchive::new(2.0, 1);
+        let programs = [
+            Program::new(&[OpCode::Nop], 0, false),
+            Program::new(&[OpCode::Nop; 2], 0, false),
+            Program::new(&[OpCode::Nop; 5], 0, false)
+        ];
+
+        for program in programs.iter() {
+            archive.consider(Length.describe(program));
+        }
+
+        assert!(archive.get_descriptors() == [vec![1.0], vec![5.0]]);
+        assert_eq!(1.0, archive.novelty(&[4.0]));
+    }
+}
@@ -10,27 +10,127 @@
 //   Module: virtual machine.
 //
 
-/// Virtual machine's computational data type (type of the `reg_v`'s value).
+use libm;
+use rand::SeedableRng;
+
+/// Virtual machine's default computational data type (type of the `reg_v`'s value).
 pub type RegValue = f32;
 
+/// Computational data type of the virtual machine (type of the `reg_v`'s and data slots' values).
+pub trait VmNumber:
+    Copy + PartialOrd + std::fmt::Debug +
+    std::ops::Neg<Output = Self> +
+    std::ops::AddAssign + std::ops::SubAssign + std::ops::MulAssign + std::ops::DivAssign
+{
+    const ZERO: Self;
+    const ONE: Self;
+
+    /// Converts the value of `reg_i` (used by `ItoV`).
+    fn from_i32(value: i32) -> Self;
+
+    /// Converts to the value of `reg_i` (used by `VtoI`); out-of-range values saturate.
+    fn to_i32(self) -> i32;
+
+    /// Converts a bound of `ArithmeticMode::Saturating` or a result of strict math (see `VirtualMachine::set_strict_math`).
+    fn from_f64(value: f64) -> Self;
+
+    /// Converts an operand of strict math (see `VirtualMachine::set_strict_math`); must be exact.
+    fn to_f64(self) -> f64;
+
+    fn abs(self) -> Self;
+
+    fn sqrt(self) -> Self;
+
+    fn powf(self, exponent: Self) -> Self;
+
+    fn ln(self) -> Self;
+
+    fn sin(self) -> Self;
+
+    fn cos(self) -> Self;
+
+    /// Returns a uniformly distributed value in [0, 1) (used by `RandV`).
+    fn random<R: rand::Rng>(rng: &mut R) -> Self;
+}
+
+impl VmNumber for f32 {
+    const ZERO: f32 = 0.0;
+    const ONE: f32 = 1.0;
+    fn from_i32(value: i32) -> f32 { value as f32 }
+    fn to_i32(self) -> i32 { self as i32 }
+    fn from_f64(value: f64) -> f32 { value as f32 }
+    fn to_f64(self) -> f64 { self as f64 }
+    fn abs(self) -> f32 { f32::abs(self) }
+    fn sqrt(self) -> f32 { f32::sqrt(self) }
+    fn powf(self, exponent: f32) -> f32 { f32::powf(self, exponent) }
+    fn ln(self) -> f32 { f32::ln(self) }
+    fn sin(self) -> f32 { f32::sin(self) }
+    fn cos(self) -> f32 { f32::cos(self) }
+    fn random<R: rand::Rng>(rng: &mut R) -> f32 { rng.gen() }
+}
+
+impl VmNumber for f64 {
+    const ZERO: f64 = 0.0;
+    const ONE: f64 = 1.0;
+    fn from_i32(value: i32) -> f64 { value as f64 }
+    fn to_i32(self) -> i32 { self as i32 }
+    fn from_f64(value: f64) -> f64 { value }
+    fn to_f64(self) -> f64 { self }
+    fn abs(self) -> f64 { f64::abs(self) }
+    fn sqrt(self) -> f64 { f64::sqrt(self) }
+    fn powf(self, exponent: f64) -> f64 { f64::powf(self, exponent) }
+    fn ln(self) -> f64 { f64::ln(self) }
+    fn sin(self) -> f64 { f64::sin(self) }
+    fn cos(self) -> f64 { f64::cos(self) }
+    fn random<R: rand::Rng>(rng: &mut R) -> f64 { rng.gen() }
+}
+
 /// Virtual machine's state.
 #[derive(Clone)]
-pub struct VmState {
-    /// Data slots.
-    pub data: Vec<RegValue>,
+pub struct VmState<T = RegValue> {
+    /// Data slots of all data banks (stored consecutively: bank `b`, slot `i` is at `b * num_data_slots + i`).
+    pub data: Vec<T>,
     /// Index register.
     pub reg_i: i32,
+    /// Data bank register.
+    pub reg_bank: i32,
     /// Value register.
-    pub reg_v: RegValue,
+    pub reg_v: T,
     /// Current instruction pointer.
-    pub iptr: usize
+    pub iptr: usize,
+    /// Used by `RandV`. Private, so a `VmState` cannot be created with a struct literal; use `new` or `with_seed`.
+    rng: rand_xorshift::XorShiftRng
 }
 
-impl VmState {
+impl<T: VmNumber> VmState<T> {
+    ///
+    /// Creates the initial state for executing `program`: zeroed registers and data slots (of all banks),
+    /// and the random number generator seeded with `VirtualMachine::DEFAULT_RNG_SEED`.
+    ///
+    /// The public fields can then be modified, e.g. to pre-populate data before passing the state
+    /// to `VirtualMachine::with_state`.
+    ///
+    pub fn new(program: &Program) -> VmState<T> {
+        VmState::with_seed(program, VirtualMachine::<T>::DEFAULT_RNG_SEED)
+    }
+
+    /// Creates the initial state for executing `program` (see `new`) with the random number generator seeded with `seed`.
+    pub fn with_seed(program: &Program, seed: u64) -> VmState<T> {
+        VmState{
+            data: vec![T::ZERO; program.get_num_data_slots() * program.get_num_data_banks()],
+            reg_i: 0,
+            reg_bank: 0,
+            reg_v: T::ZERO,
+            iptr: 0,
+            rng: rand_xorshift::XorShiftRng::seed_from_u64(seed)
+        }
+    }
+
     pub fn reset(&mut self) {
-        self.data = vec![0.0; self.data.len()];
+        self.data = vec![T::ZERO; self.data.len()];
         self.reg_i = 0;
-        self.reg_v = 0.0;
+        self.reg_bank = 0;
+        self.reg_v = T::ZERO;
         self.iptr = 0;
     }
 }
@@ -40,7 +140,7 @@ impl VmState {
 ///
 /// Instruction set is based on Slash/A language by Artur B Adib.
 ///
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum OpCode {
     /// Assign value to `reg_i`.
     SetI(i32),
@@ -97,23 +197,149 @@ pub enum OpCode {
     Neg,
     /// Set `reg_v` to its square root if non-negative, otherwise set to zero.
     Sqrt,
+    ///
+    /// Raise `reg_v` to the power of `data[reg_i]`. If either of them or the result is NaN
+    /// (e.g. for a negative `reg_v` and a non-integer exponent), set `reg_v` to zero.
+    ///
+    Pow,
+    /// Set `reg_v` to its natural logarithm if positive, otherwise (also if NaN) set to zero.
+    Log,
+    /// Set `reg_v` to its sine (`reg_v` is in radians).
+    Sin,
+    /// Set `reg_v` to its cosine (`reg_v` is in radians).
+    Cos,
+    /// Set `reg_v` to a pseudo-random value in [0, 1) from the virtual machine's random number generator.
+    RandV,
+    /// Assign value to `reg_bank` (selects the data bank accessed by instructions using `data[reg_i]`).
+    SetBank(i32),
+    ///
+    /// End execution (`EndReason::Halted`). The instruction pointer is not advanced, so running the program again
+    /// ends immediately (until `reset`).
+    ///
+    Halt,
     ///Do nothing.
     Nop
 }
 
+impl OpCode {
+    ///
+    /// All opcodes (those with an operand have it set to 0), in declaration order.
+    ///
+    /// `mnemonic` and `operand` have exhaustive matches, so a new opcode cannot be added without
+    /// updating them; `opcode_tests::all_opcodes_listed` checks that it is also added here.
+    ///
+    pub const ALL: [OpCode; 34] = [
+        OpCode::SetI(0), OpCode::Input(0), OpCode::Output(0), OpCode::ItoV, OpCode::VtoI, OpCode::IncV,
+        OpCode::DecV, OpCode::IncI, OpCode::DecI, OpCode::Load, OpCode::Store, OpCode::Swap, OpCode::EndGoTo,
+        OpCode::GoToIfP, OpCode::JumpIfN, OpCode::EndJump, OpCode::IfP, OpCode::IfN, OpCode::Cmp, OpCode::Add,
+        OpCode::Sub, OpCode::Mul, OpCode::Div, OpCode::Abs, OpCode::Neg, OpCode::Sqrt, OpCode::Pow, OpCode::Log,
+        OpCode::Sin, OpCode::Cos, OpCode::RandV, OpCode::SetBank(0), OpCode::Halt, OpCode::Nop
+    ];
+
+    /// Returns the VM assembly mnemonic (without the operand).
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            OpCode::SetI(_) =>    "seti",
+            OpCode::Input(_) =>   "input",
+            OpCode::Output(_) =>  "output",
+            OpCode::ItoV =>       "itov",
+            OpCode::VtoI =>       "vtoi",
+            OpCode::IncV =>       "incv",
+            OpCode::DecV =>       "decv",
+            OpCode::IncI =>       "inci",
+            OpCode::DecI =>       "deci",
+            OpCode::Load =>       "load",
+            OpCode::Store =>      "store",
+            OpCode::Swap =>       "swap",
+            OpCode::EndGoTo =>    "endgoto",
+            OpCode::GoToIfP =>    "gotoifp",
+            OpCode::JumpIfN =>    "jumpifn",
+            OpCode::EndJump =>    "endjump",
+            OpCode::IfP =>        "ifp",
+            OpCode::IfN =>        "ifn",
+            OpCode::Cmp =>        "cmp",
+            OpCode::Add =>        "add",
+            OpCode::Sub =>        "sub",
+            OpCode::Mul =>        "mul",
+            OpCode::Div =>        "div",
+            OpCode::Abs =>        "abs",
+            OpCode::Neg =>        "neg",
+            OpCode::Sqrt =>       "sqrt",
+            OpCode::Pow =>        "pow",
+            OpCode::Log =>        "log",
+            OpCode::Sin =>        "sin",
+            OpCode::Cos =>        "cos",
+            OpCode::RandV =>      "randv",
+            OpCode::SetBank(_) => "setbank",
+            OpCode::Halt =>       "halt",
+            OpCode::Nop =>        "nop"
+        }
+    }
+
+    /// Returns the operand of opcodes which have one.
+    pub fn operand(&self) -> Option<i32> {
+        match *self {
+            OpCode::SetI(i) | OpCode::Input(i) | OpCode::Output(i) | OpCode::SetBank(i) => Some(i),
+
+            OpCode::ItoV | OpCode::VtoI | OpCode::IncV | OpCode::DecV | OpCode::IncI | OpCode::DecI |
+            OpCode::Load | OpCode::Store | OpCode::Swap | OpCode::EndGoTo | OpCode::GoToIfP | OpCode::JumpIfN |
+            OpCode::EndJump | OpCode::IfP | OpCode::IfN | OpCode::Cmp | OpCode::Add | OpCode::Sub | OpCode::Mul |
+            OpCode::Div | OpCode::Abs | OpCode::Neg | OpCode::Sqrt | OpCode::Pow | OpCode::Log | OpCode::Sin | OpCode::Cos |
+            OpCode::RandV | OpCode::Halt | OpCode::Nop => None
+        }
+    }
+
+    /// Returns the opcode with its operand replaced by `operand`; opcodes without an operand are returned unchanged.
+    pub fn with_operand(self, operand: i32) -> OpCode {
+        match self {
+            OpCode::SetI(_) => OpCode::SetI(operand),
+            OpCode::Input(_) => OpCode::Input(operand),
+            OpCode::Output(_) => OpCode::Output(operand),
+            OpCode::SetBank(_) => OpCode::SetBank(operand),
+            _ => self
+        }
+    }
+}
+
 /// Handler of `OpCode::Input` and `OpCode::Output`.
-pub trait InputOutputHandler {
-    fn input(&mut self, input_num: i32) -> RegValue;
-    fn output(&mut self, output_num: i32, output_val: RegValue);
+pub trait InputOutputHandler<T = RegValue> {
+    fn input(&mut self, input_num: i32) -> T;
+    fn output(&mut self, output_num: i32, output_val: T);
     fn check_end_condition(&self, num_execd_instructions: usize) -> bool;
 }
 
+/// Input/output handler for programs which need no I/O: inputs are zero, outputs are ignored, the end condition is never met.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullIoHandler;
+
+///
+/// Input/output handler type for a virtual machine created with `VirtualMachine::with_handler`
+/// and no handler (`None`). Never called.
+///
+pub type NoHandler = NullIoHandler;
+
+impl<T: VmNumber> InputOutputHandler<T> for NullIoHandler {
+    fn input(&mut self, _input_num: i32) -> T { T::ZERO }
+
+    fn output(&mut self, _output_num: i32, _output_val: T) { }
+
+    fn check_end_condition(&self, _num_execd_instructions: usize) -> bool { false }
+}
+
 /// Reason for ending virtual machine program execution.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum EndReason {
     LastInstructionReached,
     NumExecInstructions,
-    EndConditionMet
+    EndConditionMet,
+    /// The program entered a loop which makes no progress (see `RunOptions::detect_loops`).
+    LoopDetected,
+    /// A `Halt` instruction was executed.
+    Halted,
+    /// The end condition was met, but later than allowed by `RunOptions::termination`.
+    EndConditionTimedOut,
+    /// Execution took longer than `RunOptions::max_duration`.
+    TimedOut
 }
 
 impl std::fmt::Display for EndReason {
@@ -122,15 +348,200 @@ impl std::fmt::Display for EndReason {
     }
 }
 
+/// Result of `VirtualMachine::run_reported`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunReport {
+    pub end_reason: EndReason,
+    /// Number of executed instructions (including the one which ended the run).
+    pub instructions_executed: usize,
+    /// Number of executed `Output` instructions.
+    pub outputs_emitted: usize,
+    /// Number of `GoToIfP` and `JumpIfN` instructions which performed a jump.
+    pub jumps_taken: usize,
+    ///
+    /// Instruction pointer at the end of the run (the same as `get_state().iptr`), e.g. of the `Halt` executed,
+    /// or of the instruction following the `Output` which met the end condition.
+    ///
+    pub final_iptr: usize
+}
+
+/// What happens when a program run passes its last instruction.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RunMode {
+    /// Execution ends (`EndReason::LastInstructionReached`).
+    #[default]
+    Once,
+    /// Execution restarts from the beginning.
+    LoopForever,
+    ///
+    /// Execution restarts from the beginning, and ends (`EndReason::LastInstructionReached`) when the program
+    /// has been passed the specified number of times during the current run. With 0, nothing is executed.
+    ///
+    LoopTimes(usize)
+}
+
+impl RunMode {
+    /// Returns `LoopForever` if `looped` is true, `Once` otherwise.
+    pub fn from_looped(looped: bool) -> RunMode {
+        if looped { RunMode::LoopForever } else { RunMode::Once }
+    }
+}
+
+///
+/// Determines whether meeting the end condition (see `RunOptions::check_end_condition`) counts as success.
+///
+/// E.g. an experiment may require the target to be reached within a budget of instructions, while still letting
+/// the program run longer (to tell the programs which reach it late from those which never do).
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TerminationPolicy {
+    /// If set, meeting the end condition after executing more instructions than this ends the run
+    /// with `EndReason::EndConditionTimedOut` instead of `EndReason::EndConditionMet`.
+    pub max_instructions: Option<usize>
+}
+
+/// Parameters of `VirtualMachine::run_with_options` and `VirtualMachine::run_reported`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RunOptions {
+    /// Max. number of instructions to execute (or their total cost, see `instruction_cost`).
+    pub num_exec_instructions: Option<usize>,
+    ///
+    /// Cost of executing an instruction; if `None`, every instruction costs 1.
+    ///
+    /// Execution ends with `EndReason::NumExecInstructions` when the cost of the next instruction would make
+    /// the total cost of executed instructions exceed `num_exec_instructions`.
+    ///
+    pub instruction_cost: Option<fn(OpCode) -> u32>,
+    /// Whether and how many times the program restarts from the beginning after reaching the last instruction.
+    pub mode: RunMode,
+    /// If true, `io_handler.check_end_condition()` is called after every `Output` instruction;
+    /// if returns true, program execution ends.
+    pub check_end_condition: bool,
+    /// Success criterion for the end condition.
+    pub termination: TerminationPolicy,
+    ///
+    /// If true, execution ends with `EndReason::LoopDetected` when the program keeps repeating the same
+    /// sequence of states (`iptr`, `reg_i`, `reg_bank`, `reg_v`) without executing `Output` or `RandV`, changing `data`
+    /// or restarting with `RunMode::LoopTimes`.
+    ///
+    /// Assumes that `io_handler.input()` keeps returning the same values as long as there is no output.
+    /// Uses Brent's cycle detection: a loop is reported after at most twice its length (plus the number
+    /// of instructions preceding it), with constant memory and time overhead per instruction.
+    ///
+    pub detect_loops: bool,
+    ///
+    /// Max. wall-clock duration of the run; when exceeded, execution ends with `EndReason::TimedOut`.
+    ///
+    /// To avoid the overhead of reading the clock, elapsed time is checked only every `TIME_CHECK_INTERVAL`
+    /// instructions, so the run may take somewhat longer (and always executes at least that many instructions,
+    /// unless it ends for another reason).
+    ///
+    pub max_duration: Option<std::time::Duration>
+}
+
+/// Number of instructions executed between checks of `RunOptions::max_duration`.
+pub const TIME_CHECK_INTERVAL: usize = 1024;
+
+///
+/// Handling of `reg_i` values which are not valid data slot indices by instructions accessing `data[reg_i]`
+/// (`Load`, `Store`, `Swap`, `Cmp`, `Add`, `Sub`, `Mul`, `Div`). Applies to `reg_bank` (data bank index) likewise.
+///
+/// If there are no data slots, such instructions do nothing regardless of the mode.
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IndexMode {
+    /// The instruction does nothing.
+    #[default]
+    Ignore,
+    /// `reg_i` is wrapped around (`reg_i.rem_euclid(num_data_slots)`), i.e. data slots form a ring buffer.
+    Wrap,
+    /// `reg_i` is clamped to [0, `num_data_slots` - 1].
+    Clamp
+}
+
+impl IndexMode {
+    /// Returns the index of the data slot accessed for `reg_i`, or `None` if there is none.
+    pub fn data_index(self, reg_i: i32, num_data_slots: usize) -> Option<usize> {
+        if reg_i >= 0 && (reg_i as usize) < num_data_slots {
+            return Some(reg_i as usize);
+        }
+        if num_data_slots == 0 {
+            return None;
+        }
+        match self {
+            IndexMode::Ignore => None,
+            IndexMode::Wrap => Some((reg_i as i64).rem_euclid(num_data_slots as i64) as usize),
+            IndexMode::Clamp => Some(if reg_i < 0 { 0 } else { num_data_slots - 1 })
+        }
+    }
+}
+
+///
+/// Handling of `reg_v` values produced by arithmetic instructions
+/// (`IncV`, `DecV`, `Add`, `Sub`, `Mul`, `Div`, `Abs`, `Neg`, `Sqrt`, `Pow`, `Log`, `Sin`, `Cos`).
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ArithmeticMode {
+    /// IEEE 754 floating-point arithmetic: results may overflow to infinity or become NaN.
+    #[default]
+    Ieee,
+    /// The result is clamped to [`min`, `max`] (so infinity is replaced by the nearest bound); NaN is replaced by zero
+    /// (clamped likewise).
+    Saturating{ min: f64, max: f64 }
+}
+
+impl ArithmeticMode {
+    /// Returns `value` (result of an arithmetic instruction) adjusted according to the mode.
+    pub fn apply<T: VmNumber>(self, value: T) -> T {
+        match self {
+            ArithmeticMode::Ieee => value,
+            ArithmeticMode::Saturating{ min, max } => {
+                let value = if value.partial_cmp(&value).is_none() { T::ZERO } else { value }; // NaN
+                let (min, max) = (T::from_f64(min), T::from_f64(max));
+                if value < min { min } else if value > max { max } else { value }
+            }
+        }
+    }
+}
+
+/// Operand problem reported by `Program::validate`; `index` is the instruction index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValidationError {
+    /// `Input` operand outside [0, `num_inputs`).
+    InputOutOfRange{ index: usize, operand: i32 },
+    /// `Output` operand outside [0, `num_outputs`).
+    OutputOutOfRange{ index: usize, operand: i32 },
+    /// `SetI` operand which is not a valid data slot index.
+    InvalidDataIndex{ index: usize, operand: i32 }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ValidationError::InputOutOfRange{ index, operand } =>
+                write!(f, "instruction {}: input {} out of range", index, operand),
+            ValidationError::OutputOutOfRange{ index, operand } =>
+                write!(f, "instruction {}: output {} out of range", index, operand),
+            ValidationError::InvalidDataIndex{ index, operand } =>
+                write!(f, "instruction {}: invalid data slot index {}", index, operand)
+        }
+    }
+}
+
+impl std::error::Error for ValidationError { }
+
 /// Program that runs on virtual machine.
 #[derive(Clone)]
 pub struct Program {
     /// Instructions.
     instr: Vec<OpCode>,
 
-    /// Number of virtual machine data slots used by program.
+    /// Number of virtual machine data slots used by program (in each data bank).
     num_data_slots: usize,
 
+    /// Number of data banks.
+    num_data_banks: usize,
+
     ///
     /// Contains destination and source addresses (indices in `instr`).
     ///
@@ -142,7 +553,16 @@ pub struct Program {
     /// If true, crossing of `GoToIfP`/`EndGoTo` pairs by `JumpIfN`/`EndJump`
     /// pairs and vice versa is allowed. Otherwise, instructions that would cause crossing are disabled
     /// in the jump table.
-    allow_crossing_blocks: bool
+    allow_crossing_blocks: bool,
+
+    /// Handling of out-of-range `reg_i` by instructions accessing data slots.
+    index_mode: IndexMode,
+
+    /// Handling of results of arithmetic instructions.
+    arithmetic_mode: ArithmeticMode,
+
+    /// `Cmp` considers `reg_v` and `data[reg_i]` equal if they differ by at most this value.
+    cmp_epsilon: f64
 }
 
 impl Program {
@@ -154,8 +574,8 @@ impl Program {
     /// * `instruction` - Instruction list.
     /// * `num_data_slots` - Number of virtual machine data slots used by program.
     /// * `allow_crossing_blocks` - If true, crossing of `GoToIfP`/`EndGoTo` pairs by `JumpIfN`/`EndJump`
-    /// pairs and vice versa is allowed. Otherwise, instructions that would cause crossing are disabled
-    /// in the jump table.
+    ///   pairs and vice versa is allowed. Otherwise, instructions that would cause crossing are disabled
+    ///   in the jump table.
     ///
     pub fn new(instructions: &[OpCode], num_data_slots: usize, allow_crossing_blocks: bool) -> Program {
         let mut jump_table = Program::create_jump_table(instructions);
@@ -166,8 +586,31 @@ impl Program {
             instr: instructions.to_vec(),
             num_data_slots,
             jump_table,
-            allow_crossing_blocks
+            allow_crossing_blocks,
+            num_data_banks: 1,
+            index_mode: IndexMode::Ignore,
+            arithmetic_mode: ArithmeticMode::Ieee,
+            cmp_epsilon: 0.0
+        }
+    }
+
+    ///
+    /// Creates new program like `new`, but with each negative `SetI` operand `n` replaced by
+    /// `abs(n) % num_data_slots`, so that it indexes a data slot instead of making memory instructions no-ops.
+    ///
+    /// Changes the evolutionary search landscape; can be used e.g. when creating programs for evaluation.
+    ///
+    pub fn new_with_wrapped_indices(instructions: &[OpCode], num_data_slots: usize, allow_crossing_blocks: bool) -> Program {
+        if num_data_slots == 0 {
+            return Program::new(instructions, num_data_slots, allow_crossing_blocks);
         }
+
+        let wrapped: Vec<OpCode> = instructions.iter().map(|opcode| match opcode {
+            OpCode::SetI(i) if *i < 0 => OpCode::SetI((i.unsigned_abs() as usize % num_data_slots) as i32),
+            _ => *opcode
+        }).collect();
+
+        Program::new(&wrapped, num_data_slots, allow_crossing_blocks)
     }
 
     pub fn get_instr(&self) -> &[OpCode] {
@@ -178,6 +621,65 @@ impl Program {
         self.num_data_slots
     }
 
+    pub fn get_num_data_banks(&self) -> usize {
+        self.num_data_banks
+    }
+
+    ///
+    /// Sets the number of independent data banks (1 by default), each containing `get_num_data_slots()` slots.
+    ///
+    /// Instructions accessing `data[reg_i]` use the bank selected with `SetBank` (bank 0 initially).
+    ///
+    pub fn set_num_data_banks(&mut self, num_data_banks: usize) {
+        self.num_data_banks = num_data_banks;
+    }
+
+    pub fn get_allow_crossing_blocks(&self) -> bool {
+        self.allow_crossing_blocks
+    }
+
+    pub fn get_index_mode(&self) -> IndexMode {
+        self.index_mode
+    }
+
+    ///
+    /// Sets handling of out-of-range `reg_i` by instructions accessing data slots (`IndexMode::Ignore` by default).
+    ///
+    /// Programs created by the `utils` functions (e.g. during breeding) use the default; the mode can be set
+    /// before evaluation. `get_optimized` and `remap_opcodes` preserve it.
+    ///
+    pub fn set_index_mode(&mut self, index_mode: IndexMode) {
+        self.index_mode = index_mode;
+    }
+
+    pub fn get_arithmetic_mode(&self) -> ArithmeticMode {
+        self.arithmetic_mode
+    }
+
+    ///
+    /// Sets handling of results of arithmetic instructions (`ArithmeticMode::Ieee` by default), e.g. to prevent
+    /// repeated `Mul`s from overflowing `reg_v` to infinity.
+    ///
+    /// Like the index mode, it is preserved by `get_optimized` and `remap_opcodes`.
+    ///
+    pub fn set_arithmetic_mode(&mut self, arithmetic_mode: ArithmeticMode) {
+        self.arithmetic_mode = arithmetic_mode;
+    }
+
+    pub fn get_cmp_epsilon(&self) -> f64 {
+        self.cmp_epsilon
+    }
+
+    ///
+    /// Sets the tolerance of `Cmp` (0 by default): `reg_v` and `data[reg_i]` are considered equal
+    /// if `abs(reg_v - data[reg_i]) <= cmp_epsilon`, so that programs can test computed values for equality.
+    ///
+    /// Like the index mode, it is preserved by `get_optimized` and `remap_opcodes`.
+    ///
+    pub fn set_cmp_epsilon(&mut self, cmp_epsilon: f64) {
+        self.cmp_epsilon = cmp_epsilon;
+    }
+
     ///
     /// Returns program's jump table.
     ///
@@ -189,6 +691,108 @@ impl Program {
         &self.jump_table
     }
 
+    ///
+    /// Returns the position, opcode and activity flag of each `GoToIfP`, `EndGoTo`, `JumpIfN`, `EndJump`
+    /// instruction (in program order).
+    ///
+    /// An instruction is inactive if it has no matching counterpart, or its block crosses another one
+    /// (unless crossing blocks are allowed); inactive instructions behave like `Nop`.
+    ///
+    pub fn control_flow_report(&self) -> Vec<(usize, OpCode, bool)> {
+        self.instr.iter().enumerate()
+            .filter(|(_, opcode)| matches!(opcode, OpCode::GoToIfP | OpCode::EndGoTo | OpCode::JumpIfN | OpCode::EndJump))
+            .map(|(i, opcode)| (i, *opcode, self.jump_table[i].is_some()))
+            .collect()
+    }
+
+    ///
+    /// Returns the number of instructions with each mnemonic (see `OpCode::mnemonic`) present in the program,
+    /// i.e. instructions differing only in operand (e.g. `SetI(0)`, `SetI(3)`) are counted together.
+    ///
+    /// Reachability is not taken into account.
+    ///
+    pub fn opcode_histogram(&self) -> std::collections::HashMap<&'static str, usize> {
+        let mut histogram = std::collections::HashMap::new();
+        for opcode in &self.instr {
+            *histogram.entry(opcode.mnemonic()).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    ///
+    /// Returns which instructions can be executed (statically, regardless of data): element `i` of the result
+    /// is true if the instruction at `i` is reachable from the first one by following fall-through, active jumps
+    /// (see `get_jump_table`) and `IfP`/`IfN` skips.
+    ///
+    /// As all jumps are conditional, only instructions after a `Halt` (not following `IfP`/`IfN`) can be unreachable.
+    ///
+    pub fn reachable_instructions(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.instr.len()];
+        let mut to_visit = if self.instr.is_empty() { vec![] } else { vec![0] };
+
+        while let Some(i) = to_visit.pop() {
+            if i >= self.instr.len() || reachable[i] { continue; }
+            reachable[i] = true;
+
+            match self.instr[i] {
+                OpCode::Halt => (),
+                OpCode::IfP | OpCode::IfN => to_visit.extend_from_slice(&[i + 1, i + 2]),
+                OpCode::GoToIfP | OpCode::JumpIfN => {
+                    to_visit.push(i + 1);
+                    if let Some(target) = self.jump_table[i] { to_visit.push(target); }
+                },
+                _ => to_visit.push(i + 1)
+            }
+        }
+
+        reachable
+    }
+
+    /// Returns the number of instructions with the same opcode as `opcode`, regardless of operand.
+    pub fn count_of(&self, opcode: OpCode) -> usize {
+        self.instr.iter().filter(|o| std::mem::discriminant(*o) == std::mem::discriminant(&opcode)).count()
+    }
+
+    ///
+    /// Returns a 64-bit FNV-1a hash of the program's binary representation (see `to_bytes`: instructions,
+    /// numbers of data slots and banks, modes), which is the same across runs, processes and platforms
+    /// (unlike hashes of `std::collections::HashMap`, which are randomly seeded).
+    ///
+    /// The jump table is not included, as it is derived from the other data.
+    ///
+    pub fn stable_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+        self.to_bytes().iter().fold(FNV_OFFSET_BASIS, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+    }
+
+    ///
+    /// Checks instruction operands against the numbers of inputs and outputs used by the experiment
+    /// (meant for tooling, e.g. checking loaded programs; not enforced when running).
+    ///
+    /// `SetI` operands are reported if they are not valid data slot indices in `IndexMode::Ignore`
+    /// (other modes accept any index) and the program has data slots. Note that such a value can still
+    /// be used by `ItoV`, or become valid after `IncI`, `DecI`.
+    ///
+    /// Returns all errors, in instruction order.
+    ///
+    pub fn validate(&self, num_inputs: i32, num_outputs: i32) -> Result<(), Vec<ValidationError>> {
+        let check_data_index = self.index_mode == IndexMode::Ignore && self.num_data_slots > 0;
+
+        let errors: Vec<ValidationError> = self.instr.iter().enumerate().filter_map(|(index, opcode)| match *opcode {
+            OpCode::Input(operand) if operand < 0 || operand >= num_inputs =>
+                Some(ValidationError::InputOutOfRange{ index, operand }),
+            OpCode::Output(operand) if operand < 0 || operand >= num_outputs =>
+                Some(ValidationError::OutputOutOfRange{ index, operand }),
+            OpCode::SetI(operand) if check_data_index && IndexMode::Ignore.data_index(operand, self.num_data_slots).is_none() =>
+                Some(ValidationError::InvalidDataIndex{ index, operand }),
+            _ => None
+        }).collect();
+
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+
     ///
     /// Creates a jump table.
     ///
@@ -206,12 +810,12 @@ impl Program {
             match instr[i] {
                 OpCode::EndGoTo => stack_end_goto.push(i),
                 OpCode::JumpIfN => stack_jump.push(i),
-                OpCode::GoToIfP => if !stack_end_goto.is_empty() {
+                OpCode::GoToIfP if !stack_end_goto.is_empty() => {
                     let back = stack_end_goto.pop().unwrap();
                     jump_table[i] = Some(back);
                     jump_table[back] = Some(i);
                 },
-                OpCode::EndJump => if !stack_jump.is_empty() {
+                OpCode::EndJump if !stack_jump.is_empty() => {
                     let back = stack_jump.pop().unwrap();
                     jump_table[back] = Some(i);
                     jump_table[i] = Some(back);
@@ -227,15 +831,15 @@ impl Program {
     /// Modifies the specified jump table to deactivate any `GoToIfP`/`EndGoTo` pairs
     /// that cross `JumpIfN`/`EndJump` pairs and vice versa.
     ///
-    fn deactivate_crossing_blocks(instr: &[OpCode], jump_table: &mut Vec<Option<usize>>) {
+    fn deactivate_crossing_blocks(instr: &[OpCode], jump_table: &mut [Option<usize>]) {
         let mut open_blocks: Vec<usize> = vec![];
 
         for pos in 0..instr.len() {
             match instr[pos] {
-                OpCode::EndGoTo | OpCode::JumpIfN => if jump_table[pos].is_some() {
+                OpCode::EndGoTo | OpCode::JumpIfN if jump_table[pos].is_some() => {
                     open_blocks.push(pos);
                 },
-                OpCode::GoToIfP | OpCode::EndJump => if jump_table[pos].is_some() {
+                OpCode::GoToIfP | OpCode::EndJump if jump_table[pos].is_some() => {
                     loop {
                         let last = open_blocks.pop().unwrap();
                         // a block ends here; going towards its beginning, deactivate any other open blocks
@@ -257,45 +861,190 @@ impl Program {
     ///
     /// Returns an optimized version of the program: sequences of instructions without effect are removed.
     ///
-    /// See the `optimization_tests` module in this file for examples.
+    /// Runs `DEFAULT_OPTIMIZATION_PASSES`. See the `optimization_tests` module in this file for examples.
     ///
     pub fn get_optimized(&self) -> Program {
-        let mut opt_instr: Vec<OpCode> = vec![]; // optimized instruction list (in reverse)
+        self.optimize_with(DEFAULT_OPTIMIZATION_PASSES)
+    }
+
+    ///
+    /// Returns the program optimized by running each of `passes` in order.
+    ///
+    /// An empty pass list returns an unchanged copy.
+    ///
+    pub fn optimize_with(&self, passes: &[&dyn OptimizationPass]) -> Program {
+        let mut result = self.clone();
+        for pass in passes {
+            result = pass.apply(&result);
+        }
+        result
+    }
+
+    ///
+    /// Returns an optimized version of the program (see `get_optimized`) and, for each of its instructions,
+    /// the index of the original instruction it comes from (`None` for synthesized or merged instructions).
+    ///
+    pub fn get_optimized_with_mapping(&self) -> (Program, Vec<Option<usize>>) {
+        self.optimize_with_mapping(DEFAULT_OPTIMIZATION_PASSES)
+    }
 
-        if self.instr.len() < 2 { return self.clone(); }
+    /// Returns the program optimized by running each of `passes` in order (see `get_optimized_with_mapping`).
+    pub fn optimize_with_mapping(&self, passes: &[&dyn OptimizationPass]) -> (Program, Vec<Option<usize>>) {
+        let mut result = self.clone();
+        let mut mapping: Vec<Option<usize>> = (0..self.instr.len()).map(Some).collect();
+        for pass in passes {
+            let (pass_result, pass_mapping) = pass.apply_with_mapping(&result);
+            result = pass_result;
+            mapping = pass_mapping.iter().map(|index| index.and_then(|index| mapping[index])).collect();
+        }
+        (result, mapping)
+    }
 
-        // scan `self.instr` backwards and look for removable sequences
-        let mut i: i32 = self.instr.len() as i32 - 1;
-        while i >= 0 {
-            let current = self.instr[i as usize];
+    ///
+    /// Creates a program with the same number of data slots and banks, crossing of blocks setting, index
+    /// and arithmetic modes and `Cmp` tolerance, but with a different instruction list. The jump table is recomputed.
+    ///
+    pub fn with_instructions(&self, instructions: &[OpCode]) -> Program {
+        let mut result = Program::new(instructions, self.num_data_slots, self.allow_crossing_blocks);
+        result.num_data_banks = self.num_data_banks;
+        result.index_mode = self.index_mode;
+        result.arithmetic_mode = self.arithmetic_mode;
+        result.cmp_epsilon = self.cmp_epsilon;
+        result
+    }
+
+    ///
+    /// Returns a copy of the program with each instruction replaced by `mapping(instruction)`.
+    ///
+    /// The jump table is recomputed. Can be used e.g. to replace opcodes no longer present
+    /// in the allowed instruction set of an experiment.
+    ///
+    pub fn remap_opcodes(&self, mapping: impl Fn(OpCode) -> OpCode) -> Program {
+        let remapped: Vec<OpCode> = self.instr.iter().map(|opcode| mapping(*opcode)).collect();
+        self.with_instructions(&remapped)
+    }
+
+    ///
+    /// Returns positions of `GoToIfP` instructions of loops which are likely infinite.
+    ///
+    /// This is a cheap heuristic (neither sound nor complete): an active loop is reported if its body
+    /// contains no instruction which could make `reg_v` negative (e.g. `DecV`, `Neg`, `Sub`, `Cmp`,
+    /// `Input`, `Load`), and its `GoToIfP` cannot be skipped by a preceding `IfN`.
+    ///
+    pub fn likely_infinite_loops(&self) -> Vec<usize> {
+        let mut result = vec![];
 
-            // skip `Nop` if not following `IfP`/`IfN`
-            if current != OpCode::Nop ||
-                (current == OpCode::Nop && i > 0 && [OpCode::IfN, OpCode::IfP].contains(&self.instr[(i-1) as usize])) {
-                opt_instr.push(current);
+        for (i, opcode) in self.instr.iter().enumerate() {
+            if *opcode != OpCode::GoToIfP || self.jump_table[i].is_none() {
+                continue;
             }
+            if i > 0 && self.instr[i - 1] == OpCode::IfN {
+                continue;
+            }
+
+            let loop_start = self.jump_table[i].unwrap();
+            let can_exit = self.instr[loop_start + 1 .. i].iter().any(|body_opcode| !matches!(body_opcode,
+                OpCode::SetI(_) |
+                OpCode::Output(_) |
+                OpCode::VtoI |
+                OpCode::IncV |
+                OpCode::IncI |
+                OpCode::DecI |
+                OpCode::Store |
+                OpCode::EndGoTo |
+                OpCode::GoToIfP |
+                OpCode::JumpIfN |
+                OpCode::EndJump |
+                OpCode::IfP |
+                OpCode::IfN |
+                OpCode::Abs |
+                OpCode::Sqrt |
+                OpCode::RandV |
+                OpCode::SetBank(_) |
+                OpCode::Nop
+            ));
+
+            if !can_exit {
+                result.push(i);
+            }
+        }
+
+        result
+    }
+}
+
+///
+/// Program optimization pass (see `Program::optimize_with`).
+///
+/// The optimized program must produce the same outputs and final `reg_v`, `reg_i` as the original.
+///
+pub trait OptimizationPass {
+    fn name(&self) -> &str;
+
+    fn apply(&self, program: &Program) -> Program;
+
+    ///
+    /// Like `apply`, but also returns, for each instruction of the optimized program, the index of the instruction
+    /// of `program` it comes from (`None` if not known, e.g. for synthesized or merged instructions).
+    ///
+    /// The default implementation returns `None` for all instructions.
+    ///
+    fn apply_with_mapping(&self, program: &Program) -> (Program, Vec<Option<usize>>) {
+        let result = self.apply(program);
+        let mapping = vec![None; result.get_instr().len()];
+        (result, mapping)
+    }
+}
+
+/// Creates a program from instructions of `program` at `indices`; returns it together with `indices` as the mapping.
+fn keep_instructions(program: &Program, indices: Vec<usize>) -> (Program, Vec<Option<usize>>) {
+    let instructions: Vec<OpCode> = indices.iter().map(|i| program.get_instr()[*i]).collect();
+    (program.with_instructions(&instructions), indices.into_iter().map(Some).collect())
+}
+
+/// Optimization passes used by `Program::get_optimized`.
+pub const DEFAULT_OPTIMIZATION_PASSES: &[&dyn OptimizationPass] = &[&FoldRedundantSetI, &RemoveDeadNop];
+
+///
+/// Removes instructions modifying `reg_i` which precede an unconditional `SetI` (i.e. not following `IfP`/`IfN`),
+/// e.g. `SetI(0), IncI, SetI(1)` becomes `SetI(1)`.
+///
+pub struct FoldRedundantSetI;
+
+impl OptimizationPass for FoldRedundantSetI {
+    fn name(&self) -> &str { "FoldRedundantSetI" }
+
+    fn apply(&self, program: &Program) -> Program {
+        self.apply_with_mapping(program).0
+    }
+
+    fn apply_with_mapping(&self, program: &Program) -> (Program, Vec<Option<usize>>) {
+        let instr = program.get_instr();
+        let mut kept: Vec<usize> = vec![]; // indices of instructions of the optimized program (in reverse)
+
+        // scan `instr` backwards and look for removable sequences
+        let mut i: i32 = instr.len() as i32 - 1;
+        while i >= 0 {
+            kept.push(i as usize);
             i -= 1;
             if i < 0 { break; }
 
             // a sequence of instructions modifying `reg_i` which ends in an unconditional `SetI`
             // (i.e. not following `IfP`/`IfN`) can be reduced to the final `SetI`
             let mut was_unconditional_seti = false;
-            match self.instr[(i+1) as usize] {
-                OpCode::SetI(_) => {
-                    match self.instr[i as usize] {
-                        OpCode::SetI(_) |
-                            OpCode::IncI |
-                            OpCode::DecI |
-                            OpCode::VtoI |
-                            OpCode::Nop => was_unconditional_seti = true,
-                        _ => ()
-                    };
-                },
-                _ => ()
+            if let OpCode::SetI(_) = instr[(i+1) as usize] {
+                match instr[i as usize] {
+                    OpCode::SetI(_) |
+                        OpCode::IncI |
+                        OpCode::DecI |
+                        OpCode::VtoI |
+                        OpCode::Nop => was_unconditional_seti = true,
+                    _ => ()
+                };
             }
             if was_unconditional_seti {
                 while i >= 0 {
-                    match self.instr[i as usize] {
+                    match instr[i as usize] {
                         OpCode::SetI(_) |
                             OpCode::IfP |
                             OpCode::IfN |
@@ -309,29 +1058,199 @@ impl Program {
             }
         }
 
-        opt_instr.reverse();
+        kept.reverse();
+        keep_instructions(program, kept)
+    }
+}
+
+///
+/// Removes `Nop`s, except those following `IfP`/`IfN` (which would otherwise skip a different instruction)
+/// and a program's only instruction.
+///
+pub struct RemoveDeadNop;
+
+impl OptimizationPass for RemoveDeadNop {
+    fn name(&self) -> &str { "RemoveDeadNop" }
+
+    fn apply(&self, program: &Program) -> Program {
+        self.apply_with_mapping(program).0
+    }
+
+    fn apply_with_mapping(&self, program: &Program) -> (Program, Vec<Option<usize>>) {
+        let instr = program.get_instr();
+        if instr.len() < 2 { return (program.clone(), (0..instr.len()).map(Some).collect()); }
+
+        let kept: Vec<usize> = instr.iter().enumerate()
+            .filter(|(i, opcode)| **opcode != OpCode::Nop || (*i > 0 && [OpCode::IfN, OpCode::IfP].contains(&instr[i - 1])))
+            .map(|(i, _)| i)
+            .collect();
+
+        keep_instructions(program, kept)
+    }
+}
+
+///
+/// Removes `Store` instructions whose value is overwritten by a subsequent `Store` before it can be read.
+///
+/// Conservative: only considers straight-line sequences, i.e. a `Store` is removed if it is followed by another
+/// unconditional `Store` with no instruction in between which reads data slots, modifies `reg_i`, or is a jump
+/// or a jump target. A `Store` following `IfP`/`IfN` is kept.
+///
+/// Not included in `DEFAULT_OPTIMIZATION_PASSES`.
+///
+pub struct RemoveDeadStore;
 
-        let mut jump_table = Program::create_jump_table(&opt_instr);
-        if !self.allow_crossing_blocks {
-            Program::deactivate_crossing_blocks(&opt_instr, &mut jump_table);
+impl RemoveDeadStore {
+    /// Returns true if the `Store` at `pos` in `instr` is overwritten before being read.
+    fn is_dead_store(instr: &[OpCode], pos: usize) -> bool {
+        if pos > 0 && [OpCode::IfP, OpCode::IfN].contains(&instr[pos - 1]) {
+            return false;
         }
+        for i in pos + 1 .. instr.len() {
+            match instr[i] {
+                OpCode::Store => if ![OpCode::IfP, OpCode::IfN].contains(&instr[i - 1]) {
+                    return true;
+                },
 
-        Program{
-            instr: opt_instr,
-            num_data_slots: self.num_data_slots,
-            jump_table,
-            allow_crossing_blocks: self.allow_crossing_blocks
+                OpCode::Input(_) |
+                OpCode::Output(_) |
+                OpCode::ItoV |
+                OpCode::IncV |
+                OpCode::DecV |
+                OpCode::IfP |
+                OpCode::IfN |
+                OpCode::Abs |
+                OpCode::Neg |
+                OpCode::Sqrt |
+                OpCode::Log |
+                OpCode::Sin |
+                OpCode::Cos |
+                OpCode::RandV |
+                OpCode::Nop => (),
+
+                _ => return false
+            }
+        }
+
+        false
+    }
+}
+
+impl OptimizationPass for RemoveDeadStore {
+    fn name(&self) -> &str { "RemoveDeadStore" }
+
+    fn apply(&self, program: &Program) -> Program {
+        self.apply_with_mapping(program).0
+    }
+
+    fn apply_with_mapping(&self, program: &Program) -> (Program, Vec<Option<usize>>) {
+        let instr = program.get_instr();
+        let kept: Vec<usize> = instr.iter().enumerate()
+            .filter(|(i, opcode)| **opcode != OpCode::Store || !RemoveDeadStore::is_dead_store(instr, *i))
+            .map(|(i, _)| i)
+            .collect();
+
+        keep_instructions(program, kept)
+    }
+}
+
+///
+/// Removes active `JumpIfN`/`EndJump` and `EndGoTo`/`GoToIfP` pairs whose body is empty or consists of `Nop`s only,
+/// except a pair following `IfP`/`IfN` and one forming the whole program. A removal which would change
+/// the pairing of other control flow instructions is not performed.
+///
+/// Note that an empty `EndGoTo`/`GoToIfP` pair loops forever if `reg_v` is non-negative; after its removal, execution
+/// continues instead. Results of programs which terminate are not affected, but programs run with an instruction
+/// limit or loop detection (see `RunOptions::detect_loops`) may behave differently. Hence the pass is not included
+/// in `DEFAULT_OPTIMIZATION_PASSES`.
+///
+pub struct RemoveEmptyBlocks;
+
+impl OptimizationPass for RemoveEmptyBlocks {
+    fn name(&self) -> &str { "RemoveEmptyBlocks" }
+
+    fn apply(&self, program: &Program) -> Program {
+        self.apply_with_mapping(program).0
+    }
+
+    fn apply_with_mapping(&self, program: &Program) -> (Program, Vec<Option<usize>>) {
+        let instr = program.get_instr();
+        let jmp_tbl = program.get_jump_table();
+        let unchanged = || (program.clone(), (0..instr.len()).map(Some).collect());
+
+        let mut removed = vec![false; instr.len()];
+        for (i, opcode) in instr.iter().enumerate() {
+            let (start, end) = match (opcode, jmp_tbl[i]) {
+                (OpCode::JumpIfN, Some(target)) => (i, target),
+                (OpCode::GoToIfP, Some(target)) => (target, i),
+                _ => continue
+            };
+            let follows_if = start > 0 && [OpCode::IfP, OpCode::IfN].contains(&instr[start - 1]);
+            if !follows_if && instr[start + 1..end].iter().all(|o| *o == OpCode::Nop) {
+                for r in &mut removed[start..=end] { *r = true; }
+            }
+        }
+
+        let kept: Vec<usize> = (0..instr.len()).filter(|i| !removed[*i]).collect();
+        if kept.is_empty() || kept.len() == instr.len() {
+            return unchanged();
+        }
+
+        // the remaining control flow instructions must keep their targets
+        let mut new_index = vec![None; instr.len()];
+        for (new_i, old_i) in kept.iter().enumerate() {
+            new_index[*old_i] = Some(new_i);
         }
+        let (result, mapping) = keep_instructions(program, kept);
+        let targets_kept = mapping.iter().enumerate().all(|(new_i, old_i)| {
+            result.get_jump_table()[new_i] == jmp_tbl[old_i.unwrap()].and_then(|target| new_index[target])
+        });
+
+        if targets_kept { (result, mapping) } else { unchanged() }
     }
 }
 
-pub struct VirtualMachine<'a> {
+/// Called before executing an instruction; receives the instruction pointer, opcode and virtual machine's state.
+pub type TraceHook<'a, T = RegValue> = Box<dyn FnMut(usize, OpCode, &VmState<T>) + 'a>;
+
+///
+/// Virtual machine executing a `Program`.
+///
+/// `T` is the computational data type; `new` creates the default (`RegValue`) variant,
+/// `with_number_type` any other, e.g. `VirtualMachine::<f64>::with_number_type(...)`.
+///
+/// `H` is the input/output handler type. By default it is a trait object; `with_handler` creates
+/// a virtual machine using a concrete type instead, which avoids dynamic dispatch of handler calls.
+///
+pub struct VirtualMachine<
+    'a,
+    T: VmNumber = RegValue,
+    H: ?Sized + InputOutputHandler<T> + 'a = dyn InputOutputHandler<T> + 'a
+> {
     /// Virtual machine state.
-    state: VmState,
+    state: VmState<T>,
     /// Executed program.
     program: &'a Program,
     /// Handles `Input` and `Output` instructions and evaluates the VM run's end condition.
-    io_handler: Option<&'a mut InputOutputHandler>,
+    io_handler: Option<&'a mut H>,
+    /// Number of instructions executed by `step`/`step_with` since creation or the last reset.
+    num_steps: usize,
+    /// Number of times a looped program restarted from the beginning since creation or the last reset.
+    num_restarts: usize,
+    /// If true, `reg_i` and `reg_v` are zeroed when a looped program restarts from the beginning.
+    reset_on_loop: bool,
+    /// Called before executing each instruction.
+    trace_hook: Option<TraceHook<'a, T>>,
+    /// Reason for ending the most recent `run` (or `step`/`step_with` which ended the program).
+    last_end_reason: Option<EndReason>,
+    /// Seed of the random number generator (`state.rng`); restored by `reset`.
+    rng_seed: u64,
+    /// See `set_strict_math`.
+    strict_math: bool,
+    /// Outputs emitted since the last `take_outputs` (`None` if output buffering is disabled).
+    output_buffer: Option<Vec<(i32, T)>>,
+    /// Values returned by the handler for each input number since the last `reset` (`None` if input caching is disabled).
+    input_cache: Option<Vec<(i32, T)>>
 }
 
 impl<'a> VirtualMachine<'a> {
@@ -354,36 +1273,248 @@ impl<'a> VirtualMachine<'a> {
     ///
     pub fn new(
         program: &'a Program,
-        io_handler: Option<&'a mut InputOutputHandler>
+        io_handler: Option<&'a mut dyn InputOutputHandler>
+    ) -> VirtualMachine<'a> {
+        VirtualMachine::with_number_type(program, io_handler)
+    }
+
+    ///
+    /// Creates a virtual machine instance (see `new`) whose random number generator (used by `RandV`)
+    /// is seeded with `seed`.
+    ///
+    pub fn new_seeded(
+        program: &'a Program,
+        io_handler: Option<&'a mut dyn InputOutputHandler>,
+        seed: u64
     ) -> VirtualMachine<'a> {
+        let mut vm = VirtualMachine::new(program, io_handler);
+        vm.set_rng_seed(seed);
+        vm
+    }
+}
+
+impl<'a, T: VmNumber> VirtualMachine<'a, T> {
+    /// Creates a virtual machine instance using `T` as the computational data type (see `new`).
+    pub fn with_number_type(
+        program: &'a Program,
+        io_handler: Option<&'a mut dyn InputOutputHandler<T>>
+    ) -> VirtualMachine<'a, T> {
+        VirtualMachine::with_handler(program, io_handler)
+    }
+
+    ///
+    /// Creates a virtual machine instance (see `new`) starting from `state` instead of zeroed registers
+    /// and data, e.g. to warm-start from pre-populated data or to resume from a `snapshot`.
+    ///
+    /// `reset` still restores the zeroed state. Panics if the number of values in `state.data`
+    /// does not match the program's data slots (of all banks).
+    ///
+    pub fn with_state(
+        program: &'a Program,
+        io_handler: Option<&'a mut dyn InputOutputHandler<T>>,
+        state: VmState<T>
+    ) -> VirtualMachine<'a, T> {
+        assert!(state.data.len() == program.get_num_data_slots() * program.get_num_data_banks(),
+            "state has {} data values, program expects {}",
+            state.data.len(), program.get_num_data_slots() * program.get_num_data_banks());
+
+        let mut vm = VirtualMachine::with_number_type(program, io_handler);
+        vm.state = state;
+        vm
+    }
+}
+
+impl<'a, T: VmNumber, H: ?Sized + InputOutputHandler<T>> VirtualMachine<'a, T, H> {
+    ///
+    /// Creates a virtual machine instance using `H` as the input/output handler type.
+    ///
+    /// If `H` is a concrete type (use `NoHandler` if there is no handler), handler calls are statically dispatched.
+    ///
+    pub fn with_handler(
+        program: &'a Program,
+        io_handler: Option<&'a mut H>
+    ) -> VirtualMachine<'a, T, H> {
         VirtualMachine{
             program,
             io_handler,
-            state: VmState{ data: vec![0.0; program.get_num_data_slots()], reg_i: 0, reg_v: 0.0, iptr: 0 }
+            state: VmState::new(program),
+            num_steps: 0,
+            num_restarts: 0,
+            reset_on_loop: false,
+            trace_hook: None,
+            last_end_reason: None,
+            rng_seed: VirtualMachine::<T, H>::DEFAULT_RNG_SEED,
+            strict_math: false,
+            output_buffer: None,
+            input_cache: None
         }
     }
 
-    pub fn get_state(&self) -> &VmState {
+    /// Seed of the random number generator (used by `RandV`) if not specified otherwise.
+    pub const DEFAULT_RNG_SEED: u64 = 0;
+
+    /// Re-seeds the random number generator used by `RandV`; the seed is also used after `reset`.
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.state.rng = rand_xorshift::XorShiftRng::seed_from_u64(seed);
+        self.rng_seed = seed;
+    }
+
+    pub fn get_state(&self) -> &VmState<T> {
         &self.state
     }
 
+    ///
+    /// Returns a copy of the virtual machine's state (registers, data, instruction pointer
+    /// and the state of the random number generator used by `RandV`), which can be passed to `restore`.
+    ///
+    pub fn snapshot(&self) -> VmState<T> {
+        self.state.clone()
+    }
+
+    ///
+    /// Restores a state previously returned by `snapshot`, e.g. to roll back after trying out
+    /// some instructions.
+    ///
+    /// The executed program and the input/output handler are not changed (so e.g. outputs already
+    /// sent to the handler are not undone), and neither are the instruction count used for
+    /// `check_end_condition` and `last_end_reason`. Panics if the number of values in `state.data`
+    /// does not match the program's data slots (of all banks).
+    ///
+    pub fn restore(&mut self, state: VmState<T>) {
+        assert!(state.data.len() == self.state.data.len(),
+            "state has {} data values, program expects {}", state.data.len(), self.state.data.len());
+        self.state = state;
+    }
+
     pub fn set_reg_i(&mut self, reg_i: i32) {
         self.state.reg_i = reg_i;
     }
 
-    pub fn set_reg_v(&mut self, reg_v: RegValue) {
+    pub fn set_reg_v(&mut self, reg_v: T) {
         self.state.reg_v = reg_v;
     }
 
-    pub fn get_data_mut(&mut self) -> &mut [RegValue] {
+    /// Returns the data slots of all banks (see `VmState::data`); their number cannot be changed.
+    pub fn get_data_mut(&mut self) -> &mut [T] {
         &mut self.state.data
     }
 
     ///
-    /// Resets the virtual machine.
+    /// If `reset_on_loop` is true, `reg_i`, `reg_bank` and `reg_v` are zeroed whenever a looped program
+    /// restarts from the beginning, so that each cycle starts from fresh registers. Data slots
+    /// are preserved. Disabled by default.
+    ///
+    pub fn set_reset_on_loop(&mut self, reset_on_loop: bool) {
+        self.reset_on_loop = reset_on_loop;
+    }
+
+    ///
+    /// If `strict_math` is true, `Add`, `Sub`, `Mul`, `Div` and `Sqrt` are computed in `f64` and rounded once
+    /// to the computational type `T`, i.e. each result is the exact result rounded to the nearest `T` value
+    /// (as JavaScript's `Math.fround(a + b)` for `f32`). `Pow`, `Log`, `Sin` and `Cos` are computed in `f64`
+    /// by the `libm` crate (a port of musl's math library) instead of the platform's math library,
+    /// and rounded to `T` likewise. Disabled by default.
+    ///
+    /// On platforms with IEEE 754 `f32` arithmetic (e.g. x86-64 with SSE, ARM) the results of the basic
+    /// operations are the same as without strict math; it guards against targets which keep intermediate results
+    /// with excess precision (e.g. x87) and documents the rounding expected by transpiled programs. The results
+    /// of the transcendental functions are the same on all platforms. The JavaScript VM (see `transpile::javascript_vm`)
+    /// matches a `VirtualMachine::<f64>`, or, if transpiled with `JsTranspileOptions::f32_arithmetic`,
+    /// the results of the basic operations of a `VirtualMachine::<f32>` (its `Math` functions are engine-dependent).
+    ///
+    pub fn set_strict_math(&mut self, strict_math: bool) {
+        self.strict_math = strict_math;
+    }
+
+    ///
+    /// Sets a hook called before executing each instruction (by `run`, `step` and `step_with`).
+    ///
+    /// The hook receives the instruction pointer, the instruction's opcode
+    /// and the virtual machine's state. Can be used e.g. for execution logs or coverage maps.
+    ///
+    pub fn set_trace_hook(&mut self, hook: TraceHook<'a, T>) {
+        self.trace_hook = Some(hook);
+    }
+
+    ///
+    /// If `output_buffering` is true, the operand and value of each executed `Output` instruction are recorded
+    /// (regardless of the input/output handler, which is still called) and can be retrieved with `take_outputs`.
+    /// Disabled by default; disabling discards the recorded outputs.
+    ///
+    pub fn set_output_buffering(&mut self, output_buffering: bool) {
+        if !output_buffering {
+            self.output_buffer = None;
+        } else if self.output_buffer.is_none() {
+            self.output_buffer = Some(vec![]);
+        }
+    }
+
+    /// Returns (and removes) the outputs recorded since the last call, `reset`, or enabling output buffering.
+    pub fn take_outputs(&mut self) -> Vec<(i32, T)> {
+        match self.output_buffer {
+            Some(ref mut outputs) => std::mem::take(outputs),
+            None => vec![]
+        }
+    }
+
+    ///
+    /// If `input_caching` is true, only the first `Input(n)` for each `n` calls the input/output handler; subsequent
+    /// `Input(n)` return the cached value until `reset`, so the program operates on a snapshot of its inputs.
+    /// Disabled by default; disabling discards the cached values.
+    ///
+    /// The cache is not a part of `VmState` (i.e. it is not affected by `snapshot` and `restore`).
+    ///
+    pub fn set_input_caching(&mut self, input_caching: bool) {
+        if !input_caching {
+            self.input_cache = None;
+        } else if self.input_cache.is_none() {
+            self.input_cache = Some(vec![]);
+        }
+    }
+
+    /// Removes the hook set with `set_trace_hook`.
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    ///
+    /// Resets the virtual machine (including its random number generator).
     ///
     pub fn reset(&mut self) {
         self.state.reset();
+        self.num_steps = 0;
+        self.num_restarts = 0;
+        self.last_end_reason = None;
+        if let Some(ref mut outputs) = self.output_buffer {
+            outputs.clear();
+        }
+        if let Some(ref mut inputs) = self.input_cache {
+            inputs.clear();
+        }
+        self.state.rng = rand_xorshift::XorShiftRng::seed_from_u64(self.rng_seed);
+    }
+
+    ///
+    /// Prepares the virtual machine for another execution of the program (e.g. for the next test case)
+    /// while preserving `data`, so that a program can carry its memory over a sequence of runs.
+    ///
+    /// Sets `iptr` to 0 and, if `reset_registers` is true, zeroes `reg_i`, `reg_bank` and `reg_v`. Like `reset`,
+    /// clears the instruction counters, `last_end_reason` and cached inputs (see `set_input_caching`); recorded
+    /// outputs and the state of the random number generator are preserved.
+    ///
+    pub fn reset_execution(&mut self, reset_registers: bool) {
+        self.state.iptr = 0;
+        if reset_registers {
+            self.state.reg_i = 0;
+            self.state.reg_bank = 0;
+            self.state.reg_v = T::ZERO;
+        }
+        self.num_steps = 0;
+        self.num_restarts = 0;
+        self.last_end_reason = None;
+        if let Some(ref mut inputs) = self.input_cache {
+            inputs.clear();
+        }
     }
 
     ///
@@ -392,9 +1523,10 @@ impl<'a> VirtualMachine<'a> {
     /// # Parameters
     ///
     /// * `num_exec_instructions` - Max. number of instructions to execute.
-    /// * `looped` - If true, program restarts from the beginning after reaching the last instruction.
+    /// * `looped` - If true, program restarts from the beginning after reaching the last instruction
+    ///   (`RunMode::LoopForever`, otherwise `RunMode::Once`; see `run_with_options` for other modes).
     /// * `check_end_condition` - If true, `io_handler.check_end_condition()` is called
-    /// after every `Output` instruction; if returns true, program execution ends.
+    ///   after every `Output` instruction; if returns true, program execution ends.
     ///
     pub fn run(
         &mut self,
@@ -402,44 +1534,264 @@ impl<'a> VirtualMachine<'a> {
         looped: bool,
         check_end_condition: bool
     ) -> EndReason {
+        self.run_with_options(&RunOptions{
+            num_exec_instructions,
+            instruction_cost: None,
+            mode: RunMode::from_looped(looped),
+            check_end_condition,
+            termination: TerminationPolicy::default(),
+            detect_loops: false,
+            max_duration: None
+        })
+    }
+
+    ///
+    /// Runs the program until it ends or `max_duration` of wall-clock time elapses (`EndReason::TimedOut`).
+    ///
+    /// Elapsed time is checked every `TIME_CHECK_INTERVAL` instructions (see `RunOptions::max_duration`).
+    /// Parameters `looped` and `check_end_condition` are as in `run`.
+    ///
+    pub fn run_timed(
+        &mut self,
+        max_duration: std::time::Duration,
+        looped: bool,
+        check_end_condition: bool
+    ) -> EndReason {
+        self.run_with_options(&RunOptions{
+            mode: RunMode::from_looped(looped),
+            check_end_condition,
+            max_duration: Some(max_duration),
+            ..Default::default()
+        })
+    }
+
+    /// Runs the program; like `run`, but with optional loop detection (see `RunOptions`).
+    pub fn run_with_options(&mut self, options: &RunOptions) -> EndReason {
+        self.run_reported(options).end_reason
+    }
+
+    /// Runs the program like `run_with_options`; returns the end reason and execution counters.
+    pub fn run_reported(&mut self, options: &RunOptions) -> RunReport {
+        let mut report = RunReport{
+            end_reason: EndReason::NumExecInstructions,
+            instructions_executed: 0,
+            outputs_emitted: 0,
+            jumps_taken: 0,
+            final_iptr: self.state.iptr
+        };
+
+        // state saved for comparison (Brent's algorithm); `None` after output or data change
+        let mut saved_state: Option<(usize, i32, i32, T)> = None;
+        let mut power = 1;
+        let mut num_since_saved = 0;
+
+        if options.mode == RunMode::LoopTimes(0) {
+            report.end_reason = EndReason::LastInstructionReached;
+            self.last_end_reason = Some(EndReason::LastInstructionReached);
+            return report;
+        }
+        let initial_num_restarts = self.num_restarts;
+
+        let start_time = options.max_duration.map(|_| std::time::Instant::now());
+
         let mut icounter = 0;
-        let instr = self.program.get_instr();
-        while num_exec_instructions.is_none() || icounter < num_exec_instructions.unwrap() {
-            let opcode = instr[self.state.iptr];
-            if self.handle_instruction(opcode) {
-                self.state.iptr += 1;
+        let mut total_cost = 0;
+        loop {
+            let opcode = self.program.get_instr().get(self.state.iptr).cloned();
+
+            let cost = match (options.instruction_cost, opcode) {
+                (Some(instruction_cost), Some(opcode)) => instruction_cost(opcode) as usize,
+                _ => 1
+            };
+            if options.num_exec_instructions.is_some_and(|limit| total_cost + cost > limit) {
+                break;
             }
+            total_cost += cost;
             icounter += 1;
-            if self.state.iptr >= instr.len() {
-                if looped {
-                    self.state.iptr = 0;
-                } else {
-                    return EndReason::LastInstructionReached;
-                }
-            }
-            if check_end_condition {
+
+            let looped = match options.mode {
+                RunMode::Once => false,
+                RunMode::LoopForever => true,
+                RunMode::LoopTimes(n) => self.num_restarts - initial_num_restarts + 1 < n
+            };
+
+            let num_restarts_before = self.num_restarts;
+
+            let modified_slot = match opcode {
+                Some(OpCode::Store) | Some(OpCode::Swap) if options.detect_loops =>
+                    self.data_index().map(|index| (index, self.state.data[index])),
+                _ => None
+            };
+
+            if let Some(opcode) = opcode {
+                report.instructions_executed += 1;
+                let has_target = || self.program.get_jump_table()[self.state.iptr].is_some();
                 match opcode {
-                    OpCode::Output(_) => if self.io_handler.iter().next().unwrap().check_end_condition(icounter) { return EndReason::EndConditionMet; },
+                    OpCode::Output(_) => report.outputs_emitted += 1,
+                    OpCode::GoToIfP if self.state.reg_v >= T::ZERO && has_target() => report.jumps_taken += 1,
+                    OpCode::JumpIfN if self.state.reg_v < T::ZERO && has_target() => report.jumps_taken += 1,
                     _ => ()
                 }
             }
+
+            if let Some(mut reason) = self.execute_instruction(looped, options.check_end_condition, icounter) {
+                if reason == EndReason::EndConditionMet && options.termination.max_instructions.is_some_and(|max| icounter > max) {
+                    reason = EndReason::EndConditionTimedOut;
+                }
+                self.last_end_reason = Some(reason);
+                report.end_reason = reason;
+                report.final_iptr = self.state.iptr;
+                return report;
+            }
+
+            if icounter % TIME_CHECK_INTERVAL == 0 {
+                if let (Some(max_duration), Some(start_time)) = (options.max_duration, start_time) {
+                    if start_time.elapsed() >= max_duration {
+                        self.last_end_reason = Some(EndReason::TimedOut);
+                        report.end_reason = EndReason::TimedOut;
+                        report.final_iptr = self.state.iptr;
+                        return report;
+                    }
+                }
+            }
+
+            if options.detect_loops {
+                let made_progress = match opcode {
+                    // `RandV` makes the subsequent states unpredictable
+                    Some(OpCode::Output(_)) | Some(OpCode::RandV) => true,
+                    // with a limited number of runs, each restart brings the end closer
+                    _ if matches!(options.mode, RunMode::LoopTimes(_)) && self.num_restarts != num_restarts_before => true,
+                    // note that a NaN is always considered a change
+                    _ => modified_slot.is_some_and(|(index, old_value)| self.state.data[index] != old_value)
+                };
+                let state = (self.state.iptr, self.state.reg_i, self.state.reg_bank, self.state.reg_v);
+
+                if made_progress {
+                    saved_state = None;
+                } else if saved_state == Some(state) {
+                    self.last_end_reason = Some(EndReason::LoopDetected);
+                    report.end_reason = EndReason::LoopDetected;
+                    report.final_iptr = self.state.iptr;
+                    return report;
+                }
+
+                num_since_saved += 1;
+                if saved_state.is_none() || num_since_saved == power {
+                    if saved_state.is_none() { power = 1; } else { power *= 2; }
+                    saved_state = Some(state);
+                    num_since_saved = 0;
+                }
+            }
+        }
+
+        self.last_end_reason = Some(EndReason::NumExecInstructions);
+        report.final_iptr = self.state.iptr;
+        report
+    }
+
+    ///
+    /// Returns the reason for ending the most recent `run` (or `step`/`step_with` which ended the program).
+    ///
+    /// Returns `None` if the program has not ended since creation or the last `reset`.
+    ///
+    pub fn last_end_reason(&self) -> Option<EndReason> {
+        self.last_end_reason
+    }
+
+    ///
+    /// Executes a single instruction of a non-looped program without checking the end condition.
+    ///
+    /// Returns `Some` if the program ended. See `step_with` for details.
+    ///
+    pub fn step(&mut self) -> Option<EndReason> {
+        self.step_with(false, false)
+    }
+
+    ///
+    /// Executes a single instruction.
+    ///
+    /// Subsequent calls resume execution where the previous one stopped; `get_state()` can be used
+    /// to inspect the virtual machine between steps.
+    ///
+    /// # Parameters
+    ///
+    /// * `looped` - If true, program restarts from the beginning after reaching the last instruction.
+    /// * `check_end_condition` - If true and the executed instruction is `Output`,
+    ///   `io_handler.check_end_condition()` is called with the number of instructions executed
+    ///   by `step`/`step_with` since creation or the last `reset`.
+    ///
+    /// Returns `Some` with the reason if the program ended (like `run`), `None` otherwise.
+    ///
+    pub fn step_with(&mut self, looped: bool, check_end_condition: bool) -> Option<EndReason> {
+        self.num_steps += 1;
+        let num_steps = self.num_steps;
+        let end_reason = self.execute_instruction(looped, check_end_condition, num_steps);
+        if end_reason.is_some() {
+            self.last_end_reason = end_reason;
         }
 
-        EndReason::NumExecInstructions
+        end_reason
     }
 
     ///
-    /// Checks if `reg_i` is a valid index into `data`.
+    /// Executes the instruction at `iptr` and advances `iptr`.
+    ///
+    /// Returns `Some` if the program ended. `num_execd_instructions` (including the current one)
+    /// is passed to `io_handler.check_end_condition()`.
     ///
-    fn is_data_index(&self) -> bool {
-        self.state.reg_i >= 0 && (self.state.reg_i as usize) < self.state.data.len()
+    fn execute_instruction(
+        &mut self,
+        looped: bool,
+        check_end_condition: bool,
+        num_execd_instructions: usize
+    ) -> Option<EndReason> {
+        let instr = self.program.get_instr();
+        if instr.is_empty() {
+            return Some(EndReason::LastInstructionReached);
+        }
+        let opcode = instr[self.state.iptr];
+        if let Some(ref mut hook) = self.trace_hook {
+            hook(self.state.iptr, opcode, &self.state);
+        }
+        if opcode == OpCode::Halt {
+            return Some(EndReason::Halted);
+        }
+        if self.handle_instruction(opcode) {
+            self.state.iptr += 1;
+        }
+        if self.state.iptr >= instr.len() {
+            if looped {
+                self.state.iptr = 0;
+                self.num_restarts += 1;
+                if self.reset_on_loop {
+                    self.state.reg_i = 0;
+                    self.state.reg_bank = 0;
+                    self.state.reg_v = T::ZERO;
+                }
+            } else {
+                return Some(EndReason::LastInstructionReached);
+            }
+        }
+        if check_end_condition {
+            if let OpCode::Output(_) = opcode {
+                if self.io_handler.iter().next().unwrap().check_end_condition(num_execd_instructions) {
+                    return Some(EndReason::EndConditionMet);
+                }
+            }
+        }
+
+        None
     }
 
     ///
-    /// Returns the value of data slot pointed to by `reg_i`.
+    /// Returns the index (in `state.data`) of the data slot pointed to by `reg_bank` and `reg_i` (see `IndexMode`).
     ///
-    fn data_val(&self) -> RegValue {
-        self.state.data[self.state.reg_i as usize]
+    fn data_index(&self) -> Option<usize> {
+        let num_data_slots = self.program.get_num_data_slots();
+        let index_mode = self.program.get_index_mode();
+        let bank = index_mode.data_index(self.state.reg_bank, self.program.get_num_data_banks())?;
+        let slot = index_mode.data_index(self.state.reg_i, num_data_slots)?;
+        Some(bank * num_data_slots + slot)
     }
 
     ///
@@ -450,89 +1802,172 @@ impl<'a> VirtualMachine<'a> {
         match opcode {
             OpCode::SetI(i) => self.state.reg_i = i,
 
+            OpCode::SetBank(b) => self.state.reg_bank = b,
+
             OpCode::Input(i) => if self.io_handler.is_some() {
-                    self.state.reg_v = self.io_handler.iter_mut().next().unwrap().input(i);
+                    let cached = self.input_cache.as_ref().and_then(|inputs| inputs.iter().find(|(n, _)| *n == i));
+                    self.state.reg_v = match cached {
+                        Some((_, value)) => *value,
+                        None => {
+                            let value = self.io_handler.iter_mut().next().unwrap().input(i);
+                            if let Some(ref mut inputs) = self.input_cache {
+                                inputs.push((i, value));
+                            }
+                            value
+                        }
+                    };
                 },
 
-            OpCode::Output(i) => if self.io_handler.is_some() {
+            OpCode::Output(i) => {
+                if let Some(ref mut outputs) = self.output_buffer {
+                    outputs.push((i, self.state.reg_v));
+                }
+                if self.io_handler.is_some() {
                     self.io_handler.iter_mut().next().unwrap().output(i, self.state.reg_v);
-                },
+                }
+            },
 
-            OpCode::ItoV => self.state.reg_v = self.state.reg_i as RegValue,
+            OpCode::ItoV => self.state.reg_v = T::from_i32(self.state.reg_i),
 
-            OpCode::VtoI => self.state.reg_i = self.state.reg_v as i32,
+            OpCode::VtoI => self.state.reg_i = self.state.reg_v.to_i32(),
 
-            OpCode::IncV => self.state.reg_v += 1.0,
+            OpCode::IncV => self.state.reg_v += T::ONE,
 
-            OpCode::DecV => self.state.reg_v -= 1.0,
+            OpCode::DecV => self.state.reg_v -= T::ONE,
 
             OpCode::IncI => self.state.reg_i = self.state.reg_i.wrapping_add(1),
 
             OpCode::DecI => self.state.reg_i = self.state.reg_i.wrapping_sub(1),
 
             OpCode::Load =>
-                if self.is_data_index() {
-                    self.state.reg_v = self.state.data[self.state.reg_i as usize];
+                if let Some(index) = self.data_index() {
+                    self.state.reg_v = self.state.data[index];
                 },
 
             OpCode::Store =>
-                if self.is_data_index() {
-                    self.state.data[self.state.reg_i as usize] = self.state.reg_v;
+                if let Some(index) = self.data_index() {
+                    self.state.data[index] = self.state.reg_v;
                 },
 
             OpCode::Swap =>
-                if self.is_data_index() {
-                    std::mem::swap(&mut self.state.data[self.state.reg_i as usize], &mut self.state.reg_v);
+                if let Some(index) = self.data_index() {
+                    std::mem::swap(&mut self.state.data[index], &mut self.state.reg_v);
                 },
 
             OpCode::EndGoTo => (),
 
             OpCode::GoToIfP =>
-                if self.state.reg_v >= 0.0 && jump_table[self.state.iptr].is_some() {
+                if self.state.reg_v >= T::ZERO && jump_table[self.state.iptr].is_some() {
                     self.state.iptr = jump_table[self.state.iptr].unwrap();
                     return false;
                 },
 
             OpCode::JumpIfN =>
-                if self.state.reg_v < 0.0 && jump_table[self.state.iptr].is_some() {
+                if self.state.reg_v < T::ZERO && jump_table[self.state.iptr].is_some() {
                     self.state.iptr = jump_table[self.state.iptr].unwrap();
                     return false;
                 },
 
             OpCode::EndJump => (),
 
-            OpCode::IfP => if self.state.reg_v < 0.0 { self.state.iptr += 1; },
+            OpCode::IfP => if self.state.reg_v < T::ZERO { self.state.iptr += 1; },
 
-            OpCode::IfN => if self.state.reg_v >= 0.0 { self.state.iptr += 1; },
+            OpCode::IfN => if self.state.reg_v >= T::ZERO { self.state.iptr += 1; },
 
-            OpCode::Cmp => if self.is_data_index() {
-                let dval = self.data_val();
-                if self.state.reg_v < dval { self.state.reg_v = -1.0; }
-                else if self.state.reg_v ==  dval { self.state.reg_v = 0.0; }
-                else if self.state.reg_v > dval { self.state.reg_v = 1.0; }
+            OpCode::Cmp => if let Some(index) = self.data_index() {
+                let dval = self.state.data[index];
+                let difference = (self.state.reg_v.to_f64() - dval.to_f64()).abs();
+                if self.state.reg_v == dval || difference <= self.program.get_cmp_epsilon() { self.state.reg_v = T::ZERO; }
+                else if self.state.reg_v < dval { self.state.reg_v = -T::ONE; }
+                else if self.state.reg_v > dval { self.state.reg_v = T::ONE; }
             },
 
-            OpCode::Add => if self.is_data_index() { self.state.reg_v += self.data_val(); },
+            OpCode::Add => if let Some(index) = self.data_index() {
+                let dval = self.state.data[index];
+                if self.strict_math { self.state.reg_v = strict_op(self.state.reg_v, dval, |a, b| a + b); }
+                else { self.state.reg_v += dval; }
+            },
 
-            OpCode::Sub => if self.is_data_index() { self.state.reg_v -= self.data_val(); },
+            OpCode::Sub => if let Some(index) = self.data_index() {
+                let dval = self.state.data[index];
+                if self.strict_math { self.state.reg_v = strict_op(self.state.reg_v, dval, |a, b| a - b); }
+                else { self.state.reg_v -= dval; }
+            },
 
-            OpCode::Mul => if self.is_data_index() { self.state.reg_v *= self.data_val(); },
+            OpCode::Mul => if let Some(index) = self.data_index() {
+                let dval = self.state.data[index];
+                if self.strict_math { self.state.reg_v = strict_op(self.state.reg_v, dval, |a, b| a * b); }
+                else { self.state.reg_v *= dval; }
+            },
 
-            OpCode::Div => if self.is_data_index() && self.data_val() != 0.0 { self.state.reg_v /= self.data_val(); },
+            OpCode::Div => if let Some(index) = self.data_index() {
+                let dval = self.state.data[index];
+                if dval != T::ZERO {
+                    if self.strict_math { self.state.reg_v = strict_op(self.state.reg_v, dval, |a, b| a / b); }
+                    else { self.state.reg_v /= dval; }
+                }
+            },
 
             OpCode::Abs => self.state.reg_v = self.state.reg_v.abs(),
 
             OpCode::Neg => self.state.reg_v = -self.state.reg_v,
 
-            OpCode::Sqrt => self.state.reg_v = if self.state.reg_v >= 0.0 { self.state.reg_v.sqrt() } else { 0.0 },
+            OpCode::Sqrt => self.state.reg_v = if self.state.reg_v >= T::ZERO {
+                if self.strict_math { strict_unary_op(self.state.reg_v, f64::sqrt) } else { self.state.reg_v.sqrt() }
+            } else {
+                T::ZERO
+            },
+
+            OpCode::Pow => if let Some(index) = self.data_index() {
+                let (base, exponent) = (self.state.reg_v, self.state.data[index]);
+                let result = if self.strict_math { strict_op(base, exponent, libm::pow) } else { base.powf(exponent) };
+                let is_nan = |x: T| x.partial_cmp(&x).is_none();
+                self.state.reg_v = if is_nan(base) || is_nan(exponent) || is_nan(result) { T::ZERO } else { result };
+            },
+
+            OpCode::Log => self.state.reg_v = if self.state.reg_v > T::ZERO {
+                if self.strict_math { strict_unary_op(self.state.reg_v, libm::log) } else { self.state.reg_v.ln() }
+            } else {
+                T::ZERO
+            },
+
+            OpCode::Sin => self.state.reg_v =
+                if self.strict_math { strict_unary_op(self.state.reg_v, libm::sin) } else { self.state.reg_v.sin() },
+
+            OpCode::Cos => self.state.reg_v =
+                if self.strict_math { strict_unary_op(self.state.reg_v, libm::cos) } else { self.state.reg_v.cos() },
+
+            OpCode::RandV => self.state.reg_v = T::random(&mut self.state.rng),
+
+            // handled by `execute_instruction`
+            OpCode::Halt => return false,
 
             OpCode::Nop => ()
         }
 
+        let arithmetic_mode = self.program.get_arithmetic_mode();
+        if arithmetic_mode != ArithmeticMode::Ieee && matches!(opcode,
+            OpCode::IncV | OpCode::DecV | OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div |
+            OpCode::Abs | OpCode::Neg | OpCode::Sqrt | OpCode::Pow | OpCode::Log | OpCode::Sin | OpCode::Cos
+        ) {
+            self.state.reg_v = arithmetic_mode.apply(self.state.reg_v);
+        }
+
         true
     }
 }
 
+/// Returns `op(a, b)` computed in `f64` and rounded to `T` (see `VirtualMachine::set_strict_math`).
+fn strict_op<T: VmNumber>(a: T, b: T, op: fn(f64, f64) -> f64) -> T {
+    T::from_f64(op(a.to_f64(), b.to_f64()))
+}
+
+/// Returns `op(a)` computed in `f64` and rounded to `T` (see `VirtualMachine::set_strict_math`).
+fn strict_unary_op<T: VmNumber>(a: T, op: fn(f64) -> f64) -> T {
+    T::from_f64(op(a.to_f64()))
+}
+
+#[cfg(test)]
 macro_rules! t_assert_eq {
     ($expected:expr, $actual:expr) => {
         if $expected != $actual {
@@ -541,6 +1976,49 @@ macro_rules! t_assert_eq {
     };
 }
 
+#[cfg(test)]
+mod opcode_tests {
+    use super::OpCode;
+
+    /// Position of `opcode` in declaration order; fails to compile if an opcode is missing.
+    fn declaration_index(opcode: OpCode) -> usize {
+        match opcode {
+            OpCode::SetI(_) => 0, OpCode::Input(_) => 1, OpCode::Output(_) => 2, OpCode::ItoV => 3, OpCode::VtoI => 4,
+            OpCode::IncV => 5, OpCode::DecV => 6, OpCode::IncI => 7, OpCode::DecI => 8, OpCode::Load => 9,
+            OpCode::Store => 10, OpCode::Swap => 11, OpCode::EndGoTo => 12, OpCode::GoToIfP => 13, OpCode::JumpIfN => 14,
+            OpCode::EndJump => 15, OpCode::IfP => 16, OpCode::IfN => 17, OpCode::Cmp => 18, OpCode::Add => 19,
+            OpCode::Sub => 20, OpCode::Mul => 21, OpCode::Div => 22, OpCode::Abs => 23, OpCode::Neg => 24,
+            OpCode::Sqrt => 25, OpCode::Pow => 26, OpCode::Log => 27, OpCode::Sin => 28, OpCode::Cos => 29,
+            OpCode::RandV => 30, OpCode::SetBank(_) => 31, OpCode::Halt => 32, OpCode::Nop => 33
+        }
+    }
+
+    #[test]
+    fn all_opcodes_listed() {
+        for (i, opcode) in OpCode::ALL.iter().enumerate() {
+            t_assert_eq!(i, declaration_index(*opcode));
+        }
+    }
+
+    #[test]
+    fn mnemonics() {
+        for (i, opcode) in OpCode::ALL.iter().enumerate() {
+            assert!(!opcode.mnemonic().is_empty());
+            assert!(OpCode::ALL[..i].iter().all(|other| other.mnemonic() != opcode.mnemonic()));
+        }
+    }
+
+    #[test]
+    fn operands() {
+        for opcode in OpCode::ALL.iter() {
+            match opcode.operand() {
+                Some(_) => assert!(opcode.with_operand(-5).operand() == Some(-5)),
+                None => assert!(opcode.with_operand(-5) == *opcode)
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod jump_table_tests {
     use super::{OpCode, Program};
@@ -852,16 +2330,187 @@ mod jump_table_tests {
                 None
             ] == program.get_jump_table());
     }
-}
-
-#[cfg(test)]
-mod instruction_tests {
-    use super::{InputOutputHandler, OpCode, Program, RegValue, VirtualMachine};
 
     #[test]
-    fn set_i() {
-        const INT_VAL: i32 = 55;
-        let program = Program::new(&[OpCode::SetI(INT_VAL)], 1, false);
+    fn control_flow_report() {
+        let program = Program::new(&[
+            OpCode::EndGoTo, // 0: destination of 3
+            OpCode::IfP,
+            OpCode::JumpIfN, // 2: unmatched
+            OpCode::GoToIfP, // 3: jumps to 0
+            OpCode::JumpIfN, // 4: jumps to 6
+            OpCode::Nop,
+            OpCode::EndJump, // 6: destination of 4
+            OpCode::GoToIfP, // 7: unmatched
+        ], 0, false);
+
+        assert!(vec![
+            (0, OpCode::EndGoTo, true),
+            (2, OpCode::JumpIfN, false),
+            (3, OpCode::GoToIfP, true),
+            (4, OpCode::JumpIfN, true),
+            (6, OpCode::EndJump, true),
+            (7, OpCode::GoToIfP, false)
+        ] == program.control_flow_report());
+
+        assert!(Program::new(&[OpCode::IfP, OpCode::Nop], 0, false).control_flow_report().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use super::{IndexMode, OpCode, Program, ValidationError};
+
+    #[test]
+    fn out_of_range_operands() {
+        let mut program = Program::new(&[
+            OpCode::Input(1),
+            OpCode::Input(99),
+            OpCode::SetI(3),
+            OpCode::Output(0),
+            OpCode::Input(-1),
+            OpCode::SetI(4),
+            OpCode::Output(2)
+        ], 4, false);
+
+        assert!(program.validate(2, 3) == Err(vec![
+            ValidationError::InputOutOfRange{ index: 1, operand: 99 },
+            ValidationError::InputOutOfRange{ index: 4, operand: -1 },
+            ValidationError::InvalidDataIndex{ index: 5, operand: 4 }
+        ]));
+
+        program.set_index_mode(IndexMode::Wrap);
+        assert!(program.validate(100, 2) == Err(vec![
+            ValidationError::InputOutOfRange{ index: 4, operand: -1 },
+            ValidationError::OutputOutOfRange{ index: 6, operand: 2 }
+        ]));
+    }
+
+    #[test]
+    fn valid_program() {
+        let program = Program::new(&[OpCode::SetI(9), OpCode::ItoV, OpCode::Output(1)], 0, false);
+        assert!(program.validate(0, 2).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod stable_hash_tests {
+    use super::{OpCode, Program};
+
+    #[test]
+    fn stable_hash() {
+        let instr = [OpCode::SetI(1), OpCode::Load, OpCode::Output(0)];
+        let program = Program::new(&instr, 2, false);
+
+        t_assert_eq!(program.stable_hash(), Program::new(&instr, 2, false).stable_hash());
+        t_assert_eq!(program.stable_hash(), program.clone().stable_hash());
+        assert!(program.stable_hash() != Program::new(&[OpCode::SetI(1), OpCode::Store, OpCode::Output(0)], 2, false).stable_hash());
+        assert!(program.stable_hash() != Program::new(&[OpCode::SetI(2), OpCode::Load, OpCode::Output(0)], 2, false).stable_hash());
+        assert!(program.stable_hash() != Program::new(&instr, 3, false).stable_hash());
+        let mut with_epsilon = program.clone();
+        with_epsilon.set_cmp_epsilon(0.5);
+        assert!(program.stable_hash() != with_epsilon.stable_hash());
+        // fixed value, the same in every run
+        t_assert_eq!(0x3c90_35da_dfd3_603e_u64, program.stable_hash());
+    }
+}
+
+#[cfg(test)]
+mod reachability_tests {
+    use super::{OpCode, Program};
+
+    #[test]
+    fn unreachable_tail() {
+        let program = Program::new(&[
+            OpCode::Input(0),
+            OpCode::Output(0),
+            OpCode::Halt,
+            OpCode::IncV,
+            OpCode::Output(0)
+        ], 0, false);
+
+        assert!(program.reachable_instructions() == [true, true, true, false, false]);
+    }
+
+    #[test]
+    fn conditional_halt() {
+        let program = Program::new(&[OpCode::IfP, OpCode::Halt, OpCode::Output(0), OpCode::IfN, OpCode::Halt], 0, false);
+        assert!(program.reachable_instructions() == [true; 5]);
+    }
+
+    #[test]
+    fn jumps() {
+        let program = Program::new(&[
+            OpCode::JumpIfN,       // 0: jumps to 3
+                OpCode::Halt,
+                OpCode::IncV,      // 2: unreachable
+            OpCode::EndJump,       // 3
+            OpCode::EndGoTo,       // 4: destination of 7
+                OpCode::Halt,
+                OpCode::Output(0), // 6: unreachable
+            OpCode::GoToIfP,       // 7: unreachable
+            OpCode::Nop            // 8: unreachable
+        ], 0, false);
+
+        assert!(program.reachable_instructions() == [true, true, false, true, true, true, false, false, false]);
+    }
+
+    #[test]
+    fn no_halt() {
+        let program = Program::new(&[OpCode::EndGoTo, OpCode::DecV, OpCode::GoToIfP, OpCode::Nop], 0, false);
+        assert!(program.reachable_instructions() == [true; 4]);
+        assert!(Program::new(&[], 0, false).reachable_instructions().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod histogram_tests {
+    use super::{OpCode, Program};
+
+    #[test]
+    fn opcode_histogram() {
+        let program = Program::new(&[
+            OpCode::SetI(0),
+            OpCode::Load,
+            OpCode::SetI(3),
+            OpCode::Cmp,
+            OpCode::IfP,
+                OpCode::Output(1),
+            OpCode::Output(2),
+            OpCode::SetI(0)
+        ], 4, false);
+
+        let histogram = program.opcode_histogram();
+        t_assert_eq!(5, histogram.len());
+        t_assert_eq!(3, histogram["seti"]);
+        t_assert_eq!(2, histogram["output"]);
+        t_assert_eq!(1, histogram["load"]);
+        t_assert_eq!(1, histogram["cmp"]);
+        t_assert_eq!(1, histogram["ifp"]);
+        assert!(!histogram.contains_key("nop"));
+
+        t_assert_eq!(3, program.count_of(OpCode::SetI(7)));
+        t_assert_eq!(2, program.count_of(OpCode::Output(0)));
+        t_assert_eq!(1, program.count_of(OpCode::Cmp));
+        t_assert_eq!(0, program.count_of(OpCode::Nop));
+    }
+
+    #[test]
+    fn empty_program() {
+        let program = Program::new(&[], 0, false);
+        assert!(program.opcode_histogram().is_empty());
+        t_assert_eq!(0, program.count_of(OpCode::Nop));
+    }
+}
+
+#[cfg(test)]
+mod instruction_tests {
+    use super::{ArithmeticMode, IndexMode, InputOutputHandler, OpCode, Program, RegValue, VirtualMachine};
+
+    #[test]
+    fn set_i() {
+        const INT_VAL: i32 = 55;
+        let program = Program::new(&[OpCode::SetI(INT_VAL)], 1, false);
         let mut vm = VirtualMachine::new(&program, None);
 
         t_assert_eq!(0, vm.get_state().reg_i);
@@ -1000,6 +2649,36 @@ mod instruction_tests {
         t_assert_eq!(INITIAL_VAL, vm.get_state().reg_v);
     }
 
+    #[test]
+    fn load_wrapped_negative_index() {
+        let instructions = [OpCode::SetI(-3), OpCode::Load];
+
+        let program = Program::new(&instructions, 4, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.get_data_mut()[3] = 5.0;
+        vm.run(None, false, false);
+        t_assert_eq!(0.0, vm.get_state().reg_v);
+
+        let program = Program::new_with_wrapped_indices(&instructions, 4, false);
+        assert!(program.get_instr()[0] == OpCode::SetI(3));
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.get_data_mut()[3] = 5.0;
+        vm.run(None, false, false);
+        t_assert_eq!(3, vm.get_state().reg_i);
+        t_assert_eq!(5.0, vm.get_state().reg_v);
+
+        let program = Program::new_with_wrapped_indices(&[OpCode::SetI(-9), OpCode::SetI(i32::MIN), OpCode::SetI(6)], 4, false);
+        assert!(program.get_instr() == [OpCode::SetI(1), OpCode::SetI(0), OpCode::SetI(6)]);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn wrapped_index_with_many_data_slots() {
+        // the number of data slots is not truncated to 32 bits (which would give 3)
+        let program = Program::new_with_wrapped_indices(&[OpCode::SetI(-5)], u32::MAX as usize + 4, false);
+        assert!(program.get_instr() == [OpCode::SetI(5)]);
+    }
+
     #[test]
     fn store() {
         const STORE_VAL: RegValue = 5.0;
@@ -1176,6 +2855,31 @@ mod instruction_tests {
         t_assert_eq!(VirtualMachine::CMP_GREATER, vm.get_state().reg_v);
     }
 
+    /// Returns `reg_v` after `Cmp` of `reg_v` and `dval` with tolerance `cmp_epsilon`.
+    fn cmp_with_epsilon(reg_v: RegValue, dval: RegValue, cmp_epsilon: f64) -> RegValue {
+        let mut program = Program::new(&[OpCode::Cmp], 1, false);
+        program.set_cmp_epsilon(cmp_epsilon);
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.get_data_mut()[0] = dval;
+        vm.set_reg_v(reg_v);
+        vm.run(None, false, false);
+        vm.get_state().reg_v
+    }
+
+    #[test]
+    fn cmp_epsilon() {
+        t_assert_eq!(VirtualMachine::CMP_LESS, cmp_with_epsilon(1.0, 1.001, 0.0));
+        t_assert_eq!(VirtualMachine::CMP_EQUAL, cmp_with_epsilon(1.0, 1.0, 0.0));
+        t_assert_eq!(VirtualMachine::CMP_EQUAL, cmp_with_epsilon(RegValue::INFINITY, RegValue::INFINITY, 0.0));
+
+        // just inside the band
+        t_assert_eq!(VirtualMachine::CMP_EQUAL, cmp_with_epsilon(1.0, 1.0625, 0.125));
+        t_assert_eq!(VirtualMachine::CMP_EQUAL, cmp_with_epsilon(1.125, 1.0, 0.125));
+        // just outside the band
+        t_assert_eq!(VirtualMachine::CMP_LESS, cmp_with_epsilon(1.0, 1.1875, 0.125));
+        t_assert_eq!(VirtualMachine::CMP_GREATER, cmp_with_epsilon(1.1875, 1.0, 0.125));
+    }
+
     #[test]
     fn cmp_data_idx_out_of_range() {
         const INITIAL_VALUE: RegValue = 55.0;
@@ -1310,6 +3014,244 @@ mod instruction_tests {
         t_assert_eq!(0.0, vm.get_state().reg_v);
     }
 
+    /// Returns `reg_v` after running `opcode` with `reg_v` = `reg_v` and `data[0]` = `dval`.
+    fn run_arithmetic(opcode: OpCode, reg_v: RegValue, dval: RegValue) -> RegValue {
+        let program = Program::new(&[opcode], 1, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.set_reg_v(reg_v);
+        vm.get_data_mut()[0] = dval;
+        vm.run(None, false, false);
+        vm.get_state().reg_v
+    }
+
+    #[test]
+    fn pow() {
+        t_assert_eq!(8.0, run_arithmetic(OpCode::Pow, 2.0, 3.0));
+        t_assert_eq!(0.25, run_arithmetic(OpCode::Pow, 2.0, -2.0));
+        t_assert_eq!(3.0, run_arithmetic(OpCode::Pow, 9.0, 0.5));
+        t_assert_eq!(-8.0, run_arithmetic(OpCode::Pow, -2.0, 3.0));
+        t_assert_eq!(1.0, run_arithmetic(OpCode::Pow, 0.0, 0.0));
+        t_assert_eq!(RegValue::INFINITY, run_arithmetic(OpCode::Pow, 0.0, -1.0));
+        t_assert_eq!(RegValue::INFINITY, run_arithmetic(OpCode::Pow, 10.0, 100.0));
+    }
+
+    #[test]
+    fn pow_nan() {
+        // negative base with a fractional exponent
+        t_assert_eq!(0.0, run_arithmetic(OpCode::Pow, -8.0, 1.0 / 3.0));
+        t_assert_eq!(0.0, run_arithmetic(OpCode::Pow, RegValue::NAN, 0.0));
+        t_assert_eq!(0.0, run_arithmetic(OpCode::Pow, 1.0, RegValue::NAN));
+    }
+
+    #[test]
+    fn pow_index_out_of_range() {
+        let program = Program::new(&[OpCode::SetI(1), OpCode::Pow], 1, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.set_reg_v(2.0);
+        vm.run(None, false, false);
+        t_assert_eq!(2.0, vm.get_state().reg_v);
+    }
+
+    #[test]
+    fn log() {
+        t_assert_eq!(0.0, run_arithmetic(OpCode::Log, 1.0, 0.0));
+        t_assert_eq!(-(2.0f32.ln()), run_arithmetic(OpCode::Log, 0.5, 0.0));
+        t_assert_eq!(100.0f32.ln(), run_arithmetic(OpCode::Log, 100.0, 0.0));
+        t_assert_eq!(RegValue::INFINITY, run_arithmetic(OpCode::Log, RegValue::INFINITY, 0.0));
+    }
+
+    #[test]
+    fn log_non_positive() {
+        t_assert_eq!(0.0, run_arithmetic(OpCode::Log, 0.0, 0.0));
+        t_assert_eq!(0.0, run_arithmetic(OpCode::Log, -0.0, 0.0));
+        t_assert_eq!(0.0, run_arithmetic(OpCode::Log, -5.0, 0.0));
+        t_assert_eq!(0.0, run_arithmetic(OpCode::Log, RegValue::NAN, 0.0));
+    }
+
+    #[test]
+    fn sin() {
+        t_assert_eq!(0.0, run_arithmetic(OpCode::Sin, 0.0, 0.0));
+        t_assert_eq!(1.0, run_arithmetic(OpCode::Sin, std::f32::consts::FRAC_PI_2, 0.0));
+        t_assert_eq!(2.0f32.sin(), run_arithmetic(OpCode::Sin, 2.0, 0.0));
+    }
+
+    #[test]
+    fn cos() {
+        t_assert_eq!(1.0, run_arithmetic(OpCode::Cos, 0.0, 0.0));
+        t_assert_eq!(-1.0, run_arithmetic(OpCode::Cos, std::f32::consts::PI, 0.0));
+        t_assert_eq!(2.0f32.cos(), run_arithmetic(OpCode::Cos, 2.0, 0.0));
+    }
+
+    /// Runs `program` with `reg_i` = `reg_i` and data slots 10, 20, 30; returns final `reg_v` and data.
+    fn run_with_index(program: &Program, reg_i: i32) -> (RegValue, Vec<RegValue>) {
+        let mut vm = VirtualMachine::new(program, None);
+        vm.get_data_mut().copy_from_slice(&[10.0, 20.0, 30.0]);
+        vm.set_reg_i(reg_i);
+        vm.set_reg_v(5.0);
+        vm.run(None, false, false);
+        (vm.get_state().reg_v, vm.get_state().data.clone())
+    }
+
+    #[test]
+    fn index_mode_ignore() {
+        let program = Program::new(&[OpCode::Add], 3, false);
+        t_assert_eq!(5.0, run_with_index(&program, 3).0);
+        t_assert_eq!(5.0, run_with_index(&program, -1).0);
+        t_assert_eq!(25.0, run_with_index(&program, 1).0);
+    }
+
+    #[test]
+    fn index_mode_wrap() {
+        let mut program = Program::new(&[OpCode::Add], 3, false);
+        program.set_index_mode(IndexMode::Wrap);
+        t_assert_eq!(15.0, run_with_index(&program, 3).0);
+        t_assert_eq!(35.0, run_with_index(&program, -1).0);
+        t_assert_eq!(25.0, run_with_index(&program, -5).0);
+        t_assert_eq!(25.0, run_with_index(&program, i32::MAX).0);
+
+        let mut program = Program::new(&[OpCode::Store], 3, false);
+        program.set_index_mode(IndexMode::Wrap);
+        assert!(run_with_index(&program, 4).1 == [10.0, 5.0, 30.0]);
+    }
+
+    #[test]
+    fn index_mode_clamp() {
+        let mut program = Program::new(&[OpCode::Swap], 3, false);
+        program.set_index_mode(IndexMode::Clamp);
+        let (reg_v, data) = run_with_index(&program, 100);
+        t_assert_eq!(30.0, reg_v);
+        assert!(data == [10.0, 20.0, 5.0]);
+        let (reg_v, data) = run_with_index(&program, i32::MIN);
+        t_assert_eq!(10.0, reg_v);
+        assert!(data == [5.0, 20.0, 30.0]);
+    }
+
+    #[test]
+    fn index_mode_no_data_slots() {
+        for index_mode in [IndexMode::Ignore, IndexMode::Wrap, IndexMode::Clamp].iter() {
+            let mut program = Program::new(&[OpCode::Load], 0, false);
+            program.set_index_mode(*index_mode);
+            let mut vm = VirtualMachine::new(&program, None);
+            vm.set_reg_i(-1);
+            vm.run(None, false, false);
+            t_assert_eq!(0.0, vm.get_state().reg_v);
+        }
+    }
+
+    #[test]
+    fn data_banks_are_isolated() {
+        let mut program = Program::new(&[
+            OpCode::SetI(0),
+            OpCode::IncV,
+            OpCode::Store,    // bank 0, slot 0 = 1
+            OpCode::SetBank(1),
+            OpCode::IncV,
+            OpCode::Store,    // bank 1, slot 0 = 2
+            OpCode::SetBank(0),
+            OpCode::Load,     // 1
+            OpCode::SetBank(1),
+            OpCode::Add       // 1 + 2
+        ], 2, false);
+        program.set_num_data_banks(2);
+
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.run(None, false, false);
+
+        assert!(vm.get_state().data == [1.0, 0.0, 2.0, 0.0]);
+        t_assert_eq!(3.0, vm.get_state().reg_v);
+        t_assert_eq!(1, vm.get_state().reg_bank);
+    }
+
+    #[test]
+    fn data_bank_index_mode() {
+        let run = |index_mode: IndexMode| {
+            let mut program = Program::new(&[OpCode::SetBank(-1), OpCode::IncV, OpCode::Store], 1, false);
+            program.set_num_data_banks(3);
+            program.set_index_mode(index_mode);
+            let mut vm = VirtualMachine::new(&program, None);
+            vm.run(None, false, false);
+            vm.get_state().data.clone()
+        };
+
+        assert!(run(IndexMode::Ignore) == [0.0, 0.0, 0.0]);
+        assert!(run(IndexMode::Wrap) == [0.0, 0.0, 1.0]);
+        assert!(run(IndexMode::Clamp) == [1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn saturating_arithmetic() {
+        // square `reg_v` repeatedly: 10^2, 10^4, ..., 10^64 (overflows `f32`)
+        let mut instructions = vec![OpCode::SetI(0)];
+        for _ in 0..6 {
+            instructions.extend_from_slice(&[OpCode::Store, OpCode::Mul]);
+        }
+        let mut program = Program::new(&instructions, 1, false);
+
+        let run = |program: &Program, reg_v: RegValue| {
+            let mut vm = VirtualMachine::new(program, None);
+            vm.set_reg_v(reg_v);
+            vm.run(None, false, false);
+            vm.get_state().reg_v
+        };
+
+        t_assert_eq!(RegValue::INFINITY, run(&program, 10.0));
+
+        program.set_arithmetic_mode(ArithmeticMode::Saturating{ min: -1.0e+6, max: 1.0e+6 });
+        t_assert_eq!(1.0e+6, run(&program, 10.0));
+        t_assert_eq!(1.0e+6, run(&program, -10.0));
+        t_assert_eq!(1.0, run(&program, 1.0));
+    }
+
+    #[test]
+    fn saturating_arithmetic_nan() {
+        let mut program = Program::new(&[OpCode::Neg, OpCode::Mul, OpCode::IncV], 1, false);
+        program.set_arithmetic_mode(ArithmeticMode::Saturating{ min: -1.0, max: 1.0 });
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.set_reg_v(RegValue::INFINITY);
+        vm.get_data_mut()[0] = 0.0;
+        vm.run(None, false, false);
+        // -inf is saturated to -1.0, -1.0 * 0.0 = -0.0, incremented to 1.0
+        t_assert_eq!(1.0, vm.get_state().reg_v);
+
+        // `inf * 0.0` is NaN, replaced by zero
+        let mut program = Program::new(&[OpCode::Mul], 1, false);
+        program.set_arithmetic_mode(ArithmeticMode::Saturating{ min: 1.0, max: 2.0 });
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.set_reg_v(RegValue::INFINITY);
+        vm.run(None, false, false);
+        t_assert_eq!(1.0, vm.get_state().reg_v); // zero, clamped to `min`
+
+        // non-arithmetic instructions are not affected
+        let mut program = Program::new(&[OpCode::Load], 1, false);
+        program.set_arithmetic_mode(ArithmeticMode::Saturating{ min: 1.0, max: 2.0 });
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.get_data_mut()[0] = 100.0;
+        vm.run(None, false, false);
+        t_assert_eq!(100.0, vm.get_state().reg_v);
+    }
+
+    /// Returns `reg_v` after each of `count` executions of `RandV`.
+    fn random_values(vm: &mut VirtualMachine, count: usize) -> Vec<RegValue> {
+        (0..count).map(|_| { vm.step_with(true, false); vm.get_state().reg_v }).collect()
+    }
+
+    #[test]
+    fn randv() {
+        let program = Program::new(&[OpCode::RandV], 0, false);
+        let mut vm1 = VirtualMachine::new_seeded(&program, None, 7);
+        let mut vm2 = VirtualMachine::new_seeded(&program, None, 7);
+        let mut vm3 = VirtualMachine::new_seeded(&program, None, 8);
+
+        let values = random_values(&mut vm1, 100);
+        assert!(values.iter().all(|v| *v >= 0.0 && *v < 1.0));
+        assert!(values.windows(2).any(|w| w[0] != w[1]));
+        assert!(values == random_values(&mut vm2, 100));
+        assert!(values != random_values(&mut vm3, 100));
+
+        vm1.reset();
+        assert!(values == random_values(&mut vm1, 100));
+    }
+
     #[test]
     fn nop() {
         let program = Program::new(&[
@@ -1336,7 +3278,10 @@ mod instruction_tests {
 
 #[cfg(test)]
 mod end_condition_tests {
-    use super::{EndReason, InputOutputHandler, OpCode, Program, RegValue, VirtualMachine};
+    use super::{
+        EndReason, InputOutputHandler, NullIoHandler, OpCode, Program, RegValue, RunMode, RunOptions, TerminationPolicy,
+        VirtualMachine, TIME_CHECK_INTERVAL
+    };
 
     #[test]
     fn last_instr_reached() {
@@ -1347,6 +3292,59 @@ mod end_condition_tests {
         t_assert_eq!(EndReason::LastInstructionReached, reason);
     }
 
+    #[test]
+    fn null_io_handler() {
+        let program = Program::new(&[OpCode::IncV, OpCode::Input(0), OpCode::Output(0), OpCode::Store], 1, false);
+        let mut io_handler = NullIoHandler;
+        let mut vm = VirtualMachine::new(&program, Some(&mut io_handler));
+
+        t_assert_eq!(EndReason::LastInstructionReached, vm.run(Some(10), false, true));
+        t_assert_eq!(0.0, vm.get_state().data[0]);
+    }
+
+    #[test]
+    fn empty_program() {
+        let program = Program::new(&[OpCode::Nop, OpCode::Nop], 0, false).get_optimized();
+        assert!(program.get_instr().is_empty());
+        let mut vm = VirtualMachine::new(&program, None);
+
+        t_assert_eq!(EndReason::LastInstructionReached, vm.run(None, false, false));
+        t_assert_eq!(EndReason::LastInstructionReached, vm.run(Some(10), true, false));
+    }
+
+    #[test]
+    fn halted() {
+        let program = Program::new(&[OpCode::IncV, OpCode::Store, OpCode::Halt, OpCode::IncV, OpCode::Store], 1, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        t_assert_eq!(EndReason::Halted, vm.run(Some(100), true, false));
+        assert!(vm.last_end_reason() == Some(EndReason::Halted));
+        t_assert_eq!(1.0, vm.get_state().reg_v);
+        t_assert_eq!(1.0, vm.get_state().data[0]);
+        t_assert_eq!(2, vm.get_state().iptr);
+
+        // stays halted
+        t_assert_eq!(EndReason::Halted, vm.run(None, false, false));
+        assert!(vm.step() == Some(EndReason::Halted));
+        t_assert_eq!(1.0, vm.get_state().reg_v);
+
+        vm.reset();
+        assert!(vm.step().is_none());
+        t_assert_eq!(1.0, vm.get_state().reg_v);
+    }
+
+    #[test]
+    fn conditional_halt() {
+        let program = Program::new(&[OpCode::IfN, OpCode::Halt, OpCode::DecV], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        // `reg_v` = 0: `Halt` is skipped
+        t_assert_eq!(EndReason::NumExecInstructions, vm.run(Some(3), true, false));
+        t_assert_eq!(-1.0, vm.get_state().reg_v);
+        t_assert_eq!(EndReason::Halted, vm.run(Some(3), true, false));
+        t_assert_eq!(1, vm.get_state().iptr);
+    }
+
     #[test]
     fn num_exec_instructions() {
         let program = Program::new(&[OpCode::Nop], 0, false);
@@ -1356,6 +3354,74 @@ mod end_condition_tests {
         t_assert_eq!(EndReason::NumExecInstructions, reason);
     }
 
+    #[test]
+    fn last_end_reason() {
+        let program = Program::new(&[OpCode::Nop], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        assert!(vm.last_end_reason().is_none());
+
+        vm.run(Some(100), true, false);
+        assert!(vm.last_end_reason() == Some(EndReason::NumExecInstructions));
+
+        vm.reset();
+        assert!(vm.last_end_reason().is_none());
+
+        vm.step();
+        assert!(vm.last_end_reason() == Some(EndReason::LastInstructionReached));
+    }
+
+    #[test]
+    fn loop_keeps_registers() {
+        let program = Program::new(&[OpCode::IncV, OpCode::IncI], 1, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        vm.run(Some(3), true, false);
+        t_assert_eq!(2.0, vm.get_state().reg_v);
+        t_assert_eq!(1, vm.get_state().reg_i);
+    }
+
+    #[test]
+    fn reset_on_loop() {
+        let program = Program::new(&[OpCode::IncV, OpCode::Store, OpCode::IncI], 1, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.set_reset_on_loop(true);
+
+        vm.run(Some(3), true, false);
+        // second cycle starts from zeroed registers, data are preserved
+        t_assert_eq!(0.0, vm.get_state().reg_v);
+        t_assert_eq!(0, vm.get_state().reg_i);
+        t_assert_eq!(1.0, vm.get_state().data[0]);
+
+        vm.run(Some(1), true, false);
+        t_assert_eq!(1.0, vm.get_state().reg_v);
+        t_assert_eq!(0, vm.get_state().reg_i);
+    }
+
+    #[test]
+    fn reset_execution() {
+        let program = Program::new(&[OpCode::Load, OpCode::IncV, OpCode::Store, OpCode::IncI, OpCode::Halt], 2, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        t_assert_eq!(EndReason::Halted, vm.run(None, false, false));
+        t_assert_eq!(4, vm.get_state().iptr);
+
+        vm.reset_execution(false);
+        t_assert_eq!(0, vm.get_state().iptr);
+        t_assert_eq!(1, vm.get_state().reg_i);
+        assert!(vm.get_state().data == [1.0, 0.0]);
+        assert!(vm.last_end_reason().is_none());
+
+        vm.run(None, false, false);
+        assert!(vm.get_state().data == [1.0, 1.0]);
+
+        vm.reset_execution(true);
+        t_assert_eq!(0, vm.get_state().iptr);
+        t_assert_eq!(0, vm.get_state().reg_i);
+        t_assert_eq!(0.0, vm.get_state().reg_v);
+        vm.run(None, false, false);
+        assert!(vm.get_state().data == [2.0, 1.0]);
+    }
+
     #[test]
     fn end_condition_met() {
         const NUM_INSTR_TO_RUN: usize = 100;
@@ -1403,26 +3469,400 @@ mod end_condition_tests {
         let reason = vm.run(Some(NUM_INSTR_TO_RUN), true, true);
         t_assert_eq!(EndReason::NumExecInstructions, reason);
     }
-}
-
-#[cfg(test)]
-mod optimization_tests {
-    use vm::{OpCode, Program};
 
     #[test]
-    fn seti() {
-        let prog = Program::new(
-            &[
-                OpCode::SetI(0), // should be optimized out
-                OpCode::SetI(1), //
-                OpCode::SetI(2), //
-                OpCode::SetI(3)
-            ],
-            1, false);
-        let opt_prog = prog.get_optimized();
-
-        assert!(opt_prog.get_instr() == &[OpCode::SetI(3)]);
+    fn end_condition_timed_out() {
+        const NUM_INSTR_TO_RUN: usize = 100;
+        const NUM_INSTR_TO_END: usize = 50;
+
+        #[derive(Default)]
+        struct IoHandler { }
+        impl InputOutputHandler for IoHandler {
+            fn input(&mut self, _: i32) -> RegValue { 0.0 }
+            fn output(&mut self, _: i32, _: RegValue) { }
+            fn check_end_condition(&self, num_execd_instructions: usize) -> bool {
+                num_execd_instructions > NUM_INSTR_TO_END
+            }
+        }
+
+        let mut io_handler = IoHandler::default();
+
+        let program = Program::new(&[OpCode::Output(0)], 0, false);
+        let mut vm = VirtualMachine::new(&program, Some(&mut io_handler));
+
+        let options = |max_instructions| RunOptions{
+            num_exec_instructions: Some(NUM_INSTR_TO_RUN),
+            mode: RunMode::LoopForever,
+            check_end_condition: true,
+            termination: TerminationPolicy{ max_instructions: Some(max_instructions) },
+            ..Default::default()
+        };
+
+        let report = vm.run_reported(&options(NUM_INSTR_TO_END));
+        t_assert_eq!(EndReason::EndConditionTimedOut, report.end_reason);
+        t_assert_eq!(NUM_INSTR_TO_END + 1, report.instructions_executed);
+        assert!(vm.last_end_reason() == Some(EndReason::EndConditionTimedOut));
+
+        vm.reset();
+        t_assert_eq!(EndReason::EndConditionMet, vm.run_with_options(&options(NUM_INSTR_TO_END + 1)));
+    }
+
+    #[test]
+    fn timed_out() {
+        let program = Program::new(&[OpCode::EndGoTo, OpCode::IncV, OpCode::GoToIfP], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        t_assert_eq!(EndReason::TimedOut, vm.run_timed(std::time::Duration::from_nanos(1), false, false));
+        assert!(vm.last_end_reason() == Some(EndReason::TimedOut));
+
+        let report = vm.run_reported(&RunOptions{
+            max_duration: Some(std::time::Duration::from_nanos(1)),
+            ..Default::default()
+        });
+        t_assert_eq!(EndReason::TimedOut, report.end_reason);
+        t_assert_eq!(TIME_CHECK_INTERVAL, report.instructions_executed);
+
+        let finite = Program::new(&[OpCode::IncV; 10], 0, false);
+        let mut vm = VirtualMachine::new(&finite, None);
+        t_assert_eq!(EndReason::LastInstructionReached, vm.run_timed(std::time::Duration::from_secs(60), false, false));
+    }
+}
+
+#[cfg(test)]
+mod loop_detection_tests {
+    use super::{EndReason, OpCode, Program, RunMode, RunOptions, TerminationPolicy, VirtualMachine};
+    use std::cell::Cell;
+
+    const MAX_EXEC_INSTRUCTIONS: usize = 10000;
+
+    /// Returns the end reason and the number of executed instructions.
+    fn run(program: &Program, looped: bool, detect_loops: bool) -> (EndReason, usize) {
+        let num_executed = Cell::new(0);
+        let reason = {
+            let mut vm = VirtualMachine::new(program, None);
+            vm.set_trace_hook(Box::new(|_, _, _| num_executed.set(num_executed.get() + 1)));
+            vm.run_with_options(&RunOptions{
+                num_exec_instructions: Some(MAX_EXEC_INSTRUCTIONS),
+                instruction_cost: None,
+                mode: RunMode::from_looped(looped),
+                check_end_condition: false,
+                termination: TerminationPolicy::default(),
+                detect_loops,
+                max_duration: None
+            })
+        };
+        (reason, num_executed.get())
+    }
+
+    #[test]
+    fn infinite_loop() {
+        let program = Program::new(&[
+            OpCode::IncI,
+            OpCode::EndGoTo,
+                OpCode::Nop,
+                OpCode::Nop,
+            OpCode::GoToIfP
+        ], 1, false);
+
+        let (reason, num_executed) = run(&program, false, true);
+        t_assert_eq!(EndReason::LoopDetected, reason);
+        assert!(num_executed < 20);
+
+        let (reason, num_executed) = run(&program, false, false);
+        t_assert_eq!(EndReason::NumExecInstructions, reason);
+        t_assert_eq!(MAX_EXEC_INSTRUCTIONS, num_executed);
+    }
+
+    #[test]
+    fn looped_program() {
+        let program = Program::new(&[OpCode::SetI(0), OpCode::Load], 1, false);
+        t_assert_eq!(EndReason::LoopDetected, run(&program, true, true).0);
+    }
+
+    #[test]
+    fn storing_unchanged_value() {
+        let program = Program::new(&[OpCode::EndGoTo, OpCode::Store, OpCode::GoToIfP], 1, false);
+        t_assert_eq!(EndReason::LoopDetected, run(&program, false, true).0);
+    }
+
+    #[test]
+    fn terminating_loop() {
+        let mut instructions = vec![OpCode::IncV; 50];
+        instructions.extend_from_slice(&[
+            OpCode::EndGoTo,
+                OpCode::DecV,
+            OpCode::GoToIfP
+        ]);
+        let program = Program::new(&instructions, 0, false);
+        t_assert_eq!(EndReason::LastInstructionReached, run(&program, false, true).0);
+    }
+
+    #[test]
+    fn loop_with_progress() {
+        // output
+        let program = Program::new(&[OpCode::EndGoTo, OpCode::Output(0), OpCode::GoToIfP], 0, false);
+        t_assert_eq!(EndReason::NumExecInstructions, run(&program, false, true).0);
+
+        // data change
+        let program = Program::new(&[OpCode::SetI(0), OpCode::EndGoTo, OpCode::IncV, OpCode::Store, OpCode::GoToIfP], 1, false);
+        t_assert_eq!(EndReason::NumExecInstructions, run(&program, false, true).0);
+
+        // random values
+        let program = Program::new(&[OpCode::EndGoTo, OpCode::RandV, OpCode::GoToIfP], 0, false);
+        t_assert_eq!(EndReason::NumExecInstructions, run(&program, false, true).0);
+    }
+
+    #[test]
+    fn loop_times_restarts() {
+        // each run ends in the same state, but the number of runs is limited
+        let program = Program::new(&[OpCode::Nop], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        let report = vm.run_reported(&RunOptions{ mode: RunMode::LoopTimes(5), detect_loops: true, ..Default::default() });
+        t_assert_eq!(EndReason::LastInstructionReached, report.end_reason);
+        t_assert_eq!(5, report.instructions_executed);
+
+        // a loop within a single run is still detected
+        let program = Program::new(&[OpCode::EndGoTo, OpCode::Nop, OpCode::GoToIfP], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        let report = vm.run_reported(&RunOptions{ mode: RunMode::LoopTimes(5), detect_loops: true, ..Default::default() });
+        t_assert_eq!(EndReason::LoopDetected, report.end_reason);
+    }
+}
+
+#[cfg(test)]
+mod run_report_tests {
+    use super::{
+        EndReason, InputOutputHandler, OpCode, Program, RegValue, RunMode, RunOptions, RunReport, TerminationPolicy, VirtualMachine
+    };
+
+    fn options(num_exec_instructions: Option<usize>, looped: bool) -> RunOptions {
+        RunOptions{ num_exec_instructions, instruction_cost: None, mode: RunMode::from_looped(looped), check_end_condition: false, termination: TerminationPolicy::default(), detect_loops: false, max_duration: None }
+    }
+
+    #[test]
+    fn non_looped() {
+        let program = Program::new(&[
+            OpCode::IncV,
+            OpCode::EndGoTo,
+                OpCode::Output(0),
+                OpCode::DecV,
+            OpCode::GoToIfP
+        ], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        assert!(vm.run_reported(&options(None, false)) == RunReport{
+            end_reason: EndReason::LastInstructionReached,
+            instructions_executed: 9,
+            outputs_emitted: 2,
+            jumps_taken: 1,
+            final_iptr: 5
+        });
+    }
+
+    /// Returns the report and final `reg_v` of running a program incrementing `reg_v` twice and outputting it.
+    fn run_in_mode(mode: RunMode, num_exec_instructions: Option<usize>) -> (RunReport, RegValue) {
+        let program = Program::new(&[OpCode::IncV, OpCode::IncV, OpCode::Output(0)], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        let report = vm.run_reported(&RunOptions{ num_exec_instructions, mode, ..Default::default() });
+        (report, vm.get_state().reg_v)
+    }
+
+    #[test]
+    fn run_once() {
+        let (report, reg_v) = run_in_mode(RunMode::Once, Some(100));
+        t_assert_eq!(EndReason::LastInstructionReached, report.end_reason);
+        t_assert_eq!(3, report.instructions_executed);
+        t_assert_eq!(1, report.outputs_emitted);
+        t_assert_eq!(2.0, reg_v);
+
+        assert!(run_in_mode(RunMode::LoopTimes(1), Some(100)) == (report, reg_v));
+    }
+
+    #[test]
+    fn loop_forever() {
+        let (report, reg_v) = run_in_mode(RunMode::LoopForever, Some(100));
+        t_assert_eq!(EndReason::NumExecInstructions, report.end_reason);
+        t_assert_eq!(100, report.instructions_executed);
+        t_assert_eq!(33, report.outputs_emitted);
+        t_assert_eq!(67.0, reg_v);
+    }
+
+    #[test]
+    fn loop_times() {
+        let (report, reg_v) = run_in_mode(RunMode::LoopTimes(4), None);
+        t_assert_eq!(EndReason::LastInstructionReached, report.end_reason);
+        t_assert_eq!(12, report.instructions_executed);
+        t_assert_eq!(4, report.outputs_emitted);
+        t_assert_eq!(8.0, reg_v);
+
+        let (report, reg_v) = run_in_mode(RunMode::LoopTimes(4), Some(10));
+        t_assert_eq!(EndReason::NumExecInstructions, report.end_reason);
+        t_assert_eq!(3, report.outputs_emitted);
+        t_assert_eq!(7.0, reg_v);
+
+        let (report, reg_v) = run_in_mode(RunMode::LoopTimes(0), None);
+        t_assert_eq!(EndReason::LastInstructionReached, report.end_reason);
+        t_assert_eq!(0, report.instructions_executed);
+        t_assert_eq!(0.0, reg_v);
+    }
+
+    #[test]
+    fn loop_times_ignores_jumps_to_start() {
+        // a jump back to the first instruction is not a restart
+        let program = Program::new(&[
+            OpCode::EndGoTo,
+                OpCode::Output(0),
+                OpCode::DecV,
+            OpCode::GoToIfP,
+            OpCode::IncV,
+            OpCode::IncV
+        ], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        let options = RunOptions{ num_exec_instructions: None, mode: RunMode::LoopTimes(2), ..Default::default() };
+
+        // the first pass ends with `reg_v` = 1, so the second one jumps back once
+        let report = vm.run_reported(&options);
+        t_assert_eq!(EndReason::LastInstructionReached, report.end_reason);
+        t_assert_eq!(16, report.instructions_executed);
+        t_assert_eq!(3, report.outputs_emitted);
+        t_assert_eq!(1, report.jumps_taken);
+    }
+
+    #[test]
+    fn halted() {
+        let program = Program::new(&[OpCode::Output(0), OpCode::Halt, OpCode::Output(0)], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        assert!(vm.run_reported(&options(Some(10), true)) == RunReport{
+            end_reason: EndReason::Halted,
+            instructions_executed: 2,
+            outputs_emitted: 1,
+            jumps_taken: 0,
+            final_iptr: 1
+        });
+    }
+
+    #[test]
+    fn looped() {
+        let program = Program::new(&[
+            OpCode::DecV,
+            OpCode::Output(0),
+            OpCode::JumpIfN,
+                OpCode::Nop,
+            OpCode::EndJump
+        ], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        assert!(vm.run_reported(&options(Some(10), true)) == RunReport{
+            end_reason: EndReason::NumExecInstructions,
+            instructions_executed: 10,
+            outputs_emitted: 3,
+            jumps_taken: 2,
+            final_iptr: 2
+        });
+    }
+
+    #[test]
+    fn end_condition_met() {
+        struct IoHandler { num_outputs: usize }
+        impl InputOutputHandler for IoHandler {
+            fn input(&mut self, _: i32) -> RegValue { 0.0 }
+            fn output(&mut self, _: i32, _: RegValue) { self.num_outputs += 1; }
+            fn check_end_condition(&self, _: usize) -> bool { self.num_outputs == 2 }
+        }
+
+        let program = Program::new(&[OpCode::Output(0), OpCode::Nop], 0, false);
+        let mut io_handler = IoHandler{ num_outputs: 0 };
+        let mut vm = VirtualMachine::new(&program, Some(&mut io_handler));
+
+        let report = vm.run_reported(&RunOptions{ check_end_condition: true, ..options(Some(100), true) });
+        assert!(report == RunReport{
+            end_reason: EndReason::EndConditionMet,
+            instructions_executed: 3,
+            outputs_emitted: 2,
+            jumps_taken: 0,
+            final_iptr: 1
+        });
+        t_assert_eq!(vm.get_state().iptr, report.final_iptr);
+    }
+
+    #[test]
+    fn empty_program() {
+        let program = Program::new(&[], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        let report = vm.run_reported(&options(Some(10), true));
+        assert!(report.end_reason == EndReason::LastInstructionReached);
+        t_assert_eq!(0, report.instructions_executed);
+    }
+
+    #[test]
+    fn instruction_cost() {
+        let program = Program::new(&[OpCode::IncV, OpCode::Sqrt, OpCode::IncV, OpCode::Sqrt], 0, false);
+        let cost = |opcode| if opcode == OpCode::Sqrt { 10 } else { 1 };
+
+        let mut vm = VirtualMachine::new(&program, None);
+        let report = vm.run_reported(&RunOptions{ instruction_cost: Some(cost), ..options(Some(25), true) });
+        // costs: 1 + 10 + 1 + 10 + 1; the next `Sqrt` would exceed the budget
+        assert!(report.end_reason == EndReason::NumExecInstructions);
+        t_assert_eq!(5, report.instructions_executed);
+        t_assert_eq!(1, vm.get_state().iptr);
+
+        let mut vm = VirtualMachine::new(&program, None);
+        t_assert_eq!(25, vm.run_reported(&options(Some(25), true)).instructions_executed);
+    }
+}
+
+#[cfg(test)]
+mod optimization_tests {
+    use vm::{DEFAULT_OPTIMIZATION_PASSES, FoldRedundantSetI, OpCode, Program, RemoveDeadNop, RemoveDeadStore, RemoveEmptyBlocks};
+
+    #[test]
+    fn seti() {
+        let prog = Program::new(
+            &[
+                OpCode::SetI(0), // should be optimized out
+                OpCode::SetI(1), //
+                OpCode::SetI(2), //
+                OpCode::SetI(3)
+            ],
+            1, false);
+        let opt_prog = prog.get_optimized();
+
+        assert!(opt_prog.get_instr() == [OpCode::SetI(3)]);
         t_assert_eq!(prog.get_num_data_slots(), opt_prog.get_num_data_slots());
+
+        let (mapped_prog, mapping) = prog.get_optimized_with_mapping();
+        assert!(mapped_prog.get_instr() == opt_prog.get_instr());
+        assert!(mapping == [Some(3)]);
+    }
+
+    #[test]
+    fn mapping() {
+        let prog = Program::new(
+            &[
+                OpCode::Add,
+                OpCode::Nop,     // removed by `RemoveDeadNop`
+                OpCode::Add,
+                OpCode::SetI(1), // removed by `FoldRedundantSetI`
+                OpCode::SetI(2),
+                OpCode::Store,   // removed by `RemoveDeadStore`
+                OpCode::Store,
+                OpCode::IfP,
+                    OpCode::Nop
+            ],
+            1, false);
+
+        let (opt_prog, mapping) = prog.optimize_with_mapping(&[&FoldRedundantSetI, &RemoveDeadNop, &RemoveDeadStore]);
+        assert!(opt_prog.get_instr() == [OpCode::Add, OpCode::Add, OpCode::SetI(2), OpCode::Store, OpCode::IfP, OpCode::Nop]);
+        assert!(mapping == [Some(0), Some(2), Some(4), Some(6), Some(7), Some(8)]);
+        for (opt_index, index) in mapping.iter().enumerate() {
+            assert!(opt_prog.get_instr()[opt_index] == prog.get_instr()[index.unwrap()]);
+        }
+
+        let (unchanged, mapping) = prog.optimize_with_mapping(&[]);
+        assert!(unchanged.get_instr() == prog.get_instr());
+        assert!(mapping == (0..9).map(Some).collect::<Vec<Option<usize>>>());
     }
 
     #[test]
@@ -1434,7 +3874,7 @@ mod optimization_tests {
             1, false);
         let opt_prog = prog.get_optimized();
 
-        assert!(opt_prog.get_instr() == &[OpCode::SetI(0)]);
+        assert!(opt_prog.get_instr() == [OpCode::SetI(0)]);
     }
 
     #[test]
@@ -1453,7 +3893,7 @@ mod optimization_tests {
             1, false);
         let opt_prog = prog.get_optimized();
 
-        assert!(opt_prog.get_instr() == &[
+        assert!(opt_prog.get_instr() == [
             OpCode::Add,
             OpCode::SetI(4),
             OpCode::Add,
@@ -1478,7 +3918,7 @@ mod optimization_tests {
             1, false);
         let opt_prog = prog.get_optimized();
 
-        assert!(opt_prog.get_instr() == &[
+        assert!(opt_prog.get_instr() == [
             OpCode::Add,
             OpCode::SetI(2),
             OpCode::Add,
@@ -1499,7 +3939,7 @@ mod optimization_tests {
             1, false);
         let opt_prog = prog.get_optimized();
 
-        assert!(opt_prog.get_instr() == &[
+        assert!(opt_prog.get_instr() == [
             OpCode::SetI(1),
             OpCode::IfP,
                 OpCode::SetI(2),
@@ -1516,7 +3956,7 @@ mod optimization_tests {
             1, false);
         let opt_prog = prog.get_optimized();
 
-        assert!(opt_prog.get_instr() == &[
+        assert!(opt_prog.get_instr() == [
             OpCode::SetI(0),
             OpCode::Add
         ]);
@@ -1532,7 +3972,7 @@ mod optimization_tests {
             1, false);
         let opt_prog = prog.get_optimized();
 
-        assert!(opt_prog.get_instr() == &[
+        assert!(opt_prog.get_instr() == [
             OpCode::IfP,
                 OpCode::SetI(0)
         ]);
@@ -1551,7 +3991,7 @@ mod optimization_tests {
             1, false);
         let opt_prog = prog.get_optimized();
 
-        assert!(opt_prog.get_instr() == &[
+        assert!(opt_prog.get_instr() == [
             OpCode::SetI(0)
         ]);
     }
@@ -1572,7 +4012,7 @@ mod optimization_tests {
             1, false);
         let opt_prog = prog.get_optimized();
 
-        assert!(opt_prog.get_instr() == &[
+        assert!(opt_prog.get_instr() == [
             OpCode::Add,
             OpCode::IfP,
                 OpCode::Nop,
@@ -1580,4 +4020,1095 @@ mod optimization_tests {
                 OpCode::Nop
         ]);
     }
-}
\ No newline at end of file
+    #[test]
+    fn empty_pass_list() {
+        let prog = Program::new(&[OpCode::Nop, OpCode::SetI(0), OpCode::SetI(1), OpCode::Add], 1, false);
+        let opt_prog = prog.optimize_with(&[]);
+
+        assert!(opt_prog.get_instr() == prog.get_instr());
+    }
+
+    #[test]
+    fn pass_lists() {
+        let prog = Program::new(
+            &[
+                OpCode::SetI(0),
+                OpCode::SetI(1),
+                OpCode::IfP,
+                    OpCode::Nop,
+                OpCode::Add,
+                OpCode::Nop
+            ],
+            1, false);
+
+        assert!(prog.optimize_with(DEFAULT_OPTIMIZATION_PASSES).get_instr() == prog.get_optimized().get_instr());
+        assert!(prog.get_optimized().get_instr() == [
+            OpCode::SetI(1),
+            OpCode::IfP,
+                OpCode::Nop,
+            OpCode::Add
+        ]);
+
+        assert!(prog.optimize_with(&[&RemoveDeadNop]).get_instr() == [
+            OpCode::SetI(0),
+            OpCode::SetI(1),
+            OpCode::IfP,
+                OpCode::Nop,
+            OpCode::Add
+        ]);
+        assert!(prog.optimize_with(&[&FoldRedundantSetI]).get_instr() == [
+            OpCode::SetI(1),
+            OpCode::IfP,
+                OpCode::Nop,
+            OpCode::Add,
+            OpCode::Nop
+        ]);
+    }
+
+    #[test]
+    fn fold_seti_keeps_trigonometric() {
+        // only contiguous `reg_i` modifications are folded; `Sin`/`Cos` (which only transform `reg_v`) interrupt the chain
+        let prog = Program::new(&[OpCode::SetI(0), OpCode::Sin, OpCode::SetI(1), OpCode::Cos, OpCode::SetI(2)], 1, false);
+        assert!(prog.optimize_with(&[&FoldRedundantSetI]).get_instr() == prog.get_instr());
+    }
+
+    #[test]
+    fn dead_store() {
+        let prog = Program::new(
+            &[
+                OpCode::SetI(0),
+                OpCode::Input(0),
+                OpCode::Store,  // should be optimized out
+                OpCode::IncV,
+                OpCode::IfP,
+                    OpCode::Store,
+                OpCode::Store,
+                OpCode::Output(0)
+            ],
+            1, false);
+        let opt_prog = prog.optimize_with(&[&RemoveDeadStore]);
+
+        assert!(opt_prog.get_instr() == [
+            OpCode::SetI(0),
+            OpCode::Input(0),
+            OpCode::IncV,
+            OpCode::IfP,
+                OpCode::Store,
+            OpCode::Store,
+            OpCode::Output(0)
+        ]);
+    }
+
+    #[test]
+    fn store_followed_by_load() {
+        let prog = Program::new(
+            &[
+                OpCode::Input(0),
+                OpCode::Store,
+                OpCode::Input(1),
+                OpCode::Add,
+                OpCode::Store,
+                OpCode::Output(0)
+            ],
+            1, false);
+        let opt_prog = prog.optimize_with(&[&RemoveDeadStore]);
+
+        assert!(opt_prog.get_instr() == prog.get_instr());
+    }
+
+    #[test]
+    fn store_before_index_change_or_jump() {
+        let programs = [
+            Program::new(&[OpCode::Store, OpCode::IncI, OpCode::Store], 2, false),
+            Program::new(&[OpCode::Store, OpCode::EndGoTo, OpCode::Store, OpCode::DecV, OpCode::GoToIfP], 2, false),
+            Program::new(&[OpCode::IfN, OpCode::Store, OpCode::Store], 2, false)
+        ];
+
+        for prog in &programs {
+            assert!(prog.optimize_with(&[&RemoveDeadStore]).get_instr() == prog.get_instr());
+        }
+    }
+
+    #[test]
+    fn empty_blocks() {
+        let prog = Program::new(
+            &[
+                OpCode::IncV,
+                OpCode::EndGoTo, // should be optimized out
+                OpCode::GoToIfP, //
+                OpCode::JumpIfN, //
+                    OpCode::Nop, //
+                OpCode::EndJump, //
+                OpCode::Output(0)
+            ],
+            0, false);
+        let (opt_prog, mapping) = prog.optimize_with_mapping(&[&RemoveEmptyBlocks]);
+
+        assert!(opt_prog.get_instr() == [OpCode::IncV, OpCode::Output(0)]);
+        assert!(mapping == [Some(0), Some(6)]);
+    }
+
+    #[test]
+    fn non_empty_blocks() {
+        let programs = [
+            Program::new(&[OpCode::EndGoTo, OpCode::DecV, OpCode::GoToIfP, OpCode::Output(0)], 0, false),
+            Program::new(&[OpCode::JumpIfN, OpCode::Nop, OpCode::IncV, OpCode::EndJump], 0, false),
+            // the `IfP` would skip a different instruction
+            Program::new(&[OpCode::IfP, OpCode::JumpIfN, OpCode::EndJump, OpCode::IncV], 0, false),
+            // inactive
+            Program::new(&[OpCode::EndJump, OpCode::JumpIfN, OpCode::IncV], 0, false),
+            // the whole program
+            Program::new(&[OpCode::EndGoTo, OpCode::GoToIfP], 0, false)
+        ];
+
+        for prog in &programs {
+            assert!(prog.optimize_with(&[&RemoveEmptyBlocks]).get_instr() == prog.get_instr());
+        }
+    }
+
+    #[test]
+    fn nested_empty_blocks() {
+        let prog = Program::new(
+            &[
+                OpCode::EndGoTo,
+                    OpCode::JumpIfN, // should be optimized out
+                    OpCode::EndJump, //
+                    OpCode::DecV,
+                OpCode::GoToIfP
+            ],
+            0, false);
+        let opt_prog = prog.optimize_with(&[&RemoveEmptyBlocks]);
+
+        assert!(opt_prog.get_instr() == [OpCode::EndGoTo, OpCode::DecV, OpCode::GoToIfP]);
+        assert!(opt_prog.get_jump_table() == [Some(2), None, Some(0)]);
+    }
+}
+
+#[cfg(test)]
+mod optimization_equivalence_tests {
+    use rand::SeedableRng;
+    use utils;
+    use vm::{
+        DEFAULT_OPTIMIZATION_PASSES, EndReason, FoldRedundantSetI, InputOutputHandler, OpCode, OptimizationPass, Program,
+        RegValue, RemoveDeadNop, RemoveDeadStore, RemoveEmptyBlocks, VirtualMachine
+    };
+
+    /// Max. number of instructions executed per run; runs exceeding it are not compared.
+    const MAX_EXEC_INSTRUCTIONS: usize = 10000;
+
+    struct IoHandler<'a> {
+        inputs: &'a [RegValue],
+        outputs: Vec<(i32, u32)>
+    }
+
+    impl<'a> InputOutputHandler for IoHandler<'a> {
+        fn input(&mut self, input_num: i32) -> RegValue {
+            self.inputs[input_num.rem_euclid(self.inputs.len() as i32) as usize]
+        }
+
+        fn output(&mut self, output_num: i32, output_val: RegValue) {
+            // compare bit patterns, so that NaNs are considered equal
+            self.outputs.push((output_num, output_val.to_bits()));
+        }
+
+        fn check_end_condition(&self, _num_execd_instructions: usize) -> bool { false }
+    }
+
+    /// Outputs (number and value bits) and final `reg_v` (bits), `reg_i`.
+    type RunResult = (Vec<(i32, u32)>, u32, i32);
+
+    /// Returns `None` if the program did not finish within `MAX_EXEC_INSTRUCTIONS`.
+    fn run_with_inputs(program: &Program, inputs: &[RegValue]) -> Option<RunResult> {
+        let mut io_handler = IoHandler{ inputs, outputs: vec![] };
+        let (end_reason, reg_v, reg_i) = {
+            let mut vm = VirtualMachine::new(program, Some(&mut io_handler));
+            let end_reason = vm.run(Some(MAX_EXEC_INSTRUCTIONS), false, false);
+            (end_reason, vm.get_state().reg_v.to_bits(), vm.get_state().reg_i)
+        };
+
+        match end_reason {
+            EndReason::NumExecInstructions => None,
+            _ => Some((io_handler.outputs, reg_v, reg_i))
+        }
+    }
+
+    fn all_opcodes() -> Vec<OpCode> {
+        vec![
+            OpCode::SetI(-1), OpCode::SetI(0), OpCode::SetI(1), OpCode::SetI(2), OpCode::SetI(3),
+            OpCode::Input(0), OpCode::Input(1), OpCode::Input(2), OpCode::Output(0), OpCode::Output(1),
+            OpCode::ItoV, OpCode::VtoI, OpCode::IncV, OpCode::DecV, OpCode::IncI, OpCode::DecI,
+            OpCode::Load, OpCode::Store, OpCode::Swap,
+            OpCode::EndGoTo, OpCode::GoToIfP, OpCode::JumpIfN, OpCode::EndJump, OpCode::IfP, OpCode::IfN,
+            OpCode::Cmp, OpCode::Add, OpCode::Sub, OpCode::Mul, OpCode::Div,
+            OpCode::Abs, OpCode::Neg, OpCode::Sqrt, OpCode::Pow, OpCode::Log, OpCode::Sin, OpCode::Cos,
+            OpCode::RandV, OpCode::Halt, OpCode::Nop
+        ]
+    }
+
+    fn check_equivalence(programs: &[Program], passes: &[&dyn OptimizationPass]) -> usize {
+        let input_sets: [&[RegValue]; 4] = [
+            &[0.0, 0.0, 0.0],
+            &[1.0, -2.0, 3.5],
+            &[-0.5, 7.0, -11.0],
+            &[1.0e+30, -1.0e-30, 2.0]
+        ];
+
+        let mut num_compared = 0;
+        for program in programs {
+            let optimized = program.optimize_with(passes);
+            for inputs in input_sets.iter() {
+                if let Some(expected) = run_with_inputs(program, inputs) {
+                    let actual = run_with_inputs(&optimized, inputs);
+                    assert!(
+                        actual.as_ref() == Some(&expected),
+                        "optimized program behaves differently for inputs {:?}:\n{}\noptimized:\n{}",
+                        inputs,
+                        utils::pretty_print(program, Some("*"), true, Some(2)),
+                        utils::pretty_print(&optimized, Some("*"), true, Some(2))
+                    );
+                    num_compared += 1;
+                }
+            }
+        }
+
+        num_compared
+    }
+
+    #[test]
+    fn random_programs() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let opcodes = all_opcodes();
+        // favor instructions involved in optimizations
+        let rel_probability: Vec<f64> = opcodes.iter().map(|opcode| match opcode {
+            OpCode::SetI(_) | OpCode::IncI | OpCode::DecI | OpCode::VtoI | OpCode::IfP | OpCode::IfN | OpCode::Nop => 4.0,
+            _ => 1.0
+        }).collect();
+
+        for &allow_crossing_blocks in &[false, true] {
+            let programs: Vec<Program> = utils::generate_random_programs(2000, 1, 40, 4, &opcodes, Some(&rel_probability), false, true, &mut rng)
+                .iter()
+                .map(|p| Program::new(p.get_instr(), p.get_num_data_slots(), allow_crossing_blocks))
+                .collect();
+
+            assert!(check_equivalence(&programs, DEFAULT_OPTIMIZATION_PASSES) > programs.len());
+        }
+    }
+
+    #[test]
+    fn random_programs_dead_stores() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(2);
+        let opcodes = all_opcodes();
+        let rel_probability: Vec<f64> = opcodes.iter().map(|opcode| match opcode {
+            OpCode::Store | OpCode::Input(_) | OpCode::Output(_) | OpCode::IncV | OpCode::IfP | OpCode::IfN => 4.0,
+            _ => 1.0
+        }).collect();
+
+        let programs = utils::generate_random_programs(2000, 1, 40, 4, &opcodes, Some(&rel_probability), false, true, &mut rng);
+        let passes: [&dyn OptimizationPass; 3] = [&FoldRedundantSetI, &RemoveDeadNop, &RemoveDeadStore];
+        assert!(check_equivalence(&programs, &passes) > programs.len());
+    }
+
+    #[test]
+    fn random_programs_empty_blocks() {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(3);
+        let opcodes = all_opcodes();
+        let rel_probability: Vec<f64> = opcodes.iter().map(|opcode| match opcode {
+            OpCode::EndGoTo | OpCode::GoToIfP | OpCode::JumpIfN | OpCode::EndJump | OpCode::Nop => 4.0,
+            _ => 1.0
+        }).collect();
+
+        for &allow_crossing_blocks in &[false, true] {
+            let programs: Vec<Program> = utils::generate_random_programs(2000, 1, 40, 4, &opcodes, Some(&rel_probability), false, true, &mut rng)
+                .iter()
+                .map(|p| Program::new(p.get_instr(), p.get_num_data_slots(), allow_crossing_blocks))
+                .collect();
+
+            assert!(check_equivalence(&programs, &[&RemoveEmptyBlocks]) > programs.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod remapping_tests {
+    use vm::{OpCode, Program};
+
+    #[test]
+    fn sqrt_to_nop() {
+        let prog = Program::new(
+            &[
+                OpCode::EndGoTo,
+                    OpCode::Sqrt,
+                    OpCode::JumpIfN,
+                        OpCode::Sqrt,
+                    OpCode::EndJump,
+                OpCode::GoToIfP,
+                OpCode::Sqrt
+            ],
+            1, false);
+        let remapped = prog.remap_opcodes(|opcode| if opcode == OpCode::Sqrt { OpCode::Nop } else { opcode });
+
+        assert!(remapped.get_instr() == [
+            OpCode::EndGoTo,
+                OpCode::Nop,
+                OpCode::JumpIfN,
+                    OpCode::Nop,
+                OpCode::EndJump,
+            OpCode::GoToIfP,
+            OpCode::Nop
+        ]);
+        assert!(!remapped.get_instr().contains(&OpCode::Sqrt));
+        assert!(remapped.get_jump_table() == prog.get_jump_table());
+        assert!(
+            vec![
+                Some(5),
+                None,
+                Some(4),
+                None,
+                Some(2),
+                Some(0),
+                None
+            ] == remapped.get_jump_table());
+        t_assert_eq!(prog.get_num_data_slots(), remapped.get_num_data_slots());
+    }
+}
+
+#[cfg(test)]
+mod step_tests {
+    use super::{EndReason, InputOutputHandler, OpCode, Program, RegValue, VirtualMachine, VmState};
+
+    #[test]
+    fn single_steps() {
+        let program = Program::new(&[
+            OpCode::SetI(5),
+            OpCode::ItoV,
+            OpCode::IncV
+        ], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        assert!(vm.step().is_none());
+        t_assert_eq!(1, vm.get_state().iptr);
+        t_assert_eq!(5, vm.get_state().reg_i);
+
+        assert!(vm.step().is_none());
+        t_assert_eq!(2, vm.get_state().iptr);
+        t_assert_eq!(5.0, vm.get_state().reg_v);
+
+        assert!(vm.step() == Some(EndReason::LastInstructionReached));
+        t_assert_eq!(6.0, vm.get_state().reg_v);
+    }
+
+    #[test]
+    fn looped_wraps_around() {
+        let program = Program::new(&[
+            OpCode::IncV,
+            OpCode::IncV
+        ], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        assert!(vm.step_with(true, false).is_none());
+        assert!(vm.step_with(true, false).is_none());
+        t_assert_eq!(0, vm.get_state().iptr);
+        assert!(vm.step_with(true, false).is_none());
+        t_assert_eq!(1, vm.get_state().iptr);
+        t_assert_eq!(3.0, vm.get_state().reg_v);
+    }
+
+    #[test]
+    fn jump_taken() {
+        let program = Program::new(&[
+            OpCode::EndGoTo,
+            OpCode::GoToIfP
+        ], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        assert!(vm.step().is_none());
+        assert!(vm.step().is_none());
+        t_assert_eq!(0, vm.get_state().iptr);
+    }
+
+    #[test]
+    fn end_condition() {
+        const NUM_INSTR_TO_END: usize = 3;
+
+        #[derive(Default)]
+        struct IoHandler { }
+        impl InputOutputHandler for IoHandler {
+            fn input(&mut self, _: i32) -> RegValue { 0.0 }
+            fn output(&mut self, _: i32, _: RegValue) { }
+            fn check_end_condition(&self, num_execd_instructions: usize) -> bool {
+                num_execd_instructions >= NUM_INSTR_TO_END
+            }
+        }
+
+        let mut io_handler = IoHandler::default();
+        let program = Program::new(&[OpCode::Output(0)], 0, false);
+        let mut vm = VirtualMachine::new(&program, Some(&mut io_handler));
+
+        assert!(vm.step_with(true, true).is_none());
+        assert!(vm.step_with(true, true).is_none());
+        assert!(vm.step_with(true, true) == Some(EndReason::EndConditionMet));
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let program = Program::new(&[
+            OpCode::SetI(1),
+            OpCode::RandV,
+            OpCode::Store,
+            OpCode::IncV,
+            OpCode::SetI(0),
+            OpCode::Store
+        ], 2, false);
+        let mut vm = VirtualMachine::new(&program, None);
+
+        for _ in 0..3 { vm.step_with(true, false); }
+        let snapshot = vm.snapshot();
+
+        for _ in 0..5 { vm.step_with(true, false); }
+        let after_steps = vm.snapshot();
+        assert!(after_steps.iptr != snapshot.iptr);
+        assert!(after_steps.data != snapshot.data);
+
+        vm.restore(snapshot.clone());
+        let state = vm.get_state();
+        assert!(state.data == snapshot.data);
+        t_assert_eq!(snapshot.reg_i, state.reg_i);
+        t_assert_eq!(snapshot.reg_bank, state.reg_bank);
+        t_assert_eq!(snapshot.reg_v, state.reg_v);
+        t_assert_eq!(snapshot.iptr, state.iptr);
+
+        // the random number generator is restored too, so the same steps lead to the same state
+        for _ in 0..5 { vm.step_with(true, false); }
+        let state = vm.get_state();
+        assert!(state.data == after_steps.data);
+        t_assert_eq!(after_steps.reg_i, state.reg_i);
+        t_assert_eq!(after_steps.reg_v, state.reg_v);
+        t_assert_eq!(after_steps.iptr, state.iptr);
+    }
+
+    #[test]
+    fn with_seeded_state() {
+        let program = Program::new(&[OpCode::RandV], 0, false);
+        let random_value = |state: VmState| {
+            let mut vm = VirtualMachine::with_state(&program, None, state);
+            vm.step();
+            vm.get_state().reg_v
+        };
+
+        let mut seeded = VirtualMachine::new_seeded(&program, None, 7);
+        seeded.step();
+        t_assert_eq!(seeded.get_state().reg_v, random_value(VmState::with_seed(&program, 7)));
+        let default_seed = VirtualMachine::<RegValue>::DEFAULT_RNG_SEED;
+        t_assert_eq!(random_value(VmState::new(&program)), random_value(VmState::with_seed(&program, default_seed)));
+    }
+
+    #[test]
+    fn with_state() {
+        let program = Program::new(&[OpCode::Add, OpCode::SetI(1), OpCode::Load], 2, false);
+
+        let mut state = VmState::new(&program);
+        state.data[0] = 2.5;
+        state.data[1] = 7.0;
+        state.reg_v = 1.0;
+        let mut vm = VirtualMachine::with_state(&program, None, state);
+
+        vm.step();
+        t_assert_eq!(3.5, vm.get_state().reg_v);
+        vm.step();
+        vm.step();
+        t_assert_eq!(7.0, vm.get_state().reg_v);
+    }
+
+    #[test]
+    #[should_panic]
+    fn with_state_data_size_mismatch() {
+        let program = Program::new(&[OpCode::Load], 2, false);
+        let mut state = VmState::new(&program);
+        state.data.push(0.0);
+        VirtualMachine::with_state(&program, None, state);
+    }
+
+    #[test]
+    #[should_panic(expected = "state has 3 data values, program expects 2")]
+    fn restore_data_size_mismatch() {
+        let program = Program::new(&[OpCode::Load], 2, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        let mut state = vm.snapshot();
+        state.data.push(0.0);
+        vm.restore(state);
+    }
+
+    #[test]
+    fn state_with_data_banks() {
+        let mut program = Program::new(&[OpCode::Load], 2, false);
+        program.set_num_data_banks(2);
+
+        let mut state = VmState::new(&program);
+        t_assert_eq!(4, state.data.len());
+        state.data[3] = 1.0;
+        let mut vm = VirtualMachine::with_state(&program, None, state);
+        t_assert_eq!(1.0, vm.get_data_mut()[3]);
+
+        let snapshot = vm.snapshot();
+        vm.get_data_mut()[3] = 2.0;
+        vm.restore(snapshot);
+        t_assert_eq!(1.0, vm.get_data_mut()[3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "state has 2 data values, program expects 4")]
+    fn restore_ignoring_data_banks() {
+        let mut program = Program::new(&[OpCode::Load], 2, false);
+        program.set_num_data_banks(2);
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.restore(VmState::new(&Program::new(&[OpCode::Load], 2, false)));
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use super::{OpCode, Program, VirtualMachine};
+
+    #[test]
+    fn trace_executed_instructions() {
+        let program = Program::new(&[
+            OpCode::SetI(-1),
+            OpCode::ItoV,
+            OpCode::JumpIfN,
+                OpCode::IncV,
+            OpCode::EndJump,
+            OpCode::Nop
+        ], 0, false);
+
+        let mut trace: Vec<(usize, OpCode, i32)> = vec![];
+        {
+            let mut vm = VirtualMachine::new(&program, None);
+            vm.set_trace_hook(Box::new(|iptr, opcode, state| trace.push((iptr, opcode, state.reg_i))));
+            vm.run(None, false, false);
+        }
+
+        assert!(trace == vec![
+            (0, OpCode::SetI(-1), 0),
+            (1, OpCode::ItoV, -1),
+            (2, OpCode::JumpIfN, -1),
+            (4, OpCode::EndJump, -1),
+            (5, OpCode::Nop, -1)
+        ]);
+    }
+
+    #[test]
+    fn clear_trace_hook() {
+        let program = Program::new(&[OpCode::Nop, OpCode::Nop], 0, false);
+
+        let mut num_traced = 0;
+        {
+            let mut vm = VirtualMachine::new(&program, None);
+            vm.set_trace_hook(Box::new(|_, _, _| num_traced += 1));
+            vm.step();
+            vm.clear_trace_hook();
+            vm.step();
+        }
+        t_assert_eq!(1, num_traced);
+    }
+}
+
+#[cfg(test)]
+mod output_buffer_tests {
+    use super::{InputOutputHandler, OpCode, Program, RegValue, VirtualMachine};
+
+    #[derive(Default)]
+    struct IoHandler { num_outputs: usize }
+
+    impl InputOutputHandler for IoHandler {
+        fn input(&mut self, _: i32) -> RegValue { 0.0 }
+        fn output(&mut self, _: i32, _: RegValue) { self.num_outputs += 1; }
+        fn check_end_condition(&self, _: usize) -> bool { false }
+    }
+
+    #[test]
+    fn buffered_outputs() {
+        let program = Program::new(&[
+            OpCode::IncV,
+            OpCode::Output(0),
+            OpCode::IncV,
+            OpCode::Output(2),
+            OpCode::Neg,
+            OpCode::Output(1)
+        ], 0, false);
+
+        let mut io_handler = IoHandler::default();
+        {
+            let mut vm = VirtualMachine::new(&program, Some(&mut io_handler));
+            vm.run(None, false, false);
+            assert!(vm.take_outputs().is_empty());
+
+            vm.reset();
+            vm.set_output_buffering(true);
+            vm.run(None, false, false);
+            assert!(vm.take_outputs() == vec![(0, 1.0), (2, 2.0), (1, -2.0)]);
+            assert!(vm.take_outputs().is_empty());
+        }
+        t_assert_eq!(6, io_handler.num_outputs);
+    }
+
+    #[test]
+    fn reset_clears_outputs() {
+        let program = Program::new(&[OpCode::Output(3)], 0, false);
+        let mut vm = VirtualMachine::new(&program, None);
+        vm.set_output_buffering(true);
+
+        vm.run(None, false, false);
+        vm.reset();
+        vm.run(None, false, false);
+        assert!(vm.take_outputs() == vec![(3, 0.0)]);
+
+        vm.reset();
+        vm.run(None, false, false);
+        vm.set_output_buffering(false);
+        assert!(vm.take_outputs().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod input_cache_tests {
+    use super::{InputOutputHandler, OpCode, Program, RegValue, VirtualMachine};
+
+    /// Returns consecutive numbers (1, 2, ...) regardless of the input number.
+    #[derive(Default)]
+    struct Counter { num_inputs: usize }
+
+    impl InputOutputHandler for Counter {
+        fn input(&mut self, _: i32) -> RegValue { self.num_inputs += 1; self.num_inputs as RegValue }
+        fn output(&mut self, _: i32, _: RegValue) {}
+        fn check_end_condition(&self, _: usize) -> bool { false }
+    }
+
+    fn run(program: &Program, input_caching: bool) -> Vec<RegValue> {
+        let mut counter = Counter::default();
+        let mut vm = VirtualMachine::new(program, Some(&mut counter));
+        vm.set_input_caching(input_caching);
+        vm.set_output_buffering(true);
+        vm.run(None, false, false);
+        let mut outputs: Vec<RegValue> = vm.take_outputs().iter().map(|(_, value)| *value).collect();
+
+        vm.reset();
+        vm.run(None, false, false);
+        outputs.extend(vm.take_outputs().iter().map(|(_, value)| *value));
+        outputs
+    }
+
+    #[test]
+    fn cached_vs_live() {
+        let program = Program::new(&[
+            OpCode::Input(0), OpCode::Output(0),
+            OpCode::Input(1), OpCode::Output(0),
+            OpCode::Input(0), OpCode::Output(0),
+            OpCode::Input(1), OpCode::Output(0)
+        ], 0, false);
+
+        assert!(run(&program, false) == vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]);
+        // after `reset`, the handler is queried again
+        assert!(run(&program, true) == vec![1.0, 2.0, 1.0, 2.0, 3.0, 4.0, 3.0, 4.0]);
+    }
+}
+
+#[cfg(test)]
+mod infinite_loop_tests {
+    use vm::{OpCode, Program};
+
+    #[test]
+    fn monotonic_increment_loop() {
+        let prog = Program::new(
+            &[
+                OpCode::Load,
+                OpCode::EndGoTo,
+                    OpCode::IncV,
+                    OpCode::Output(0),
+                OpCode::GoToIfP
+            ],
+            1, false);
+
+        assert!(prog.likely_infinite_loops() == vec![4]);
+    }
+
+    #[test]
+    fn decrement_loop() {
+        let prog = Program::new(
+            &[
+                OpCode::EndGoTo,
+                    OpCode::Output(0),
+                    OpCode::DecV,
+                OpCode::GoToIfP
+            ],
+            1, false);
+
+        assert!(prog.likely_infinite_loops().is_empty());
+    }
+
+    #[test]
+    fn nested_loops() {
+        let prog = Program::new(
+            &[
+                OpCode::EndGoTo,     // 0
+                    OpCode::EndGoTo, // 1
+                        OpCode::Nop, // 2
+                    OpCode::GoToIfP, // 3: likely infinite
+                    OpCode::Neg,     // 4
+                OpCode::GoToIfP,     // 5
+                OpCode::GoToIfP      // 6: unmatched
+            ],
+            1, false);
+
+        assert!(prog.likely_infinite_loops() == vec![3]);
+    }
+
+    #[test]
+    fn skippable_goto() {
+        let prog = Program::new(
+            &[
+                OpCode::EndGoTo,
+                    OpCode::IncV,
+                    OpCode::IfN,
+                OpCode::GoToIfP
+            ],
+            1, false);
+
+        assert!(prog.likely_infinite_loops().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod number_type_tests {
+    use super::{InputOutputHandler, OpCode, Program, VirtualMachine, VmNumber};
+
+    struct IoHandler {
+        outputs: Vec<f64>
+    }
+
+    impl<T: VmNumber + Into<f64>> InputOutputHandler<T> for IoHandler {
+        fn input(&mut self, input_num: i32) -> T { T::from_i32(2 * input_num + 1) }
+
+        fn output(&mut self, _output_num: i32, output_val: T) { self.outputs.push(output_val.into()); }
+
+        fn check_end_condition(&self, _num_execd_instructions: usize) -> bool { false }
+    }
+
+    /// Returns data slots, `reg_v`, `reg_i` and outputs after running `program`.
+    fn run<T: VmNumber + Into<f64>>(program: &Program) -> (Vec<f64>, f64, i32, Vec<f64>) {
+        let mut io_handler = IoHandler{ outputs: vec![] };
+        let (data, reg_v, reg_i) = {
+            let mut vm = VirtualMachine::<T>::with_number_type(program, Some(&mut io_handler));
+            vm.run(Some(500), true, false);
+            let state = vm.get_state();
+            (state.data.iter().map(|&x| x.into()).collect(), state.reg_v.into(), state.reg_i)
+        };
+
+        (data, reg_v, reg_i, io_handler.outputs)
+    }
+
+    #[test]
+    fn f32_and_f64_behave_identically() {
+        let program = Program::new(&[
+            OpCode::SetI(0),
+            OpCode::Input(3),
+            OpCode::Store,
+            OpCode::SetI(1),
+            OpCode::Input(0),
+            OpCode::Store,
+            OpCode::EndGoTo,
+                OpCode::SetI(0),
+                OpCode::Load,
+                OpCode::SetI(1),
+                OpCode::Mul,
+                OpCode::Store,
+                OpCode::Output(0),
+                OpCode::SetI(0),
+                OpCode::Load,
+                OpCode::DecV,
+                OpCode::DecV,
+                OpCode::Store,
+            OpCode::GoToIfP,
+            OpCode::Abs,
+            OpCode::Neg,
+            OpCode::VtoI,
+            OpCode::IncI,
+            OpCode::IncI,
+            OpCode::ItoV,
+            OpCode::Swap,
+            OpCode::Cmp,
+            OpCode::Output(1),
+            OpCode::SetI(2),
+            OpCode::Input(7),
+            OpCode::IncV,
+            OpCode::Store,
+            OpCode::Sqrt,
+            OpCode::Div,
+            OpCode::Output(2)
+        ], 3, false);
+
+        let result_f32 = run::<f32>(&program);
+        let result_f64 = run::<f64>(&program);
+
+        assert!(result_f32 == result_f64);
+        assert!(result_f32.3[..6] == [7.0, 35.0, 105.0, 105.0, 1.0, 0.25]);
+    }
+
+    /// Returns `reg_v` after executing `opcode` with strict math, starting with `reg_v` = `a` and `data[0]` = `b`.
+    fn strict_result<T: VmNumber>(opcode: OpCode, a: T, b: T) -> T {
+        let program = Program::new(&[opcode], 1, false);
+        let mut vm = VirtualMachine::<T>::with_number_type(&program, None);
+        vm.set_strict_math(true);
+        vm.set_reg_v(a);
+        vm.get_data_mut()[0] = b;
+        vm.run(None, false, false);
+        vm.get_state().reg_v
+    }
+
+    ///
+    /// Returns `reg_v` after each instruction of `program`, starting with `reg_v` and `data`, as computed by the JavaScript VM
+    /// transpiled with `JsTranspileOptions::f32_arithmetic` (see `handleInstruction` in `transpile::javascript_vm`):
+    /// in `f64`, with the result rounded by `Math.fround`.
+    ///
+    fn js_f32_results(program: &[OpCode], mut reg_v: f64, data: &[f64]) -> Vec<f64> {
+        let fround = |value: f64| value as f32 as f64;
+        let mut reg_i = 0;
+        let mut results = vec![];
+        for &opcode in program {
+            match opcode {
+                OpCode::SetI(i) => reg_i = i as usize,
+                OpCode::IncV => reg_v = fround(reg_v + 1.0),
+                OpCode::Add => reg_v = fround(reg_v + data[reg_i]),
+                OpCode::Sub => reg_v = fround(reg_v - data[reg_i]),
+                OpCode::Mul => reg_v = fround(reg_v * data[reg_i]),
+                OpCode::Div => if data[reg_i] != 0.0 { reg_v = fround(reg_v / data[reg_i]); },
+                OpCode::Sqrt => reg_v = if reg_v >= 0.0 { fround(reg_v.sqrt()) } else { 0.0 },
+                _ => panic!("unexpected opcode")
+            }
+            results.push(reg_v);
+        }
+
+        results
+    }
+
+    fn same<T: VmNumber>(x: T, y: T) -> bool {
+        // NaNs are considered equal, signed zeros are not
+        format!("{:?}", x) == format!("{:?}", y)
+    }
+
+    #[test]
+    fn strict_math_matches_javascript() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(1);
+        let special = [0.0, -0.0, 1.0, -1.0, 3.0, 0.1, 1.0e-30, f32::MAX, f32::MIN_POSITIVE, f32::INFINITY, f32::NAN];
+        let values: Vec<f32> = special.iter().cloned()
+            .chain((0..200).map(|_| rng.gen_range(-1.0e6, 1.0e6) * if rng.gen() { 1.0e-9 } else { 1.0 }))
+            .collect();
+
+        let program = [
+            OpCode::Add, OpCode::SetI(1), OpCode::Mul, OpCode::IncV, OpCode::SetI(2), OpCode::Div,
+            OpCode::Sqrt, OpCode::SetI(0), OpCode::Sub, OpCode::SetI(1), OpCode::Div, OpCode::Mul
+        ];
+        let vm_program = Program::new(&program, 3, false);
+        for (i, &reg_v) in values.iter().enumerate() {
+            let data = [values[(i * 7 + 1) % values.len()], values[(i * 13 + 2) % values.len()], values[(i * 5 + 3) % values.len()]];
+
+            let mut vm = VirtualMachine::<f32>::with_number_type(&vm_program, None);
+            vm.set_strict_math(true);
+            vm.set_reg_v(reg_v);
+            vm.get_data_mut().copy_from_slice(&data);
+
+            let data64: Vec<f64> = data.iter().map(|&d| d as f64).collect();
+            for expected in js_f32_results(&program, reg_v as f64, &data64) {
+                vm.step();
+                assert!(same(expected as f32, vm.get_state().reg_v));
+            }
+        }
+    }
+
+    #[test]
+    fn strict_math_transcendental_functions() {
+        // exact results rounded to `f64` (and `f32`), regardless of the platform's math library
+        let cases = [
+            (OpCode::Sin, 1.0, 0.0, 0.8414709848078965),
+            (OpCode::Sin, 1.0e6, 0.0, -0.34999350217129294),
+            (OpCode::Cos, 2.0, 0.0, -0.4161468365471424),
+            (OpCode::Log, 10.0, 0.0, std::f64::consts::LN_10),
+            (OpCode::Pow, 2.0, 0.5, std::f64::consts::SQRT_2),
+            (OpCode::Pow, 1.5, -3.25, 0.2677339269955096)
+        ];
+        for &(opcode, a, b, expected) in cases.iter() {
+            t_assert_eq!(expected as f32, strict_result::<f32>(opcode, a as f32, b as f32));
+            t_assert_eq!(expected, strict_result::<f64>(opcode, a, b));
+        }
+
+        // the same special cases as without strict math
+        t_assert_eq!(0.0, strict_result::<f32>(OpCode::Log, -1.0, 0.0));
+        t_assert_eq!(0.0, strict_result::<f32>(OpCode::Pow, -1.0, 0.5));
+    }
+}
+
+#[cfg(test)]
+mod static_dispatch_tests {
+    use super::{InputOutputHandler, NoHandler, OpCode, Program, RegValue, VirtualMachine};
+
+    struct IoHandler {
+        outputs: Vec<(i32, RegValue)>
+    }
+
+    impl InputOutputHandler for IoHandler {
+        fn input(&mut self, input_num: i32) -> RegValue { input_num as RegValue * 0.5 }
+
+        fn output(&mut self, output_num: i32, output_val: RegValue) { self.outputs.push((output_num, output_val)); }
+
+        fn check_end_condition(&self, _num_execd_instructions: usize) -> bool { self.outputs.len() >= 20 }
+    }
+
+    fn test_program() -> Program {
+        Program::new(&[
+            OpCode::Input(3),
+            OpCode::EndGoTo,
+                OpCode::SetI(0),
+                OpCode::Add,
+                OpCode::Store,
+                OpCode::Output(1),
+                OpCode::Input(-1),
+            OpCode::GoToIfP,
+            OpCode::Output(2)
+        ], 1, false)
+    }
+
+    #[test]
+    fn same_behavior_as_dynamic_dispatch() {
+        let program = test_program();
+
+        let mut dyn_handler = IoHandler{ outputs: vec![] };
+        let (dyn_reason, dyn_state) = {
+            let mut vm = VirtualMachine::new(&program, Some(&mut dyn_handler));
+            (vm.run(Some(1000), true, true), vm.get_state().clone())
+        };
+
+        let mut static_handler = IoHandler{ outputs: vec![] };
+        let (static_reason, static_state) = {
+            let mut vm = VirtualMachine::with_handler(&program, Some(&mut static_handler));
+            (vm.run(Some(1000), true, true), vm.get_state().clone())
+        };
+
+        assert!(dyn_reason == static_reason);
+        assert_eq!(dyn_handler.outputs, static_handler.outputs);
+        assert_eq!(20, static_handler.outputs.len());
+        assert!(dyn_state.data == static_state.data);
+        assert_eq!(dyn_state.reg_v, static_state.reg_v);
+        assert_eq!(dyn_state.reg_i, static_state.reg_i);
+        assert_eq!(dyn_state.iptr, static_state.iptr);
+    }
+
+    #[test]
+    fn no_handler() {
+        let program = test_program();
+        let mut vm = VirtualMachine::<RegValue, NoHandler>::with_handler(&program, None);
+        vm.run(Some(100), true, false);
+        t_assert_eq!(0.0, vm.get_state().reg_v);
+    }
+}
+
+#[cfg(test)]
+mod fuzz_tests {
+    use rand::{Rng, SeedableRng};
+    use super::{ArithmeticMode, IndexMode, InputOutputHandler, OpCode, Program, RegValue, RunMode, RunOptions, VirtualMachine, VmState};
+
+    const NUM_PROGRAMS: u64 = 3000;
+    const MAX_EXEC_INSTRUCTIONS: usize = 2000;
+
+    /// Values likely to trigger edge cases (overflow, invalid indices, NaN propagation).
+    const EXTREME_I32: [i32; 7] = [i32::MIN, i32::MIN + 1, -1, 0, 1, i32::MAX - 1, i32::MAX];
+    const EXTREME_VALUES: [RegValue; 9] =
+        [RegValue::NAN, RegValue::INFINITY, RegValue::NEG_INFINITY, RegValue::MAX, RegValue::MIN, -0.0, 0.5, -1.0e10, 3.0e9];
+
+    struct IoHandler {
+        rng: rand_xorshift::XorShiftRng
+    }
+
+    impl InputOutputHandler for IoHandler {
+        fn input(&mut self, _: i32) -> RegValue { random_value(&mut self.rng) }
+        fn output(&mut self, _: i32, _: RegValue) {}
+        fn check_end_condition(&self, _: usize) -> bool { false }
+    }
+
+    fn random_i32<R: Rng>(rng: &mut R) -> i32 {
+        if rng.gen() { EXTREME_I32[rng.gen_range(0, EXTREME_I32.len())] } else { rng.gen_range(-10, 10) }
+    }
+
+    fn random_value<R: Rng>(rng: &mut R) -> RegValue {
+        if rng.gen() { EXTREME_VALUES[rng.gen_range(0, EXTREME_VALUES.len())] } else { rng.gen_range(-10.0, 10.0) }
+    }
+
+    /// Returns a program of arbitrary instructions, operands and settings.
+    fn random_program<R: Rng>(rng: &mut R) -> Program {
+        let length = rng.gen_range(0, 64);
+        let instructions: Vec<OpCode> = (0..length).map(|_| {
+            let opcode = OpCode::ALL[rng.gen_range(0, OpCode::ALL.len())];
+            opcode.with_operand(random_i32(rng))
+        }).collect();
+
+        let num_data_slots = rng.gen_range(0, 5);
+        let mut program = Program::new(&instructions, num_data_slots, rng.gen());
+        program.set_num_data_banks(if num_data_slots == 0 { rng.gen_range(0, 3) } else { rng.gen_range(1, 3) });
+        program.set_index_mode([IndexMode::Ignore, IndexMode::Wrap, IndexMode::Clamp][rng.gen_range(0, 3)]);
+        if rng.gen() {
+            program.set_arithmetic_mode(ArithmeticMode::Saturating{ min: -1.0e6, max: 1.0e6 });
+        }
+        if rng.gen() {
+            program.set_cmp_epsilon(rng.gen_range(0.0, 1.0));
+        }
+
+        program
+    }
+
+    /// Runs a random program from a random state; panics (as the VM would) if the VM panics.
+    fn run_random(seed: u64) {
+        let mut rng = rand_xorshift::XorShiftRng::seed_from_u64(seed);
+        let program = random_program(&mut rng);
+
+        let mut state = VmState::new(&program);
+        state.reg_i = random_i32(&mut rng);
+        state.reg_bank = random_i32(&mut rng);
+        state.reg_v = random_value(&mut rng);
+        for value in state.data.iter_mut() {
+            *value = random_value(&mut rng);
+        }
+        if !program.get_instr().is_empty() {
+            state.iptr = rng.gen_range(0, program.get_instr().len());
+        }
+
+        let mut io_handler = IoHandler{ rng: rand_xorshift::XorShiftRng::seed_from_u64(seed) };
+        let options = RunOptions{
+            num_exec_instructions: Some(MAX_EXEC_INSTRUCTIONS),
+            mode: [RunMode::Once, RunMode::LoopForever, RunMode::LoopTimes(3)][rng.gen_range(0, 3)],
+            detect_loops: rng.gen(),
+            ..Default::default()
+        };
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut vm = VirtualMachine::with_state(&program, Some(&mut io_handler), state);
+            vm.set_reset_on_loop(rng.gen());
+            vm.set_strict_math(rng.gen());
+            vm.set_input_caching(rng.gen());
+            vm.run_reported(&options);
+        }));
+
+        if let Err(panic) = result {
+            println!("VM panicked for seed {}; program: {:?} (data slots: {}, banks: {}, {:?}, {:?}), options: {:?}",
+                seed, program.get_instr(), program.get_num_data_slots(), program.get_num_data_banks(),
+                program.get_index_mode(), program.get_arithmetic_mode(), options);
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    #[test]
+    fn random_programs_do_not_panic() {
+        for seed in 0..NUM_PROGRAMS {
+            run_random(seed);
+        }
+    }
+}